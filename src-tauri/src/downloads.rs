@@ -0,0 +1,2196 @@
+//! Downloads started by the frontend, queued and run a few at a time with
+//! [`reqwest_resume`], see the commands in [`commands`].
+//!
+//! Each download is `Queued` until a worker is free, then `Running` until it's
+//! `Done` or `Failed`. Pausing one that's running cancels its request; the
+//! `.part` file stays behind so resuming it picks up where it stopped. A
+//! download given an [`ExpectedHash`] that doesn't match it ends up
+//! `FailedVerification` instead, its file moved aside to a temporary name.
+//! One given an [`Extraction`] is `Extracting` before it's `Done`, without
+//! taking up a slot in the queue.
+//!
+//! The queue is saved in a [`Store`], so downloads that were running or
+//! queued when the app exited are resumed when it starts again. The rest is
+//! up to the submodules: how new downloads are named and sorted into
+//! [`Categories`], which are duplicates, importing and exporting the queue,
+//! keeping the webview up to date through [`events`], and when the
+//! [`network`], a [`metered`] connection and the [`time_windows`] let
+//! downloads run.
+
+mod categories;
+pub mod commands;
+mod duplicates;
+pub mod events;
+mod extract;
+mod history;
+mod import;
+mod metered;
+mod naming;
+mod network;
+mod notify;
+mod portable;
+mod speed;
+mod store;
+mod time_windows;
+mod transfer_log;
+pub mod tray;
+
+pub use categories::{Categories, Category, CategoryRule, CategorySettings};
+pub use duplicates::{Duplicate, DuplicateAction, DuplicateMatch, Existing};
+pub use extract::{Extraction, SymlinkPolicy};
+pub use history::{HistoryEntry, HistoryPage, HistoryRetention, HistoryStats};
+pub use import::{ImportError, ImportFormat, ImportResult};
+pub use metered::MeteredPolicy;
+pub use naming::{ConflictPolicy, NamingSettings};
+pub use network::NetworkState;
+pub use notify::NotificationSettings;
+pub use portable::{BaseFolder, ExportedDownload, ExportedSettings, QueueExport};
+pub use speed::SpeedSample;
+pub use store::Store;
+pub use time_windows::TimeWindow;
+pub use transfer_log::{LogEntry, LogEvent, TransferLog};
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local, Utc};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tauri::{
+    async_runtime::{self, JoinHandle},
+    AppHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    err,
+    errors::{Context, Result},
+    logerr,
+    reqwest_resume::{
+        self,
+        chunks::{ChunkIndex, ChunkStore},
+        metalink::{Metalink, MetalinkFile},
+        Checksum, Client, Download, Event, Probe, ProgressHandle, RateLimit, RequestBuilder,
+        RetryPolicy, Sidecar,
+    },
+};
+
+use self::{events::ProgressEmitter, speed::SpeedHistory, store::Record};
+
+pub type DownloadId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Status {
+    Queued,
+    Running,
+    /// Downloaded, and being unpacked as its [`Extraction`] says.
+    Extracting,
+    Paused,
+    Failed,
+    /// Complete, but it doesn't match its [`ExpectedHash`].
+    FailedVerification,
+    Done,
+}
+
+/// A digest a download must match once it's complete.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpectedHash {
+    pub algorithm: HashAlgorithm,
+    /// Hex-encoded.
+    pub hex: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HashAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+/// Which queued downloads start first. A `High` one waiting for a free slot
+/// pauses a running download of lower priority to take its place.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// How a download is to be run, besides where from and to.
+#[derive(Clone, Debug, Default)]
+pub struct DownloadOptions {
+    /// What it's sorted into, if not what the [`CategoryRule`]s say.
+    pub category: Option<Category>,
+    pub priority: Priority,
+    /// A digest it must match once it's complete.
+    pub expected_hash: Option<ExpectedHash>,
+    /// Where to extract it once it's complete.
+    pub extraction: Option<Extraction>,
+    /// When to start it, if not right away.
+    pub start_at: Option<DateTime<Utc>>,
+    /// What to do if the queue already has it. Without one, it isn't queued.
+    pub on_duplicate: Option<DuplicateAction>,
+    /// What to do if its destination is taken, if not what the
+    /// [`NamingSettings`] say.
+    pub on_conflict: Option<ConflictPolicy>,
+    /// Where its [`ChunkIndex`] is published, to download only the chunks
+    /// that aren't stored yet while the chunk store is enabled.
+    pub chunk_index: Option<Url>,
+    /// Other URLs serving the same file, to go on from when `url` fails.
+    pub mirrors: Vec<Url>,
+    /// How big it is, if that's known before it's requested.
+    pub size: Option<u64>,
+    /// Whether `destination` was named already, e.g. by the naming template
+    /// on the computer it was exported from, so the template isn't applied
+    /// to it again.
+    pub named: bool,
+}
+
+/// What [`DownloadManager::start`] did.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Started {
+    Queued(DownloadId),
+    /// Attached to this existing download.
+    Attached(DownloadId),
+    /// Skipped as a duplicate of this one, which may have left the queue.
+    Skipped(DownloadId),
+    /// Not queued, as it's a duplicate and no [`DuplicateAction`] was given.
+    Duplicate(Box<Duplicate>),
+    /// Skipped, as this destination is taken and
+    /// [`ConflictPolicy::Skip`] says to.
+    Exists(PathBuf),
+    /// Not queued, as this destination is taken and
+    /// [`ConflictPolicy::Ask`] says to ask.
+    Conflict(PathBuf),
+}
+
+/// What the frontend is told about a download.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadInfo {
+    pub id: DownloadId,
+    pub url: String,
+    pub destination: PathBuf,
+    pub status: Status,
+    pub category: Category,
+    pub priority: Priority,
+    /// Bytes per second it's capped at, besides the global limit.
+    pub bandwidth_limit: Option<u64>,
+    pub expected_hash: Option<ExpectedHash>,
+    pub extraction: Option<Extraction>,
+    pub start_at: Option<DateTime<Utc>>,
+    pub chunk_index: Option<String>,
+    /// Bytes on disk.
+    pub bytes: u64,
+    pub total: Option<u64>,
+    /// Bytes per second, 0 unless it's running.
+    pub speed: f64,
+    /// Seconds left at that speed, if the total is known.
+    pub eta: Option<f64>,
+    /// Why it failed, if it did.
+    pub error: Option<String>,
+}
+
+/// How a download that didn't fail ended.
+enum Outcome {
+    Done(Download),
+    /// It didn't match its [`ExpectedHash`], and was quarantined.
+    Unverified(String),
+}
+
+/// Which downloads [`DownloadManager::list`] returns.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Filter {
+    /// Queued, running, extracting or paused.
+    Active,
+    Completed,
+    /// Failed, or failed its verification.
+    Failed,
+}
+
+/// A page of [`DownloadManager::list`].
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadPage {
+    /// How many downloads match the filter, on every page.
+    pub total: usize,
+    pub downloads: Vec<DownloadInfo>,
+}
+
+/// Owns the queue of downloads and the tasks running them. Clones share the
+/// same queue.
+#[derive(Clone)]
+pub struct DownloadManager {
+    app: AppHandle,
+    client: Client,
+    // shared by every download, each drawing from it through a child of its
+    // category's, which are never replaced
+    rate_limit: RateLimit,
+    category_rate_limits: Arc<HashMap<Category, RateLimit>>,
+    // apart from the queue, whose lock is held while statuses change
+    notifications: Arc<Mutex<NotificationSettings>>,
+    history_retention: Arc<Mutex<HistoryRetention>>,
+    // where the network is probed, if anywhere
+    connectivity_check: Arc<Mutex<Option<Url>>>,
+    // chunks of downloads to reuse, if enabled
+    chunk_store: Arc<Mutex<Option<ChunkStore>>>,
+    queue: Arc<Mutex<Queue>>,
+    store: Arc<Store>,
+}
+
+struct Queue {
+    entries: Vec<Entry>,
+    next_id: DownloadId,
+    max_concurrent: usize,
+    // so downloads from the same mirror don't take every slot
+    max_per_host: usize,
+    // bytes to leave free on the disks downloads are written to
+    min_free_space: u64,
+    // nothing is started unless it's online
+    network: NetworkState,
+    // the global limit the user set, lowered while metered
+    bandwidth_limit: Option<u64>,
+    metered_policy: MeteredPolicy,
+    // what the OS says, unless the policy overrides it
+    os_metered: Option<bool>,
+    metered: bool,
+    // none means any time
+    time_windows: Vec<TimeWindow>,
+    in_time_window: bool,
+    naming: NamingSettings,
+    categories: Categories,
+}
+
+struct Entry {
+    id: DownloadId,
+    url: Url,
+    // where `url` redirected to, and the ETag of what's there, to recognize
+    // duplicates by
+    final_url: Option<Url>,
+    etag: Option<String>,
+    destination: PathBuf,
+    status: Status,
+    category: Category,
+    priority: Priority,
+    bandwidth_limit: Option<u64>,
+    expected_hash: Option<ExpectedHash>,
+    extraction: Option<Extraction>,
+    start_at: Option<DateTime<Utc>>,
+    chunk_index: Option<Url>,
+    mirrors: Vec<Url>,
+    error: Option<String>,
+    // how far it got when it last stopped
+    bytes: u64,
+    total: Option<u64>,
+    // how long it's been running and what it received meanwhile, since the
+    // app started, for the history
+    active: Duration,
+    received: u64,
+    // shared with the event callback of its request
+    log: Arc<Mutex<TransferLog>>,
+    speeds: SpeedHistory,
+    // set from the moment the download starts until its task has returned,
+    // which may be a little after it was paused, and while it's extracted
+    task: Option<Task>,
+}
+
+struct Task {
+    cancel: CancellationToken,
+    progress: ProgressHandle,
+    rate_limit: RateLimit,
+    handle: JoinHandle<()>,
+    started: Instant,
+}
+
+/// What the task of an [`Entry`] downloads, copied from it as it starts.
+struct Job {
+    id: DownloadId,
+    url: Url,
+    destination: PathBuf,
+    expected_hash: Option<ExpectedHash>,
+    chunk_index: Option<Url>,
+    log: Arc<Mutex<TransferLog>>,
+}
+
+/// How many downloads from the same host run at once unless
+/// [`DownloadManager::set_max_concurrent`] says otherwise.
+pub const DEFAULT_MAX_PER_HOST: usize = 2;
+
+/// Free space downloads leave on a disk unless
+/// [`DownloadManager::set_min_free_space`] says otherwise.
+pub const DEFAULT_MIN_FREE_SPACE: u64 = 1 << 30;
+
+/// How often free space is checked while downloads run.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the time windows and start times of downloads are checked.
+const TIME_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often the progress in the tray is updated.
+const TRAY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a new download's `HEAD` request may take, before it's queued
+/// without checking for duplicates by its final URL and ETag.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl DownloadManager {
+    /// A manager running up to `max_concurrent` downloads at once with
+    /// `client`, picking up the queue saved in `store`.
+    pub fn new(app: AppHandle, client: Client, max_concurrent: usize, store: Store) -> Self {
+        let queue = Queue {
+            entries: Vec::new(),
+            next_id: 0,
+            max_concurrent: max_concurrent.max(1),
+            max_per_host: DEFAULT_MAX_PER_HOST.min(max_concurrent.max(1)),
+            min_free_space: DEFAULT_MIN_FREE_SPACE,
+            network: NetworkState::Online,
+            bandwidth_limit: None,
+            metered_policy: MeteredPolicy::default(),
+            os_metered: None,
+            metered: false,
+            time_windows: Vec::new(),
+            in_time_window: true,
+            naming: NamingSettings::default(),
+            categories: Categories::default(),
+        };
+        let rate_limit = RateLimit::new(None);
+        let category_rate_limits = (Category::ALL.into_iter())
+            .map(|category| (category, rate_limit.child(None)))
+            .collect();
+        let manager = DownloadManager {
+            app,
+            client,
+            rate_limit,
+            category_rate_limits: Arc::new(category_rate_limits),
+            notifications: Arc::default(),
+            history_retention: Arc::default(),
+            connectivity_check: Arc::new(Mutex::new(None)),
+            chunk_store: Arc::default(),
+            queue: Arc::new(Mutex::new(queue)),
+            store: Arc::new(store),
+        };
+        manager.restore();
+        manager.watch_disk_space();
+        manager.watch_progress();
+        manager.watch_speeds();
+        manager.watch_clock();
+        network::watch(manager.clone());
+        manager
+    }
+
+    /// Load the saved queue and resume the downloads the app was running.
+    fn restore(&self) {
+        let records = match self.store.load() {
+            Ok(records) => records,
+            Err(err) => {
+                log::error!("failed to load the download queue: {}", err);
+                return;
+            }
+        };
+        let mut queue = self.queue.lock().unwrap();
+        for record in records {
+            queue.next_id = queue.next_id.max(record.id + 1);
+            let Ok(url) = Url::parse(&record.url) else {
+                log::warn!(
+                    "skipping download {} of invalid url {}",
+                    record.id,
+                    record.url
+                );
+                continue;
+            };
+            let status = match record.status {
+                // interrupted by the app exiting
+                Status::Running | Status::Queued => {
+                    log::info!(
+                        "resuming download {} of {} from {} bytes",
+                        record.id,
+                        url,
+                        record.pos
+                    );
+                    Status::Queued
+                }
+                status => status,
+            };
+            queue.entries.push(Entry {
+                id: record.id,
+                url,
+                final_url: record.final_url.and_then(|url| Url::parse(&url).ok()),
+                etag: record.etag,
+                destination: record.destination,
+                status,
+                category: record.category,
+                priority: record.priority,
+                bandwidth_limit: record.bandwidth_limit,
+                expected_hash: record.expected_hash,
+                extraction: record.extraction,
+                start_at: record.start_at,
+                chunk_index: record.chunk_index.and_then(|url| Url::parse(&url).ok()),
+                mirrors: (record.mirrors.iter())
+                    .filter_map(|url| Url::parse(url).ok())
+                    .collect(),
+                error: record.error,
+                bytes: record.pos,
+                total: record.total,
+                active: Duration::ZERO,
+                received: 0,
+                log: Arc::new(Mutex::new(record.log)),
+                speeds: SpeedHistory::default(),
+                task: None,
+            });
+        }
+        // interrupted by the app exiting as well, and started over
+        for entry in &mut queue.entries {
+            if entry.status == Status::Extracting {
+                self.extract(entry);
+            }
+        }
+        self.schedule(&mut queue);
+    }
+
+    /// Queue a download of `url` to `destination`, named as the
+    /// [`NamingSettings`] say, unless the queue already has it: then it's up
+    /// to `options.on_duplicate`.
+    pub async fn start(&self, url: Url, destination: PathBuf, options: DownloadOptions) -> Started {
+        let on_duplicate = options.on_duplicate;
+        // not every server answers `HEAD`, which only makes duplicates
+        // harder to recognize, so it's tried once
+        let probe = (self.client.get(url.clone()))
+            .timeout(PROBE_TIMEOUT)
+            .retry_policy(RetryPolicy::none())
+            .probe();
+        let probe = match probe.await {
+            Ok(probe) => Some(probe),
+            Err(err) => {
+                log::debug!("failed to probe {}: {}", url, err);
+                None
+            }
+        };
+        let mut queue = self.queue.lock().unwrap();
+        let duplicate = (queue.entries.iter_mut()).find_map(|entry| {
+            duplicates::matches(entry.known(), &url, probe.as_ref()).map(|matched| (entry, matched))
+        });
+        if let Some((entry, matched)) = duplicate {
+            log::info!(
+                "{} is a duplicate of download {} by {:?}",
+                url,
+                entry.id,
+                matched
+            );
+            match on_duplicate {
+                None => {
+                    return Started::Duplicate(Box::new(Duplicate {
+                        existing: Existing::Queue(entry.info()),
+                        matched,
+                    }))
+                }
+                Some(DuplicateAction::Skip) => return Started::Skipped(entry.id),
+                Some(DuplicateAction::Attach) => {
+                    let id = entry.id;
+                    if matches!(
+                        entry.status,
+                        Status::Paused | Status::Failed | Status::FailedVerification
+                    ) {
+                        self.requeue(entry);
+                        self.schedule(&mut queue);
+                    }
+                    return Started::Attached(id);
+                }
+                Some(DuplicateAction::DownloadAgain) => {}
+            }
+        } else if let Some((entry, matched)) = self.downloaded_before(&url, probe.as_ref()) {
+            log::info!(
+                "{} was downloaded before by download {} by {:?}",
+                url,
+                entry.download_id,
+                matched
+            );
+            match on_duplicate {
+                None => {
+                    return Started::Duplicate(Box::new(Duplicate {
+                        existing: Existing::History(entry),
+                        matched,
+                    }))
+                }
+                // the file it downloaded is still there to use
+                Some(DuplicateAction::Skip | DuplicateAction::Attach) => {
+                    return Started::Skipped(entry.download_id)
+                }
+                Some(DuplicateAction::DownloadAgain) => {}
+            }
+        }
+        let record = match self.add(&mut queue, url, destination, options, probe.as_ref()) {
+            Ok(record) => record,
+            Err(started) => return started,
+        };
+        logerr!(self.store.insert(&record));
+        events::emit_state(&self.app, record.id, Status::Queued, None);
+        self.schedule(&mut queue);
+        Started::Queued(record.id)
+    }
+
+    /// Queue the downloads `contents` lists as `format`, or what it looks
+    /// like, saving them all at once. Rows that aren't valid are skipped, as
+    /// are those whose destination is taken unless the [`ConflictPolicy`]
+    /// renames or overwrites them. Unlike [`DownloadManager::start`], it
+    /// doesn't look for duplicates, which would take a request for each.
+    pub fn import(
+        &self,
+        contents: &str,
+        format: Option<ImportFormat>,
+        directory: Option<&Path>,
+    ) -> Result<ImportResult> {
+        let format = format.unwrap_or_else(|| import::detect(contents));
+        let rows = import::parse(contents, format, directory)?;
+        let mut result = ImportResult::default();
+        let mut records = Vec::new();
+        let mut queue = self.queue.lock().unwrap();
+        for (row, import) in rows {
+            let added = import.and_then(|import| {
+                let options = DownloadOptions {
+                    expected_hash: import.expected_hash,
+                    ..DownloadOptions::default()
+                };
+                let added = self.add(&mut queue, import.url, import.destination, options, None);
+                added.map_err(not_added)
+            });
+            match added {
+                Ok(record) => records.push(record),
+                Err(error) => result.errors.push(ImportError { row, error }),
+            }
+        }
+        log::info!("imported {} downloads", records.len());
+        logerr!(self.store.insert_all(&records));
+        for record in records {
+            events::emit_state(&self.app, record.id, Status::Queued, None);
+            result.queued.push(record.id);
+        }
+        self.schedule(&mut queue);
+        Ok(result)
+    }
+
+    /// Queue the files `metalink` lists in `directory`, each downloaded from
+    /// the first of its URLs and going on from the others when that fails,
+    /// and verified if it has a SHA-256 hash. As with
+    /// [`DownloadManager::import`], invalid files and those whose destination
+    /// is taken are skipped, and they're saved all at once.
+    pub fn import_metalink(&self, metalink: Metalink, directory: &Path) -> ImportResult {
+        let mut result = ImportResult::default();
+        let mut records = Vec::new();
+        let mut queue = self.queue.lock().unwrap();
+        for (file, row) in metalink.files.into_iter().zip(1..) {
+            match self.add_metalink_file(&mut queue, file, directory) {
+                Ok(record) => records.push(record),
+                Err(error) => result.errors.push(ImportError { row, error }),
+            }
+        }
+        log::info!("imported {} downloads from a metalink", records.len());
+        logerr!(self.store.insert_all(&records));
+        for record in records {
+            events::emit_state(&self.app, record.id, Status::Queued, None);
+            result.queued.push(record.id);
+        }
+        self.schedule(&mut queue);
+        result
+    }
+
+    /// Add `file`, from a metalink, to the end of `queue`.
+    fn add_metalink_file(
+        &self,
+        queue: &mut Queue,
+        file: MetalinkFile,
+        directory: &Path,
+    ) -> std::result::Result<Record, String> {
+        // the name comes from the document, and mustn't lead out of `directory`
+        let name = Path::new(&file.name);
+        let inside = (name.components()).all(|component| matches!(component, Component::Normal(_)));
+        if file.name.is_empty() || !inside {
+            return Err(format!("{:?} isn't a path inside the directory", file.name));
+        }
+        let mut urls = file.urls.into_iter();
+        let url = (urls.next()).ok_or_else(|| format!("{} has no HTTP url", file.name))?;
+        let expected_hash = match file.checksum {
+            Some(Checksum::Sha256(hex)) => Some(ExpectedHash {
+                algorithm: HashAlgorithm::Sha256,
+                hex,
+            }),
+            Some(Checksum::Blake3(hex)) => Some(ExpectedHash {
+                algorithm: HashAlgorithm::Blake3,
+                hex,
+            }),
+            Some(Checksum::Md5(_)) => {
+                log::info!("{} only has an MD5 hash, it won't be verified", file.name);
+                None
+            }
+            None => None,
+        };
+        if let Some(hash) = expected_hash.as_ref().filter(|hash| !hash.is_valid()) {
+            return Err(format!(
+                "Invalid {:?} digest {:?}",
+                hash.algorithm, hash.hex
+            ));
+        }
+        let options = DownloadOptions {
+            expected_hash,
+            mirrors: urls.collect(),
+            size: file.size,
+            ..DownloadOptions::default()
+        };
+        let added = self.add(queue, url, directory.join(name), options, None);
+        added.map_err(not_added)
+    }
+
+    /// The downloads that aren't done yet and the settings, for
+    /// [`DownloadManager::import_queue`] to pick up on another computer.
+    pub fn export_queue(&self) -> QueueExport {
+        let queue = self.queue.lock().unwrap();
+        let settings = ExportedSettings {
+            max_concurrent: queue.max_concurrent,
+            max_per_host: queue.max_per_host,
+            min_free_space: queue.min_free_space,
+            bandwidth_limit: queue.bandwidth_limit,
+            metered_policy: queue.metered_policy,
+            time_windows: queue.time_windows.clone(),
+            naming: queue.naming.clone(),
+            categories: queue.categories.clone(),
+            notifications: *self.notifications.lock().unwrap(),
+            history_retention: *self.history_retention.lock().unwrap(),
+        };
+        let downloads = (queue.entries.iter())
+            .filter(|entry| !matches!(entry.status, Status::Done | Status::Extracting))
+            .map(|entry| ExportedDownload {
+                url: entry.url.to_string(),
+                destination: entry.destination.clone(),
+                stopped: matches!(
+                    entry.status,
+                    Status::Paused | Status::Failed | Status::FailedVerification
+                ),
+                category: entry.category,
+                priority: entry.priority,
+                bandwidth_limit: entry.bandwidth_limit,
+                expected_hash: entry.expected_hash.clone(),
+                extraction: entry.extraction.clone(),
+                start_at: entry.start_at,
+                chunk_index: entry.chunk_index.as_ref().map(Url::to_string),
+                mirrors: entry.mirrors.iter().map(Url::to_string).collect(),
+            })
+            .collect();
+        QueueExport {
+            version: portable::FORMAT_VERSION,
+            exported_at: Utc::now(),
+            settings,
+            downloads,
+        }
+    }
+
+    /// Apply the settings of `export` and queue its downloads after those
+    /// already queued, their paths moved to another `base` folder if it's
+    /// given. As with [`DownloadManager::import`], invalid downloads and those
+    /// whose destination is taken are skipped, and they're saved all at once.
+    pub fn import_queue(
+        &self,
+        mut export: QueueExport,
+        base: Option<&BaseFolder>,
+    ) -> Result<ImportResult> {
+        if export.version > portable::FORMAT_VERSION {
+            err!("The queue was exported by a newer version of the app");
+        }
+        if let Some(base) = base {
+            export.remap(base);
+        }
+        let settings = export.settings;
+        self.set_max_concurrent(settings.max_concurrent, Some(settings.max_per_host));
+        self.set_min_free_space(settings.min_free_space);
+        self.set_bandwidth_limit(None, settings.bandwidth_limit)?;
+        self.set_metered_policy(settings.metered_policy);
+        self.set_time_windows(settings.time_windows);
+        self.set_naming(settings.naming);
+        self.set_categories(settings.categories);
+        self.set_notifications(settings.notifications);
+        self.set_history_retention(settings.history_retention)?;
+        let mut result = ImportResult::default();
+        let mut records = Vec::new();
+        let mut queue = self.queue.lock().unwrap();
+        for (download, row) in export.downloads.into_iter().zip(1..) {
+            match self.add_exported(&mut queue, download) {
+                Ok(record) => records.push(record),
+                Err(error) => result.errors.push(ImportError { row, error }),
+            }
+        }
+        log::info!("imported a queue of {} downloads", records.len());
+        logerr!(self.store.insert_all(&records));
+        for record in records {
+            events::emit_state(&self.app, record.id, record.status, None);
+            result.queued.push(record.id);
+        }
+        self.schedule(&mut queue);
+        Ok(result)
+    }
+
+    /// Add `download`, from an exported queue, to the end of `queue`.
+    fn add_exported(
+        &self,
+        queue: &mut Queue,
+        download: ExportedDownload,
+    ) -> std::result::Result<Record, String> {
+        let url = Url::parse(&download.url).map_err(|err| format!("Invalid url: {err}"))?;
+        let chunk_index = (download.chunk_index.as_deref())
+            .map(|index| Url::parse(index).map_err(|err| format!("Invalid chunk index url: {err}")))
+            .transpose()?;
+        let mirrors = (download.mirrors.iter())
+            .map(|mirror| Url::parse(mirror).map_err(|err| format!("Invalid mirror url: {err}")))
+            .collect::<std::result::Result<_, _>>()?;
+        // e.g. one from Windows without a base folder to move it to
+        if !download.destination.is_absolute() {
+            return Err(format!(
+                "{:?} isn't a path on this computer",
+                download.destination
+            ));
+        }
+        let options = DownloadOptions {
+            category: Some(download.category),
+            priority: download.priority,
+            expected_hash: download.expected_hash,
+            extraction: download.extraction,
+            start_at: download.start_at,
+            chunk_index,
+            mirrors,
+            named: true,
+            ..DownloadOptions::default()
+        };
+        let added = self.add(queue, url, download.destination, options, None);
+        let mut record = added.map_err(not_added)?;
+        // the one just added
+        let entry = queue.entries.last_mut().unwrap();
+        entry.bandwidth_limit = download.bandwidth_limit;
+        record.bandwidth_limit = download.bandwidth_limit;
+        if download.stopped {
+            entry.status = Status::Paused;
+            record.status = Status::Paused;
+        }
+        Ok(record)
+    }
+
+    /// Add a new download of `url` to `destination` to the end of `queue`,
+    /// named, sorted into its category and with its conflicts resolved as
+    /// `options` and the settings say, returning its record to save; or what
+    /// happened instead if its destination is taken and it isn't queued.
+    fn add(
+        &self,
+        queue: &mut Queue,
+        url: Url,
+        mut destination: PathBuf,
+        options: DownloadOptions,
+        probe: Option<&Probe>,
+    ) -> std::result::Result<Record, Started> {
+        let DownloadOptions {
+            category,
+            priority,
+            expected_hash,
+            mut extraction,
+            start_at,
+            on_duplicate,
+            on_conflict,
+            chunk_index,
+            mirrors,
+            size,
+            named,
+        } = options;
+        let category = category.unwrap_or_else(|| queue.categories.categorize(&url, &destination));
+        let settings = queue.categories.get(category);
+        if let (true, Some(dir)) = (destination.is_relative(), &settings.directory) {
+            destination = dir.join(destination);
+        }
+        if let (false, Some(template)) = (named, &queue.naming.template) {
+            let hash = expected_hash.as_ref().map(|hash| hash.hex.as_str());
+            destination = naming::apply(template, &destination, &url, hash);
+        }
+        let taken = |path: &Path| {
+            queue.entries.iter().any(|entry| entry.destination == path) || path.exists()
+        };
+        // another download's `.part` file can't be shared, whatever the policy
+        let writing = |path: &Path| {
+            (queue.entries.iter())
+                .any(|entry| entry.destination == path && entry.status != Status::Done)
+        };
+        let policy = match on_duplicate {
+            Some(DuplicateAction::DownloadAgain) => on_conflict.unwrap_or(ConflictPolicy::Rename),
+            _ => on_conflict.unwrap_or(queue.naming.conflicts),
+        };
+        match policy {
+            _ if !taken(&destination) => {}
+            ConflictPolicy::Overwrite if !writing(&destination) => {
+                log::info!("{:?} will be overwritten", destination);
+            }
+            ConflictPolicy::Rename | ConflictPolicy::Overwrite => {
+                destination = naming::unique_destination(&destination, taken);
+            }
+            ConflictPolicy::Skip => return Err(Started::Exists(destination)),
+            ConflictPolicy::Ask => return Err(Started::Conflict(destination)),
+        }
+        if extraction.is_none() && settings.extract {
+            extraction = extract::default_directory(&destination).map(|directory| Extraction {
+                directory,
+                symlinks: SymlinkPolicy::default(),
+            });
+        }
+        let id = queue.next_id;
+        queue.next_id += 1;
+        let final_url = probe.map(|probe| probe.url.clone());
+        let final_url = final_url.filter(|final_url| *final_url != url);
+        let etag = probe
+            .and_then(|probe| probe.etag.as_ref()?.to_str().ok())
+            .map(str::to_owned);
+        let total = probe.and_then(|probe| probe.content_length).or(size);
+        log::info!("queued download {} of {} to {:?}", id, url, destination);
+        let record = Record {
+            id,
+            url: url.to_string(),
+            final_url: final_url.as_ref().map(Url::to_string),
+            destination: destination.clone(),
+            status: Status::Queued,
+            category,
+            priority,
+            position: id,
+            bandwidth_limit: None,
+            expected_hash: expected_hash.clone(),
+            extraction: extraction.clone(),
+            start_at,
+            chunk_index: chunk_index.as_ref().map(Url::to_string),
+            mirrors: mirrors.iter().map(Url::to_string).collect(),
+            error: None,
+            pos: 0,
+            total,
+            etag: etag.clone(),
+            last_modified: None,
+            log: TransferLog::default(),
+        };
+        queue.entries.push(Entry {
+            id,
+            url,
+            final_url,
+            etag,
+            destination,
+            status: Status::Queued,
+            category,
+            priority,
+            bandwidth_limit: None,
+            expected_hash,
+            extraction,
+            start_at,
+            chunk_index,
+            mirrors,
+            error: None,
+            bytes: 0,
+            total,
+            active: Duration::ZERO,
+            received: 0,
+            log: Arc::default(),
+            speeds: SpeedHistory::default(),
+            task: None,
+        });
+        Ok(record)
+    }
+
+    /// The newest download in the history that a new download of `url`
+    /// duplicates, and how; only one that was done and whose file is still
+    /// there counts.
+    fn downloaded_before(
+        &self,
+        url: &Url,
+        probe: Option<&Probe>,
+    ) -> Option<(HistoryEntry, DuplicateMatch)> {
+        let final_url = probe.map(|probe| probe.url.as_str());
+        let entries = match self.store.downloaded(url.as_str(), final_url) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::error!("failed to look up {} in the download history: {}", url, err);
+                return None;
+            }
+        };
+        (entries.into_iter())
+            .filter(|entry| entry.destination.exists())
+            .find_map(|entry| {
+                let entry_url = Url::parse(&entry.url).ok()?;
+                let known = duplicates::Known {
+                    url: &entry_url,
+                    final_url: None,
+                    etag: None,
+                    size: entry.size,
+                };
+                let matched = duplicates::matches(known, url, probe)?;
+                Some((entry, matched))
+            })
+    }
+
+    /// Up to `limit` of the downloads matching `filter`, in the order they
+    /// were started, skipping the first `offset`.
+    pub fn list(
+        &self,
+        filter: Option<Filter>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> DownloadPage {
+        let queue = self.queue.lock().unwrap();
+        let matching = || {
+            queue.entries.iter().filter(|entry| match filter {
+                Some(filter) => filter.matches(entry.status),
+                None => true,
+            })
+        };
+        DownloadPage {
+            total: matching().count(),
+            downloads: matching()
+                .skip(offset)
+                .take(limit.unwrap_or(usize::MAX))
+                .map(Entry::info)
+                .collect(),
+        }
+    }
+
+    /// Run up to `max` downloads at once, and up to `max_per_host` of them
+    /// from the same host. Lowering the limits doesn't stop downloads that are
+    /// already running; fewer are started until they're within them.
+    pub fn set_max_concurrent(&self, max: usize, max_per_host: Option<usize>) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.max_concurrent = max.max(1);
+        queue.max_per_host = max_per_host.unwrap_or(queue.max_per_host).max(1);
+        log::info!(
+            "running up to {} downloads, {} per host",
+            queue.max_concurrent,
+            queue.max_per_host
+        );
+        self.schedule(&mut queue);
+    }
+
+    pub fn set_priority(&self, id: DownloadId, priority: Priority) -> Result<()> {
+        let mut queue = self.queue.lock().unwrap();
+        queue.get_mut(id)?.priority = priority;
+        logerr!(self.store.set_priority(id, priority));
+        self.schedule(&mut queue);
+        Ok(())
+    }
+
+    /// Move download `id` to `position` in the queue, or to the end if
+    /// there are fewer downloads. Among queued downloads of the same
+    /// priority, those further up start first.
+    pub fn reorder(&self, id: DownloadId, position: usize) -> Result<()> {
+        let mut queue = self.queue.lock().unwrap();
+        let index = queue.index(id)?;
+        let entry = queue.entries.remove(index);
+        let position = position.min(queue.entries.len());
+        queue.entries.insert(position, entry);
+        let ids: Vec<_> = queue.entries.iter().map(|entry| entry.id).collect();
+        logerr!(self.store.set_order(&ids));
+        self.schedule(&mut queue);
+        Ok(())
+    }
+
+    /// Cap download `id`, or every download together if it's `None`, at
+    /// `bytes_per_sec`, or lift the cap if that's `None`. Running downloads
+    /// slow down or speed up from their next chunk.
+    pub fn set_bandwidth_limit(
+        &self,
+        id: Option<DownloadId>,
+        bytes_per_sec: Option<u64>,
+    ) -> Result<()> {
+        let Some(id) = id else {
+            log::info!("limiting downloads to {:?} bytes/s", bytes_per_sec);
+            let mut queue = self.queue.lock().unwrap();
+            queue.bandwidth_limit = bytes_per_sec;
+            self.apply_rate_limit(&queue);
+            return Ok(());
+        };
+        let mut queue = self.queue.lock().unwrap();
+        let entry = queue.get_mut(id)?;
+        entry.bandwidth_limit = bytes_per_sec;
+        if let Some(task) = &entry.task {
+            task.rate_limit.set(bytes_per_sec);
+        }
+        logerr!(self.store.set_bandwidth_limit(id, bytes_per_sec));
+        Ok(())
+    }
+
+    /// Leave at least `bytes` free on the disks downloads are written to: a
+    /// download that wouldn't fit fails before it writes anything, and every
+    /// download is paused once a disk has less than that left.
+    pub fn set_min_free_space(&self, bytes: u64) {
+        self.queue.lock().unwrap().min_free_space = bytes;
+    }
+
+    /// Check the free space of the disks downloads are running on every
+    /// [`DISK_CHECK_INTERVAL`], for as long as the app runs.
+    fn watch_disk_space(&self) {
+        let manager = self.clone();
+        async_runtime::spawn(async move {
+            loop {
+                tokio::time::sleep(DISK_CHECK_INTERVAL).await;
+                manager.check_disk_space();
+            }
+        });
+    }
+
+    /// Pause every download if one of the disks downloads are running on has
+    /// less than `min_free_space` left, rather than have them fail with I/O
+    /// errors once it's full.
+    fn check_disk_space(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        let dirs: HashSet<_> = (queue.entries.iter())
+            .filter(|entry| entry.status == Status::Running)
+            .filter_map(|entry| entry.destination.parent())
+            .collect();
+        // a directory that doesn't exist yet has nothing written to it
+        let low = dirs.into_iter().find_map(|dir| {
+            let available = fs4::available_space(dir).ok()?;
+            (available < queue.min_free_space).then(|| (dir.to_owned(), available))
+        });
+        let Some((dir, available)) = low else {
+            return;
+        };
+        log::warn!(
+            "pausing downloads, {:?} has only {} bytes left",
+            dir,
+            available
+        );
+        events::emit_low_space(&self.app, &dir, available, queue.min_free_space);
+        for entry in &mut queue.entries {
+            if matches!(entry.status, Status::Queued | Status::Running) {
+                entry.error = Some(format!("Paused because {dir:?} is almost full"));
+                self.set_status(entry, Status::Paused);
+                if let Some(task) = &entry.task {
+                    task.cancel.cancel();
+                }
+            }
+        }
+    }
+
+    pub fn network_state(&self) -> NetworkState {
+        self.queue.lock().unwrap().network
+    }
+
+    /// Check whether the internet can be reached by fetching `url`, which
+    /// must answer `204 No Content`, or not at all, taking it to be online,
+    /// as it is until this is called.
+    pub fn set_connectivity_check(&self, url: Option<Url>) {
+        let disabled = url.is_none();
+        *self.connectivity_check.lock().unwrap() = url;
+        if disabled {
+            self.set_network_state(NetworkState::Online);
+        }
+    }
+
+    fn connectivity_check(&self) -> Option<Url> {
+        self.connectivity_check.lock().unwrap().clone()
+    }
+
+    /// Hold the queue while the network is down or behind a captive portal,
+    /// sending running downloads back to it so they don't use up their
+    /// retries, and start them again once it's back.
+    fn set_network_state(&self, state: NetworkState) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.network == state {
+            return;
+        }
+        log::info!("network is {:?}", state);
+        queue.network = state;
+        events::emit_network(&self.app, state);
+        self.schedule(&mut queue);
+    }
+
+    /// Choose which notifications are shown.
+    pub fn set_notifications(&self, settings: NotificationSettings) {
+        *self.notifications.lock().unwrap() = settings;
+    }
+
+    /// The latest events of download `id`'s attempts, oldest first.
+    pub fn transfer_log(&self, id: DownloadId) -> Result<Vec<LogEntry>> {
+        let queue = self.queue.lock().unwrap();
+        let entry = &queue.entries[queue.index(id)?];
+        let log = entry.log.lock().unwrap().entries();
+        Ok(log)
+    }
+
+    /// Download `id`'s speed, sampled every second while it ran during the
+    /// last `window`, or as far back as it's kept.
+    pub fn speed_history(
+        &self,
+        id: DownloadId,
+        window: Option<Duration>,
+    ) -> Result<Vec<SpeedSample>> {
+        let queue = self.queue.lock().unwrap();
+        let entry = &queue.entries[queue.index(id)?];
+        Ok(entry.speeds.since(window))
+    }
+
+    /// Search the history of finished and failed downloads for those whose
+    /// URL or destination contains `query`, if it's given, returning up to
+    /// `limit` of them, newest first, after skipping the first `offset`.
+    pub fn history(
+        &self,
+        query: Option<&str>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<HistoryPage> {
+        (self.store.history(query, offset, limit))
+            .with_context(|| "Failed to read the download history")
+    }
+
+    pub fn clear_history(&self) -> Result<()> {
+        (self.store.clear_history()).with_context(|| "Failed to clear the download history")
+    }
+
+    /// Keep as much of the history as `retention` says, deleting the rest.
+    pub fn set_history_retention(&self, retention: HistoryRetention) -> Result<()> {
+        *self.history_retention.lock().unwrap() = retention;
+        (self.store.prune_history(retention))
+            .with_context(|| "Failed to prune the download history")
+    }
+
+    /// Sort downloads started from now on into categories as `categories`
+    /// say, and cap each category's bandwidth as it says from now on.
+    pub fn set_categories(&self, categories: Categories) {
+        for (category, rate_limit) in self.category_rate_limits.iter() {
+            rate_limit.set(categories.get(*category).bandwidth_limit);
+        }
+        self.queue.lock().unwrap().categories = categories;
+    }
+
+    /// Name downloads started from now on as `settings` say.
+    pub fn set_naming(&self, settings: NamingSettings) {
+        self.queue.lock().unwrap().naming = settings;
+    }
+
+    /// Keep the chunks of finished downloads in the app's cache, for those
+    /// given a [`ChunkIndex`] to reuse. One of those that's interrupted starts
+    /// over, but keeps the chunks it fetched. Disabling it deletes them.
+    pub async fn set_chunk_store(&self, enabled: bool) -> Result<()> {
+        if enabled {
+            let dir = (self.app.path_resolver().app_cache_dir())
+                .with_context(|| "Failed to resolve the app cache dir")?;
+            *self.chunk_store.lock().unwrap() = Some(ChunkStore::new(dir.join("chunks")));
+            return Ok(());
+        }
+        let store = self.chunk_store.lock().unwrap().take();
+        if let Some(store) = store {
+            store
+                .clear()
+                .await
+                .with_context(|| "Failed to delete the stored chunks")?;
+        }
+        Ok(())
+    }
+
+    /// Show download `id` in the file manager, or the directory it was
+    /// extracted to.
+    pub fn reveal(&self, id: DownloadId) -> Result<()> {
+        let path = {
+            let mut queue = self.queue.lock().unwrap();
+            let entry = queue.get_mut(id)?;
+            match &entry.extraction {
+                Some(extraction) if entry.status == Status::Done => extraction.directory.clone(),
+                _ => entry.destination.clone(),
+            }
+        };
+        notify::reveal(&path)
+    }
+
+    /// Show how far along the active downloads are in the tray every
+    /// [`TRAY_INTERVAL`], for as long as the app runs.
+    fn watch_progress(&self) {
+        let manager = self.clone();
+        async_runtime::spawn(async move {
+            let mut last = None;
+            loop {
+                let (active, percent) = manager.progress();
+                // whole percents are all that's shown
+                let shown = (active, percent.map(|percent| percent as u64));
+                if last != Some(shown) {
+                    tray::update(&manager.app, active, percent);
+                    last = Some(shown);
+                }
+                tokio::time::sleep(TRAY_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Sample the speed of every running download every
+    /// [`speed::SAMPLE_INTERVAL`].
+    fn watch_speeds(&self) {
+        let manager = self.clone();
+        async_runtime::spawn(async move {
+            loop {
+                tokio::time::sleep(speed::SAMPLE_INTERVAL).await;
+                let mut queue = manager.queue.lock().unwrap();
+                for entry in &mut queue.entries {
+                    if let (Status::Running, Some(task)) = (entry.status, &entry.task) {
+                        entry.speeds.push(task.progress.speed());
+                    }
+                }
+            }
+        });
+    }
+
+    /// How many downloads are queued, running or extracting, and how far
+    /// along those of known size are together, in percent.
+    fn progress(&self) -> (usize, Option<f64>) {
+        let queue = self.queue.lock().unwrap();
+        let (mut active, mut bytes, mut total) = (0, 0, 0);
+        for entry in &queue.entries {
+            if !matches!(
+                entry.status,
+                Status::Queued | Status::Running | Status::Extracting
+            ) {
+                continue;
+            }
+            active += 1;
+            let info = entry.info();
+            if let Some(size) = info.total {
+                bytes += info.bytes.min(size);
+                total += size;
+            }
+        }
+        let percent = (total > 0).then(|| bytes as f64 * 100.0 / total as f64);
+        (active, percent)
+    }
+
+    /// Pause every queued or running download.
+    pub fn pause_all(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        for entry in &mut queue.entries {
+            if matches!(entry.status, Status::Queued | Status::Running) {
+                self.set_status(entry, Status::Paused);
+                if let Some(task) = &entry.task {
+                    task.cancel.cancel();
+                }
+            }
+        }
+    }
+
+    /// Queue every paused download again.
+    pub fn resume_all(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        for entry in &mut queue.entries {
+            if entry.status == Status::Paused {
+                self.requeue(entry);
+            }
+        }
+        self.schedule(&mut queue);
+    }
+
+    /// Choose what downloads do while the connection is metered.
+    pub fn set_metered_policy(&self, policy: MeteredPolicy) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.metered_policy = policy;
+        self.apply_metered(&mut queue);
+    }
+
+    pub fn is_metered(&self) -> bool {
+        self.queue.lock().unwrap().metered
+    }
+
+    /// Record whether the OS says the connection is metered.
+    fn set_os_metered(&self, metered: Option<bool>) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.os_metered = metered;
+        self.apply_metered(&mut queue);
+    }
+
+    /// Slow down downloads and send those too big for it back to the queue if
+    /// the connection is metered, or let them go at full speed if it's not.
+    fn apply_metered(&self, queue: &mut Queue) {
+        let metered = (queue.metered_policy.metered)
+            .or(queue.os_metered)
+            .unwrap_or(false);
+        if metered != queue.metered {
+            log::info!("connection is metered: {}", metered);
+            queue.metered = metered;
+            events::emit_metered(&self.app, metered);
+        }
+        self.apply_rate_limit(queue);
+        self.schedule(queue);
+    }
+
+    /// Limit every download together to what the user set, or less while
+    /// the connection is metered.
+    fn apply_rate_limit(&self, queue: &Queue) {
+        let metered_limit = (queue.metered)
+            .then_some(queue.metered_policy.bandwidth_limit)
+            .flatten();
+        let limit = match (queue.bandwidth_limit, metered_limit) {
+            (Some(limit), Some(metered_limit)) => Some(limit.min(metered_limit)),
+            (limit, metered_limit) => limit.or(metered_limit),
+        };
+        self.rate_limit.set(limit);
+    }
+
+    /// Only run downloads within `windows`, or at any time if there are none.
+    pub fn set_time_windows(&self, windows: Vec<TimeWindow>) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.time_windows = windows;
+        self.check_time(&mut queue);
+    }
+
+    /// Check the time every [`TIME_CHECK_INTERVAL`], for as long as the app
+    /// runs.
+    fn watch_clock(&self) {
+        let manager = self.clone();
+        async_runtime::spawn(async move {
+            loop {
+                tokio::time::sleep(TIME_CHECK_INTERVAL).await;
+                manager.check_time(&mut manager.queue.lock().unwrap());
+            }
+        });
+    }
+
+    /// Send running downloads back to the queue at the end of a time window,
+    /// and start queued ones when the next begins or their start time has
+    /// come.
+    fn check_time(&self, queue: &mut Queue) {
+        let open = time_windows::is_open(&queue.time_windows, Local::now().time());
+        if open != queue.in_time_window {
+            log::info!(
+                "{} a download time window",
+                if open { "entering" } else { "leaving" }
+            );
+            queue.in_time_window = open;
+        }
+        self.schedule(queue);
+    }
+
+    /// Stop download `id`, keeping what it has downloaded so far.
+    pub fn pause(&self, id: DownloadId) -> Result<()> {
+        let mut queue = self.queue.lock().unwrap();
+        let entry = queue.get_mut(id)?;
+        match entry.status {
+            Status::Queued | Status::Running => {}
+            Status::Paused => return Ok(()),
+            status => err!("Download {id} can't be paused, it's {status:?}"),
+        }
+        self.set_status(entry, Status::Paused);
+        if let Some(task) = &entry.task {
+            task.cancel.cancel();
+        }
+        self.schedule(&mut queue);
+        Ok(())
+    }
+
+    /// Queue download `id` again after it was paused or failed. One that
+    /// failed its verification is downloaded again from scratch.
+    pub fn resume(&self, id: DownloadId) -> Result<()> {
+        let mut queue = self.queue.lock().unwrap();
+        let entry = queue.get_mut(id)?;
+        match entry.status {
+            Status::Paused | Status::Failed | Status::FailedVerification => {}
+            Status::Queued | Status::Running | Status::Extracting => return Ok(()),
+            Status::Done => err!("Download {id} is already done"),
+        }
+        self.requeue(entry);
+        self.schedule(&mut queue);
+        Ok(())
+    }
+
+    /// Queue every download that failed again.
+    pub fn retry_failed(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        for entry in &mut queue.entries {
+            if matches!(entry.status, Status::Failed | Status::FailedVerification) {
+                self.requeue(entry);
+            }
+        }
+        self.schedule(&mut queue);
+    }
+
+    /// Queue `entry` again, from scratch if it failed its verification.
+    fn requeue(&self, entry: &mut Entry) {
+        if entry.status == Status::FailedVerification {
+            let quarantine = quarantine_path(&entry.destination);
+            match std::fs::remove_file(&quarantine) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    log::warn!("failed to delete {:?}: {}", quarantine, err)
+                }
+                _ => {}
+            }
+            entry.bytes = 0;
+        }
+        entry.error = None;
+        self.set_status(entry, Status::Queued);
+    }
+
+    /// Stop download `id`, delete what it has downloaded so far and forget it.
+    pub async fn cancel(&self, id: DownloadId) -> Result<()> {
+        self.cancel_selected(&[id]).await
+    }
+
+    /// Cancel every download in `ids`, or none of them if one doesn't exist
+    /// or is already done.
+    pub async fn cancel_selected(&self, ids: &[DownloadId]) -> Result<()> {
+        let entries = {
+            let mut queue = self.queue.lock().unwrap();
+            for &id in ids {
+                let index = queue.index(id)?;
+                if queue.entries[index].status == Status::Done {
+                    err!("Download {id} is already done");
+                }
+            }
+            let (cancelled, kept) = std::mem::take(&mut queue.entries)
+                .into_iter()
+                .partition(|entry| ids.contains(&entry.id));
+            queue.entries = kept;
+            for &id in ids {
+                logerr!(self.store.delete(id));
+            }
+            cancelled
+        };
+        let results =
+            futures::future::join_all(entries.into_iter().map(|entry| self.discard(entry))).await;
+        results.into_iter().collect()
+    }
+
+    /// Forget download `id`, cancelling it if it hasn't finished. With
+    /// `delete_file`, a finished download's file is deleted too.
+    pub async fn remove(&self, id: DownloadId, delete_file: bool) -> Result<()> {
+        let entry = self.take(id)?;
+        if entry.status != Status::Done {
+            return self.discard(entry).await;
+        }
+        if delete_file {
+            tokio::fs::remove_file(&entry.destination)
+                .await
+                .with_context(|| format!("Failed to delete {:?}", entry.destination))?;
+        }
+        Ok(())
+    }
+
+    /// Take download `id` out of the queue.
+    fn take(&self, id: DownloadId) -> Result<Entry> {
+        let mut queue = self.queue.lock().unwrap();
+        let index = queue.index(id)?;
+        logerr!(self.store.delete(id));
+        Ok(queue.entries.remove(index))
+    }
+
+    /// Move `entry` to `status`, save it and tell the webview, and the user
+    /// and the history if it's done or failed.
+    fn set_status(&self, entry: &mut Entry, status: Status) {
+        entry.status = status;
+        let error = entry.error.as_deref();
+        logerr!(self.store.set_status(entry.id, status, error));
+        events::emit_state(&self.app, entry.id, status, error);
+        let settings = *self.notifications.lock().unwrap();
+        notify::notify(&self.app, settings, &entry.destination, status, error);
+        if matches!(
+            status,
+            Status::Done | Status::Failed | Status::FailedVerification
+        ) {
+            let retention = *self.history_retention.lock().unwrap();
+            logerr!(self.store.add_history(&entry.history()));
+            logerr!(self.store.prune_history(retention));
+        }
+    }
+
+    /// Stop `entry`, taken out of the queue, and delete its partial or
+    /// quarantined file.
+    async fn discard(&self, mut entry: Entry) -> Result<()> {
+        if let Some(task) = entry.task.take() {
+            task.cancel.cancel();
+            logerr!(task.handle.await);
+        }
+        log::info!("cancelled download {} of {}", entry.id, entry.url);
+        let quarantine = quarantine_path(&entry.destination);
+        match tokio::fs::remove_file(&quarantine).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err).with_context(|| format!("Failed to delete {quarantine:?}"));
+            }
+            _ => {}
+        }
+        reqwest_resume::discard_partial(&entry.destination)
+            .await
+            .with_context(|| format!("Failed to delete the partial {:?}", entry.destination))
+    }
+
+    /// Start and hold downloads as [`Queue::plan`] decides.
+    fn schedule(&self, queue: &mut Queue) {
+        let plan = queue.plan(Utc::now());
+        for index in plan.hold {
+            self.hold(&mut queue.entries[index]);
+        }
+        let min_free_space = queue.min_free_space;
+        for index in plan.start {
+            self.launch(&mut queue.entries[index], min_free_space);
+        }
+    }
+
+    /// Start the task that downloads `entry`.
+    fn launch(&self, entry: &mut Entry, min_free_space: u64) {
+        entry.error = None;
+        entry.log(LogEvent::Started { pos: entry.bytes });
+        self.set_status(entry, Status::Running);
+        let cancel = CancellationToken::new();
+        let progress = ProgressHandle::new();
+        let rate_limit = self.category_rate_limits[&entry.category].child(entry.bandwidth_limit);
+        let mut request = self
+            .client
+            .get(entry.url.clone())
+            .cancel_token(cancel.clone())
+            .progress_handle(progress.clone())
+            .rate_limit(rate_limit.clone())
+            .min_free_space(min_free_space)
+            .mirrors(entry.mirrors.clone());
+        if let Some(hash) = &entry.expected_hash {
+            request = request.checksum(hash.checksum());
+        }
+        let handle = async_runtime::spawn(self.clone().run(entry.job(), request, progress.clone()));
+        entry.task = Some(Task {
+            cancel,
+            progress,
+            rate_limit,
+            handle,
+            started: Instant::now(),
+        });
+    }
+
+    /// Send running `entry` back to the queue, to resume once it may run
+    /// again.
+    fn hold(&self, entry: &mut Entry) {
+        self.set_status(entry, Status::Queued);
+        if let Some(task) = &entry.task {
+            task.cancel.cancel();
+        }
+    }
+
+    async fn run(self, job: Job, request: RequestBuilder, progress: ProgressHandle) {
+        let (id, destination) = (job.id, &job.destination);
+        let emitter = Arc::new(ProgressEmitter::new(self.app.clone(), id, progress));
+        let outcome = self.download(&job, request, emitter.clone()).await;
+        emitter.emit();
+        // where it can resume from, if it can
+        let sidecar = match outcome {
+            Ok(_) => None,
+            Err(_) => Sidecar::read(destination).await.ok(),
+        };
+        let mut queue = self.queue.lock().unwrap();
+        // gone if it was cancelled
+        if let Ok(entry) = queue.get_mut(id) {
+            if let Some(task) = entry.task.take() {
+                entry.active += task.started.elapsed();
+                entry.received += task.progress.bytes_transferred();
+            }
+            if let Some(sidecar) = &sidecar {
+                entry.bytes = sidecar.pos;
+                entry.total = sidecar.total;
+                entry.etag = sidecar.etag.clone();
+                logerr!(self.store.set_resume_state(id, sidecar));
+            }
+            match outcome {
+                Ok(Outcome::Done(download)) => {
+                    log::info!("download {} to {:?} is done", id, destination);
+                    entry.bytes = download.resumed_from + download.bytes_written;
+                    entry.total = Some(entry.bytes);
+                    logerr!(self.store.set_position(id, entry.bytes, entry.total));
+                    entry.log(LogEvent::Done { bytes: entry.bytes });
+                    self.extract(entry);
+                }
+                Ok(Outcome::Unverified(error)) => {
+                    log::error!("download {} failed verification: {}", id, error);
+                    entry.log(LogEvent::Failed {
+                        error: error.clone(),
+                    });
+                    entry.error = Some(error);
+                    self.set_status(entry, Status::FailedVerification);
+                }
+                // failed on its own rather than because it was paused
+                Err(err) if entry.status == Status::Running => {
+                    log::error!("download {} failed: {}", id, err);
+                    entry.error = Some(err.to_string());
+                    entry.log(LogEvent::Failed {
+                        error: err.to_string(),
+                    });
+                    self.set_status(entry, Status::Failed);
+                }
+                Err(_) => entry.log(LogEvent::Stopped { pos: entry.bytes }),
+            }
+            logerr!(self.store.set_log(id, &entry.log.lock().unwrap()));
+        }
+        self.schedule(&mut queue);
+    }
+
+    /// Extract `entry`, which has just been downloaded, if it's to be
+    /// extracted; it's done otherwise.
+    fn extract(&self, entry: &mut Entry) {
+        let Some(extraction) = entry.extraction.clone() else {
+            self.set_status(entry, Status::Done);
+            return;
+        };
+        self.set_status(entry, Status::Extracting);
+        let cancel = CancellationToken::new();
+        let handle = async_runtime::spawn(self.clone().run_extraction(
+            entry.id,
+            entry.destination.clone(),
+            extraction,
+            cancel.clone(),
+        ));
+        entry.task = Some(Task {
+            cancel,
+            progress: ProgressHandle::new(),
+            rate_limit: RateLimit::new(None),
+            handle,
+            started: Instant::now(),
+        });
+    }
+
+    async fn run_extraction(
+        self,
+        id: DownloadId,
+        archive: PathBuf,
+        extraction: Extraction,
+        cancel: CancellationToken,
+    ) {
+        log::info!("extracting {:?} to {:?}", archive, extraction.directory);
+        let app = self.app.clone();
+        let result = async_runtime::spawn_blocking(move || {
+            let mut last: Option<Instant> = None;
+            extract::extract(&archive, &extraction, &cancel, &mut |bytes, total| {
+                let now = Instant::now();
+                if bytes < total && last.is_some_and(|last| now - last < events::PROGRESS_INTERVAL)
+                {
+                    return;
+                }
+                last = Some(now);
+                events::emit_extract_progress(&app, id, bytes, total);
+            })
+        })
+        .await
+        .with_context(|| "Extraction failed")
+        .and_then(|result| result);
+        let mut queue = self.queue.lock().unwrap();
+        // gone if it was cancelled
+        if let Ok(entry) = queue.get_mut(id) {
+            entry.task = None;
+            match result {
+                Ok(()) => {
+                    log::info!("extracted download {}", id);
+                    self.set_status(entry, Status::Done);
+                }
+                Err(err) => {
+                    log::error!("extracting download {} failed: {}", id, err);
+                    entry.error = Some(err.to_string());
+                    entry.log(LogEvent::Failed {
+                        error: err.to_string(),
+                    });
+                    logerr!(self.store.set_log(id, &entry.log.lock().unwrap()));
+                    self.set_status(entry, Status::Failed);
+                }
+            }
+        }
+        self.schedule(&mut queue);
+    }
+
+    async fn download(
+        &self,
+        job: &Job,
+        request: RequestBuilder,
+        emitter: Arc<ProgressEmitter>,
+    ) -> Result<Outcome> {
+        let destination = &*job.destination;
+        if let Some(dir) = destination.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("Failed to create {dir:?}"))?;
+        }
+        let log = job.log.clone();
+        let request = request.on_event(move |event| match event {
+            Event::ChunkReceived { .. } => emitter.chunk_received(),
+            event => log.lock().unwrap().record(event),
+        });
+        let chunk_store = self.chunk_store.lock().unwrap().clone();
+        let result = match (&chunk_store, job.chunk_index.clone()) {
+            (Some(store), Some(index)) => {
+                let fetch = async {
+                    let index: ChunkIndex = self.client.get(index).send().await?.json().await?;
+                    store.download(request, &index, destination).await
+                };
+                fetch.await
+            }
+            _ => {
+                let result = request.download_to_file(destination).await;
+                // for later downloads of files with chunks in common
+                if let (Some(store), Ok(_)) = (&chunk_store, &result) {
+                    logerr!(store.insert_file(destination).await);
+                }
+                result
+            }
+        };
+        let quarantine = quarantine_path(destination);
+        match &result {
+            Err(err @ reqwest_resume::Error::ChecksumMismatch { .. }) => {
+                logerr!(reqwest_resume::quarantine_partial(destination, &quarantine).await);
+                return Ok(Outcome::Unverified(err.to_string()));
+            }
+            Err(reqwest_resume::Error::InsufficientSpace {
+                needed, available, ..
+            }) => events::emit_low_space(&self.app, destination, *available, *needed),
+            _ => {}
+        }
+        let download = result.with_context(|| format!("Failed to download {}", job.url))?;
+        let Some(hash) = (job.expected_hash.as_ref()).filter(|_| !download.summary.verified) else {
+            return Ok(Outcome::Done(download));
+        };
+        // couldn't be hashed while it streamed
+        log::info!("verifying {:?}", destination);
+        let checksum = hash.checksum();
+        let actual = reqwest_resume::hash_file(destination, &checksum)
+            .await
+            .with_context(|| format!("Failed to verify {destination:?}"))?;
+        if actual.eq_ignore_ascii_case(checksum.hex()) {
+            return Ok(Outcome::Done(download));
+        }
+        logerr!(tokio::fs::rename(destination, &quarantine).await);
+        Ok(Outcome::Unverified(format!(
+            "{} of {:?} is {}, expected {}",
+            checksum.algorithm(),
+            destination,
+            actual,
+            checksum.hex()
+        )))
+    }
+}
+
+/// Why [`DownloadManager::add`] didn't queue a download, for the errors of an
+/// import.
+fn not_added(started: Started) -> String {
+    match started {
+        Started::Exists(path) | Started::Conflict(path) => format!("{path:?} already exists"),
+        started => format!("{started:?}"),
+    }
+}
+
+/// Where a download that didn't match its [`ExpectedHash`] is moved, so it's
+/// neither mistaken for a good one nor resumed.
+fn quarantine_path(destination: &Path) -> PathBuf {
+    let mut path = destination.as_os_str().to_owned();
+    path.push(".unverified");
+    PathBuf::from(path)
+}
+
+impl ExpectedHash {
+    fn checksum(&self) -> Checksum {
+        match self.algorithm {
+            HashAlgorithm::Sha256 => Checksum::Sha256(self.hex.clone()),
+            HashAlgorithm::Blake3 => Checksum::Blake3(self.hex.clone()),
+        }
+    }
+
+    /// Whether `hex` is a digest of the length the algorithm produces.
+    pub fn is_valid(&self) -> bool {
+        let hex = self.hex.trim();
+        hex.len() == 64 && hex.bytes().all(|byte| byte.is_ascii_hexdigit())
+    }
+}
+
+impl Entry {
+    fn host(&self) -> String {
+        self.url.host_str().unwrap_or_default().to_owned()
+    }
+
+    fn log(&self, event: LogEvent) {
+        self.log.lock().unwrap().push(event);
+    }
+
+    /// How big it is, once that's known.
+    fn size(&self) -> Option<u64> {
+        let task = self.task.as_ref();
+        (task.and_then(|task| task.progress.total())).or(self.total)
+    }
+
+    /// What new downloads are compared with to tell whether they're
+    /// duplicates of it.
+    fn known(&self) -> duplicates::Known<'_> {
+        duplicates::Known {
+            url: &self.url,
+            final_url: self.final_url.as_ref(),
+            etag: self.etag.as_deref(),
+            size: self.size(),
+        }
+    }
+
+    /// It as the history remembers it, having just finished or failed.
+    fn history(&self) -> HistoryEntry {
+        let duration = self.active.as_secs_f64();
+        HistoryEntry {
+            id: 0,
+            download_id: self.id,
+            url: self.url.to_string(),
+            destination: self.destination.clone(),
+            status: self.status,
+            size: self.size(),
+            duration,
+            average_speed: match duration > 0.0 {
+                true => self.received as f64 / duration,
+                false => 0.0,
+            },
+            verified: match self.status {
+                Status::FailedVerification => Some(false),
+                Status::Done if self.expected_hash.is_some() => Some(true),
+                _ => None,
+            },
+            error: self.error.clone(),
+            finished_at: Utc::now(),
+        }
+    }
+
+    fn info(&self) -> DownloadInfo {
+        let mut info = DownloadInfo {
+            id: self.id,
+            url: self.url.to_string(),
+            destination: self.destination.clone(),
+            status: self.status,
+            category: self.category,
+            priority: self.priority,
+            bandwidth_limit: self.bandwidth_limit,
+            expected_hash: self.expected_hash.clone(),
+            extraction: self.extraction.clone(),
+            start_at: self.start_at,
+            chunk_index: self.chunk_index.as_ref().map(Url::to_string),
+            bytes: self.bytes,
+            total: self.total,
+            speed: 0.0,
+            eta: None,
+            error: self.error.clone(),
+        };
+        if let Some(Task { progress, .. }) = &self.task {
+            // nothing's been received yet right after it starts
+            if progress.position() > 0 {
+                info.bytes = progress.position();
+            }
+            info.total = self.size();
+            info.speed = progress.speed();
+            info.eta = progress.eta().map(|eta| eta.as_secs_f64());
+        }
+        info
+    }
+
+    fn job(&self) -> Job {
+        Job {
+            id: self.id,
+            url: self.url.clone(),
+            destination: self.destination.clone(),
+            expected_hash: self.expected_hash.clone(),
+            chunk_index: self.chunk_index.clone(),
+            log: self.log.clone(),
+        }
+    }
+}
+
+impl Filter {
+    fn matches(self, status: Status) -> bool {
+        match self {
+            Filter::Active => matches!(
+                status,
+                Status::Queued | Status::Running | Status::Extracting | Status::Paused
+            ),
+            Filter::Completed => status == Status::Done,
+            Filter::Failed => matches!(status, Status::Failed | Status::FailedVerification),
+        }
+    }
+}
+
+/// What [`Queue::plan`] decided, by index into the queue's entries.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Plan {
+    start: Vec<usize>,
+    // running downloads to send back to the queue
+    hold: Vec<usize>,
+}
+
+impl Queue {
+    /// Which queued downloads to start at `now`, and which running ones to
+    /// send back to the queue.
+    ///
+    /// Nothing runs while offline or outside the time windows, nor, while
+    /// metered, what's bigger than the metered policy allows. Queued
+    /// downloads start, highest priority first, while fewer than
+    /// `max_concurrent` are running, skipping those whose host already has
+    /// `max_per_host`. A `High` priority download that doesn't fit preempts
+    /// the last running download of the lowest priority.
+    fn plan(&self, now: DateTime<Utc>) -> Plan {
+        let mut plan = Plan::default();
+        let entries = self.entries.iter().enumerate();
+        let running_now = entries.filter(|(_, entry)| entry.status == Status::Running);
+        if self.network != NetworkState::Online || !self.in_time_window {
+            plan.hold = running_now.map(|(index, _)| index).collect();
+            return plan;
+        }
+        let max_size = self.max_size();
+        let too_big = |entry: &Entry| matches!((entry.size(), max_size), (Some(size), Some(max)) if size > max);
+        for (index, entry) in running_now.filter(|(_, entry)| too_big(entry)) {
+            log::info!("download {} waits for an unmetered connection", entry.id);
+            plan.hold.push(index);
+        }
+
+        let mut running = 0;
+        // slots of held downloads, free once their tasks have returned
+        let mut freeing = 0;
+        let mut per_host = HashMap::<_, usize>::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            if entry.task.is_none() || entry.status == Status::Extracting {
+                continue;
+            }
+            running += 1;
+            if entry.status != Status::Running || plan.hold.contains(&index) {
+                freeing += 1;
+            }
+            *per_host.entry(entry.host()).or_default() += 1;
+        }
+        let mut order: Vec<_> = (0..self.entries.len()).collect();
+        // stable, so the queue's order is kept within each priority
+        order.sort_by_key(|&index| Reverse(self.entries[index].priority));
+        for index in order {
+            let entry = &self.entries[index];
+            if entry.status != Status::Queued || entry.task.is_some() {
+                continue;
+            }
+            if entry.start_at.is_some_and(|start_at| start_at > now) {
+                continue;
+            }
+            // waiting for an unmetered connection
+            if too_big(entry) {
+                continue;
+            }
+            let host = entry.host();
+            if per_host.get(&host).copied().unwrap_or(0) >= self.max_per_host {
+                continue;
+            }
+            if running >= self.max_concurrent {
+                // wait for a held download's slot, or preempt one
+                if freeing > 0 {
+                    freeing -= 1;
+                } else if entry.priority == Priority::High {
+                    plan.hold.extend(self.victim(&plan.hold));
+                }
+                continue;
+            }
+            *per_host.entry(host).or_default() += 1;
+            plan.start.push(index);
+            running += 1;
+        }
+        plan
+    }
+
+    /// The last running download of the lowest priority below `High` that
+    /// isn't `held` already, to make room for a `High` priority one.
+    fn victim(&self, held: &[usize]) -> Option<usize> {
+        let running = (self.entries.iter().enumerate()).filter(|(index, entry)| {
+            entry.status == Status::Running
+                && entry.priority < Priority::High
+                && !held.contains(index)
+        });
+        let (index, entry) = running.rev().min_by_key(|(_, entry)| entry.priority)?;
+        log::info!("pausing download {} for one of higher priority", entry.id);
+        Some(index)
+    }
+
+    /// Size of the biggest download that may run, if there's a limit.
+    fn max_size(&self) -> Option<u64> {
+        self.metered
+            .then_some(self.metered_policy.max_size)
+            .flatten()
+    }
+
+    fn index(&self, id: DownloadId) -> Result<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.id == id)
+            .with_context(|| format!("No download with id {id}"))
+    }
+
+    fn get_mut(&mut self, id: DownloadId) -> Result<&mut Entry> {
+        let index = self.index(id)?;
+        Ok(&mut self.entries[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use chrono::Utc;
+    use reqwest::Url;
+    use tauri::async_runtime;
+    use tokio_util::sync::CancellationToken;
+
+    use super::{
+        Categories, Category, Entry, MeteredPolicy, NamingSettings, NetworkState, Plan, Priority,
+        Queue, SpeedHistory, Status, Task,
+    };
+    use crate::reqwest_resume::{ProgressHandle, RateLimit};
+
+    /// A download from `host`, running if it's `Running` or held but not
+    /// returned yet if it's `Queued` with `task`.
+    fn entry(host: &str, priority: Priority, status: Status, task: bool) -> Entry {
+        Entry {
+            id: 0,
+            url: Url::parse(&format!("https://{host}/model.bin")).unwrap(),
+            final_url: None,
+            etag: None,
+            destination: "model.bin".into(),
+            status,
+            category: Category::default(),
+            priority,
+            bandwidth_limit: None,
+            expected_hash: None,
+            extraction: None,
+            start_at: None,
+            chunk_index: None,
+            mirrors: Vec::new(),
+            error: None,
+            bytes: 0,
+            total: None,
+            active: Duration::ZERO,
+            received: 0,
+            log: Arc::default(),
+            speeds: SpeedHistory::default(),
+            task: task.then(|| Task {
+                cancel: CancellationToken::new(),
+                progress: ProgressHandle::new(),
+                rate_limit: RateLimit::new(None),
+                handle: async_runtime::spawn(async {}),
+                started: Instant::now(),
+            }),
+        }
+    }
+
+    fn running(host: &str, priority: Priority) -> Entry {
+        entry(host, priority, Status::Running, true)
+    }
+
+    fn queued(host: &str, priority: Priority) -> Entry {
+        entry(host, priority, Status::Queued, false)
+    }
+
+    fn queue(entries: Vec<Entry>) -> Queue {
+        Queue {
+            entries,
+            next_id: 0,
+            max_concurrent: 2,
+            max_per_host: 2,
+            min_free_space: 0,
+            network: NetworkState::Online,
+            bandwidth_limit: None,
+            metered_policy: MeteredPolicy::default(),
+            os_metered: None,
+            metered: false,
+            time_windows: Vec::new(),
+            in_time_window: true,
+            naming: NamingSettings::default(),
+            categories: Categories::default(),
+        }
+    }
+
+    fn plan(queue: &Queue) -> (Vec<usize>, Vec<usize>) {
+        let Plan { start, hold } = queue.plan(Utc::now());
+        (start, hold)
+    }
+
+    #[test]
+    fn starts_by_priority() {
+        let mut later = queued("d.com", Priority::High);
+        later.start_at = Some(Utc::now() + chrono::Duration::hours(1));
+        let queue = queue(vec![
+            queued("a.com", Priority::Low),
+            queued("b.com", Priority::Normal),
+            later,
+            queued("c.com", Priority::High),
+            queued("e.com", Priority::Normal),
+        ]);
+        assert_eq!(plan(&queue), (vec![3, 1], vec![]));
+    }
+
+    #[test]
+    fn caps_downloads_per_host() {
+        let mut queue = queue(vec![
+            running("a.com", Priority::Normal),
+            queued("a.com", Priority::High),
+            queued("b.com", Priority::Normal),
+        ]);
+        queue.max_per_host = 1;
+        assert_eq!(plan(&queue), (vec![2], vec![]));
+    }
+
+    #[test]
+    fn preempts_for_high_priority() {
+        let mixed = queue(vec![
+            running("a.com", Priority::Normal),
+            running("b.com", Priority::Low),
+            running("c.com", Priority::Low),
+            queued("d.com", Priority::High),
+        ]);
+        // the last of the lowest priority, to start once its slot is free
+        assert_eq!(plan(&mixed), (vec![], vec![2]));
+
+        let all_high = queue(vec![
+            running("a.com", Priority::High),
+            running("b.com", Priority::High),
+            queued("c.com", Priority::High),
+            queued("d.com", Priority::Normal),
+        ]);
+        assert_eq!(plan(&all_high), (vec![], vec![]));
+    }
+
+    #[test]
+    fn waits_for_freeing_slots() {
+        // held, but its task hasn't returned yet
+        let queue = queue(vec![
+            running("a.com", Priority::Normal),
+            entry("b.com", Priority::Normal, Status::Queued, true),
+            queued("c.com", Priority::High),
+            queued("d.com", Priority::High),
+        ]);
+        // the first takes the slot being freed, the second preempts
+        assert_eq!(plan(&queue), (vec![], vec![0]));
+    }
+
+    #[test]
+    fn holds_big_downloads_while_metered() {
+        let mut big = running("a.com", Priority::Normal);
+        big.total = Some(1000);
+        let mut big_queued = queued("b.com", Priority::High);
+        big_queued.total = Some(1000);
+        let mut small = queued("c.com", Priority::Normal);
+        small.total = Some(10);
+        let mut queue = queue(vec![big, big_queued, small, queued("d.com", Priority::Low)]);
+        queue.metered_policy.max_size = Some(100);
+        assert_eq!(plan(&queue), (vec![1], vec![]));
+
+        queue.metered = true;
+        // the small one starts, and the next waits for the held one's slot
+        assert_eq!(plan(&queue), (vec![2], vec![0]));
+    }
+
+    #[test]
+    fn holds_everything_while_it_may_not_run() {
+        let mut queue = queue(vec![
+            running("a.com", Priority::Normal),
+            queued("b.com", Priority::High),
+            running("c.com", Priority::Low),
+        ]);
+        queue.in_time_window = false;
+        assert_eq!(plan(&queue), (vec![], vec![0, 2]));
+
+        queue.in_time_window = true;
+        queue.network = NetworkState::Offline;
+        assert_eq!(plan(&queue), (vec![], vec![0, 2]));
+    }
+}
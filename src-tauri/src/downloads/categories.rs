@@ -0,0 +1,195 @@
+//! Categories downloads are sorted into, by the frontend or by the first
+//! [`CategoryRule`] a new download matches, each with its own directory,
+//! bandwidth cap and post-processing, see [`CategorySettings`].
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// What kind of file a download is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Category {
+    Models,
+    Archives,
+    Video,
+    #[default]
+    Other,
+}
+
+/// What downloads of a category have in common.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CategorySettings {
+    /// Where those whose destination is only a file name go.
+    pub directory: Option<PathBuf>,
+    /// Bytes per second they're capped at together.
+    pub bandwidth_limit: Option<u64>,
+    /// Whether archives are extracted next to themselves once they're done,
+    /// unless they're given an [`Extraction`](super::Extraction).
+    #[serde(default)]
+    pub extract: bool,
+}
+
+/// Puts downloads in `category` if both the extension and the URL match,
+/// of those that are given.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryRule {
+    pub category: Category,
+    /// Extensions of the file without the dot, e.g. `gguf` or `tar.gz`.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Text the URL contains, e.g. its host.
+    pub url_contains: Option<String>,
+}
+
+/// The categories' settings and the rules assigning them.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Categories {
+    /// Those missing have the default settings.
+    #[serde(default)]
+    pub settings: HashMap<Category, CategorySettings>,
+    /// The first that matches wins; `Other` if none does.
+    #[serde(default)]
+    pub rules: Vec<CategoryRule>,
+}
+
+impl Category {
+    pub const ALL: [Category; 4] = [
+        Category::Models,
+        Category::Archives,
+        Category::Video,
+        Category::Other,
+    ];
+}
+
+impl CategoryRule {
+    fn matches(&self, url: &Url, destination: &Path) -> bool {
+        if self.extensions.is_empty() && self.url_contains.is_none() {
+            return false;
+        }
+        let name = destination
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let name = name.to_lowercase();
+        let extension = self.extensions.is_empty()
+            || (self.extensions.iter())
+                .any(|ext| name.ends_with(&format!(".{}", ext.to_lowercase())));
+        let url_contains = (self.url_contains.as_ref())
+            .is_none_or(|text| url.as_str().to_lowercase().contains(&text.to_lowercase()));
+        extension && url_contains
+    }
+}
+
+impl Default for Categories {
+    fn default() -> Self {
+        let rule = |category, extensions: &[&str]| CategoryRule {
+            category,
+            extensions: extensions.iter().map(|ext| ext.to_string()).collect(),
+            url_contains: None,
+        };
+        Categories {
+            settings: HashMap::new(),
+            rules: vec![
+                rule(
+                    Category::Models,
+                    &["gguf", "ggml", "safetensors", "onnx", "pt", "pth", "ckpt"],
+                ),
+                rule(
+                    Category::Archives,
+                    &["zip", "tar.gz", "tgz", "tar.zst", "tzst", "7z", "rar"],
+                ),
+                rule(Category::Video, &["mp4", "mkv", "webm", "mov", "avi"]),
+            ],
+        }
+    }
+}
+
+impl Categories {
+    /// The category of a new download of `url` to `destination`.
+    pub(super) fn categorize(&self, url: &Url, destination: &Path) -> Category {
+        (self.rules.iter())
+            .find(|rule| rule.matches(url, destination))
+            .map_or(Category::Other, |rule| rule.category)
+    }
+
+    pub(super) fn get(&self, category: Category) -> CategorySettings {
+        self.settings.get(&category).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Categories, Category, CategoryRule};
+    use reqwest::Url;
+    use std::path::Path;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn rule(category: Category, extensions: &[&str], url_contains: Option<&str>) -> CategoryRule {
+        CategoryRule {
+            category,
+            extensions: extensions.iter().map(|ext| ext.to_string()).collect(),
+            url_contains: url_contains.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn matches_extensions() {
+        let archives = rule(Category::Archives, &["zip", "tar.gz"], None);
+        let url = url("https://example.com/file");
+        assert!(archives.matches(&url, Path::new("a.zip")));
+        assert!(archives.matches(&url, Path::new("dir/A.TAR.GZ")));
+        assert!(!archives.matches(&url, Path::new("a.gz")));
+        assert!(!archives.matches(&url, Path::new("zip")));
+    }
+
+    #[test]
+    fn matches_urls() {
+        let models = rule(Category::Models, &[], Some("HuggingFace.co"));
+        let destination = Path::new("model.bin");
+        assert!(models.matches(&url("https://huggingface.co/m/model.bin"), destination));
+        assert!(!models.matches(&url("https://example.com/model.bin"), destination));
+    }
+
+    #[test]
+    fn matches_both_fields() {
+        let models = rule(Category::Models, &["bin"], Some("huggingface.co"));
+        let hugging_face = url("https://huggingface.co/m/file");
+        assert!(models.matches(&hugging_face, Path::new("model.bin")));
+        assert!(!models.matches(&hugging_face, Path::new("model.txt")));
+        assert!(!models.matches(&url("https://example.com/"), Path::new("model.bin")));
+        // a rule without either field doesn't match everything
+        let empty = rule(Category::Video, &[], None);
+        assert!(!empty.matches(&hugging_face, Path::new("model.bin")));
+    }
+
+    #[test]
+    fn first_rule_wins() {
+        let categories = Categories {
+            rules: vec![
+                rule(Category::Models, &[], Some("models.example.com")),
+                rule(Category::Archives, &["zip"], None),
+            ],
+            ..Categories::default()
+        };
+        let zip = Path::new("a.zip");
+        let models = url("https://models.example.com/a.zip");
+        assert_eq!(categories.categorize(&models, zip), Category::Models);
+        let other = url("https://example.com/a.zip");
+        assert_eq!(categories.categorize(&other, zip), Category::Archives);
+        let mp4 = Path::new("a.mp4");
+        assert_eq!(categories.categorize(&other, mp4), Category::Other);
+        let defaults = Categories::default();
+        assert_eq!(defaults.categorize(&other, mp4), Category::Video);
+    }
+}
@@ -0,0 +1,323 @@
+use std::{path::PathBuf, time::Duration};
+
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use tauri::State;
+
+use super::{
+    extract, naming, network, BaseFolder, Categories, Category, ConflictPolicy, DownloadId,
+    DownloadManager, DownloadOptions, DownloadPage, DuplicateAction, ExpectedHash, Extraction,
+    Filter, HistoryPage, HistoryRetention, ImportFormat, ImportResult, LogEntry, MeteredPolicy,
+    NamingSettings, NetworkState, NotificationSettings, Priority, QueueExport, SpeedSample,
+    Started, TimeWindow,
+};
+use crate::{
+    err,
+    errors::{Context, Result},
+    reqwest_resume::metalink,
+};
+
+#[tauri::command(async)]
+#[allow(clippy::too_many_arguments)]
+pub async fn download_start(
+    url: String,
+    destination: PathBuf,
+    category: Option<Category>,
+    priority: Option<Priority>,
+    expected_hash: Option<ExpectedHash>,
+    extraction: Option<Extraction>,
+    start_at: Option<DateTime<Utc>>,
+    on_duplicate: Option<DuplicateAction>,
+    on_conflict: Option<ConflictPolicy>,
+    chunk_index: Option<String>,
+    manager: State<'_, DownloadManager>,
+) -> Result<Started> {
+    let url = Url::parse(&url).with_context(|| format!("Invalid download url {url:?}"))?;
+    let chunk_index = chunk_index
+        .map(|index| {
+            Url::parse(&index).with_context(|| format!("Invalid chunk index url {index:?}"))
+        })
+        .transpose()?;
+    if let Some(hash) = expected_hash.as_ref().filter(|hash| !hash.is_valid()) {
+        err!("Invalid {:?} digest {:?}", hash.algorithm, hash.hex);
+    }
+    if extraction.is_some() && !extract::is_supported(&destination) {
+        err!("Can't extract {destination:?}, it isn't a zip, .tar.gz or .tar.zst archive");
+    }
+    let options = DownloadOptions {
+        category,
+        priority: priority.unwrap_or_default(),
+        expected_hash,
+        extraction,
+        start_at,
+        on_duplicate,
+        on_conflict,
+        chunk_index,
+        ..DownloadOptions::default()
+    };
+    Ok(manager.start(url, destination, options).await)
+}
+
+#[tauri::command]
+pub fn import_urls(
+    contents: String,
+    format: Option<ImportFormat>,
+    directory: Option<PathBuf>,
+    manager: State<'_, DownloadManager>,
+) -> Result<ImportResult> {
+    if let Some(directory) = directory
+        .as_ref()
+        .filter(|directory| directory.is_relative())
+    {
+        err!("{directory:?} isn't an absolute path");
+    }
+    manager.import(&contents, format, directory.as_deref())
+}
+
+#[tauri::command(async)]
+pub async fn import_metalink(
+    path: PathBuf,
+    directory: PathBuf,
+    manager: State<'_, DownloadManager>,
+) -> Result<ImportResult> {
+    if directory.is_relative() {
+        err!("{directory:?} isn't an absolute path");
+    }
+    let xml = (tokio::fs::read_to_string(&path).await)
+        .with_context(|| format!("Failed to read {path:?}"))?;
+    let metalink = metalink::parse(&xml).with_context(|| format!("Failed to parse {path:?}"))?;
+    Ok(manager.import_metalink(metalink, &directory))
+}
+
+#[tauri::command(async)]
+pub async fn export_queue(path: PathBuf, manager: State<'_, DownloadManager>) -> Result<()> {
+    let export = manager.export_queue();
+    let json = serde_json::to_vec_pretty(&export).with_context(|| "Failed to export the queue")?;
+    (tokio::fs::write(&path, json).await).with_context(|| format!("Failed to write {path:?}"))
+}
+
+#[tauri::command(async)]
+pub async fn import_queue(
+    path: PathBuf,
+    base_folder: Option<BaseFolder>,
+    manager: State<'_, DownloadManager>,
+) -> Result<ImportResult> {
+    let json =
+        (tokio::fs::read(&path).await).with_context(|| format!("Failed to read {path:?}"))?;
+    let export: QueueExport = serde_json::from_slice(&json)
+        .with_context(|| format!("{path:?} isn't an exported download queue"))?;
+    if let Some(base) = base_folder.as_ref().filter(|base| base.to.is_relative()) {
+        err!("{:?} isn't an absolute path", base.to);
+    }
+    manager.import_queue(export, base_folder.as_ref())
+}
+
+#[tauri::command]
+pub fn list_downloads(
+    filter: Option<Filter>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    manager: State<'_, DownloadManager>,
+) -> DownloadPage {
+    manager.list(filter, offset.unwrap_or(0), limit)
+}
+
+#[tauri::command]
+pub fn get_download_log(
+    id: DownloadId,
+    manager: State<'_, DownloadManager>,
+) -> Result<Vec<LogEntry>> {
+    manager.transfer_log(id)
+}
+
+#[tauri::command]
+pub fn get_speed_history(
+    id: DownloadId,
+    window: Option<f64>,
+    manager: State<'_, DownloadManager>,
+) -> Result<Vec<SpeedSample>> {
+    let window = window
+        .map(|secs| {
+            Duration::try_from_secs_f64(secs)
+                .with_context(|| format!("Invalid speed history window {secs}"))
+        })
+        .transpose()?;
+    manager.speed_history(id, window)
+}
+
+#[tauri::command]
+pub fn get_history(
+    query: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    manager: State<'_, DownloadManager>,
+) -> Result<HistoryPage> {
+    let query = query.as_deref().filter(|query| !query.is_empty());
+    manager.history(query, offset.unwrap_or(0), limit)
+}
+
+#[tauri::command]
+pub fn clear_history(manager: State<'_, DownloadManager>) -> Result<()> {
+    manager.clear_history()
+}
+
+#[tauri::command]
+pub fn set_history_retention(
+    retention: HistoryRetention,
+    manager: State<'_, DownloadManager>,
+) -> Result<()> {
+    manager.set_history_retention(retention)
+}
+
+#[tauri::command]
+pub fn set_max_concurrent(
+    max: usize,
+    max_per_host: Option<usize>,
+    manager: State<'_, DownloadManager>,
+) {
+    manager.set_max_concurrent(max, max_per_host);
+}
+
+#[tauri::command]
+pub fn set_download_priority(
+    id: DownloadId,
+    priority: Priority,
+    manager: State<'_, DownloadManager>,
+) -> Result<()> {
+    manager.set_priority(id, priority)
+}
+
+#[tauri::command]
+pub fn reorder_download(
+    id: DownloadId,
+    position: usize,
+    manager: State<'_, DownloadManager>,
+) -> Result<()> {
+    manager.reorder(id, position)
+}
+
+#[tauri::command]
+pub fn set_bandwidth_limit(
+    id: Option<DownloadId>,
+    bytes_per_sec: Option<u64>,
+    manager: State<'_, DownloadManager>,
+) -> Result<()> {
+    manager.set_bandwidth_limit(id, bytes_per_sec)
+}
+
+#[tauri::command]
+pub fn set_min_free_space(bytes: u64, manager: State<'_, DownloadManager>) {
+    manager.set_min_free_space(bytes);
+}
+
+#[tauri::command]
+pub fn network_state(manager: State<'_, DownloadManager>) -> NetworkState {
+    manager.network_state()
+}
+
+#[tauri::command]
+pub fn set_connectivity_check(
+    enabled: bool,
+    url: Option<String>,
+    manager: State<'_, DownloadManager>,
+) -> Result<()> {
+    let url = if enabled {
+        let url = url.as_deref().unwrap_or(network::PROBE_URL);
+        Some(Url::parse(url).with_context(|| format!("Invalid url {url:?}"))?)
+    } else {
+        None
+    };
+    manager.set_connectivity_check(url);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_time_windows(windows: Vec<TimeWindow>, manager: State<'_, DownloadManager>) {
+    manager.set_time_windows(windows);
+}
+
+#[tauri::command]
+pub fn set_categories(categories: Categories, manager: State<'_, DownloadManager>) {
+    manager.set_categories(categories);
+}
+
+#[tauri::command]
+pub fn set_naming(settings: NamingSettings, manager: State<'_, DownloadManager>) -> Result<()> {
+    if let Some(template) = &settings.template {
+        naming::validate(template)?;
+    }
+    manager.set_naming(settings);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_metered_policy(policy: MeteredPolicy, manager: State<'_, DownloadManager>) {
+    manager.set_metered_policy(policy);
+}
+
+#[tauri::command]
+pub fn is_metered(manager: State<'_, DownloadManager>) -> bool {
+    manager.is_metered()
+}
+
+#[tauri::command]
+pub fn set_notifications(settings: NotificationSettings, manager: State<'_, DownloadManager>) {
+    manager.set_notifications(settings);
+}
+
+#[tauri::command(async)]
+pub async fn set_chunk_store(enabled: bool, manager: State<'_, DownloadManager>) -> Result<()> {
+    manager.set_chunk_store(enabled).await
+}
+
+#[tauri::command]
+pub fn reveal_download(id: DownloadId, manager: State<'_, DownloadManager>) -> Result<()> {
+    manager.reveal(id)
+}
+
+#[tauri::command]
+pub fn download_pause(id: DownloadId, manager: State<'_, DownloadManager>) -> Result<()> {
+    manager.pause(id)
+}
+
+#[tauri::command]
+pub fn download_resume(id: DownloadId, manager: State<'_, DownloadManager>) -> Result<()> {
+    manager.resume(id)
+}
+
+#[tauri::command(async)]
+pub async fn download_cancel(id: DownloadId, manager: State<'_, DownloadManager>) -> Result<()> {
+    manager.cancel(id).await
+}
+
+#[tauri::command]
+pub fn pause_all(manager: State<'_, DownloadManager>) {
+    manager.pause_all();
+}
+
+#[tauri::command]
+pub fn resume_all(manager: State<'_, DownloadManager>) {
+    manager.resume_all();
+}
+
+#[tauri::command]
+pub fn retry_failed(manager: State<'_, DownloadManager>) {
+    manager.retry_failed();
+}
+
+#[tauri::command(async)]
+pub async fn cancel_selected(
+    ids: Vec<DownloadId>,
+    manager: State<'_, DownloadManager>,
+) -> Result<()> {
+    manager.cancel_selected(&ids).await
+}
+
+#[tauri::command(async)]
+pub async fn download_remove(
+    id: DownloadId,
+    delete_file: bool,
+    manager: State<'_, DownloadManager>,
+) -> Result<()> {
+    manager.remove(id, delete_file).await
+}
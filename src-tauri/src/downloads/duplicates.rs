@@ -0,0 +1,183 @@
+//! Telling whether a new download is one the queue already has, or one the
+//! history remembers being done, so the same file isn't downloaded twice.
+//! Besides the URL itself, the URL it redirects to and its ETag and size are
+//! compared, as learned from a `HEAD` request before it's queued.
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use super::{DownloadInfo, HistoryEntry};
+use crate::reqwest_resume::Probe;
+
+/// What to do with a download that turns out to be a duplicate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DuplicateAction {
+    /// Use the existing download instead, resuming it if it's stopped, or the
+    /// file it downloaded if it's left the queue.
+    Attach,
+    /// Download it anyway, to a name no other download uses.
+    DownloadAgain,
+    Skip,
+}
+
+/// What gave a duplicate away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DuplicateMatch {
+    Url,
+    /// One of them redirects to the other, or both to the same place.
+    FinalUrl,
+    /// A different URL, but the same ETag and size.
+    Content,
+}
+
+/// The download a new one duplicates.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase", tag = "in")]
+pub enum Existing {
+    Queue(DownloadInfo),
+    /// It was done and has left the queue, and its file is still there.
+    History(HistoryEntry),
+}
+
+/// A download that's already in the queue or was done before, for the
+/// frontend to ask what to do about it.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Duplicate {
+    pub existing: Existing,
+    pub matched: DuplicateMatch,
+}
+
+/// What's known of an existing download, to compare new ones with.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Known<'a> {
+    pub url: &'a Url,
+    /// Where `url` redirects to, if somewhere else.
+    pub final_url: Option<&'a Url>,
+    pub etag: Option<&'a str>,
+    pub size: Option<u64>,
+}
+
+/// How `known` and a new download of `url` are the same, if they are.
+pub(super) fn matches(
+    known: Known<'_>,
+    url: &Url,
+    probe: Option<&Probe>,
+) -> Option<DuplicateMatch> {
+    if known.url == url {
+        return Some(DuplicateMatch::Url);
+    }
+    let redirected = |target: &Url| known.url == target || known.final_url == Some(target);
+    if redirected(url) || probe.is_some_and(|probe| redirected(&probe.url)) {
+        return Some(DuplicateMatch::FinalUrl);
+    }
+    let probe = probe?;
+    let etag = probe.etag.as_ref().and_then(|etag| etag.to_str().ok());
+    let same_etag = etag.is_some() && known.etag == etag;
+    let same_size = probe.content_length.is_some() && known.size == probe.content_length;
+    (same_etag && same_size).then_some(DuplicateMatch::Content)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{header::HeaderValue, Url};
+
+    use super::{matches, DuplicateMatch, Known};
+    use crate::reqwest_resume::Probe;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn probe(url: &str, etag: Option<&'static str>, size: Option<u64>) -> Probe {
+        Probe {
+            content_length: size,
+            accept_byte_ranges: true,
+            etag: etag.map(HeaderValue::from_static),
+            last_modified: None,
+            url: self::url(url),
+        }
+    }
+
+    #[test]
+    fn matches_urls() {
+        let existing = url("https://example.com/a.bin");
+        let known = Known {
+            url: &existing,
+            final_url: None,
+            etag: None,
+            size: None,
+        };
+        assert_eq!(matches(known, &existing, None), Some(DuplicateMatch::Url));
+        assert_eq!(
+            matches(known, &url("https://example.com/b.bin"), None),
+            None
+        );
+    }
+
+    #[test]
+    fn matches_redirects() {
+        let existing = url("https://example.com/latest");
+        let target = url("https://cdn.example.com/a-1.2.bin");
+        let known = Known {
+            url: &existing,
+            final_url: Some(&target),
+            etag: None,
+            size: None,
+        };
+        // the new one is where the existing one redirects to
+        assert_eq!(
+            matches(known, &target, None),
+            Some(DuplicateMatch::FinalUrl)
+        );
+        // both redirect to the same place
+        let other = url("https://example.com/stable");
+        let redirect = probe("https://cdn.example.com/a-1.2.bin", None, None);
+        assert_eq!(
+            matches(known, &other, Some(&redirect)),
+            Some(DuplicateMatch::FinalUrl)
+        );
+        // the new one redirects to the existing one
+        let unredirected = Known {
+            final_url: None,
+            ..known
+        };
+        let redirect = probe("https://example.com/latest", None, None);
+        assert_eq!(
+            matches(unredirected, &other, Some(&redirect)),
+            Some(DuplicateMatch::FinalUrl)
+        );
+        let elsewhere = probe("https://cdn.example.com/b.bin", None, None);
+        assert_eq!(matches(known, &other, Some(&elsewhere)), None);
+    }
+
+    #[test]
+    fn matches_content() {
+        let existing = url("https://example.com/a.bin");
+        let known = Known {
+            url: &existing,
+            final_url: None,
+            etag: Some("\"v1\""),
+            size: Some(1000),
+        };
+        let mirror = url("https://mirror.example.org/a.bin");
+        let same = probe(mirror.as_str(), Some("\"v1\""), Some(1000));
+        assert_eq!(
+            matches(known, &mirror, Some(&same)),
+            Some(DuplicateMatch::Content)
+        );
+        let resized = probe(mirror.as_str(), Some("\"v1\""), Some(999));
+        assert_eq!(matches(known, &mirror, Some(&resized)), None);
+        let changed = probe(mirror.as_str(), Some("\"v2\""), Some(1000));
+        assert_eq!(matches(known, &mirror, Some(&changed)), None);
+        // neither knowing its ETag isn't the same ETag
+        let unknown = Known {
+            etag: None,
+            ..known
+        };
+        let untagged = probe(mirror.as_str(), None, Some(1000));
+        assert_eq!(matches(unknown, &mirror, Some(&untagged)), None);
+    }
+}
@@ -0,0 +1,158 @@
+//! Events telling the webview how downloads are doing: `download://state`
+//! whenever one changes [`Status`], and `download://progress` while it runs,
+//! at most every [`PROGRESS_INTERVAL`] so fast downloads don't flood the IPC
+//! bridge. `download://extract-progress` follows the extraction of an archive
+//! the same way. `download://low-space` says a disk is too full to go on, and
+//! `network://state` whether downloads can reach the internet, and
+//! `network://metered` whether it's through a metered connection.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use super::{DownloadId, NetworkState, Status};
+use crate::{logerr, reqwest_resume::ProgressHandle};
+
+pub const PROGRESS_EVENT: &str = "download://progress";
+pub const STATE_EVENT: &str = "download://state";
+pub const EXTRACT_PROGRESS_EVENT: &str = "download://extract-progress";
+pub const LOW_SPACE_EVENT: &str = "download://low-space";
+pub const NETWORK_EVENT: &str = "network://state";
+pub const METERED_EVENT: &str = "network://metered";
+
+/// Shortest time between two progress or extraction progress events of the
+/// same download.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressPayload {
+    pub id: DownloadId,
+    /// Bytes on disk, including those from before it was resumed.
+    pub bytes: u64,
+    pub total: Option<u64>,
+    /// Bytes per second over the last few seconds.
+    pub speed: f64,
+    /// Seconds left at that speed, if the total is known.
+    pub eta: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractProgressPayload {
+    pub id: DownloadId,
+    /// Bytes of the archive read so far.
+    pub bytes: u64,
+    pub total: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatePayload {
+    pub id: DownloadId,
+    pub status: Status,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LowSpacePayload {
+    /// The download, or the directory, that ran out of room.
+    pub path: PathBuf,
+    pub available: u64,
+    pub needed: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkPayload {
+    pub state: NetworkState,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeteredPayload {
+    pub metered: bool,
+}
+
+/// Emits the progress events of one download.
+pub(super) struct ProgressEmitter {
+    app: AppHandle,
+    id: DownloadId,
+    progress: ProgressHandle,
+    last: Mutex<Option<Instant>>,
+}
+
+impl ProgressEmitter {
+    pub fn new(app: AppHandle, id: DownloadId, progress: ProgressHandle) -> Self {
+        ProgressEmitter {
+            app,
+            id,
+            progress,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Emit the progress unless the last event was less than
+    /// [`PROGRESS_INTERVAL`] ago.
+    pub fn chunk_received(&self) {
+        let now = Instant::now();
+        {
+            let mut last = self.last.lock().unwrap();
+            if last.is_some_and(|last| now - last < PROGRESS_INTERVAL) {
+                return;
+            }
+            *last = Some(now);
+        }
+        self.emit();
+    }
+
+    /// Emit the progress now, e.g. once the download has stopped so the last
+    /// chunks aren't left out.
+    pub fn emit(&self) {
+        let payload = ProgressPayload {
+            id: self.id,
+            bytes: self.progress.position(),
+            total: self.progress.total(),
+            speed: self.progress.speed(),
+            eta: self.progress.eta().map(|eta| eta.as_secs_f64()),
+        };
+        logerr!(self.app.emit_all(PROGRESS_EVENT, payload));
+    }
+}
+
+pub(super) fn emit_extract_progress(app: &AppHandle, id: DownloadId, bytes: u64, total: u64) {
+    let payload = ExtractProgressPayload { id, bytes, total };
+    logerr!(app.emit_all(EXTRACT_PROGRESS_EVENT, payload));
+}
+
+pub(super) fn emit_low_space(app: &AppHandle, path: &Path, available: u64, needed: u64) {
+    let payload = LowSpacePayload {
+        path: path.to_owned(),
+        available,
+        needed,
+    };
+    logerr!(app.emit_all(LOW_SPACE_EVENT, payload));
+}
+
+pub(super) fn emit_network(app: &AppHandle, state: NetworkState) {
+    logerr!(app.emit_all(NETWORK_EVENT, NetworkPayload { state }));
+}
+
+pub(super) fn emit_metered(app: &AppHandle, metered: bool) {
+    logerr!(app.emit_all(METERED_EVENT, MeteredPayload { metered }));
+}
+
+pub(super) fn emit_state(app: &AppHandle, id: DownloadId, status: Status, error: Option<&str>) {
+    let payload = StatePayload {
+        id,
+        status,
+        error: error.map(str::to_owned),
+    };
+    logerr!(app.emit_all(STATE_EVENT, payload));
+}
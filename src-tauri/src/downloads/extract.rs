@@ -0,0 +1,734 @@
+//! Unpacking downloaded zip, `.tar.gz` and `.tar.zst` archives. This blocks,
+//! so it's run on a blocking thread.
+//!
+//! Nothing is written outside the target directory: entries with `..` or
+//! absolute paths fail the extraction, and so do those whose parent turns out
+//! to be a link leading elsewhere. Links in the archive are handled according
+//! to a [`SymlinkPolicy`]. When the extraction fails or is cancelled, what it
+//! wrote so far is deleted again.
+
+use std::{
+    cell::Cell,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    err,
+    errors::{Context, Result},
+};
+
+/// Where and how a download is extracted once it's complete.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Extraction {
+    pub directory: PathBuf,
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+}
+
+/// What to do with symbolic and hard links in an archive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SymlinkPolicy {
+    /// Leave them out.
+    #[default]
+    Skip,
+    /// Fail the extraction.
+    Reject,
+    /// Create those pointing inside the target directory, fail on others.
+    Contained,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Zip,
+    TarGz,
+    TarZst,
+}
+
+const BUF_SIZE: usize = 64 * 1024;
+
+/// Whether `path` is named like an archive this module can extract.
+pub fn is_supported(path: &Path) -> bool {
+    Format::of(path).is_some()
+}
+
+/// Where `archive` is extracted to unless it's told otherwise: next to it,
+/// in a directory named like it without the extension. `None` if it isn't
+/// named like an archive, or there's nothing left of its name without the
+/// extension to name a directory.
+pub(super) fn default_directory(archive: &Path) -> Option<PathBuf> {
+    let name = archive.file_name()?.to_string_lossy();
+    let stem = [".tar.gz", ".tgz", ".tar.zst", ".tzst", ".zip"]
+        .iter()
+        .find_map(|ext| {
+            let split = name.len().checked_sub(ext.len())?;
+            let suffix = name.get(split..)?;
+            suffix.eq_ignore_ascii_case(ext).then(|| &name[..split])
+        })?;
+    // e.g. `.zip`, which would be extracted into the directory it's in
+    if matches!(stem, "" | "." | "..") {
+        return None;
+    }
+    Some(archive.with_file_name(stem))
+}
+
+/// Extract `archive` as `extraction` says, calling `progress` with how many
+/// bytes of the archive have been read and its size as it goes.
+pub(super) fn extract(
+    archive: &Path,
+    extraction: &Extraction,
+    cancel: &CancellationToken,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    let format = Format::of(archive).with_context(|| format!("{archive:?} isn't an archive"))?;
+    let file = File::open(archive).with_context(|| format!("Failed to open {archive:?}"))?;
+    let total = file
+        .metadata()
+        .with_context(|| format!("Failed to read {archive:?}"))?
+        .len();
+    fs::create_dir_all(&extraction.directory)
+        .with_context(|| format!("Failed to create {:?}", extraction.directory))?;
+    let root = extraction
+        .directory
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {:?}", extraction.directory))?;
+    let read = Rc::new(Cell::new(0));
+    let reader = Counting {
+        inner: file,
+        read: read.clone(),
+    };
+    let mut report = || progress(read.get().min(total), total);
+    let mut extractor = Extractor {
+        root,
+        policy: extraction.symlinks,
+        cancel,
+        progress: &mut report,
+        files: Vec::new(),
+        dirs: Vec::new(),
+    };
+    let result = match format {
+        Format::Zip => extractor.zip(reader),
+        Format::TarGz => extractor.tar(flate2::read::GzDecoder::new(reader)),
+        Format::TarZst => match zstd::stream::read::Decoder::new(reader) {
+            Ok(decoder) => extractor.tar(decoder),
+            Err(err) => Err(err).with_context(|| format!("Failed to read {archive:?}")),
+        },
+    };
+    if result.is_err() {
+        extractor.clean_up();
+    }
+    result.with_context(|| format!("Failed to extract {archive:?}"))
+}
+
+impl Format {
+    fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Format::TarZst)
+        } else {
+            None
+        }
+    }
+}
+
+struct Extractor<'a> {
+    // canonical, so resolved paths can be checked against it
+    root: PathBuf,
+    policy: SymlinkPolicy,
+    cancel: &'a CancellationToken,
+    // called after every chunk written
+    progress: &'a mut dyn FnMut(),
+    // created so far, to delete if it fails
+    files: Vec<PathBuf>,
+    dirs: Vec<PathBuf>,
+}
+
+/// A link in an archive, by what its target is relative to.
+enum Link {
+    Symbolic(PathBuf),
+    /// Relative to the root of the archive.
+    Hard(PathBuf),
+}
+
+impl Extractor<'_> {
+    fn zip(&mut self, reader: impl Read + Seek) -> Result<()> {
+        let mut archive = zip::ZipArchive::new(reader).with_context(|| "Invalid zip archive")?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).with_context(|| "Invalid zip archive")?;
+            let name = PathBuf::from(file.name());
+            let mode = file.unix_mode();
+            if file.is_dir() {
+                let path = self.path(&name)?;
+                self.create_dirs(&path)?;
+            } else if mode.is_some_and(|mode| mode & 0o170000 == 0o120000) {
+                let mut target = String::new();
+                file.read_to_string(&mut target)
+                    .with_context(|| format!("Failed to read {name:?}"))?;
+                self.link(&name, Link::Symbolic(target.into()))?;
+            } else {
+                self.file(&name, &mut file, mode)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn tar(&mut self, reader: impl Read) -> Result<()> {
+        let mut archive = tar::Archive::new(reader);
+        let entries = archive.entries().with_context(|| "Invalid tar archive")?;
+        for entry in entries {
+            let mut entry = entry.with_context(|| "Invalid tar archive")?;
+            let name = entry
+                .path()
+                .with_context(|| "Invalid tar archive")?
+                .into_owned();
+            let kind = entry.header().entry_type();
+            let target = || -> Result<PathBuf> {
+                let target = entry.link_name().with_context(|| "Invalid tar archive")?;
+                Ok(target
+                    .with_context(|| format!("{name:?} has no target"))?
+                    .into_owned())
+            };
+            if kind.is_dir() {
+                let path = self.path(&name)?;
+                self.create_dirs(&path)?;
+            } else if kind.is_symlink() {
+                let target = target()?;
+                self.link(&name, Link::Symbolic(target))?;
+            } else if kind.is_hard_link() {
+                let target = target()?;
+                self.link(&name, Link::Hard(target))?;
+            } else if kind.is_file() {
+                let mode = entry.header().mode().ok();
+                self.file(&name, &mut entry, mode)?;
+            } else {
+                log::debug!("skipping {:?} of type {:?}", name, kind);
+            }
+        }
+        Ok(())
+    }
+
+    /// Where entry `name` goes, if that's inside the target directory.
+    fn path(&self, name: &Path) -> Result<PathBuf> {
+        let mut path = self.root.clone();
+        for component in name.components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                _ => err!("{name:?} would be extracted outside of {:?}", self.root),
+            }
+        }
+        if path == self.root {
+            err!("Invalid entry {name:?}");
+        }
+        Ok(path)
+    }
+
+    /// Create `dir` and the parents it's missing, none of which may lead
+    /// outside the target directory.
+    fn create_dirs(&mut self, dir: &Path) -> Result<()> {
+        let mut missing: Vec<_> = dir.ancestors().take_while(|dir| !dir.exists()).collect();
+        missing.reverse();
+        for dir in missing {
+            fs::create_dir(dir).with_context(|| format!("Failed to create {dir:?}"))?;
+            self.dirs.push(dir.to_owned());
+        }
+        // a link from the archive or already there could point anywhere
+        let resolved = dir
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {dir:?}"))?;
+        if !resolved.starts_with(&self.root) {
+            err!("{dir:?} leads outside of {:?}", self.root);
+        }
+        Ok(())
+    }
+
+    /// Make way for entry `name`, returning where it goes.
+    fn prepare(&mut self, name: &Path) -> Result<PathBuf> {
+        if self.cancel.is_cancelled() {
+            err!("Extraction cancelled");
+        }
+        let path = self.path(name)?;
+        if let Some(parent) = path.parent() {
+            self.create_dirs(parent)?;
+        }
+        // rather than write through a link that's already there
+        if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            fs::remove_file(&path).with_context(|| format!("Failed to replace {path:?}"))?;
+        }
+        Ok(path)
+    }
+
+    fn file(&mut self, name: &Path, reader: &mut dyn Read, mode: Option<u32>) -> Result<()> {
+        let path = self.prepare(name)?;
+        // a file that was already there isn't ours to delete again
+        let existed = fs::symlink_metadata(&path).is_ok();
+        let mut file = File::create(&path).with_context(|| format!("Failed to create {path:?}"))?;
+        if !existed {
+            self.files.push(path.clone());
+        }
+        let mut buf = vec![0; BUF_SIZE];
+        loop {
+            if self.cancel.is_cancelled() {
+                err!("Extraction cancelled");
+            }
+            let n = reader
+                .read(&mut buf)
+                .with_context(|| format!("Failed to read {name:?}"))?;
+            if n == 0 {
+                break;
+            }
+            io::Write::write_all(&mut file, &buf[..n])
+                .with_context(|| format!("Failed to write {path:?}"))?;
+            (self.progress)();
+        }
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+
+            let permissions = fs::Permissions::from_mode(mode & 0o777);
+            fs::set_permissions(&path, permissions)
+                .with_context(|| format!("Failed to set the permissions of {path:?}"))?;
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        Ok(())
+    }
+
+    fn link(&mut self, name: &Path, link: Link) -> Result<()> {
+        match self.policy {
+            SymlinkPolicy::Skip => {
+                log::debug!("skipping link {:?}", name);
+                return Ok(());
+            }
+            SymlinkPolicy::Reject => err!("{name:?} is a link"),
+            SymlinkPolicy::Contained => {}
+        }
+        let path = self.prepare(name)?;
+        match link {
+            Link::Symbolic(target) => {
+                let parent = path.parent().unwrap_or(&self.root);
+                match resolve(parent, &target) {
+                    Some(resolved) if resolved.starts_with(&self.root) => {}
+                    _ => err!("{name:?} links to {target:?}, outside of {:?}", self.root),
+                }
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(&target, &path)
+                    .with_context(|| format!("Failed to create {path:?}"))?;
+                #[cfg(not(unix))]
+                {
+                    log::warn!("skipping symlink {:?}: not supported here", name);
+                    return Ok(());
+                }
+            }
+            Link::Hard(target) => {
+                let target = self.path(&target)?;
+                fs::hard_link(&target, &path)
+                    .with_context(|| format!("Failed to create {path:?}"))?;
+            }
+        }
+        self.files.push(path);
+        Ok(())
+    }
+
+    /// Delete what was extracted, after it failed.
+    fn clean_up(&mut self) {
+        for file in self.files.drain(..).rev() {
+            if let Err(err) = fs::remove_file(&file) {
+                log::warn!("failed to delete {:?}: {}", file, err);
+            }
+        }
+        // only those left empty, in case something else was put there
+        for dir in self.dirs.drain(..).rev() {
+            let _ = fs::remove_dir(&dir);
+        }
+    }
+}
+
+/// Where a link in `dir` to `target` leads, following the links already on
+/// disk the way the system will, or `None` if that can't be told.
+///
+/// Once a component doesn't exist yet, a later `..` is refused: what it goes
+/// above depends on what's put there, which may well be another link.
+fn resolve(dir: &Path, target: &Path) -> Option<PathBuf> {
+    let mut resolved = dir.canonicalize().ok()?;
+    let mut missing = false;
+    for component in target.components() {
+        match component {
+            Component::ParentDir => {
+                if missing || !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir => {}
+            Component::Normal(name) => {
+                resolved.push(name);
+                if !missing {
+                    match resolved.canonicalize() {
+                        Ok(real) => resolved = real,
+                        Err(_) => missing = true,
+                    }
+                }
+            }
+            component => resolved.push(component),
+        }
+    }
+    Some(resolved)
+}
+
+/// Counts the bytes read through it, for the progress of an extraction.
+struct Counting<R> {
+    inner: R,
+    read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.set(self.read.get() + n as u64);
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Counting<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        io::Write,
+        path::{Path, PathBuf},
+    };
+
+    use tokio_util::sync::CancellationToken;
+
+    use super::{default_directory, extract, resolve, Extraction, SymlinkPolicy};
+
+    /// An entry of an archive built for a test, named as is, `..` and all.
+    enum Entry<'a> {
+        File(&'a str, &'a [u8]),
+        Dir(&'a str),
+        Symlink(&'a str, &'a str),
+        HardLink(&'a str, &'a str),
+    }
+
+    /// A directory of the test's own, empty, with `root` to extract into and
+    /// `outside` next to it.
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("extract-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    fn zip(path: &Path, entries: &[Entry<'_>]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        let options = zip::write::FileOptions::default();
+        for entry in entries {
+            match *entry {
+                Entry::File(name, data) => {
+                    zip.start_file(name, options).unwrap();
+                    zip.write_all(data).unwrap();
+                }
+                Entry::Dir(name) => zip.add_directory(name, options).unwrap(),
+                Entry::Symlink(name, target) => zip.add_symlink(name, target, options).unwrap(),
+                Entry::HardLink(..) => unreachable!("zip archives have no hard links"),
+            }
+        }
+        zip.finish().unwrap();
+    }
+
+    fn tar_gz(path: &Path, entries: &[Entry<'_>]) {
+        let file = File::create(path).unwrap();
+        let gz = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        let mut tar = tar::Builder::new(gz);
+        for entry in entries {
+            let (name, kind, data, target) = match *entry {
+                Entry::File(name, data) => (name, tar::EntryType::Regular, data, ""),
+                Entry::Dir(name) => (name, tar::EntryType::Directory, &[][..], ""),
+                Entry::Symlink(name, target) => (name, tar::EntryType::Symlink, &[][..], target),
+                Entry::HardLink(name, target) => (name, tar::EntryType::Link, &[][..], target),
+            };
+            let mut header = tar::Header::new_old();
+            // written as is, since `set_path` won't take `..` or absolute paths
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.as_old_mut().linkname[..target.len()].copy_from_slice(target.as_bytes());
+            header.set_entry_type(kind);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            tar.append(&header, data).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    /// Build `entries` into a zip and a `.tar.gz` archive in `dir`, for the
+    /// entries both can hold.
+    fn archives(dir: &Path, entries: &[Entry<'_>]) -> Vec<PathBuf> {
+        let mut archives = Vec::new();
+        if !entries
+            .iter()
+            .any(|entry| matches!(entry, Entry::HardLink(..)))
+        {
+            let path = dir.join("archive.zip");
+            zip(&path, entries);
+            archives.push(path);
+        }
+        let path = dir.join("archive.tar.gz");
+        tar_gz(&path, entries);
+        archives.push(path);
+        archives
+    }
+
+    /// Extract `archive` into `dir/root`, emptied first.
+    fn run(dir: &Path, archive: &Path, symlinks: SymlinkPolicy) -> crate::errors::Result<PathBuf> {
+        let root = dir.join("root");
+        let _ = fs::remove_dir_all(&root);
+        let extraction = Extraction {
+            directory: root.clone(),
+            symlinks,
+        };
+        extract(
+            archive,
+            &extraction,
+            &CancellationToken::new(),
+            &mut |_, _| {},
+        )?;
+        Ok(root)
+    }
+
+    /// Everything under `dir`, relative to it, sorted.
+    fn listing(dir: &Path) -> Vec<String> {
+        let mut listing = Vec::new();
+        let mut pending = vec![dir.to_owned()];
+        while let Some(next) = pending.pop() {
+            for entry in fs::read_dir(&next).unwrap() {
+                let path = entry.unwrap().path();
+                let relative = path.strip_prefix(dir).unwrap();
+                listing.push(relative.to_string_lossy().replace('\\', "/"));
+                if fs::symlink_metadata(&path).unwrap().is_dir() {
+                    pending.push(path);
+                }
+            }
+        }
+        listing.sort();
+        listing
+    }
+
+    #[test]
+    fn extracts_archives() {
+        let dir = temp_dir("extracts");
+        let entries = [
+            Entry::Dir("models/"),
+            Entry::File("models/a.bin", b"a"),
+            Entry::File("./readme.txt", b"readme"),
+            Entry::File("nested/deeper/b.bin", b"b"),
+        ];
+        for archive in archives(&dir, &entries) {
+            let root = run(&dir, &archive, SymlinkPolicy::default()).unwrap();
+            assert_eq!(
+                listing(&root),
+                [
+                    "models",
+                    "models/a.bin",
+                    "nested",
+                    "nested/deeper",
+                    "nested/deeper/b.bin",
+                    "readme.txt"
+                ]
+            );
+            assert_eq!(fs::read(root.join("nested/deeper/b.bin")).unwrap(), b"b");
+        }
+    }
+
+    #[test]
+    fn rejects_entries_outside_the_root() {
+        let dir = temp_dir("outside");
+        let absolute = dir.join("outside").join("absolute.txt");
+        let absolute = absolute.to_str().unwrap();
+        for name in [
+            "../outside/parent.txt",
+            "a/../../outside/parent.txt",
+            absolute,
+        ] {
+            for archive in archives(&dir, &[Entry::File(name, b"evil")]) {
+                assert!(run(&dir, &archive, SymlinkPolicy::Contained).is_err());
+                assert!(listing(&dir.join("outside")).is_empty(), "{name}");
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_writing_through_links() {
+        let dir = temp_dir("through-links");
+        // a link in the archive, then a file through it
+        let entries = [
+            Entry::Symlink("link", "../outside"),
+            Entry::File("link/evil.txt", b"evil"),
+        ];
+        for archive in archives(&dir, &entries) {
+            assert!(run(&dir, &archive, SymlinkPolicy::Contained).is_err());
+            // skipping the link, the file goes in a directory of that name
+            let root = run(&dir, &archive, SymlinkPolicy::Skip).unwrap();
+            assert_eq!(listing(&root), ["link", "link/evil.txt"]);
+        }
+        assert!(listing(&dir.join("outside")).is_empty());
+
+        // a link that's already in the directory
+        let archive = dir.join("archive.zip");
+        zip(&archive, &[Entry::File("link/evil.txt", b"evil")]);
+        let root = dir.join("root");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir(&root).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside"), root.join("link")).unwrap();
+        let extraction = Extraction {
+            directory: root,
+            symlinks: SymlinkPolicy::Skip,
+        };
+        let cancel = CancellationToken::new();
+        assert!(extract(&archive, &extraction, &cancel, &mut |_, _| {}).is_err());
+        assert!(listing(&dir.join("outside")).is_empty());
+    }
+
+    #[test]
+    fn rejects_hard_links_outside_the_root() {
+        let dir = temp_dir("hard-links");
+        fs::write(dir.join("outside/secret.txt"), b"secret").unwrap();
+        let secret = dir.join("outside/secret.txt");
+        for target in ["../outside/secret.txt", secret.to_str().unwrap()] {
+            let archive = dir.join("archive.tar.gz");
+            tar_gz(&archive, &[Entry::HardLink("secret.txt", target)]);
+            assert!(run(&dir, &archive, SymlinkPolicy::Contained).is_err());
+        }
+        let entries = [
+            Entry::File("a.txt", b"a"),
+            Entry::HardLink("b.txt", "a.txt"),
+        ];
+        let archive = dir.join("archive.tar.gz");
+        tar_gz(&archive, &entries);
+        let root = run(&dir, &archive, SymlinkPolicy::Contained).unwrap();
+        assert_eq!(fs::read(root.join("b.txt")).unwrap(), b"a");
+    }
+
+    #[test]
+    fn follows_the_symlink_policy() {
+        let dir = temp_dir("symlinks");
+        let entries = [
+            Entry::File("data/a.txt", b"a"),
+            Entry::Symlink("latest", "data/a.txt"),
+        ];
+        for archive in archives(&dir, &entries) {
+            let root = run(&dir, &archive, SymlinkPolicy::Skip).unwrap();
+            assert_eq!(listing(&root), ["data", "data/a.txt"]);
+
+            assert!(run(&dir, &archive, SymlinkPolicy::Reject).is_err());
+
+            let root = run(&dir, &archive, SymlinkPolicy::Contained).unwrap();
+            #[cfg(unix)]
+            {
+                let target = fs::read_link(root.join("latest")).unwrap();
+                assert_eq!(target, Path::new("data/a.txt"));
+                assert_eq!(fs::read(root.join("latest")).unwrap(), b"a");
+            }
+            #[cfg(not(unix))]
+            assert_eq!(listing(&root), ["data", "data/a.txt"]);
+        }
+        for target in ["../outside", "/etc/passwd", "data/../../outside"] {
+            for archive in archives(&dir, &[Entry::Symlink("link", target)]) {
+                assert!(run(&dir, &archive, SymlinkPolicy::Contained).is_err());
+            }
+        }
+        // each link stays inside on its own, but not one through the other
+        let chains = [
+            [
+                Entry::Symlink("a", "."),
+                Entry::Symlink("b", "a/../outside"),
+            ],
+            [
+                Entry::Symlink("b", "a/../outside"),
+                Entry::Symlink("a", "."),
+            ],
+        ];
+        for entries in chains {
+            for archive in archives(&dir, &entries) {
+                assert!(run(&dir, &archive, SymlinkPolicy::Contained).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn cleans_up_after_failures() {
+        let dir = temp_dir("clean-up");
+        let entries = [
+            Entry::Dir("models/"),
+            Entry::File("models/a.bin", b"a"),
+            Entry::File("nested/b.bin", b"b"),
+            Entry::File("../outside/evil.txt", b"evil"),
+        ];
+        for archive in archives(&dir, &entries) {
+            assert!(run(&dir, &archive, SymlinkPolicy::Skip).is_err());
+            assert!(listing(&dir.join("root")).is_empty());
+        }
+        // what was there before is left alone, even when it's overwritten
+        let archive = dir.join("archive.zip");
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("models")).unwrap();
+        fs::write(root.join("models/kept.bin"), b"kept").unwrap();
+        fs::write(root.join("models/a.bin"), b"mine").unwrap();
+        zip(&archive, &entries);
+        let extraction = Extraction {
+            directory: root.clone(),
+            symlinks: SymlinkPolicy::Skip,
+        };
+        let cancel = CancellationToken::new();
+        assert!(extract(&archive, &extraction, &cancel, &mut |_, _| {}).is_err());
+        assert_eq!(
+            listing(&root),
+            ["models", "models/a.bin", "models/kept.bin"]
+        );
+    }
+
+    #[test]
+    fn resolves_link_targets() {
+        let dir = temp_dir("resolve");
+        fs::create_dir(dir.join("data")).unwrap();
+        let resolved = resolve(&dir, Path::new("data/../outside/./a")).unwrap();
+        assert_eq!(resolved, dir.join("outside/a"));
+        assert_eq!(resolve(&dir, &dir.join("data/..")).unwrap(), dir);
+        assert_eq!(resolve(&dir, Path::new("/..")), None);
+        assert_eq!(resolve(&dir, Path::new("missing/..")), None);
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(".", dir.join("data/here")).unwrap();
+            let resolved = resolve(&dir, Path::new("data/here/../outside")).unwrap();
+            assert_eq!(resolved, dir.join("outside"));
+        }
+    }
+
+    #[test]
+    fn names_default_directories() {
+        let dir = Path::new("downloads");
+        let directory = |name: &str| default_directory(&dir.join(name));
+        assert_eq!(directory("model.tar.gz"), Some(dir.join("model")));
+        assert_eq!(directory("Model.ZIP"), Some(dir.join("Model")));
+        assert_eq!(directory("data.v2.tzst"), Some(dir.join("data.v2")));
+        assert_eq!(directory(".zip"), None);
+        assert_eq!(directory("..tar.gz"), None);
+        assert_eq!(directory("model.bin"), None);
+    }
+}
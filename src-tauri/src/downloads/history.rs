@@ -0,0 +1,82 @@
+//! Downloads that finished or failed, kept in the [`Store`](super::Store)
+//! after they've left the queue, for as long as the [`HistoryRetention`]
+//! allows.
+
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{DownloadId, Status};
+
+/// A download as the history remembers it.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// Assigned by the store when it's added.
+    pub id: u64,
+    /// Of the download in the queue, which may be gone.
+    pub download_id: DownloadId,
+    pub url: String,
+    pub destination: PathBuf,
+    /// `Done`, `Failed` or `FailedVerification`.
+    pub status: Status,
+    /// Bytes, if it's known.
+    pub size: Option<u64>,
+    /// Seconds it was running for, over every attempt since the app started.
+    pub duration: f64,
+    /// Bytes per second received while it was running.
+    pub average_speed: f64,
+    /// Whether it matched its expected hash, if it had one.
+    pub verified: Option<bool>,
+    pub error: Option<String>,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// Totals of the entries matching a search.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryStats {
+    pub completed: u64,
+    pub failed: u64,
+    /// Bytes of the completed downloads.
+    pub bytes: u64,
+    /// Bytes per second, over every entry.
+    pub average_speed: f64,
+}
+
+/// A page of the history, newest first.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryPage {
+    /// How many entries match the search, on every page.
+    pub total: usize,
+    pub entries: Vec<HistoryEntry>,
+    pub stats: HistoryStats,
+}
+
+/// How much of the history is kept; everything if neither is set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryRetention {
+    pub max_age_days: Option<u32>,
+    /// The newest are kept.
+    pub max_entries: Option<u32>,
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        HistoryRetention {
+            max_age_days: Some(90),
+            max_entries: Some(10_000),
+        }
+    }
+}
+
+impl HistoryRetention {
+    /// When the oldest entry to keep may have finished, if there's a limit.
+    pub(super) fn cutoff(&self) -> Option<DateTime<Utc>> {
+        let days = self.max_age_days?;
+        Some(Utc::now() - chrono::Duration::days(days.into()))
+    }
+}
@@ -0,0 +1,306 @@
+//! Downloads imported in bulk, e.g. from another downloader: a list of URLs,
+//! one per line, a JSON manifest of `{ url, destination, sha256 }` objects or
+//! a CSV file with `url`, `destination` and `sha256` columns, the first row
+//! naming them. Only `url` is required, the file name being taken from it
+//! without a destination. Relative destinations go in the directory the
+//! import is given, and without one they aren't valid. Rows that aren't valid
+//! are reported by number rather than failing the whole import.
+
+use std::path::{Path, PathBuf};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use super::{DownloadId, ExpectedHash, HashAlgorithm};
+use crate::{
+    errors::{Context, Result},
+    reqwest_resume::content_disposition,
+};
+
+/// How the downloads to import are listed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportFormat {
+    /// A URL per line; blank lines and those starting with `#` are skipped.
+    Lines,
+    Json,
+    Csv,
+}
+
+/// A download to import, as it's listed.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct ImportRow {
+    pub url: String,
+    pub destination: Option<PathBuf>,
+    pub sha256: Option<String>,
+}
+
+/// A download to import, checked.
+#[derive(Debug)]
+pub(super) struct Import {
+    pub url: Url,
+    pub destination: PathBuf,
+    pub expected_hash: Option<ExpectedHash>,
+}
+
+/// Why a row wasn't imported.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportError {
+    /// Line of a list or CSV file, or 1-based index in a manifest.
+    pub row: usize,
+    pub error: String,
+}
+
+/// What [`DownloadManager::import`](super::DownloadManager::import) did.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResult {
+    pub queued: Vec<DownloadId>,
+    pub errors: Vec<ImportError>,
+}
+
+/// What `contents` looks like: JSON if it starts like it, CSV if its first
+/// line names a `url` column, a list of URLs otherwise.
+pub(super) fn detect(contents: &str) -> ImportFormat {
+    let contents = contents.trim_start();
+    let header = contents.lines().next().unwrap_or_default();
+    if contents.starts_with('[') {
+        ImportFormat::Json
+    } else if (csv_fields(header).iter()).any(|field| field.eq_ignore_ascii_case("url")) {
+        ImportFormat::Csv
+    } else {
+        ImportFormat::Lines
+    }
+}
+
+/// The rows of `contents`, numbered, checked, and with relative destinations
+/// put in `directory` if it's given; or why they're invalid. Fails if
+/// `contents` isn't `format` at all.
+pub(super) fn parse(
+    contents: &str,
+    format: ImportFormat,
+    directory: Option<&Path>,
+) -> Result<Vec<(usize, std::result::Result<Import, String>)>> {
+    let rows = match format {
+        ImportFormat::Lines => (contents.lines().zip(1..))
+            .map(|(line, row)| (row, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(row, line)| {
+                let import = ImportRow {
+                    url: line.to_owned(),
+                    ..ImportRow::default()
+                };
+                (row, Ok(import))
+            })
+            .collect(),
+        ImportFormat::Json => {
+            let rows: Vec<serde_json::Value> =
+                serde_json::from_str(contents).with_context(|| "Invalid JSON manifest")?;
+            (rows.into_iter().zip(1..))
+                .map(|(value, row)| {
+                    (
+                        row,
+                        serde_json::from_value(value).map_err(|e| e.to_string()),
+                    )
+                })
+                .collect()
+        }
+        ImportFormat::Csv => parse_csv(contents)?,
+    };
+    let rows = rows.into_iter().map(|(row, import)| {
+        let import = import.and_then(|import: ImportRow| check(import, directory));
+        (row, import)
+    });
+    Ok(rows.collect())
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<(usize, std::result::Result<ImportRow, String>)>> {
+    let mut lines = (contents.lines().zip(1..)).filter(|(line, _)| !line.trim().is_empty());
+    let Some((header, _)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    let header = csv_fields(header);
+    let column = |name: &str| (header.iter()).position(|field| field.eq_ignore_ascii_case(name));
+    let url = column("url").with_context(|| "The CSV file has no url column")?;
+    let (destination, sha256) = (column("destination"), column("sha256"));
+    let rows = lines.map(|(line, row)| {
+        let fields = csv_fields(line);
+        let field = |index: Option<usize>| {
+            let field = fields.get(index?)?;
+            (!field.is_empty()).then(|| field.clone())
+        };
+        let import = match field(Some(url)) {
+            Some(url) => Ok(ImportRow {
+                url,
+                destination: field(destination).map(PathBuf::from),
+                sha256: field(sha256),
+            }),
+            None => Err("The url is missing".to_owned()),
+        };
+        (row, import)
+    });
+    Ok(rows.collect())
+}
+
+/// The fields of a line of CSV, trimmed, with quotes around them removed and
+/// doubled ones inside them unescaped.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_owned()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_owned());
+    fields
+}
+
+fn check(import: ImportRow, directory: Option<&Path>) -> std::result::Result<Import, String> {
+    let url = Url::parse(import.url.trim()).map_err(|err| format!("Invalid url: {err}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Can't download {url}, it isn't HTTP"));
+    }
+    let destination = match import.destination {
+        Some(destination) => destination,
+        None => content_disposition::from_url(&url)
+            .map(PathBuf::from)
+            .ok_or("The url has no file name, give it a destination")?,
+    };
+    let destination = match directory {
+        Some(directory) if destination.is_relative() => directory.join(destination),
+        // it would end up wherever the app happens to run from
+        None if destination.is_relative() => {
+            return Err(format!("{destination:?} is relative, give it a directory"));
+        }
+        _ => destination,
+    };
+    let expected_hash = import.sha256.map(|hex| ExpectedHash {
+        algorithm: HashAlgorithm::Sha256,
+        hex: hex.trim().to_owned(),
+    });
+    if let Some(hash) = expected_hash.as_ref().filter(|hash| !hash.is_valid()) {
+        return Err(format!("Invalid SHA-256 digest {:?}", hash.hex));
+    }
+    Ok(Import {
+        url,
+        destination,
+        expected_hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_fields, detect, parse, ImportFormat};
+
+    /// The rows of `contents` that aren't valid, with why.
+    fn invalid_rows(contents: &str, format: ImportFormat) -> Vec<(usize, String)> {
+        let directory = std::env::temp_dir();
+        let rows = parse(contents, format, Some(&directory)).unwrap();
+        (rows.into_iter())
+            .filter_map(|(row, import)| Some((row, import.err()?)))
+            .collect()
+    }
+
+    #[test]
+    fn detects_formats() {
+        let json = "\n  [{\"url\": \"https://example.com/a.bin\"}]";
+        assert_eq!(detect(json), ImportFormat::Json);
+        let csv = "Destination,URL\na.bin,https://example.com/a.bin";
+        assert_eq!(detect(csv), ImportFormat::Csv);
+        let lines = "https://example.com/a.bin\nhttps://example.com/b.bin";
+        assert_eq!(detect(lines), ImportFormat::Lines);
+        assert_eq!(detect(""), ImportFormat::Lines);
+    }
+
+    #[test]
+    fn unquotes_csv_fields() {
+        let line = r#" a , "b, c" ,"say ""hi""","" "#;
+        assert_eq!(csv_fields(line), ["a", "b, c", r#"say "hi""#, ""]);
+        assert_eq!(csv_fields(""), [""]);
+    }
+
+    #[test]
+    fn parses_csv() {
+        let directory = std::env::temp_dir();
+        let absolute = directory.join("elsewhere").join("b.bin");
+        let csv = format!(
+            "url,sha256,destination\n\n\"https://example.com/a,b\",,sub/a.bin\n\
+            https://example.com/b.bin,,\"{}\"\nhttps://example.com/c.bin\n",
+            absolute.display()
+        );
+        let rows = parse(&csv, ImportFormat::Csv, Some(&directory)).unwrap();
+        let rows: Vec<_> = (rows.into_iter())
+            .map(|(row, import)| {
+                let import = import.unwrap();
+                (row, import.url.to_string(), import.destination)
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (
+                    3,
+                    "https://example.com/a,b".to_owned(),
+                    directory.join("sub/a.bin")
+                ),
+                (4, "https://example.com/b.bin".to_owned(), absolute),
+                (
+                    5,
+                    "https://example.com/c.bin".to_owned(),
+                    directory.join("c.bin")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_csv_without_a_url_column() {
+        let csv = "link,destination\nhttps://example.com/a.bin,a.bin";
+        assert!(parse(csv, ImportFormat::Csv, None).is_err());
+    }
+
+    #[test]
+    fn reports_invalid_rows() {
+        let lines = "# mirrors\nnot a url\n\nftp://example.com/a.bin\nhttps://example.com/\n";
+        let rows = invalid_rows(lines, ImportFormat::Lines);
+        assert_eq!(
+            rows.iter().map(|(row, _)| *row).collect::<Vec<_>>(),
+            [2, 4, 5]
+        );
+        assert!(rows[0].1.starts_with("Invalid url"), "{}", rows[0].1);
+        assert!(rows[1].1.contains("isn't HTTP"), "{}", rows[1].1);
+
+        let json = r#"[
+            {"url": "https://example.com/a.bin", "sha256": "xyz"},
+            {"url": 5},
+            {"url": "https://example.com/b.bin"}
+        ]"#;
+        let rows = invalid_rows(json, ImportFormat::Json);
+        assert_eq!(rows.iter().map(|(row, _)| *row).collect::<Vec<_>>(), [1, 2]);
+        assert!(rows[0].1.starts_with("Invalid SHA-256"), "{}", rows[0].1);
+    }
+
+    #[test]
+    fn rejects_relative_destinations_without_a_directory() {
+        let rows = parse("https://example.com/a.bin", ImportFormat::Lines, None).unwrap();
+        assert!(rows[0].1.is_err());
+        let destination = std::env::temp_dir().join("a.bin");
+        let json = serde_json::json!([{
+            "url": "https://example.com/a.bin",
+            "destination": destination,
+        }]);
+        let rows = parse(&json.to_string(), ImportFormat::Json, None).unwrap();
+        assert_eq!(rows[0].1.as_ref().unwrap().destination, destination);
+    }
+}
@@ -0,0 +1,95 @@
+//! Whether the internet connection is metered, like a phone's hotspot or a
+//! cellular modem, so big downloads can wait for Wi-Fi or Ethernet and the
+//! others be slowed down, see [`MeteredPolicy`].
+//!
+//! Windows and macOS say so; elsewhere it's up to the user to.
+
+use serde::{Deserialize, Serialize};
+
+/// What the downloads do while the connection is metered. By default, nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeteredPolicy {
+    /// Whether the connection is metered whatever the OS says, if it's set.
+    pub metered: Option<bool>,
+    /// Downloads bigger than this many bytes wait for an unmetered connection.
+    pub max_size: Option<u64>,
+    /// Bytes per second every download together is capped at.
+    pub bandwidth_limit: Option<u64>,
+}
+
+/// Whether the OS says the connection is metered, if it knows.
+#[cfg(windows)]
+pub(super) fn detect() -> Option<bool> {
+    use windows::Networking::Connectivity::{NetworkCostType, NetworkInformation};
+
+    // fails when there's no connection
+    let profile = NetworkInformation::GetInternetConnectionProfile().ok()?;
+    let cost = profile.GetConnectionCost().ok()?;
+    let kind = cost.NetworkCostType().ok()?;
+    let limited = matches!(kind, NetworkCostType::Fixed | NetworkCostType::Variable);
+    Some(limited || cost.Roaming().unwrap_or(false) || cost.OverDataLimit().unwrap_or(false))
+}
+
+#[cfg(target_os = "macos")]
+pub(super) use self::apple::detect;
+
+#[cfg(not(any(windows, target_os = "macos")))]
+pub(super) fn detect() -> Option<bool> {
+    None
+}
+
+/// Network.framework only reports the state of the connection to a monitor,
+/// which is started the first time it's asked for and keeps it here.
+#[cfg(target_os = "macos")]
+mod apple {
+    use std::{
+        ffi::c_void,
+        sync::{
+            atomic::{AtomicU8, Ordering},
+            Once,
+        },
+    };
+
+    use block2::{Block, RcBlock};
+
+    const UNKNOWN: u8 = 2;
+    static METERED: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+    #[link(name = "Network", kind = "framework")]
+    extern "C" {
+        fn nw_path_monitor_create() -> *mut c_void;
+        fn nw_path_monitor_set_update_handler(
+            monitor: *mut c_void,
+            handler: &Block<dyn Fn(*mut c_void)>,
+        );
+        fn nw_path_monitor_set_queue(monitor: *mut c_void, queue: *mut c_void);
+        fn nw_path_monitor_start(monitor: *mut c_void);
+        fn nw_path_is_expensive(path: *mut c_void) -> bool;
+        fn nw_path_is_constrained(path: *mut c_void) -> bool;
+    }
+
+    extern "C" {
+        fn dispatch_get_global_queue(identifier: isize, flags: usize) -> *mut c_void;
+    }
+
+    pub fn detect() -> Option<bool> {
+        static START: Once = Once::new();
+        START.call_once(|| unsafe {
+            let handler = RcBlock::new(|path: *mut c_void| {
+                // constrained is Low Data Mode
+                let metered = nw_path_is_expensive(path) || nw_path_is_constrained(path);
+                METERED.store(metered as u8, Ordering::Relaxed);
+            });
+            // never released, it runs for as long as the app does
+            let monitor = nw_path_monitor_create();
+            nw_path_monitor_set_update_handler(monitor, &handler);
+            nw_path_monitor_set_queue(monitor, dispatch_get_global_queue(0, 0));
+            nw_path_monitor_start(monitor);
+        });
+        match METERED.load(Ordering::Relaxed) {
+            UNKNOWN => None,
+            metered => Some(metered == 1),
+        }
+    }
+}
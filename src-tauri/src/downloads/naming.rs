@@ -0,0 +1,218 @@
+//! What downloads are named: a [`NamingSettings::template`] for the file
+//! name, and what to do when that's taken by a file already there or by
+//! another download, see [`ConflictPolicy`].
+
+use std::path::{Component, Path, PathBuf};
+
+use chrono::Local;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    err,
+    errors::{Context, Result},
+};
+
+/// How downloads are named.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NamingSettings {
+    /// File name the destination's is replaced with, which may put it in a
+    /// subdirectory. `{basename}` is the name it was given, `{date}` today's
+    /// date, `{host}` the host of its URL and `{hash8}` the first 8 digits of
+    /// its expected hash, or of the SHA-256 of its URL without one.
+    pub template: Option<String>,
+    pub conflicts: ConflictPolicy,
+}
+
+/// What to do when a download's destination is taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictPolicy {
+    /// Add ` (1)`, ` (2)`… to the name until it isn't taken.
+    #[default]
+    Rename,
+    /// Replace the file once the download is complete. One another download
+    /// is still writing to is renamed anyway.
+    Overwrite,
+    Skip,
+    /// Leave it to the frontend, to start it again with another policy.
+    Ask,
+}
+
+const PLACEHOLDERS: &[&str] = &["basename", "date", "host", "hash8"];
+
+/// Fail unless `template` only has known placeholders and names a file
+/// inside the destination's directory.
+pub(super) fn validate(template: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let len = (rest[start..].find('}'))
+            .with_context(|| format!("Unclosed placeholder in {template:?}"))?;
+        let name = &rest[start + 1..start + len];
+        if !PLACEHOLDERS.contains(&name) {
+            err!("Unknown placeholder {{{name}}} in {template:?}");
+        }
+        rest = &rest[start + len + 1..];
+    }
+    let path = Path::new(template);
+    let inside = (path.components()).all(|component| matches!(component, Component::Normal(_)));
+    if template.is_empty() || !inside {
+        err!("{template:?} must name a file inside the download directory");
+    }
+    Ok(())
+}
+
+/// `destination` with its file name replaced by `template`, filled in for a
+/// download of `url` whose hash is `hash` if it's known. Keeps the file name
+/// if nothing's left of it.
+pub(super) fn apply(template: &str, destination: &Path, url: &Url, hash: Option<&str>) -> PathBuf {
+    let basename = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let hash8 = match hash {
+        Some(hash) => hash.to_ascii_lowercase(),
+        None => (Sha256::digest(url.as_str().as_bytes()).iter())
+            .map(|byte| format!("{byte:02x}"))
+            .collect(),
+    };
+    let name = template
+        .replace("{basename}", &basename)
+        .replace("{date}", &Local::now().format("%Y-%m-%d").to_string())
+        .replace("{host}", url.host_str().unwrap_or("unknown"))
+        .replace("{hash8}", hash8.get(..8).unwrap_or(&hash8));
+    // nor can what's filled in lead elsewhere
+    let mut name: PathBuf = Path::new(&name)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect();
+    if name.as_os_str().is_empty() {
+        name = PathBuf::from(&*basename);
+    }
+    match destination.parent() {
+        Some(dir) => dir.join(name),
+        None => name,
+    }
+}
+
+/// `destination`, or the first of `name (1).ext`, `name (2).ext`… next to it
+/// for which `taken` is false.
+pub(super) fn unique_destination(destination: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    if !taken(destination) {
+        return destination.to_owned();
+    }
+    let name = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    // after the `.tar` of a `.tar.gz`, so it's still recognized as one
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => match stem.strip_suffix(".tar") {
+            Some(stem) if !stem.is_empty() => (stem, Some(format!("tar.{ext}"))),
+            _ => (stem, Some(ext.to_owned())),
+        },
+        _ => (&*name, None),
+    };
+    (1..)
+        .map(|n| {
+            let name = match &extension {
+                Some(ext) => format!("{stem} ({n}).{ext}"),
+                None => format!("{stem} ({n})"),
+            };
+            destination.with_file_name(name)
+        })
+        .find(|path| !taken(path))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, unique_destination, validate};
+    use reqwest::Url;
+    use std::path::{Path, PathBuf};
+
+    fn url() -> Url {
+        Url::parse("https://example.com/files/a.zip").unwrap()
+    }
+
+    #[test]
+    fn validates_templates() {
+        assert!(validate("{date} {basename}").is_ok());
+        assert!(validate("{host}/{hash8}-{basename}").is_ok());
+        assert!(validate("{size}").is_err());
+        assert!(validate("{basename").is_err());
+        assert!(validate("").is_err());
+        assert!(validate("../{basename}").is_err());
+        assert!(validate("/tmp/{basename}").is_err());
+        assert!(validate("./{basename}").is_err());
+    }
+
+    #[test]
+    fn fills_in_placeholders() {
+        let destination = Path::new("downloads").join("a.zip");
+        let named = apply(
+            "{host}/{hash8}-{basename}",
+            &destination,
+            &url(),
+            Some("ABCDEF0123"),
+        );
+        assert_eq!(named, Path::new("downloads/example.com/abcdef01-a.zip"));
+        // the SHA-256 of the URL without a hash
+        let named = apply("{hash8}", &destination, &url(), None);
+        let name = named.file_name().unwrap().to_str().unwrap();
+        assert_eq!(name.len(), 8);
+        assert!(name.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(named, apply("{hash8}", &destination, &url(), None));
+    }
+
+    #[test]
+    fn strips_traversal() {
+        let destination = Path::new("downloads").join("a.zip");
+        let named = apply("../../{basename}", &destination, &url(), None);
+        assert_eq!(named, Path::new("downloads/a.zip"));
+        let named = apply("/etc/{basename}", &destination, &url(), None);
+        assert_eq!(named, Path::new("downloads/etc/a.zip"));
+    }
+
+    #[test]
+    fn keeps_the_name_when_nothing_is_left() {
+        let destination = Path::new("downloads").join("a.zip");
+        assert_eq!(apply("..", &destination, &url(), None), destination);
+        assert_eq!(apply("./", &destination, &url(), None), destination);
+    }
+
+    #[test]
+    fn numbers_taken_destinations() {
+        let taken = |taken: &'static [&'static str]| {
+            move |path: &Path| taken.iter().any(|taken| path == Path::new(taken))
+        };
+        let unique = |path: &str, in_use| unique_destination(Path::new(path), taken(in_use));
+        assert_eq!(unique("d/a.zip", &[]), PathBuf::from("d/a.zip"));
+        assert_eq!(
+            unique("d/a.zip", &["d/a.zip"]),
+            PathBuf::from("d/a (1).zip")
+        );
+        assert_eq!(
+            unique("d/a.zip", &["d/a.zip", "d/a (1).zip"]),
+            PathBuf::from("d/a (2).zip")
+        );
+        assert_eq!(
+            unique("d/a.tar.gz", &["d/a.tar.gz"]),
+            PathBuf::from("d/a (1).tar.gz")
+        );
+        assert_eq!(
+            unique("d/README", &["d/README"]),
+            PathBuf::from("d/README (1)")
+        );
+        assert_eq!(
+            unique("d/.bashrc", &["d/.bashrc"]),
+            PathBuf::from("d/.bashrc (1)")
+        );
+        assert_eq!(
+            unique("d/.tar.gz", &["d/.tar.gz"]),
+            PathBuf::from("d/.tar (1).gz")
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Watching the network, so downloads are held while the machine is offline
+//! or behind a captive portal instead of burning through their retries, and
+//! picked up again once it's back.
+//!
+//! The OS reports network interfaces coming and going; whether that means the
+//! internet can be reached is found out by fetching a URL that answers
+//! `204 No Content` unless a captive portal intercepts it, such as
+//! [`PROBE_URL`]. That's a request to a third party, so it's only made once
+//! [`DownloadManager::set_connectivity_check`] turns it on. The URL may be
+//! blocked where the rest of the internet isn't, so a failed check only holds
+//! the queue after an interface went down.
+
+use std::time::Duration;
+
+use futures::{future, StreamExt};
+use if_watch::{tokio::IfWatcher, IfEvent};
+use reqwest::{redirect, StatusCode, Url};
+use serde::Serialize;
+use tauri::async_runtime;
+
+use super::{metered, DownloadManager};
+use crate::logerr;
+
+/// A connectivity check to turn on. Over HTTPS, a captive portal can't answer
+/// in its place, so it shows up as being offline instead.
+pub const PROBE_URL: &str = "https://www.gstatic.com/generate_204";
+
+/// How often the network is probed when the OS reports no changes.
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+const OFFLINE_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time for a burst of interface changes to settle before probing.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NetworkState {
+    #[default]
+    Online,
+    Offline,
+    /// Connected, but a login page answers instead of the internet.
+    CaptivePortal,
+}
+
+/// Probe the network whenever an interface changes, or every
+/// [`PROBE_INTERVAL`], and tell `manager` what was found.
+pub(super) fn watch(manager: DownloadManager) {
+    async_runtime::spawn(async move {
+        let client = match reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .redirect(redirect::Policy::none())
+            .build()
+        {
+            Ok(client) => client,
+            Err(err) => {
+                log::error!("can't watch the network: {}", err);
+                return;
+            }
+        };
+        let mut watcher = IfWatcher::new()
+            .map_err(|err| log::warn!("can't watch network interfaces: {}", err))
+            .ok();
+        // since the check last succeeded; without that, it failing more likely
+        // means it's blocked than that the internet can't be reached
+        let mut went_down = false;
+        loop {
+            let state = match manager.connectivity_check() {
+                Some(url) => match probe(&client, url).await {
+                    NetworkState::Online => {
+                        went_down = false;
+                        NetworkState::Online
+                    }
+                    state if went_down => state,
+                    state => {
+                        log::debug!("ignoring {:?}, as no interface went down", state);
+                        NetworkState::Online
+                    }
+                },
+                None => NetworkState::Online,
+            };
+            manager.set_network_state(state);
+            // which changes with the network as well
+            manager.set_os_metered(metered::detect());
+            let interval = match state {
+                NetworkState::Online => PROBE_INTERVAL,
+                _ => OFFLINE_PROBE_INTERVAL,
+            };
+            let changed = async {
+                match &mut watcher {
+                    Some(watcher) => {
+                        if let Some(event) = watcher.next().await {
+                            went_down |= matches!(event, Ok(IfEvent::Down(_)));
+                            logerr!(event.map(|event| log::debug!("{:?}", event)));
+                        }
+                        tokio::time::sleep(SETTLE_TIME).await;
+                    }
+                    None => future::pending().await,
+                }
+            };
+            let _ = tokio::time::timeout(interval, changed).await;
+        }
+    });
+}
+
+async fn probe(client: &reqwest::Client, url: Url) -> NetworkState {
+    match client.get(url).send().await {
+        Ok(response) if response.status() == StatusCode::NO_CONTENT => NetworkState::Online,
+        Ok(response) => {
+            log::debug!("connectivity check answered {}", response.status());
+            NetworkState::CaptivePortal
+        }
+        Err(err) => {
+            log::debug!("connectivity check failed: {}", err);
+            NetworkState::Offline
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Native notifications when downloads finish or fail, sent by the manager so
+//! they're shown while the window is hidden too.
+//!
+//! Tauri doesn't report clicks on notifications, so revealing the file is up
+//! to the `reveal_download` command.
+
+use std::{path::Path, process::Command};
+
+use serde::{Deserialize, Serialize};
+use tauri::{api::notification::Notification, AppHandle};
+
+use super::Status;
+use crate::{
+    errors::{Context, Result},
+    logerr,
+};
+
+/// Which notifications are shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationSettings {
+    pub completed: bool,
+    /// Including downloads that failed their verification.
+    pub failed: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            completed: true,
+            failed: true,
+        }
+    }
+}
+
+/// Tell the user a download to `destination` is now `status`, if that's the
+/// end of it and they want to know.
+pub(super) fn notify(
+    app: &AppHandle,
+    settings: NotificationSettings,
+    destination: &Path,
+    status: Status,
+    error: Option<&str>,
+) {
+    let name = destination
+        .file_name()
+        .unwrap_or(destination.as_os_str())
+        .to_string_lossy();
+    let (title, body) = match status {
+        Status::Done if settings.completed => ("Download complete", name.into_owned()),
+        Status::Failed | Status::FailedVerification if settings.failed => (
+            "Download failed",
+            match error {
+                Some(error) => format!("{name}: {error}"),
+                None => name.into_owned(),
+            },
+        ),
+        _ => return,
+    };
+    logerr!(Notification::new(&app.config().tauri.bundle.identifier)
+        .title(title)
+        .body(body)
+        .show());
+}
+
+/// Show `path` in the file manager, selected if the platform allows it.
+pub(super) fn reveal(path: &Path) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut command = {
+        let mut command = Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    };
+    #[cfg(windows)]
+    let mut command = {
+        let mut select = std::ffi::OsString::from("/select,");
+        select.push(path);
+        let mut command = Command::new("explorer");
+        command.arg(select);
+        command
+    };
+    // file managers don't agree on how to select a file
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let mut command = {
+        let dir = if path.is_dir() {
+            path
+        } else {
+            path.parent().unwrap_or(path)
+        };
+        let mut command = Command::new("xdg-open");
+        command.arg(dir);
+        command
+    };
+    command
+        .spawn()
+        .with_context(|| format!("Failed to reveal {path:?}"))?;
+    Ok(())
+}
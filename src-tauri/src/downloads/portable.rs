@@ -0,0 +1,169 @@
+//! The queue and its settings as a JSON file, for moving the downloads that
+//! aren't done yet to another computer. What they've downloaded so far stays
+//! behind, so they start over there. Their paths can be moved from one base
+//! folder to another on the way, see [`BaseFolder`].
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Categories, Category, ExpectedHash, Extraction, HistoryRetention, MeteredPolicy,
+    NamingSettings, NotificationSettings, Priority, TimeWindow,
+};
+
+/// Version of the format written, bumped when older apps can't read it.
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueExport {
+    /// [`FORMAT_VERSION`] of the app that wrote it.
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub settings: ExportedSettings,
+    /// In the queue's order.
+    pub downloads: Vec<ExportedDownload>,
+}
+
+/// What the setters of the [`DownloadManager`](super::DownloadManager) were
+/// given, apart from machine-specific ones like the chunk store.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedSettings {
+    pub max_concurrent: usize,
+    pub max_per_host: usize,
+    pub min_free_space: u64,
+    pub bandwidth_limit: Option<u64>,
+    pub metered_policy: MeteredPolicy,
+    pub time_windows: Vec<TimeWindow>,
+    pub naming: NamingSettings,
+    pub categories: Categories,
+    pub notifications: NotificationSettings,
+    pub history_retention: HistoryRetention,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedDownload {
+    pub url: String,
+    pub destination: PathBuf,
+    /// Whether it was paused or had failed, and is only to be resumed by hand.
+    #[serde(default)]
+    pub stopped: bool,
+    #[serde(default)]
+    pub category: Category,
+    #[serde(default)]
+    pub priority: Priority,
+    pub bandwidth_limit: Option<u64>,
+    pub expected_hash: Option<ExpectedHash>,
+    pub extraction: Option<Extraction>,
+    pub start_at: Option<DateTime<Utc>>,
+    pub chunk_index: Option<String>,
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+}
+
+/// Puts the paths in `from` in `to` instead, e.g. from `C:\Users\me\Models`
+/// to `/home/me/models`. Either kind of separator is understood in `from`,
+/// so paths from Windows can be moved elsewhere and the other way around.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaseFolder {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl BaseFolder {
+    /// `path` moved to `to`, if it's in `from`. Paths with `.` or `..` after
+    /// `from` are left alone, since those may well lead out of it again.
+    fn remap(&self, path: &Path) -> Option<PathBuf> {
+        let normalize = |path: &Path| path.to_string_lossy().replace('\\', "/");
+        let (path, from) = (normalize(path), normalize(&self.from));
+        let rest = path.strip_prefix(from.trim_end_matches('/'))?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        let mut remapped = self.to.clone();
+        for component in rest.split('/').filter(|component| !component.is_empty()) {
+            if matches!(component, "." | "..") {
+                return None;
+            }
+            remapped.push(component);
+        }
+        Some(remapped)
+    }
+
+    fn apply(&self, path: &mut PathBuf) {
+        if let Some(remapped) = self.remap(path) {
+            *path = remapped;
+        }
+    }
+}
+
+impl QueueExport {
+    /// Move the destinations and the directories of extractions and
+    /// categories that are in `base.from`.
+    pub(super) fn remap(&mut self, base: &BaseFolder) {
+        for download in &mut self.downloads {
+            base.apply(&mut download.destination);
+            if let Some(extraction) = &mut download.extraction {
+                base.apply(&mut extraction.directory);
+            }
+        }
+        let settings = self.settings.categories.settings.values_mut();
+        for directory in settings.filter_map(|settings| settings.directory.as_mut()) {
+            base.apply(directory);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::BaseFolder;
+
+    fn base(from: &str, to: &str) -> BaseFolder {
+        BaseFolder {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    #[test]
+    fn remaps_windows_paths() {
+        let base = base(r"C:\Users\me\Models", "/home/me/models");
+        let path = PathBuf::from(r"C:\Users\me\Models\llama\a.gguf");
+        let expected = PathBuf::from("/home/me/models")
+            .join("llama")
+            .join("a.gguf");
+        assert_eq!(base.remap(&path), Some(expected));
+        let path = PathBuf::from(r"C:\Users\me\Models\..\Documents\a.txt");
+        assert_eq!(base.remap(&path), None);
+    }
+
+    #[test]
+    fn remaps_with_a_trailing_slash() {
+        let base = base("/a/b/", "/c");
+        assert_eq!(
+            base.remap("/a/b/d.bin".as_ref()),
+            Some(PathBuf::from("/c/d.bin"))
+        );
+        assert_eq!(base.remap("/a/b".as_ref()), Some(PathBuf::from("/c")));
+        assert_eq!(base.remap("/a/b/./d.bin".as_ref()), None);
+    }
+
+    #[test]
+    fn leaves_siblings_alone() {
+        let base = base("/a/b", "/c");
+        assert_eq!(base.remap("/a/bc/d.bin".as_ref()), None);
+        assert_eq!(base.remap("/a/bc".as_ref()), None);
+        assert_eq!(base.remap("/d/a/b".as_ref()), None);
+        assert_eq!(base.remap("/a/b/../bc/d.bin".as_ref()), None);
+        let mut path = PathBuf::from("/a/bc");
+        base.apply(&mut path);
+        assert_eq!(path, PathBuf::from("/a/bc"));
+    }
+}
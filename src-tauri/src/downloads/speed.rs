@@ -0,0 +1,50 @@
+//! The speed of each running download, sampled every [`SAMPLE_INTERVAL`] for
+//! the frontend to chart. Only the last [`SpeedHistory::CAPACITY`] samples
+//! are kept, and only in memory; there are none while it isn't running.
+
+use std::{collections::VecDeque, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// How often the speeds of running downloads are sampled.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpeedSample {
+    pub at: DateTime<Utc>,
+    /// Bytes per second over the last few seconds.
+    pub speed: f64,
+}
+
+/// The latest speed samples of a download, oldest first.
+#[derive(Clone, Debug, Default)]
+pub(super) struct SpeedHistory {
+    samples: VecDeque<SpeedSample>,
+}
+
+impl SpeedHistory {
+    /// Ten minutes' worth.
+    pub const CAPACITY: usize = 600;
+
+    pub fn push(&mut self, speed: f64) {
+        if self.samples.len() >= Self::CAPACITY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(SpeedSample {
+            at: Utc::now(),
+            speed,
+        });
+    }
+
+    /// The samples from the last `window`, or all of them.
+    pub fn since(&self, window: Option<Duration>) -> Vec<SpeedSample> {
+        let start =
+            window.and_then(|window| Some(Utc::now() - chrono::Duration::from_std(window).ok()?));
+        (self.samples.iter())
+            .filter(|sample| start.is_none_or(|start| sample.at >= start))
+            .copied()
+            .collect()
+    }
+}
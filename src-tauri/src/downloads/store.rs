@@ -0,0 +1,826 @@
+//! The download queue saved in SQLite, so it survives the app restarting or
+//! crashing. The `.part` files' sidecars stay the source of truth for
+//! resuming; the position and validators here are copied from them whenever
+//! a download stops, the ETag having been learned when it was queued until
+//! then.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, Row};
+
+use super::{
+    Category, DownloadId, ExpectedHash, Extraction, HashAlgorithm, HistoryEntry, HistoryPage,
+    HistoryRetention, HistoryStats, Priority, Status, TransferLog,
+};
+use crate::reqwest_resume::Sidecar;
+
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+/// Changes to the schema, the first of them taking it to `user_version` 1.
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE downloads ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal';
+    ALTER TABLE downloads ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+    UPDATE downloads SET position = id;",
+    "ALTER TABLE downloads ADD COLUMN bandwidth_limit INTEGER;",
+    "ALTER TABLE downloads ADD COLUMN expected_hash TEXT;",
+    "ALTER TABLE downloads ADD COLUMN extraction TEXT;",
+    "ALTER TABLE downloads ADD COLUMN start_at INTEGER;",
+    "ALTER TABLE downloads ADD COLUMN final_url TEXT;",
+    "ALTER TABLE downloads ADD COLUMN chunk_index TEXT;",
+    "ALTER TABLE downloads ADD COLUMN category TEXT NOT NULL DEFAULT 'other';",
+    "CREATE TABLE history (
+        id INTEGER PRIMARY KEY,
+        download_id INTEGER NOT NULL,
+        url TEXT NOT NULL,
+        destination TEXT NOT NULL,
+        status TEXT NOT NULL,
+        size INTEGER,
+        duration REAL NOT NULL,
+        average_speed REAL NOT NULL,
+        verified INTEGER,
+        error TEXT,
+        finished_at INTEGER NOT NULL
+    );
+    CREATE INDEX history_finished_at ON history (finished_at);
+    CREATE INDEX history_url ON history (url);",
+    "ALTER TABLE downloads ADD COLUMN log TEXT;",
+    "ALTER TABLE downloads ADD COLUMN mirrors TEXT;",
+];
+
+/// A download as saved in the database.
+#[derive(Debug)]
+pub(super) struct Record {
+    pub id: DownloadId,
+    pub url: String,
+    /// Where `url` redirected to, if that's somewhere else.
+    pub final_url: Option<String>,
+    pub destination: PathBuf,
+    pub status: Status,
+    pub category: Category,
+    pub priority: Priority,
+    /// Where it is in the queue, which is ordered by this and then by id.
+    pub position: u64,
+    /// Bytes per second it's capped at, besides the global limit.
+    pub bandwidth_limit: Option<u64>,
+    pub expected_hash: Option<ExpectedHash>,
+    /// Saved as JSON.
+    pub extraction: Option<Extraction>,
+    /// Saved in milliseconds since the epoch.
+    pub start_at: Option<DateTime<Utc>>,
+    /// URL of its chunk index.
+    pub chunk_index: Option<String>,
+    /// Saved as JSON.
+    pub mirrors: Vec<String>,
+    pub error: Option<String>,
+    /// Bytes on disk when the download last stopped.
+    pub pos: u64,
+    pub total: Option<u64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Saved as JSON, once there's anything in it.
+    pub log: TransferLog,
+}
+
+impl Store {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS downloads (
+                id INTEGER PRIMARY KEY,
+                url TEXT NOT NULL,
+                destination TEXT NOT NULL,
+                status TEXT NOT NULL,
+                error TEXT,
+                pos INTEGER NOT NULL DEFAULT 0,
+                total INTEGER,
+                etag TEXT,
+                last_modified TEXT
+            );",
+        )?;
+        // columns added since, one migration per schema version, each applied
+        // along with its version or not at all
+        let current: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (migration, version) in MIGRATIONS.iter().zip(1..).skip(current) {
+            let transaction = conn.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.execute_batch(&format!("PRAGMA user_version = {version}"))?;
+            transaction.commit()?;
+        }
+        Ok(Store {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// A database that's forgotten when the app exits.
+    pub fn in_memory() -> rusqlite::Result<Self> {
+        Self::open(Path::new(":memory:"))
+    }
+
+    /// Every saved download, in the order they were started.
+    pub(super) fn load(&self) -> rusqlite::Result<Vec<Record>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT id, url, destination, status, error, pos, total, etag, last_modified,
+                priority, position, bandwidth_limit, expected_hash, extraction, start_at,
+                final_url, chunk_index, category, log, mirrors
+            FROM downloads ORDER BY position, id",
+        )?;
+        let records = statement.query_map([], Record::from_row)?;
+        records.collect()
+    }
+
+    pub(super) fn insert(&self, record: &Record) -> rusqlite::Result<()> {
+        self.insert_all(std::slice::from_ref(record))
+    }
+
+    /// Insert `records` all at once, or none of them if one fails.
+    pub(super) fn insert_all(&self, records: &[Record]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        for record in records {
+            insert(&transaction, record)?;
+        }
+        transaction.commit()
+    }
+
+    pub(super) fn set_priority(&self, id: DownloadId, priority: Priority) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE downloads SET priority = ?2 WHERE id = ?1",
+            params![id, priority.as_str()],
+        )?;
+        Ok(())
+    }
+
+    pub(super) fn set_bandwidth_limit(
+        &self,
+        id: DownloadId,
+        bytes_per_sec: Option<u64>,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE downloads SET bandwidth_limit = ?2 WHERE id = ?1",
+            params![id, bytes_per_sec],
+        )?;
+        Ok(())
+    }
+
+    /// Save the order of the queue, `ids` being every download in order.
+    pub(super) fn set_order(&self, ids: &[DownloadId]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        for (position, id) in ids.iter().enumerate() {
+            transaction.execute(
+                "UPDATE downloads SET position = ?2 WHERE id = ?1",
+                params![id, position],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    pub(super) fn set_status(
+        &self,
+        id: DownloadId,
+        status: Status,
+        error: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE downloads SET status = ?2, error = ?3 WHERE id = ?1",
+            params![id, status.as_str(), error],
+        )?;
+        Ok(())
+    }
+
+    /// Copy where download `id` can resume from out of its sidecar.
+    pub(super) fn set_resume_state(
+        &self,
+        id: DownloadId,
+        sidecar: &Sidecar,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE downloads SET pos = ?2, total = ?3, etag = ?4, last_modified = ?5
+            WHERE id = ?1",
+            params![
+                id,
+                sidecar.pos,
+                sidecar.total,
+                sidecar.etag,
+                sidecar.last_modified,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Record how far download `id` got, e.g. once it's done.
+    pub(super) fn set_position(
+        &self,
+        id: DownloadId,
+        pos: u64,
+        total: Option<u64>,
+    ) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE downloads SET pos = ?2, total = ?3 WHERE id = ?1",
+            params![id, pos, total],
+        )?;
+        Ok(())
+    }
+
+    pub(super) fn set_log(&self, id: DownloadId, log: &TransferLog) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE downloads SET log = ?2 WHERE id = ?1",
+            params![id, serde_json::to_string(log).ok()],
+        )?;
+        Ok(())
+    }
+
+    pub(super) fn delete(&self, id: DownloadId) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM downloads WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Remember a download that's just finished or failed, ignoring its id.
+    pub(super) fn add_history(&self, entry: &HistoryEntry) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO history
+            (download_id, url, destination, status, size, duration, average_speed, verified,
+                error, finished_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                entry.download_id,
+                entry.url,
+                entry.destination.to_string_lossy(),
+                entry.status.as_str(),
+                entry.size,
+                entry.duration,
+                entry.average_speed,
+                entry.verified,
+                entry.error,
+                entry.finished_at.timestamp_millis(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Up to `limit` of the entries whose URL or destination contains
+    /// `query`, ignoring case, newest first, skipping the first `offset`.
+    pub(super) fn history(
+        &self,
+        query: Option<&str>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> rusqlite::Result<HistoryPage> {
+        const MATCHING: &str = "FROM history WHERE ?1 IS NULL
+            OR instr(lower(url), lower(?1)) > 0 OR instr(lower(destination), lower(?1)) > 0";
+        let conn = self.conn.lock().unwrap();
+        let (total, completed, bytes, received, duration): (usize, u64, u64, f64, f64) = conn
+            .query_row(
+                &format!(
+                    "SELECT count(*), coalesce(sum(status = 'done'), 0),
+                        coalesce(sum(CASE WHEN status = 'done' THEN size END), 0),
+                        coalesce(sum(average_speed * duration), 0), coalesce(sum(duration), 0)
+                    {MATCHING}"
+                ),
+                params![query],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )?;
+        let mut statement = conn.prepare(&format!(
+            "SELECT id, download_id, url, destination, status, size, duration, average_speed,
+                verified, error, finished_at
+            {MATCHING} ORDER BY finished_at DESC, id DESC LIMIT ?2 OFFSET ?3"
+        ))?;
+        // a negative limit is none
+        let limit = limit.map_or(-1, |limit| limit.min(i64::MAX as usize) as i64);
+        let entries = statement.query_map(params![query, limit, offset], HistoryEntry::from_row)?;
+        Ok(HistoryPage {
+            total,
+            entries: entries.collect::<rusqlite::Result<_>>()?,
+            stats: HistoryStats {
+                completed,
+                failed: total as u64 - completed,
+                bytes,
+                average_speed: match duration > 0.0 {
+                    true => received / duration,
+                    false => 0.0,
+                },
+            },
+        })
+    }
+
+    /// The entries of downloads of `url` or `final_url` that were done,
+    /// newest first.
+    pub(super) fn downloaded(
+        &self,
+        url: &str,
+        final_url: Option<&str>,
+    ) -> rusqlite::Result<Vec<HistoryEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT id, download_id, url, destination, status, size, duration, average_speed,
+                verified, error, finished_at
+            FROM history WHERE status = 'done' AND (url = ?1 OR url = ?2)
+            ORDER BY finished_at DESC, id DESC",
+        )?;
+        let entries = statement.query_map(params![url, final_url], HistoryEntry::from_row)?;
+        entries.collect()
+    }
+
+    pub(super) fn clear_history(&self) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM history", [])?;
+        Ok(())
+    }
+
+    /// Delete the entries `retention` doesn't keep.
+    pub(super) fn prune_history(&self, retention: HistoryRetention) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        if let Some(cutoff) = retention.cutoff() {
+            conn.execute(
+                "DELETE FROM history WHERE finished_at < ?1",
+                params![cutoff.timestamp_millis()],
+            )?;
+        }
+        if let Some(max) = retention.max_entries {
+            conn.execute(
+                "DELETE FROM history WHERE id NOT IN
+                (SELECT id FROM history ORDER BY finished_at DESC, id DESC LIMIT ?1)",
+                params![max],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Insert or replace `record` through `conn`, which may be a transaction.
+fn insert(conn: &Connection, record: &Record) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO downloads
+        (id, url, destination, status, error, pos, total, etag, last_modified,
+            priority, position, bandwidth_limit, expected_hash, extraction, start_at,
+            final_url, chunk_index, category, mirrors)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+            ?18, ?19)",
+        params![
+            record.id,
+            record.url,
+            record.destination.to_string_lossy(),
+            record.status.as_str(),
+            record.error,
+            record.pos,
+            record.total,
+            record.etag,
+            record.last_modified,
+            record.priority.as_str(),
+            record.position,
+            record.bandwidth_limit,
+            record.expected_hash.as_ref().map(ExpectedHash::to_column),
+            (record.extraction.as_ref()).and_then(|e| serde_json::to_string(e).ok()),
+            record.start_at.map(|start_at| start_at.timestamp_millis()),
+            record.final_url,
+            record.chunk_index,
+            record.category.as_str(),
+            serde_json::to_string(&record.mirrors).ok(),
+        ],
+    )?;
+    Ok(())
+}
+
+impl Record {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let status: String = row.get(3)?;
+        let priority: String = row.get(9)?;
+        let category: String = row.get(17)?;
+        let expected_hash: Option<String> = row.get(12)?;
+        let extraction: Option<String> = row.get(13)?;
+        let start_at: Option<i64> = row.get(14)?;
+        let log: Option<String> = row.get(18)?;
+        let mirrors: Option<String> = row.get(19)?;
+        Ok(Record {
+            id: row.get(0)?,
+            url: row.get(1)?,
+            final_url: row.get(15)?,
+            destination: PathBuf::from(row.get::<_, String>(2)?),
+            // rows from a newer version of the app with states this one
+            // doesn't know are left paused
+            status: Status::parse(&status).unwrap_or(Status::Paused),
+            category: Category::parse(&category).unwrap_or_default(),
+            priority: Priority::parse(&priority).unwrap_or_default(),
+            position: row.get(10)?,
+            bandwidth_limit: row.get(11)?,
+            expected_hash: expected_hash.as_deref().and_then(ExpectedHash::from_column),
+            extraction: extraction.and_then(|json| serde_json::from_str(&json).ok()),
+            start_at: start_at.and_then(|millis| Utc.timestamp_millis_opt(millis).single()),
+            chunk_index: row.get(16)?,
+            mirrors: (mirrors.and_then(|json| serde_json::from_str(&json).ok()))
+                .unwrap_or_default(),
+            error: row.get(4)?,
+            pos: row.get(5)?,
+            total: row.get(6)?,
+            etag: row.get(7)?,
+            last_modified: row.get(8)?,
+            log: (log.and_then(|json| serde_json::from_str(&json).ok())).unwrap_or_default(),
+        })
+    }
+}
+
+impl HistoryEntry {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let status: String = row.get(4)?;
+        let finished_at: i64 = row.get(10)?;
+        Ok(HistoryEntry {
+            id: row.get(0)?,
+            download_id: row.get(1)?,
+            url: row.get(2)?,
+            destination: PathBuf::from(row.get::<_, String>(3)?),
+            status: Status::parse(&status).unwrap_or(Status::Failed),
+            size: row.get(5)?,
+            duration: row.get(6)?,
+            average_speed: row.get(7)?,
+            verified: row.get(8)?,
+            error: row.get(9)?,
+            finished_at: Utc
+                .timestamp_millis_opt(finished_at)
+                .single()
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Queued => "queued",
+            Status::Running => "running",
+            Status::Extracting => "extracting",
+            Status::Paused => "paused",
+            Status::Failed => "failed",
+            Status::FailedVerification => "failed_verification",
+            Status::Done => "done",
+        }
+    }
+
+    fn parse(status: &str) -> Option<Self> {
+        [
+            Status::Queued,
+            Status::Running,
+            Status::Extracting,
+            Status::Paused,
+            Status::Failed,
+            Status::FailedVerification,
+            Status::Done,
+        ]
+        .into_iter()
+        .find(|s| s.as_str() == status)
+    }
+}
+
+impl ExpectedHash {
+    /// `<algorithm>:<hex>`, e.g. `sha256:9f86d0…`.
+    fn to_column(&self) -> String {
+        let algorithm = match self.algorithm {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        };
+        format!("{algorithm}:{}", self.hex)
+    }
+
+    fn from_column(column: &str) -> Option<Self> {
+        let (algorithm, hex) = column.split_once(':')?;
+        let algorithm = match algorithm {
+            "sha256" => HashAlgorithm::Sha256,
+            "blake3" => HashAlgorithm::Blake3,
+            _ => return None,
+        };
+        Some(ExpectedHash {
+            algorithm,
+            hex: hex.to_owned(),
+        })
+    }
+}
+
+impl Category {
+    fn as_str(self) -> &'static str {
+        match self {
+            Category::Models => "models",
+            Category::Archives => "archives",
+            Category::Video => "video",
+            Category::Other => "other",
+        }
+    }
+
+    fn parse(category: &str) -> Option<Self> {
+        Category::ALL.into_iter().find(|c| c.as_str() == category)
+    }
+}
+
+impl Priority {
+    fn as_str(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+
+    fn parse(priority: &str) -> Option<Self> {
+        [Priority::Low, Priority::Normal, Priority::High]
+            .into_iter()
+            .find(|p| p.as_str() == priority)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Category, DownloadId, ExpectedHash, Extraction, HashAlgorithm, HistoryEntry,
+        HistoryRetention, Priority, Record, Status, Store, TransferLog, MIGRATIONS,
+    };
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use std::path::PathBuf;
+
+    fn record(id: DownloadId) -> Record {
+        Record {
+            id,
+            url: format!("https://example.com/{id}"),
+            final_url: None,
+            destination: PathBuf::from(format!("{id}.bin")),
+            status: Status::Queued,
+            category: Category::Other,
+            priority: Priority::Normal,
+            position: id,
+            bandwidth_limit: None,
+            expected_hash: None,
+            extraction: None,
+            start_at: None,
+            chunk_index: None,
+            mirrors: Vec::new(),
+            error: None,
+            pos: 0,
+            total: None,
+            etag: None,
+            last_modified: None,
+            log: TransferLog::default(),
+        }
+    }
+
+    fn entry(url: &str, status: Status, size: u64, finished_at: DateTime<Utc>) -> HistoryEntry {
+        HistoryEntry {
+            id: 0,
+            download_id: 1,
+            url: url.to_owned(),
+            destination: PathBuf::from("file.bin"),
+            status,
+            size: Some(size),
+            duration: 10.0,
+            average_speed: size as f64 / 10.0,
+            verified: None,
+            error: None,
+            finished_at,
+        }
+    }
+
+    fn urls(entries: &[HistoryEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.url.as_str()).collect()
+    }
+
+    #[test]
+    fn reopens_migrated_databases() {
+        let path = std::env::temp_dir().join(format!("downloads-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Store::open(&path).unwrap();
+        {
+            let store = Store::open(&path).unwrap();
+            let conn = store.conn.lock().unwrap();
+            let version: usize = conn
+                .query_row("PRAGMA user_version", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(version, MIGRATIONS.len());
+        }
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[test]
+    fn loads_what_was_inserted() {
+        let store = Store::in_memory().unwrap();
+        let full = Record {
+            final_url: Some("https://cdn.example.com/1".to_owned()),
+            status: Status::Paused,
+            category: Category::Models,
+            priority: Priority::High,
+            bandwidth_limit: Some(1024),
+            expected_hash: Some(ExpectedHash {
+                algorithm: HashAlgorithm::Blake3,
+                hex: "ab".repeat(32),
+            }),
+            extraction: Some(Extraction {
+                directory: PathBuf::from("unpacked"),
+                symlinks: Default::default(),
+            }),
+            start_at: Some(Utc.timestamp_millis_opt(1_700_000_000_000).unwrap()),
+            chunk_index: Some("https://example.com/1.chunks".to_owned()),
+            mirrors: vec!["https://mirror.example.com/1".to_owned()],
+            error: Some("connection reset".to_owned()),
+            pos: 100,
+            total: Some(1000),
+            etag: Some("\"v1\"".to_owned()),
+            last_modified: Some("Tue, 14 Nov 2023 22:13:20 GMT".to_owned()),
+            ..record(1)
+        };
+        store.insert_all(&[full, record(2)]).unwrap();
+        let records = store.load().unwrap();
+        assert_eq!(records.len(), 2);
+        let loaded = &records[0];
+        assert_eq!(loaded.id, 1);
+        assert_eq!(loaded.url, "https://example.com/1");
+        assert_eq!(
+            loaded.final_url.as_deref(),
+            Some("https://cdn.example.com/1")
+        );
+        assert_eq!(loaded.destination, PathBuf::from("1.bin"));
+        assert_eq!(loaded.status, Status::Paused);
+        assert_eq!(loaded.category, Category::Models);
+        assert_eq!(loaded.priority, Priority::High);
+        assert_eq!(loaded.bandwidth_limit, Some(1024));
+        assert_eq!(
+            loaded.expected_hash.as_ref().map(|hash| hash.algorithm),
+            Some(HashAlgorithm::Blake3)
+        );
+        assert_eq!(
+            loaded
+                .extraction
+                .as_ref()
+                .map(|extraction| &extraction.directory),
+            Some(&PathBuf::from("unpacked"))
+        );
+        assert_eq!(
+            loaded.start_at.map(|start_at| start_at.timestamp_millis()),
+            Some(1_700_000_000_000)
+        );
+        assert_eq!(
+            loaded.chunk_index.as_deref(),
+            Some("https://example.com/1.chunks")
+        );
+        assert_eq!(loaded.mirrors, ["https://mirror.example.com/1"]);
+        assert_eq!(loaded.error.as_deref(), Some("connection reset"));
+        assert_eq!((loaded.pos, loaded.total), (100, Some(1000)));
+        assert_eq!(loaded.etag.as_deref(), Some("\"v1\""));
+        assert!(loaded.last_modified.is_some());
+
+        let loaded = &records[1];
+        assert_eq!(loaded.id, 2);
+        assert_eq!(loaded.destination, PathBuf::from("2.bin"));
+        assert_eq!(loaded.status, Status::Queued);
+        assert!(loaded.expected_hash.is_none() && loaded.extraction.is_none());
+        assert!(loaded.mirrors.is_empty());
+        assert_eq!(loaded.log, TransferLog::default());
+    }
+
+    #[test]
+    fn inserts_all_or_nothing() {
+        let store = Store::in_memory().unwrap();
+        store.insert(&record(1)).unwrap();
+        {
+            let conn = store.conn.lock().unwrap();
+            conn.execute_batch(
+                "CREATE TRIGGER no_empty_urls BEFORE INSERT ON downloads WHEN NEW.url = ''
+                BEGIN SELECT RAISE(ABORT, 'empty URL'); END;",
+            )
+            .unwrap();
+        }
+        // replaces the first, then fails on the second
+        let replaced = Record {
+            status: Status::Done,
+            ..record(1)
+        };
+        let broken = Record {
+            url: String::new(),
+            ..record(2)
+        };
+        assert!(store.insert_all(&[replaced, broken]).is_err());
+        let records = store.load().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].status, Status::Queued);
+    }
+
+    #[test]
+    fn saves_the_order() {
+        let store = Store::in_memory().unwrap();
+        store
+            .insert_all(&[record(1), record(2), record(3)])
+            .unwrap();
+        store.set_order(&[3, 1, 2]).unwrap();
+        let ids: Vec<_> = store
+            .load()
+            .unwrap()
+            .iter()
+            .map(|record| record.id)
+            .collect();
+        assert_eq!(ids, [3, 1, 2]);
+    }
+
+    #[test]
+    fn searches_history() {
+        let store = Store::in_memory().unwrap();
+        let now = Utc::now();
+        for (i, (url, status)) in [
+            ("https://example.com/a", Status::Done),
+            ("https://EXAMPLE.com/b", Status::Failed),
+            ("https://other.org/c", Status::Done),
+            ("https://example.com/d", Status::Done),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let finished_at = now + Duration::seconds(i as i64);
+            store
+                .add_history(&entry(url, status, 1000, finished_at))
+                .unwrap();
+        }
+
+        let page = store.history(None, 0, None).unwrap();
+        assert_eq!(page.total, 4);
+        assert_eq!(
+            urls(&page.entries),
+            [
+                "https://example.com/d",
+                "https://other.org/c",
+                "https://EXAMPLE.com/b",
+                "https://example.com/a"
+            ]
+        );
+
+        let page = store.history(Some("Example"), 0, None).unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(page.stats.completed, 2);
+        assert_eq!(page.stats.failed, 1);
+        assert_eq!(page.stats.bytes, 2000);
+        assert_eq!(page.stats.average_speed, 100.0);
+
+        let page = store.history(Some("example"), 1, Some(1)).unwrap();
+        // the total of every page
+        assert_eq!(page.total, 3);
+        assert_eq!(urls(&page.entries), ["https://EXAMPLE.com/b"]);
+        let page = store.history(Some("example"), 3, Some(1)).unwrap();
+        assert!(page.entries.is_empty());
+
+        let page = store.history(Some("nowhere"), 0, None).unwrap();
+        assert_eq!(page.total, 0);
+        assert_eq!(page.stats.average_speed, 0.0);
+    }
+
+    #[test]
+    fn prunes_history() {
+        let store = Store::in_memory().unwrap();
+        let now = Utc::now();
+        for (url, days) in [("old", 100), ("a", 3), ("b", 2), ("c", 1)] {
+            let finished_at = now - Duration::days(days);
+            store
+                .add_history(&entry(url, Status::Done, 1, finished_at))
+                .unwrap();
+        }
+        let unlimited = HistoryRetention {
+            max_age_days: None,
+            max_entries: None,
+        };
+        store.prune_history(unlimited).unwrap();
+        assert_eq!(store.history(None, 0, None).unwrap().total, 4);
+
+        let by_age = HistoryRetention {
+            max_age_days: Some(90),
+            ..unlimited
+        };
+        store.prune_history(by_age).unwrap();
+        let page = store.history(None, 0, None).unwrap();
+        assert_eq!(urls(&page.entries), ["c", "b", "a"]);
+
+        let by_count = HistoryRetention {
+            max_entries: Some(2),
+            ..unlimited
+        };
+        store.prune_history(by_count).unwrap();
+        let page = store.history(None, 0, None).unwrap();
+        assert_eq!(urls(&page.entries), ["c", "b"]);
+    }
+}
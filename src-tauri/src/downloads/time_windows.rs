@@ -0,0 +1,103 @@
+//! Times of day downloads may run in, e.g. at night while nobody else needs
+//! the connection. Outside of them, the queue waits and running downloads
+//! are sent back to it, to resume in the next one.
+
+use std::cmp::Ordering;
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+/// From `start` until `end` local time, past midnight if `end` is earlier.
+/// The whole day if they're the same.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeWindow {
+    #[serde(with = "hours_minutes")]
+    pub start: NaiveTime,
+    #[serde(with = "hours_minutes")]
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        match self.start.cmp(&self.end) {
+            Ordering::Less => self.start <= time && time < self.end,
+            Ordering::Greater => self.start <= time || time < self.end,
+            Ordering::Equal => true,
+        }
+    }
+}
+
+/// Whether downloads may run at `time`: always when there are no `windows`.
+pub(super) fn is_open(windows: &[TimeWindow], time: NaiveTime) -> bool {
+    windows.is_empty() || windows.iter().any(|window| window.contains(time))
+}
+
+/// Times as `HH:MM`.
+mod hours_minutes {
+    use chrono::NaiveTime;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    const FORMAT: &str = "%H:%M";
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&time.format(FORMAT))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&time, FORMAT).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_open, TimeWindow};
+    use chrono::NaiveTime;
+
+    fn at(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn window(start: NaiveTime, end: NaiveTime) -> TimeWindow {
+        TimeWindow { start, end }
+    }
+
+    #[test]
+    fn contains_times_within_the_day() {
+        let office = window(at(9, 0), at(17, 30));
+        assert!(office.contains(at(9, 0)));
+        assert!(office.contains(at(12, 0)));
+        assert!(!office.contains(at(17, 30)));
+        assert!(!office.contains(at(8, 59)));
+        assert!(!office.contains(at(23, 0)));
+    }
+
+    #[test]
+    fn wraps_past_midnight() {
+        let night = window(at(22, 0), at(6, 0));
+        assert!(night.contains(at(22, 0)));
+        assert!(night.contains(at(23, 59)));
+        assert!(night.contains(at(0, 0)));
+        assert!(night.contains(at(5, 59)));
+        assert!(!night.contains(at(6, 0)));
+        assert!(!night.contains(at(12, 0)));
+    }
+
+    #[test]
+    fn same_start_and_end_is_the_whole_day() {
+        let day = window(at(8, 0), at(8, 0));
+        assert!(day.contains(at(8, 0)));
+        assert!(day.contains(at(7, 59)));
+        assert!(day.contains(at(20, 0)));
+    }
+
+    #[test]
+    fn open_in_any_window() {
+        assert!(is_open(&[], at(3, 0)));
+        let windows = [window(at(1, 0), at(2, 0)), window(at(22, 0), at(6, 0))];
+        assert!(is_open(&windows, at(1, 30)));
+        assert!(is_open(&windows, at(5, 0)));
+        assert!(!is_open(&windows, at(12, 0)));
+    }
+}
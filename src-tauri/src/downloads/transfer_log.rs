@@ -0,0 +1,111 @@
+//! What happened to each attempt at a download, for telling why one keeps
+//! failing: when it started and from where, which URL it reached, how long it
+//! waited to reconnect and why, and how it ended. Only the last
+//! [`TransferLog::CAPACITY`] events are kept, saved with the download.
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::reqwest_resume::Event;
+
+/// Something that happened to a download.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum LogEvent {
+    /// It started, resuming from `pos` bytes if that isn't 0.
+    Started {
+        pos: u64,
+    },
+    /// A request for what's left of it from `pos` got a response from `url`.
+    Connected {
+        attempt: u32,
+        pos: u64,
+        status: u16,
+        url: String,
+    },
+    /// Attempt `attempt` starts after `delay` seconds because of `reason`.
+    Reconnecting {
+        attempt: u32,
+        pos: u64,
+        delay: f64,
+        reason: String,
+    },
+    /// It was paused or sent back to the queue at `pos` bytes.
+    Stopped {
+        pos: u64,
+    },
+    Failed {
+        error: String,
+    },
+    /// It was downloaded, `bytes` in all.
+    Done {
+        bytes: u64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntry {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: LogEvent,
+}
+
+/// The latest events of a download, oldest first.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct TransferLog {
+    entries: VecDeque<LogEntry>,
+}
+
+impl TransferLog {
+    /// How many events are kept, the oldest being dropped first.
+    pub const CAPACITY: usize = 200;
+
+    pub(super) fn push(&mut self, event: LogEvent) {
+        if self.entries.len() >= Self::CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LogEntry {
+            at: Utc::now(),
+            event,
+        });
+    }
+
+    /// Record `event` if it's worth remembering; chunks aren't, and failures
+    /// are recorded once the download has given up.
+    pub(super) fn record(&mut self, event: &Event) {
+        match event {
+            Event::Connected {
+                attempt,
+                pos,
+                status,
+                url,
+            } => self.push(LogEvent::Connected {
+                attempt: *attempt,
+                pos: *pos,
+                status: status.as_u16(),
+                url: url.to_string(),
+            }),
+            Event::Reconnecting {
+                attempt,
+                pos,
+                delay,
+                reason,
+                ..
+            } => self.push(LogEvent::Reconnecting {
+                attempt: *attempt,
+                pos: *pos,
+                delay: delay.as_secs_f64(),
+                reason: reason.clone(),
+            }),
+            Event::ChunkReceived { .. } | Event::Failed { .. } => {}
+        }
+    }
+
+    pub fn entries(&self) -> Vec<LogEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
@@ -0,0 +1,59 @@
+//! The downloads part of the tray menu: how far along they are together,
+//! kept up to date by the manager while the window is closed, and items to
+//! pause or resume them all and to open the window on them.
+
+use tauri::{AppHandle, CustomMenuItem, Manager, SystemTrayMenu};
+
+use super::DownloadManager;
+use crate::logerr;
+
+const STATUS_ITEM: &str = "downloads_status";
+const PAUSE_ALL_ITEM: &str = "downloads_pause_all";
+const RESUME_ALL_ITEM: &str = "downloads_resume_all";
+const OPEN_ITEM: &str = "downloads_open";
+
+/// Emitted when the downloads are opened from the tray, for the webview to
+/// show them.
+pub const OPEN_EVENT: &str = "download://open";
+
+/// Add the downloads' items to `menu`.
+pub fn add_items(menu: SystemTrayMenu) -> SystemTrayMenu {
+    menu.add_item(CustomMenuItem::new(STATUS_ITEM, "No downloads").disabled())
+        .add_item(CustomMenuItem::new(PAUSE_ALL_ITEM, "Pause all downloads"))
+        .add_item(CustomMenuItem::new(RESUME_ALL_ITEM, "Resume all downloads"))
+        .add_item(CustomMenuItem::new(OPEN_ITEM, "Downloads"))
+}
+
+/// Handle a click on tray item `id` if it's one of the downloads'.
+pub fn on_click(app: &AppHandle, id: &str) {
+    let manager = app.state::<DownloadManager>();
+    match id {
+        PAUSE_ALL_ITEM => manager.pause_all(),
+        RESUME_ALL_ITEM => manager.resume_all(),
+        OPEN_ITEM => {
+            let Some(window) = app.get_window("main") else {
+                log::error!("Couldn't get window from for label 'main'");
+                return;
+            };
+            logerr!(window.show());
+            logerr!(window.set_focus());
+            logerr!(app.emit_all(OPEN_EVENT, ()));
+        }
+        _ => {}
+    }
+}
+
+/// Show that `active` downloads are queued or running, `percent` of the way
+/// through if their sizes are known.
+pub(super) fn update(app: &AppHandle, active: usize, percent: Option<f64>) {
+    let status = match (active, percent) {
+        (0, _) => "No downloads".to_owned(),
+        (1, None) => "1 download".to_owned(),
+        (n, None) => format!("{n} downloads"),
+        (1, Some(percent)) => format!("1 download, {percent:.0}%"),
+        (n, Some(percent)) => format!("{n} downloads, {percent:.0}%"),
+    };
+    let tray = app.tray_handle();
+    logerr!(tray.get_item(STATUS_ITEM).set_title(&status));
+    logerr!(tray.set_tooltip(&status));
+}
@@ -3,7 +3,9 @@
 
 mod controller_binaries;
 mod download;
+mod downloads;
 mod errors;
+mod reqwest_resume;
 mod swarm;
 mod utils;
 
@@ -209,6 +211,8 @@ fn main() {
 
     let tray_menu = SystemTrayMenu::new()
         .add_item(running)
+        .add_native_item(SystemTrayMenuItem::Separator);
+    let tray_menu = downloads::tray::add_items(tray_menu)
         .add_native_item(SystemTrayMenuItem::Separator)
         .add_item(show)
         .add_item(hide)
@@ -239,6 +243,40 @@ fn main() {
             controller_binaries::delete_registry,
             controller_binaries::fetch_registries,
             controller_binaries::reset_default_registry,
+            downloads::commands::download_start,
+            downloads::commands::import_urls,
+            downloads::commands::import_metalink,
+            downloads::commands::export_queue,
+            downloads::commands::import_queue,
+            downloads::commands::list_downloads,
+            downloads::commands::get_download_log,
+            downloads::commands::get_speed_history,
+            downloads::commands::get_history,
+            downloads::commands::clear_history,
+            downloads::commands::set_history_retention,
+            downloads::commands::set_max_concurrent,
+            downloads::commands::set_download_priority,
+            downloads::commands::reorder_download,
+            downloads::commands::set_bandwidth_limit,
+            downloads::commands::set_min_free_space,
+            downloads::commands::network_state,
+            downloads::commands::set_connectivity_check,
+            downloads::commands::set_time_windows,
+            downloads::commands::set_metered_policy,
+            downloads::commands::is_metered,
+            downloads::commands::set_notifications,
+            downloads::commands::set_chunk_store,
+            downloads::commands::set_naming,
+            downloads::commands::set_categories,
+            downloads::commands::reveal_download,
+            downloads::commands::download_pause,
+            downloads::commands::download_resume,
+            downloads::commands::download_cancel,
+            downloads::commands::pause_all,
+            downloads::commands::resume_all,
+            downloads::commands::retry_failed,
+            downloads::commands::cancel_selected,
+            downloads::commands::download_remove,
             swarm::is_swarm_supported,
             swarm::get_username,
             swarm::get_petals_models,
@@ -285,11 +323,28 @@ fn main() {
                     logerr!(window.set_focus());
                     logerr!(window.show());
                 }
-                _ => {}
+                id => downloads::tray::on_click(app, id),
             },
             _ => {}
         })
         .setup(|app| {
+            // Resume the downloads the app was running when it last exited
+            let data_dir = app
+                .path_resolver()
+                .app_data_dir()
+                .expect("failed to resolve app data dir");
+            logerr!(std::fs::create_dir_all(&data_dir));
+            let queue_path = data_dir.join("downloads.sqlite");
+            let store = downloads::Store::open(&queue_path).unwrap_or_else(|err| {
+                log::error!("Failed to open {queue_path:?}, downloads won't be saved: {err}");
+                downloads::Store::in_memory().expect("failed to create the download queue")
+            });
+            app.manage(downloads::DownloadManager::new(
+                app.handle(),
+                reqwest_resume::Client::new(),
+                3,
+                store,
+            ));
             tauri::async_runtime::block_on(async move {
                 //Create a store with default registry if doesn't exist
                 let store_path = app
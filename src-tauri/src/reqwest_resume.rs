@@ -0,0 +1,6236 @@
+//! Wrapper around `reqwest` that uses the `Range` HTTP header to resume GET
+//! requests when the body stream is interrupted.
+//!
+//! ```ignore
+//! let mut stream = reqwest_resume::get(url).await?.bytes_stream();
+//! while let Some(chunk) = stream.next().await {
+//!     file.write_all(&chunk?).await?;
+//! }
+//! ```
+
+// a client library, more of which is public than the app uses so far
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    io::SeekFrom,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    pin::{pin, Pin},
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder, ZstdDecoder};
+use bytes::{Buf, Bytes};
+use futures::{
+    future::{select, BoxFuture, Either},
+    ready, Stream, StreamExt,
+};
+use reqwest::{
+    cookie::Jar,
+    dns::{Name, Resolve, Resolving},
+    header::{
+        HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_DISPOSITION,
+        CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, DATE, ETAG, IF_RANGE, LAST_MODIFIED,
+        RANGE, RETRY_AFTER,
+    },
+    redirect,
+    tls::{Certificate, Identity, TlsInfo},
+    Method, StatusCode, Url,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Digest;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{mpsc, OwnedSemaphorePermit, Semaphore},
+    time::{sleep, sleep_until, timeout_at, Instant, Sleep},
+};
+use tokio_util::sync::CancellationToken;
+
+use self::guard::{AddressGuard, BlockedAddress};
+use self::middleware::{Middleware, Middlewares};
+
+/// Why a resumable request or its body stream failed. Errors tied to a
+/// transfer carry its URL and the number of bytes received until then.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The connection failed or the server answered with an error status, in
+    /// a way that isn't worth retrying.
+    #[error("{url} failed after {pos} bytes were received: {source}")]
+    Network {
+        url: Url,
+        pos: u64,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("{url} timed out after {pos} bytes were received")]
+    Timeout { url: Url, pos: u64 },
+    #[error("{url} was cancelled after {pos} bytes were received")]
+    Cancelled { url: Url, pos: u64 },
+    /// The file changed on the server since the transfer started.
+    #[error("{url} changed on the server after {pos} bytes were received")]
+    ValidatorMismatch { url: Url, pos: u64 },
+    /// The server ignored the range or answered with a different one.
+    #[error("{url} didn't resume at byte {pos} (Content-Range: {content_range:?})")]
+    RangeNotHonored {
+        url: Url,
+        pos: u64,
+        content_range: Option<String>,
+    },
+    /// The retry policy gave up after `attempts` consecutive failures.
+    #[error("{url} failed {attempts} times in a row after {pos} bytes were received: {source}")]
+    TooManyRetries {
+        url: Url,
+        pos: u64,
+        attempts: u32,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("I/O error on {url} after {pos} bytes were received: {source}")]
+    Io {
+        url: Url,
+        pos: u64,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The body's digest differs from the expected one, see
+    /// [`RequestBuilder::checksum`].
+    #[error("{algorithm} of {url} is {actual}, expected {expected}")]
+    ChecksumMismatch {
+        url: Url,
+        pos: u64,
+        algorithm: &'static str,
+        expected: String,
+        actual: String,
+    },
+    /// A resumed response reports a different complete length for the body
+    /// than an earlier one, so the file must have changed on the server.
+    #[error(
+        "{url} changed size from {expected} to {actual} bytes after {pos} bytes were received"
+    )]
+    SizeChanged {
+        url: Url,
+        pos: u64,
+        expected: u64,
+        actual: u64,
+    },
+    /// The body is, or announced it would be, larger than allowed, see
+    /// [`RequestBuilder::max_size`].
+    #[error("{url} is larger than {max} bytes, {pos} bytes were received")]
+    TooLarge { url: Url, pos: u64, max: u64 },
+    /// The disk doesn't have room for the rest of the body, see
+    /// [`RequestBuilder::min_free_space`].
+    #[error("{url} needs {needed} bytes of disk space but only {available} are free")]
+    InsufficientSpace {
+        url: Url,
+        pos: u64,
+        needed: u64,
+        available: u64,
+    },
+    /// The body isn't valid for its `Content-Encoding`, see
+    /// [`Response::decoded_stream`], or isn't valid JSON, see
+    /// [`Response::json`].
+    #[error("failed to decode {url} after {pos} bytes were received: {source}")]
+    Decode {
+        url: Url,
+        pos: u64,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The server's certificate doesn't have any of the public keys pinned
+    /// with [`ClientBuilder::pin_public_key`].
+    #[error("{url} presented a certificate without a pinned public key")]
+    UnpinnedCertificate { url: Url, pos: u64 },
+    /// The URL, a redirect or a resolved host name points at a loopback,
+    /// private or link-local address, see
+    /// [`ClientBuilder::block_private_addresses`].
+    #[error("{url} leads to {addr}, a private address")]
+    BlockedAddress { url: Url, pos: u64, addr: IpAddr },
+    /// The URL or a redirect uses plaintext HTTP, which
+    /// [`PlaintextPolicy::Reject`] doesn't allow; `target` is that URL.
+    #[error("{url} leads to {target} over plaintext HTTP")]
+    Plaintext { url: Url, pos: u64, target: Url },
+    /// The redirects went on for longer than
+    /// [`ClientBuilder::max_redirects`]; `chain` holds every URL visited,
+    /// starting with the one requested.
+    #[error("{url} redirected too many times, through {} URLs", chain.len())]
+    TooManyRedirects { url: Url, pos: u64, chain: Vec<Url> },
+    /// The server answered an [`upload`] request in a way the tus or S3
+    /// multipart protocol doesn't allow.
+    #[error("{url} broke the upload protocol at {pos} bytes: {reason}")]
+    Protocol {
+        url: Url,
+        pos: u64,
+        reason: &'static str,
+    },
+    #[error("invalid query string: {0}")]
+    Query(#[from] serde_urlencoded::ser::Error),
+    /// The `reqwest::Client` for a [`ClientBuilder`] or
+    /// [`RequestBuilder::proxy`] couldn't be built.
+    #[error("failed to build the HTTP client: {0}")]
+    Client(#[source] Arc<reqwest::Error>),
+    /// The resume metadata next to a partial download is missing or unreadable.
+    #[error("can't resume from {}: {source}", path.display())]
+    Sidecar {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        use std::io::ErrorKind;
+        match err {
+            Error::Io { source, .. } | Error::Decode { source, .. } => source,
+            Error::Timeout { .. } => std::io::Error::new(ErrorKind::TimedOut, err),
+            Error::Cancelled { .. } => std::io::Error::new(ErrorKind::Interrupted, err),
+            Error::ChecksumMismatch { .. } => std::io::Error::new(ErrorKind::InvalidData, err),
+            _ => std::io::Error::other(err),
+        }
+    }
+}
+
+impl Error {
+    fn io(url: &Url, pos: u64, source: std::io::Error) -> Self {
+        Error::Io {
+            url: url.clone(),
+            pos,
+            source,
+        }
+    }
+
+    /// A failed attempt that won't be retried, be it a timeout or anything else.
+    fn network(url: &Url, pos: u64, source: reqwest::Error) -> Self {
+        let url = url.clone();
+        if source.is_timeout() {
+            Error::Timeout { url, pos }
+        } else if let Some(addr) = guard::blocked_address(&source) {
+            Error::BlockedAddress { url, pos, addr }
+        } else if let Some(redirect) = find_source::<RedirectError>(&source) {
+            match redirect.clone() {
+                RedirectError::Plaintext(target) => Error::Plaintext { url, pos, target },
+                RedirectError::TooMany(chain) => Error::TooManyRedirects { url, pos, chain },
+            }
+        } else {
+            Error::Network { url, pos, source }
+        }
+    }
+
+    pub fn url(&self) -> Option<&Url> {
+        match self {
+            Error::Network { url, .. }
+            | Error::Timeout { url, .. }
+            | Error::Cancelled { url, .. }
+            | Error::ValidatorMismatch { url, .. }
+            | Error::RangeNotHonored { url, .. }
+            | Error::TooManyRetries { url, .. }
+            | Error::Io { url, .. }
+            | Error::ChecksumMismatch { url, .. }
+            | Error::SizeChanged { url, .. }
+            | Error::TooLarge { url, .. }
+            | Error::InsufficientSpace { url, .. }
+            | Error::UnpinnedCertificate { url, .. }
+            | Error::BlockedAddress { url, .. }
+            | Error::Plaintext { url, .. }
+            | Error::TooManyRedirects { url, .. }
+            | Error::Protocol { url, .. }
+            | Error::Decode { url, .. } => Some(url),
+            Error::Query(_) | Error::Client(_) | Error::Sidecar { .. } => None,
+        }
+    }
+
+    /// Bytes received before the error, since the last restart.
+    pub fn position(&self) -> Option<u64> {
+        match self {
+            Error::Network { pos, .. }
+            | Error::Timeout { pos, .. }
+            | Error::Cancelled { pos, .. }
+            | Error::ValidatorMismatch { pos, .. }
+            | Error::RangeNotHonored { pos, .. }
+            | Error::TooManyRetries { pos, .. }
+            | Error::Io { pos, .. }
+            | Error::ChecksumMismatch { pos, .. }
+            | Error::SizeChanged { pos, .. }
+            | Error::TooLarge { pos, .. }
+            | Error::InsufficientSpace { pos, .. }
+            | Error::UnpinnedCertificate { pos, .. }
+            | Error::BlockedAddress { pos, .. }
+            | Error::Plaintext { pos, .. }
+            | Error::TooManyRedirects { pos, .. }
+            | Error::Protocol { pos, .. }
+            | Error::Decode { pos, .. } => Some(*pos),
+            Error::Query(_) | Error::Client(_) | Error::Sidecar { .. } => None,
+        }
+    }
+
+    /// Whether the server's copy of the file no longer matches what was received
+    /// so far, as opposed to the transfer itself failing.
+    pub fn is_validation(&self) -> bool {
+        matches!(
+            self,
+            Error::ValidatorMismatch { .. }
+                | Error::RangeNotHonored { .. }
+                | Error::SizeChanged { .. }
+                | Error::ChecksumMismatch { .. }
+        )
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Extension trait turning a `reqwest::Client` into a resumable [`Client`].
+pub trait ClientExt {
+    fn resumable(self) -> Client;
+}
+
+impl ClientExt for reqwest::Client {
+    fn resumable(self) -> Client {
+        Client::from_reqwest(self)
+    }
+}
+
+/// How long to wait between attempts.
+///
+/// The default waits a second, doubling up to a minute, with a jitter of
+/// `0.5`. Waiting a fixed second every time, as this client used to, hammers
+/// servers that are struggling already; `Backoff::Fixed(Duration::from_secs(1))`
+/// still does that where it's wanted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Backoff {
+    /// Always wait the same amount of time.
+    Fixed(Duration),
+    /// Wait `initial`, then `increment` longer on every following attempt, up to `max`.
+    Linear {
+        initial: Duration,
+        increment: Duration,
+        max: Duration,
+    },
+    /// Wait `base`, doubling on every following attempt up to `max`. Each delay
+    /// is then shortened by a random fraction of up to `jitter` (`0.0..=1.0`) so
+    /// that clients which failed together don't all reconnect at the same time.
+    Exponential {
+        base: Duration,
+        max: Duration,
+        jitter: f64,
+    },
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::exponential(Duration::from_secs(1), Duration::from_secs(60)).with_jitter(0.5)
+    }
+}
+
+impl Backoff {
+    /// Exponential backoff without jitter.
+    pub fn exponential(base: Duration, max: Duration) -> Self {
+        Backoff::Exponential {
+            base,
+            max,
+            jitter: 0.0,
+        }
+    }
+
+    /// Set the jitter of an exponential backoff, other strategies are left untouched.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        if let Backoff::Exponential { jitter: j, .. } = &mut self {
+            *j = jitter.clamp(0.0, 1.0);
+        }
+        self
+    }
+
+    /// Delay before retry number `retry` (1-based).
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self.max_delay(retry);
+        match *self {
+            Backoff::Exponential { jitter, .. } if jitter > 0.0 => {
+                delay.mul_f64(1.0 - jitter * rand::random::<f64>())
+            }
+            _ => delay,
+        }
+    }
+
+    /// Delay before retry number `retry` (1-based), ignoring jitter.
+    fn max_delay(&self, retry: u32) -> Duration {
+        match *self {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Linear {
+                initial,
+                increment,
+                max,
+            } => increment
+                .checked_mul(retry.saturating_sub(1))
+                .and_then(|extra| initial.checked_add(extra))
+                .map_or(max, |delay| delay.min(max)),
+            Backoff::Exponential { base, max, .. } => 2u32
+                .checked_pow(retry.saturating_sub(1))
+                .and_then(|factor| base.checked_mul(factor))
+                .map_or(max, |delay| delay.min(max)),
+        }
+    }
+}
+
+type RetryPredicate = Arc<dyn Fn(&reqwest::Error) -> bool + Send + Sync>;
+
+/// What went wrong with a failed attempt, to back off differently depending on
+/// the cause; see [`RetryPolicy::backoff_for`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FailureKind {
+    /// The host name couldn't be resolved.
+    Dns,
+    /// Nothing was listening on the server's port.
+    ConnectionRefused,
+    /// The TLS handshake failed, e.g. over an invalid certificate.
+    Tls,
+    /// The connection was reset or closed early.
+    Reset,
+    /// The attempt timed out or the body stalled.
+    Timeout,
+    Other,
+}
+
+impl FailureKind {
+    /// Classify `err` by the I/O error underneath it or, failing that, by the
+    /// messages of its causes, since `hyper` doesn't expose DNS and TLS errors
+    /// as types of their own.
+    pub fn of(err: &reqwest::Error) -> Self {
+        if err.is_timeout() {
+            return FailureKind::Timeout;
+        }
+        classify(err).unwrap_or(FailureKind::Other)
+    }
+}
+
+fn classify(err: &(dyn std::error::Error + 'static)) -> Option<FailureKind> {
+    use std::io::ErrorKind;
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<std::io::Error>() {
+            match err.kind() {
+                ErrorKind::ConnectionRefused => return Some(FailureKind::ConnectionRefused),
+                ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof => return Some(FailureKind::Reset),
+                ErrorKind::TimedOut => return Some(FailureKind::Timeout),
+                _ => {}
+            }
+        }
+        let message = err.to_string().to_ascii_lowercase();
+        let mentions = |words: &[&str]| words.iter().any(|word| message.contains(word));
+        if mentions(&["dns error", "failed to lookup address"]) {
+            return Some(FailureKind::Dns);
+        }
+        if mentions(&["certificate", "tls", "ssl", "handshake"]) {
+            return Some(FailureKind::Tls);
+        }
+        if mentions(&["connection closed", "incomplete message"]) {
+            return Some(FailureKind::Reset);
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// Backoff and budget for one [`FailureKind`], overriding the policy's own.
+#[derive(Clone, Debug, Default)]
+struct KindPolicy {
+    backoff: Option<Backoff>,
+    max_attempts: Option<u32>,
+}
+
+/// Decides whether and when a failed request or interrupted body is retried.
+///
+/// `max_attempts` and `max_elapsed` bound the number of consecutive attempts
+/// made without receiving any bytes and the time spent on them; both are reset
+/// every time the body makes progress. Once either is exceeded the request or
+/// stream fails with [`Error::TooManyRetries`].
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: Option<u32>,
+    max_elapsed: Option<Duration>,
+    backoff: Backoff,
+    retryable: RetryPredicate,
+    max_retry_after: Duration,
+    kinds: HashMap<FailureKind, KindPolicy>,
+}
+
+impl Default for RetryPolicy {
+    /// Retries forever with the default exponential backoff.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: None,
+            max_elapsed: None,
+            backoff: Backoff::default(),
+            retryable: Arc::new(is_transient),
+            max_retry_after: Duration::from_secs(5 * 60),
+            kinds: HashMap::new(),
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("max_elapsed", &self.max_elapsed)
+            .field("backoff", &self.backoff)
+            .field("max_retry_after", &self.max_retry_after)
+            .field("kinds", &self.kinds)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Never retry.
+    pub fn none() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    /// Give up after `max_attempts` consecutive attempts (including the first one).
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts.max(1));
+        self
+    }
+
+    /// Give up once `max_elapsed` has passed since the first of a series of
+    /// consecutive failures.
+    pub fn max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    /// Retry without an upper bound on the number of attempts or the time
+    /// spent on them.
+    pub fn unlimited(mut self) -> Self {
+        self.max_attempts = None;
+        self.max_elapsed = None;
+        self
+    }
+
+    /// How long to wait between attempts, see [`Backoff`] for the default.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Only retry errors for which `retryable` returns `true`.
+    pub fn retry_if<F>(mut self, retryable: F) -> Self
+    where
+        F: Fn(&reqwest::Error) -> bool + Send + Sync + 'static,
+    {
+        self.retryable = Arc::new(retryable);
+        self
+    }
+
+    /// Back off with `backoff` after failures of `kind` instead.
+    pub fn backoff_for(mut self, kind: FailureKind, backoff: Backoff) -> Self {
+        self.kinds.entry(kind).or_default().backoff = Some(backoff);
+        self
+    }
+
+    /// Give up after `max_attempts` consecutive attempts when the last one
+    /// failed with `kind`, on top of the overall limits.
+    pub fn max_attempts_for(mut self, kind: FailureKind, max_attempts: u32) -> Self {
+        self.kinds.entry(kind).or_default().max_attempts = Some(max_attempts.max(1));
+        self
+    }
+
+    /// The default policy tuned per cause: DNS failures, which take a while to
+    /// clear up, back off longer; resets, usually a one-off, are retried right
+    /// away a few times; TLS errors rarely fix themselves and are retried once.
+    pub fn adaptive() -> Self {
+        Self::default()
+            .backoff_for(
+                FailureKind::Dns,
+                Backoff::exponential(Duration::from_secs(5), Duration::from_secs(5 * 60))
+                    .with_jitter(0.5),
+            )
+            .backoff_for(
+                FailureKind::Reset,
+                Backoff::Fixed(Duration::from_millis(200)),
+            )
+            .max_attempts_for(FailureKind::Reset, 5)
+            .max_attempts_for(FailureKind::Tls, 2)
+    }
+
+    /// Upper bound on the delay a server can request through `Retry-After`.
+    pub fn max_retry_after(mut self, max_retry_after: Duration) -> Self {
+        self.max_retry_after = max_retry_after;
+        self
+    }
+
+    /// Whether the budget allows another try after `failures` consecutive
+    /// failures, the first of which happened at `since`.
+    fn may_retry(&self, failures: u32, since: Option<Instant>) -> bool {
+        let elapsed = since.map_or(Duration::ZERO, |since| since.elapsed());
+        !matches!(self.max_attempts, Some(max) if failures >= max)
+            && !matches!(self.max_elapsed, Some(max) if elapsed >= max)
+    }
+
+    fn delay(&self, failures: u32) -> Duration {
+        self.backoff.delay(failures)
+    }
+
+    /// [`Self::may_retry`] after the last failure was of `kind`.
+    fn may_retry_kind(&self, kind: FailureKind, failures: u32, since: Option<Instant>) -> bool {
+        let max = self.kinds.get(&kind).and_then(|kind| kind.max_attempts);
+        self.may_retry(failures, since) && !matches!(max, Some(max) if failures >= max)
+    }
+
+    /// [`Self::delay`] after a failure of `kind`.
+    fn delay_kind(&self, kind: FailureKind, failures: u32) -> Duration {
+        match self.kinds.get(&kind).and_then(|kind| kind.backoff.as_ref()) {
+            Some(backoff) => backoff.delay(failures),
+            None => self.delay(failures),
+        }
+    }
+
+    /// Delay before retrying a throttled `response`: the server's `Retry-After`
+    /// (capped to `max_retry_after`) if present, the regular backoff otherwise.
+    fn throttled_delay(&self, response: &reqwest::Response, failures: u32) -> Duration {
+        response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after)
+            .map_or_else(
+                || self.delay(failures),
+                |delay| delay.min(self.max_retry_after),
+            )
+    }
+}
+
+/// Statuses telling us to come back later rather than that the request is wrong.
+fn is_throttled(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+fn is_unauthorized(status: StatusCode) -> bool {
+    status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN
+}
+
+/// Parse a `Retry-After` value, either delay-seconds or an HTTP-date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // a date in the past means "now"
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Errors that are worth retrying: anything but malformed requests, redirect
+/// loops, error statuses and blocked addresses.
+pub fn is_transient(err: &reqwest::Error) -> bool {
+    !err.is_builder()
+        && !err.is_redirect()
+        && !err.is_status()
+        && guard::blocked_address(err).is_none()
+}
+
+/// Per-host connection limits of a [`Client`].
+#[derive(Clone, Debug, Default)]
+struct HostLimits {
+    default: Option<usize>,
+    overrides: HashMap<String, usize>,
+    // created on first use and shared by clones of the client
+    semaphores: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+    request_rate: Option<(usize, Duration)>,
+    // start times of the recent and scheduled requests to each host
+    requests: Arc<Mutex<HashMap<String, VecDeque<Instant>>>>,
+}
+
+impl HostLimits {
+    /// Wait for a free connection slot for the host of `url`, if it's limited.
+    async fn acquire(&self, url: &Url) -> Option<OwnedSemaphorePermit> {
+        let semaphore = self.semaphore(url)?;
+        semaphore.acquire_owned().await.ok()
+    }
+
+    /// Wait until the host of `url` may be sent another request.
+    async fn pace(&self, url: &Url) {
+        let (Some((max, per)), Some(host)) = (self.request_rate, url.host_str()) else {
+            return;
+        };
+        let slot = {
+            let mut requests = self.requests.lock().unwrap();
+            let times = requests.entry(host.to_owned()).or_default();
+            let now = Instant::now();
+            while matches!(times.front(), Some(&time) if time + per <= now) {
+                times.pop_front();
+            }
+            // the window starting with the request `max` places back must be
+            // over; slots are handed out in order, so waiting is first come,
+            // first served
+            let slot = match times.len().checked_sub(max) {
+                Some(i) => (times[i] + per).max(now),
+                None => now,
+            };
+            times.push_back(slot);
+            slot
+        };
+        sleep_until(slot).await;
+    }
+
+    fn semaphore(&self, url: &Url) -> Option<Arc<Semaphore>> {
+        let host = url.host_str()?;
+        let max = self.overrides.get(host).copied().or(self.default)?;
+        let mut semaphores = self.semaphores.lock().unwrap();
+        let semaphore = semaphores
+            .entry(host.to_owned())
+            .or_insert_with(|| Arc::new(Semaphore::new(max)));
+        Some(semaphore.clone())
+    }
+}
+
+/// What to do with plaintext `http://` URLs, see
+/// [`ClientBuilder::plaintext`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlaintextPolicy {
+    #[default]
+    Allow,
+    /// Fail with [`Error::Plaintext`], for redirects too.
+    Reject,
+    /// Request the `https://` URL instead, for redirects too.
+    Upgrade,
+}
+
+/// Settings a `reqwest::Client` is built from, kept so a request can rebuild
+/// it with its own overrides.
+#[derive(Clone, Debug, Default)]
+struct ClientConfig {
+    proxies: Vec<reqwest::Proxy>,
+    no_proxy: bool,
+    // shared by every client built from the config
+    cookies: Option<Arc<Jar>>,
+    user_agent: Option<String>,
+    connect_timeout: Option<Duration>,
+    // addresses used instead of resolving these domains
+    resolve: Vec<(String, SocketAddr)>,
+    resolver: Option<Resolver>,
+    identity: Option<Identity>,
+    root_certificates: Vec<Certificate>,
+    no_built_in_roots: bool,
+    // SHA-256 of the public keys the server's certificate may have
+    pinned_keys: Vec<[u8; 32]>,
+    #[cfg(feature = "http3")]
+    http3: bool,
+    guard: AddressGuard,
+    plaintext: PlaintextPolicy,
+    max_redirects: Option<usize>,
+    // wraps the client, so it's kept along with what built it
+    middleware: Middlewares,
+}
+
+/// Custom DNS resolver shared by every client built from a config.
+#[derive(Clone)]
+struct Resolver(Arc<dyn Resolve>);
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        self.0.resolve(name)
+    }
+}
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Resolver(..)")
+    }
+}
+
+impl ClientConfig {
+    fn build(&self) -> reqwest::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        // `no_proxy` clears the proxies added before it
+        if self.no_proxy {
+            builder = builder.no_proxy();
+        }
+        for proxy in &self.proxies {
+            builder = builder.proxy(proxy.clone());
+        }
+        if let Some(cookies) = &self.cookies {
+            builder = builder.cookie_provider(cookies.clone());
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent.clone());
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if self.guard.enabled {
+            let resolver = self.guard.resolver(self.resolver.clone());
+            builder = builder.dns_resolver(Arc::new(resolver));
+        } else if let Some(resolver) = &self.resolver {
+            builder = builder.dns_resolver(Arc::new(resolver.clone()));
+        }
+        if self.guard.enabled
+            || self.plaintext != PlaintextPolicy::Allow
+            || self.max_redirects.is_some()
+        {
+            builder = builder.redirect(self.redirect_policy());
+        }
+        for (domain, addr) in &self.resolve {
+            builder = builder.resolve(domain, *addr);
+        }
+        if let Some(identity) = &self.identity {
+            builder = builder.identity(identity.clone());
+        }
+        for certificate in &self.root_certificates {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        if self.no_built_in_roots {
+            builder = builder.tls_built_in_root_certs(false);
+        }
+        if !self.pinned_keys.is_empty() {
+            builder = builder.tls_info(true);
+        }
+        #[cfg(feature = "http3")]
+        if self.http3 {
+            builder = builder.http3_prior_knowledge();
+        }
+        builder.build()
+    }
+
+    /// reqwest's default policy of following up to 10 redirects, with the
+    /// configured limit, plaintext policy and address guard.
+    fn redirect_policy(&self) -> redirect::Policy {
+        let max_redirects = self.max_redirects.unwrap_or(10);
+        let plaintext = self.plaintext;
+        let guard = self.guard.clone();
+        redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                let mut chain = attempt.previous().to_vec();
+                chain.push(attempt.url().clone());
+                attempt.error(RedirectError::TooMany(chain))
+            } else if plaintext != PlaintextPolicy::Allow && attempt.url().scheme() == "http" {
+                let target = attempt.url().clone();
+                attempt.error(RedirectError::Plaintext(target))
+            } else if let Some(addr) = guard.blocked_url(attempt.url()) {
+                attempt.error(BlockedAddress(addr))
+            } else {
+                attempt.follow()
+            }
+        })
+    }
+
+    /// Whether the certificate `response` was received with has a pinned
+    /// public key, if any are pinned.
+    fn is_pinned(&self, response: &reqwest::Response) -> bool {
+        if self.pinned_keys.is_empty() {
+            return true;
+        }
+        let key = response
+            .extensions()
+            .get::<TlsInfo>()
+            .and_then(TlsInfo::peer_certificate)
+            .and_then(spki::sha256);
+        matches!(key, Some(key) if self.pinned_keys.contains(&key))
+    }
+}
+
+/// Settings every request from a [`Client`] starts with; each can still be
+/// overridden on the [`RequestBuilder`].
+#[derive(Clone, Debug, Default)]
+struct RequestDefaults {
+    retry: RetryPolicy,
+    timeout: Option<Duration>,
+    stall_timeout: Option<Duration>,
+    rate_limit: Option<RateLimit>,
+    fallback: FallbackPolicy,
+    require_validator: bool,
+}
+
+/// Builds a [`Client`] along with the `reqwest::Client` underneath it.
+#[derive(Debug, Default)]
+pub struct ClientBuilder {
+    config: ClientConfig,
+    defaults: RequestDefaults,
+    host_limits: HostLimits,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send requests through `proxy`, e.g. `reqwest::Proxy::all("socks5://…")`.
+    /// May be called several times; the first proxy matching a URL is used.
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.config.proxies.push(proxy);
+        self
+    }
+
+    /// Ignore the system proxy settings, including `HTTP_PROXY` and friends.
+    pub fn no_proxy(mut self) -> Self {
+        self.config.no_proxy = true;
+        self
+    }
+
+    /// Keep cookies set by any response, the first attempt's or a resume's,
+    /// and send them with later requests and resumes.
+    pub fn cookie_store(mut self, enable: bool) -> Self {
+        self.config.cookies = enable.then(Default::default);
+        self
+    }
+
+    /// Like [`ClientBuilder::cookie_store`], with a jar that may be shared with
+    /// other clients or seeded with cookies.
+    pub fn cookie_jar(mut self, jar: Arc<Jar>) -> Self {
+        self.config.cookies = Some(jar);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.config.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Timeout for connecting, on every attempt.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.config.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// Client certificate to authenticate with on every attempt.
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.config.identity = Some(identity);
+        self
+    }
+
+    /// Trust servers whose certificates `certificate` signed.
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.config.root_certificates.push(certificate);
+        self
+    }
+
+    /// Whether to trust the system's root certificates as well as those added
+    /// with [`Self::add_root_certificate`]; they are by default.
+    pub fn tls_built_in_root_certs(mut self, built_in: bool) -> Self {
+        self.config.no_built_in_roots = !built_in;
+        self
+    }
+
+    /// Only accept servers whose certificate has the public key with this
+    /// SHA-256 hash, see [`spki`]; call again to pin more than one key. Every
+    /// attempt is checked, resumes included, and fails with
+    /// [`Error::UnpinnedCertificate`] otherwise. Only the certificate of the
+    /// server that answers last, after redirects, is checked.
+    pub fn pin_public_key(mut self, sha256: [u8; 32]) -> Self {
+        self.config.pinned_keys.push(sha256);
+        self
+    }
+
+    /// Send requests over HTTP/3 (QUIC), whose connections survive switching
+    /// networks. If an attempt fails, that request falls back to HTTP/2 or
+    /// HTTP/1.1 for the rest of its attempts, resuming where it was. Needs
+    /// building with `RUSTFLAGS="--cfg reqwest_unstable"`, like reqwest's own
+    /// `http3` feature.
+    #[cfg(feature = "http3")]
+    pub fn http3_prior_knowledge(mut self) -> Self {
+        self.config.http3 = true;
+        self
+    }
+
+    /// Pass every request, resumes included, through `middleware`; the first
+    /// one added sees requests first.
+    pub fn with(mut self, middleware: impl Middleware) -> Self {
+        self.config.middleware.push(middleware);
+        self
+    }
+
+    /// Connect to `addr` for `domain` instead of resolving it; the port of the
+    /// URL is used unless `addr` has one other than 0.
+    pub fn resolve(mut self, domain: impl Into<String>, addr: SocketAddr) -> Self {
+        self.config.resolve.push((domain.into(), addr));
+        self
+    }
+
+    /// Resolve host names with `resolver` rather than the system's resolver.
+    pub fn dns_resolver<R: Resolve + 'static>(mut self, resolver: Arc<R>) -> Self {
+        self.config.resolver = Some(Resolver(resolver));
+        self
+    }
+
+    /// Reject plaintext `http://` URLs or upgrade them to `https://`, for the
+    /// first request, redirects, mirrors and every resume.
+    pub fn plaintext(mut self, plaintext: PlaintextPolicy) -> Self {
+        self.config.plaintext = plaintext;
+        self
+    }
+
+    /// Follow at most `max` redirects (10 by default), failing with
+    /// [`Error::TooManyRedirects`] after that; 0 follows none.
+    pub fn max_redirects(mut self, max: usize) -> Self {
+        self.config.max_redirects = Some(max);
+        self
+    }
+
+    /// Refuse to connect to loopback, private (RFC 1918, carrier-grade NAT
+    /// and unique local IPv6), link-local and broadcast addresses, including
+    /// those mapped to IPv6 or behind NAT64, failing with
+    /// [`Error::BlockedAddress`]. Host names are checked as they're resolved,
+    /// for redirects and resumes too, except those given to
+    /// [`ClientBuilder::resolve`]. Requests through a proxy aren't covered,
+    /// as the proxy resolves them.
+    pub fn block_private_addresses(mut self, block: bool) -> Self {
+        self.config.guard.enabled = block;
+        self
+    }
+
+    /// Let `host`, a name or an IP address, through
+    /// [`ClientBuilder::block_private_addresses`], e.g. for a local server.
+    pub fn allow_private_host(mut self, host: impl Into<String>) -> Self {
+        self.config.guard.allowed_hosts.push(host.into());
+        self
+    }
+
+    /// Default retry policy for requests created from the client.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.defaults.retry = retry;
+        self
+    }
+
+    /// Default for [`RequestBuilder::timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.defaults.timeout = Some(timeout);
+        self
+    }
+
+    /// Default for [`RequestBuilder::stall_timeout`].
+    pub fn stall_timeout(mut self, stall_timeout: Duration) -> Self {
+        self.defaults.stall_timeout = Some(stall_timeout);
+        self
+    }
+
+    /// Cap the combined speed of every body stream from the client, see
+    /// [`RequestBuilder::rate_limit`].
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.defaults.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Default for [`RequestBuilder::on_mismatch`].
+    pub fn on_mismatch(self, on_mismatch: MismatchPolicy) -> Self {
+        self.fallback(on_mismatch.into())
+    }
+
+    /// Default for [`RequestBuilder::fallback`].
+    pub fn fallback(mut self, fallback: FallbackPolicy) -> Self {
+        self.defaults.fallback = fallback;
+        self
+    }
+
+    /// Default for [`RequestBuilder::require_validator`].
+    pub fn require_validator(mut self, require_validator: bool) -> Self {
+        self.defaults.require_validator = require_validator;
+        self
+    }
+
+    /// See [`Client::max_connections_per_host`].
+    pub fn max_connections_per_host(mut self, max: usize) -> Self {
+        self.host_limits.default = Some(max.max(1));
+        self
+    }
+
+    /// See [`Client::max_requests_per_host`].
+    pub fn max_requests_per_host(mut self, max: usize, per: Duration) -> Self {
+        self.host_limits.request_rate = Some((max.max(1), per));
+        self
+    }
+
+    pub fn build(self) -> Result<Client> {
+        let client = self
+            .config
+            .build()
+            .map_err(|err| Error::Client(Arc::new(err)))?;
+        Ok(Client {
+            client,
+            config: self.config,
+            defaults: self.defaults,
+            host_limits: self.host_limits,
+        })
+    }
+}
+
+/// A `reqwest::Client` whose requests resume on failure.
+#[derive(Clone, Debug, Default)]
+pub struct Client {
+    client: reqwest::Client,
+    config: ClientConfig,
+    defaults: RequestDefaults,
+    host_limits: HostLimits,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    pub fn from_reqwest(client: reqwest::Client) -> Self {
+        Client {
+            client,
+            config: ClientConfig::default(),
+            defaults: RequestDefaults::default(),
+            host_limits: HostLimits::default(),
+        }
+    }
+
+    /// Default retry policy for requests created from this client.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.defaults.retry = retry;
+        self
+    }
+
+    /// Cookies kept by this client, if [`ClientBuilder::cookie_store`] is on.
+    pub fn cookie_jar(&self) -> Option<&Arc<Jar>> {
+        self.config.cookies.as_ref()
+    }
+
+    /// Allow at most `max` simultaneous connections to any one host across
+    /// all requests from this client and its clones. Further requests wait for
+    /// one of them to finish; a body stream holds its slot until it ends or is
+    /// dropped.
+    pub fn max_connections_per_host(mut self, max: usize) -> Self {
+        self.host_limits.default = Some(max.max(1));
+        self
+    }
+
+    /// Allow at most `max` requests, retries included, to any one host within
+    /// each window of `per`, e.g. to stop many failing downloads from all
+    /// hammering the same server at once. Requests over the budget wait their
+    /// turn.
+    pub fn max_requests_per_host(mut self, max: usize, per: Duration) -> Self {
+        self.host_limits.request_rate = Some((max.max(1), per));
+        self
+    }
+
+    /// Override [`Client::max_connections_per_host`] for `host`.
+    pub fn max_connections_for_host(mut self, host: impl Into<String>, max: usize) -> Self {
+        self.host_limits.overrides.insert(host.into(), max.max(1));
+        self
+    }
+
+    pub fn get(&self, url: Url) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    /// A `POST`, for APIs that hand out a body in response to one; set what
+    /// to send with [`RequestBuilder::body`].
+    pub fn post(&self, url: Url) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    pub fn put(&self, url: Url) -> RequestBuilder {
+        self.request(Method::PUT, url)
+    }
+
+    /// Continue a [`RequestBuilder::download_to_file`] to `path` that was
+    /// interrupted, e.g. by the app exiting, from the URL and validators saved
+    /// next to the partial file. Headers and credentials aren't saved; set them
+    /// on a new request to the same URL instead if it needs them.
+    pub async fn resume_from_sidecar(&self, path: impl AsRef<Path>) -> Result<Download> {
+        let path = path.as_ref();
+        let sidecar_err = |source| Error::Sidecar {
+            path: sidecar_path(path),
+            source,
+        };
+        let sidecar = Sidecar::read(path).await.map_err(sidecar_err)?;
+        let url = Url::parse(&sidecar.url).map_err(|err| {
+            sidecar_err(std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+        })?;
+        self.get(url).download_to_file(path).await
+    }
+
+    pub fn request(&self, method: Method, mut url: Url) -> RequestBuilder {
+        let defaults = self.defaults.clone();
+        if self.config.plaintext == PlaintextPolicy::Upgrade {
+            upgrade(&mut url);
+        }
+        RequestBuilder {
+            request: Request {
+                client: self.client.clone(),
+                config: self.config.clone(),
+                method,
+                url,
+                headers: HeaderMap::new(),
+                auth: None,
+                timeout: defaults.timeout,
+                deadline: None,
+                cancel: None,
+                rate_limit: defaults.rate_limit,
+                first: 0,
+                start: 0,
+                end: None,
+                verify_overlap: 0,
+                chunk_size: None,
+                if_range: None,
+                checksum: None,
+                #[cfg(feature = "digest")]
+                digest: None,
+                max_size: None,
+                min_free_space: None,
+                error: None,
+                retry: defaults.retry,
+                fallback: defaults.fallback,
+                require_validator: defaults.require_validator,
+                skip_ignored_range: true,
+                resume_without_accept_ranges: false,
+                identity_encoding: false,
+                mirrors: Vec::new(),
+                pin_redirects: false,
+                pin_ip: false,
+                pinned: None,
+                stall_timeout: defaults.stall_timeout,
+                hedge_after: None,
+                on_event: None,
+                map_request: None,
+                refresh_auth: None,
+                transfer: TransferHandle::default(),
+                progress: None,
+                body: None,
+                host_limits: self.host_limits.clone(),
+            },
+        }
+    }
+}
+
+/// What to do when a resumed request returns the whole file instead of the
+/// remaining range because it changed on the server since the transfer
+/// started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MismatchPolicy {
+    /// End the stream with [`Error::ValidatorMismatch`].
+    #[default]
+    Fail,
+    /// Start streaming the new body from its first byte. Consumers must watch
+    /// [`Decoder::restarts`] and discard everything received before a restart.
+    Restart,
+}
+
+impl From<MismatchPolicy> for FallbackPolicy {
+    fn from(policy: MismatchPolicy) -> Self {
+        match policy {
+            MismatchPolicy::Fail => FallbackPolicy::Error,
+            MismatchPolicy::Restart => FallbackPolicy::RestartFromZero { max_restarts: None },
+        }
+    }
+}
+
+type FallbackCallback = Arc<dyn Fn(&Error) -> bool + Send + Sync>;
+
+/// What to do when a transfer can't continue where it stopped: the server
+/// doesn't support ranges, the file changed, or a range request was answered
+/// with a different range or the whole body. Generalizes [`MismatchPolicy`].
+#[derive(Clone, Default)]
+pub enum FallbackPolicy {
+    /// Fail with the error explaining why resuming is impossible.
+    #[default]
+    Error,
+    /// Start over from the first byte, at most `max_restarts` times if set.
+    /// Consumers must watch [`Decoder::restarts`] and discard everything
+    /// received before a restart.
+    RestartFromZero { max_restarts: Option<u32> },
+    /// Let the callback decide, given the error the transfer would otherwise
+    /// fail with: `true` starts over like `RestartFromZero`.
+    AskCallback(FallbackCallback),
+}
+
+impl FallbackPolicy {
+    pub fn ask<F>(callback: F) -> Self
+    where
+        F: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        FallbackPolicy::AskCallback(Arc::new(callback))
+    }
+
+    /// Whether to start over after `err` made resuming impossible, having
+    /// done so `restarts` times already.
+    fn allows_restart(&self, err: &Error, restarts: u32) -> bool {
+        match self {
+            FallbackPolicy::Error => false,
+            FallbackPolicy::RestartFromZero { max_restarts } => {
+                !matches!(max_restarts, Some(max) if restarts >= *max)
+            }
+            FallbackPolicy::AskCallback(callback) => callback(err),
+        }
+    }
+}
+
+impl fmt::Debug for FallbackPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FallbackPolicy::Error => f.write_str("Error"),
+            FallbackPolicy::RestartFromZero { max_restarts } => f
+                .debug_struct("RestartFromZero")
+                .field("max_restarts", max_restarts)
+                .finish(),
+            FallbackPolicy::AskCallback(_) => f.write_str("AskCallback(..)"),
+        }
+    }
+}
+
+/// An error from building a request, deferred until `send` like `reqwest`
+/// does.
+#[derive(Clone, Debug)]
+enum Deferred {
+    Query(serde_urlencoded::ser::Error),
+    Client(Arc<reqwest::Error>),
+}
+
+/// Everything needed to (re-)issue a request.
+#[derive(Clone, Debug)]
+struct Request {
+    client: reqwest::Client,
+    // what `client` was built from, for per-request overrides
+    config: ClientConfig,
+    method: Method,
+    url: Url,
+    headers: HeaderMap,
+    auth: Option<Auth>,
+    timeout: Option<Duration>,
+    deadline: Option<Duration>,
+    cancel: Option<CancellationToken>,
+    rate_limit: Option<RateLimit>,
+    // byte range of the body to fetch, `end` being exclusive; `start` moves
+    // forward when resuming while `first` stays at the start of the window
+    first: u64,
+    start: u64,
+    end: Option<u64>,
+    verify_overlap: u64,
+    // largest chunk a body stream yields
+    chunk_size: Option<usize>,
+    // validator for a request that starts past the first byte
+    if_range: Option<HeaderValue>,
+    checksum: Option<Checksum>,
+    // digest to compute for the summary, see `RequestBuilder::digest`
+    #[cfg(feature = "digest")]
+    digest: Option<DigestAlgorithm>,
+    max_size: Option<u64>,
+    min_free_space: Option<u64>,
+    error: Option<Deferred>,
+    retry: RetryPolicy,
+    fallback: FallbackPolicy,
+    require_validator: bool,
+    skip_ignored_range: bool,
+    resume_without_accept_ranges: bool,
+    identity_encoding: bool,
+    // URLs of the same content to fall back to, in order
+    mirrors: Vec<Url>,
+    pin_redirects: bool,
+    pin_ip: bool,
+    // where `url` redirected to, requested directly until it stops working
+    pinned: Option<Url>,
+    stall_timeout: Option<Duration>,
+    hedge_after: Option<Duration>,
+    on_event: Option<EventHook>,
+    map_request: Option<MapRequest>,
+    refresh_auth: Option<RefreshHook>,
+    transfer: TransferHandle,
+    // shared with the caller, see `RequestBuilder::progress_handle`
+    progress: Option<ProgressHandle>,
+    body: Option<ReplayBody>,
+    host_limits: HostLimits,
+}
+
+impl Request {
+    fn builder(&self) -> reqwest::RequestBuilder {
+        let mut headers = self.headers.clone();
+        if self.identity_encoding {
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+        }
+        let mut builder = self
+            .client
+            .request(
+                self.method.clone(),
+                self.pinned.as_ref().unwrap_or(&self.url).clone(),
+            )
+            .headers(headers);
+        if let Some(body) = &self.body {
+            builder = builder.body(body.make());
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        match &self.auth {
+            Some(Auth::Bearer(token)) => builder.bearer_auth(token),
+            Some(Auth::Basic(username, password)) => {
+                builder.basic_auth(username, password.as_ref())
+            }
+            None => builder,
+        }
+    }
+
+    /// Request for attempt number `attempt`, asking for the body from `offset`
+    /// if that isn't the start.
+    fn ranged(
+        &self,
+        offset: u64,
+        validator: Option<&HeaderValue>,
+        attempt: u32,
+    ) -> reqwest::RequestBuilder {
+        let mut builder = self.builder();
+        if offset > 0 || self.end.is_some() {
+            let last = self.end.map(|end| (end - 1).to_string());
+            builder = builder.header(
+                RANGE,
+                format!("bytes={}-{}", offset, last.unwrap_or_default()),
+            );
+            if let Some(validator) = validator {
+                builder = builder.header(IF_RANGE, validator.clone());
+            }
+        }
+        match &self.map_request {
+            Some(map_request) => (map_request.0)(builder, attempt, offset),
+            None => builder,
+        }
+    }
+
+    /// Send the request, retrying connection errors and throttled responses
+    /// and moving on to the next mirror when one fails.
+    async fn execute(&mut self) -> Result<reqwest::Response> {
+        if let Some(err) = self.error.clone() {
+            return Err(match err {
+                Deferred::Query(err) => err.into(),
+                Deferred::Client(err) => Error::Client(err),
+            });
+        }
+        let mut failures = 0;
+        let mut failing_since = None;
+        let mut attempt = 0;
+        loop {
+            if let Err(err) = self.check_url() {
+                if !self.next_mirror(&err) {
+                    return Err(err);
+                }
+                continue;
+            }
+            self.host_limits.pace(&self.url).await;
+            attempt += 1;
+            let retry = &self.retry;
+            let builder = self.ranged(self.start, self.if_range.as_ref(), attempt);
+            let err = match self.send(builder).await {
+                Ok(response) if !self.config.is_pinned(&response) => Error::UnpinnedCertificate {
+                    url: self.url.clone(),
+                    pos: 0,
+                },
+                Ok(response)
+                    if is_throttled(response.status())
+                        && retry.may_retry(failures + 1, failing_since) =>
+                {
+                    failures += 1;
+                    failing_since.get_or_insert_with(Instant::now);
+                    sleep(retry.throttled_delay(&response, failures)).await;
+                    continue;
+                }
+                // a signed URL that expired; ask the original URL for a new one
+                Ok(response)
+                    if self.pinned.is_some()
+                        && matches!(
+                            response.status(),
+                            StatusCode::FORBIDDEN | StatusCode::GONE
+                        ) =>
+                {
+                    let pinned = self.pinned.take().unwrap();
+                    log::info!(
+                        "{} returned {}, resolving {} again",
+                        pinned,
+                        response.status(),
+                        self.url
+                    );
+                    continue;
+                }
+                Ok(response)
+                    if self.refresh_auth.is_some()
+                        && is_unauthorized(response.status())
+                        && retry.may_retry(failures + 1, failing_since) =>
+                {
+                    let refresh_auth = self.refresh_auth.clone().unwrap();
+                    failures += 1;
+                    failing_since.get_or_insert_with(Instant::now);
+                    log::info!(
+                        "{} returned {}, refreshing credentials",
+                        self.url,
+                        response.status()
+                    );
+                    match (refresh_auth.0)(response.status()).await {
+                        Some(refresh) => {
+                            self.refresh(refresh);
+                            continue;
+                        }
+                        None if self.mirrors.is_empty() => return Ok(response),
+                        None => Error::network(&self.url, 0, status_error(response)),
+                    }
+                }
+                Ok(response)
+                    if self.mirrors.is_empty()
+                        || !(response.status().is_client_error()
+                            || response.status().is_server_error()) =>
+                {
+                    self.pin(&response);
+                    return Ok(response);
+                }
+                Ok(response) => Error::network(&self.url, 0, status_error(response)),
+                Err(err)
+                    if self.config.plaintext == PlaintextPolicy::Upgrade
+                        && find_source::<RedirectError>(&err).is_some() =>
+                {
+                    if self.upgrade_redirect(&err) {
+                        continue;
+                    }
+                    Error::network(&self.url, 0, err)
+                }
+                #[cfg(feature = "http3")]
+                Err(err) if self.config.http3 => match self.fall_back_from_http3(&err) {
+                    Ok(()) => continue,
+                    Err(err) => err,
+                },
+                Err(err) => {
+                    failures += 1;
+                    let since = *failing_since.get_or_insert_with(Instant::now);
+                    let kind = FailureKind::of(&err);
+                    if !(retry.retryable)(&err) {
+                        Error::network(&self.url, 0, err)
+                    } else if !retry.may_retry_kind(kind, failures, Some(since)) {
+                        Error::TooManyRetries {
+                            url: self.url.clone(),
+                            pos: 0,
+                            attempts: failures,
+                            source: err,
+                        }
+                    } else {
+                        log::debug!("{} failed ({:?}): {}", self.url, kind, err);
+                        sleep(retry.delay_kind(kind, failures)).await;
+                        continue;
+                    }
+                }
+            };
+            if !self.next_mirror(&err) {
+                return Err(err);
+            }
+            failures = 0;
+            failing_since = None;
+        }
+    }
+
+    /// Report `event` to the observer, if there is one, and to `tracing`.
+    fn emit(&self, event: impl FnOnce() -> Event) {
+        if self.on_event.is_none() && !cfg!(any(feature = "tracing", feature = "metrics")) {
+            return;
+        }
+        let event = event();
+        #[cfg(feature = "tracing")]
+        trace_event(&self.url, &event);
+        #[cfg(feature = "metrics")]
+        count_event(&self.url, &event);
+        if let Some(on_event) = &self.on_event {
+            (on_event.0)(&event);
+        }
+    }
+
+    /// Send `builder` through the middleware, hedged if it's set up and the
+    /// request can be sent twice.
+    fn send(&self, builder: reqwest::RequestBuilder) -> Sending {
+        let hedge = self.hedge_after.and_then(|after| {
+            let hedge = self.config.middleware.send(builder.try_clone()?);
+            Some((hedge, after))
+        });
+        let send = self.config.middleware.send(builder);
+        match hedge {
+            Some((hedge, after)) => Box::pin(hedged(send, hedge, after)),
+            None => send,
+        }
+    }
+
+    /// Use the credentials from [`RequestBuilder::refresh_auth`] from now on.
+    fn refresh(&mut self, refresh: Refresh) {
+        match refresh {
+            Refresh::BearerToken(token) => self.auth = Some(Auth::Bearer(token)),
+            Refresh::Url(url) => {
+                self.url = url;
+                self.pinned = None;
+            }
+        }
+    }
+
+    /// Apply the plaintext policy and address guard to the URL about to be
+    /// requested, which may be a mirror or a refreshed URL.
+    fn check_url(&mut self) -> Result<()> {
+        if self.url.scheme() == "http" {
+            match self.config.plaintext {
+                PlaintextPolicy::Allow => {}
+                PlaintextPolicy::Reject => {
+                    return Err(Error::Plaintext {
+                        url: self.url.clone(),
+                        pos: 0,
+                        target: self.url.clone(),
+                    })
+                }
+                PlaintextPolicy::Upgrade => upgrade(&mut self.url),
+            }
+        }
+        match self.config.guard.blocked_url(&self.url) {
+            Some(addr) => Err(Error::BlockedAddress {
+                url: self.url.clone(),
+                pos: 0,
+                addr,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Request the `https://` URL a redirect to plaintext HTTP pointed at
+    /// from now on, unless that's what was just requested.
+    fn upgrade_redirect(&mut self, err: &reqwest::Error) -> bool {
+        let Some(RedirectError::Plaintext(target)) = find_source(err) else {
+            return false;
+        };
+        let mut target = target.clone();
+        upgrade(&mut target);
+        if self.pinned.as_ref() == Some(&target) {
+            return false;
+        }
+        log::info!("{} redirected to {}, upgrading to HTTPS", self.url, target);
+        self.pinned = Some(target);
+        true
+    }
+
+    /// Switch to the next mirror after `err`, if any is left.
+    fn next_mirror(&mut self, err: &Error) -> bool {
+        if self.mirrors.is_empty() {
+            return false;
+        }
+        let mirror = self.mirrors.remove(0);
+        log::warn!("{}, switching to {}", err, mirror);
+        self.url = mirror;
+        self.pinned = None;
+        true
+    }
+
+    /// Stop using HTTP/3 for this request after it failed with `err`.
+    #[cfg(feature = "http3")]
+    fn fall_back_from_http3(&mut self, err: &reqwest::Error) -> Result<()> {
+        log::warn!("{} over HTTP/3 failed, falling back: {}", self.url, err);
+        let mut config = self.config.clone();
+        config.http3 = false;
+        self.client = config.build().map_err(|err| Error::Client(Arc::new(err)))?;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Send later attempts to the address `response` came from, by rebuilding
+    /// the client to resolve its host to it.
+    fn pin_address(&mut self, response: &reqwest::Response) {
+        let (Some(domain), Some(addr)) = (response.url().domain(), response.remote_addr()) else {
+            return;
+        };
+        let mut config = self.config.clone();
+        config.resolve.push((domain.to_owned(), addr));
+        match config.build() {
+            Ok(client) => {
+                log::debug!("pinning {} to {}", domain, addr);
+                self.client = client;
+                self.config = config;
+            }
+            Err(err) => log::warn!("not pinning {} to {}: {}", domain, addr, err),
+        }
+    }
+
+    /// Remember where `response` was redirected to, if redirects are pinned.
+    fn pin(&mut self, response: &reqwest::Response) {
+        if self.pin_redirects && self.pinned.is_none() && response.url() != &self.url {
+            log::debug!("pinning {} to {}", self.url, response.url());
+            self.pinned = Some(response.url().clone());
+        }
+    }
+}
+
+/// Something that happened to a request or its body stream, reported to the
+/// callback set with [`RequestBuilder::on_event`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A request for the body, or what's left of it, succeeded. `url` is
+    /// where the response came from, which may be a mirror or where the
+    /// request was redirected.
+    Connected {
+        attempt: u32,
+        pos: u64,
+        status: StatusCode,
+        url: Url,
+    },
+    ChunkReceived {
+        pos: u64,
+        len: usize,
+    },
+    /// Attempt number `attempt` starts after `delay` because of `reason`,
+    /// a connection failure of kind `failure` if it was one.
+    Reconnecting {
+        attempt: u32,
+        pos: u64,
+        delay: Duration,
+        reason: String,
+        failure: Option<FailureKind>,
+    },
+    /// The stream gave up; it ends after yielding the error.
+    Failed {
+        pos: u64,
+        error: String,
+    },
+}
+
+#[cfg(feature = "tracing")]
+fn trace_event(url: &Url, event: &Event) {
+    match event {
+        Event::Connected {
+            attempt,
+            pos,
+            status,
+            url: from,
+        } => tracing::debug!(%url, attempt, pos, status = status.as_u16(), %from, "connected"),
+        Event::ChunkReceived { pos, len } => tracing::trace!(%url, pos, len, "chunk received"),
+        Event::Reconnecting {
+            attempt,
+            pos,
+            delay,
+            reason,
+            failure,
+        } => tracing::warn!(%url, attempt, pos, ?delay, %reason, ?failure, "reconnecting"),
+        Event::Failed { pos, error } => tracing::error!(%url, pos, %error, "failed"),
+    }
+}
+
+/// Host label of the metrics for `url`.
+#[cfg(feature = "metrics")]
+fn host_label(url: &Url) -> String {
+    url.host_str().unwrap_or_default().to_owned()
+}
+
+/// Update the `bytes_downloaded_total` and `resume_attempts_total` counters.
+#[cfg(feature = "metrics")]
+fn count_event(url: &Url, event: &Event) {
+    match event {
+        Event::ChunkReceived { len, .. } => {
+            metrics::counter!("bytes_downloaded_total", "host" => host_label(url))
+                .increment(*len as u64)
+        }
+        Event::Reconnecting { .. } => {
+            metrics::counter!("resume_attempts_total", "host" => host_label(url)).increment(1)
+        }
+        Event::Connected { .. } | Event::Failed { .. } => {}
+    }
+}
+
+type EventCallback = Arc<dyn Fn(&Event) + Send + Sync>;
+
+#[derive(Clone)]
+struct EventHook(EventCallback);
+
+impl fmt::Debug for EventHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventHook(..)")
+    }
+}
+
+type MapRequestFn =
+    Arc<dyn Fn(reqwest::RequestBuilder, u32, u64) -> reqwest::RequestBuilder + Send + Sync>;
+
+#[derive(Clone)]
+struct MapRequest(MapRequestFn);
+
+impl fmt::Debug for MapRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MapRequest(..)")
+    }
+}
+
+/// New credentials from the callback set with [`RequestBuilder::refresh_auth`],
+/// used for the attempt that failed and all later ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Refresh {
+    /// Replace the bearer token, or any other credentials, with this one.
+    BearerToken(String),
+    /// Request this URL instead, e.g. a freshly signed one.
+    Url(Url),
+}
+
+type RefreshFn = Arc<dyn Fn(StatusCode) -> BoxFuture<'static, Option<Refresh>> + Send + Sync>;
+
+#[derive(Clone)]
+struct RefreshHook(RefreshFn);
+
+impl fmt::Debug for RefreshHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RefreshHook(..)")
+    }
+}
+
+/// Request body sent again with every attempt.
+#[derive(Clone)]
+enum ReplayBody {
+    Bytes(Bytes),
+    Factory(Arc<dyn Fn() -> reqwest::Body + Send + Sync>),
+}
+
+impl ReplayBody {
+    fn make(&self) -> reqwest::Body {
+        match self {
+            ReplayBody::Bytes(bytes) => bytes.clone().into(),
+            ReplayBody::Factory(factory) => factory(),
+        }
+    }
+}
+
+impl fmt::Debug for ReplayBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayBody::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            ReplayBody::Factory(_) => f.write_str("Factory(..)"),
+        }
+    }
+}
+
+/// Credentials applied to every attempt; kept apart from the other headers so
+/// `reqwest` encodes them and marks them sensitive.
+#[derive(Clone)]
+enum Auth {
+    Bearer(String),
+    Basic(String, Option<String>),
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Bearer(_) => f.write_str("Bearer(..)"),
+            Auth::Basic(username, _) => f.debug_tuple("Basic").field(username).finish(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RequestBuilder {
+    request: Request,
+}
+
+impl RequestBuilder {
+    /// Add a header to the first request and every retry or resume.
+    pub fn header(mut self, key: HeaderName, value: HeaderValue) -> Self {
+        self.request.headers.insert(key, value);
+        self
+    }
+
+    /// Add several headers, replacing any previously set with the same name.
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.request.headers.extend(headers);
+        self
+    }
+
+    pub fn bearer_auth<T: fmt::Display>(mut self, token: T) -> Self {
+        self.request.auth = Some(Auth::Bearer(token.to_string()));
+        self
+    }
+
+    pub fn basic_auth<U: fmt::Display, P: fmt::Display>(
+        mut self,
+        username: U,
+        password: Option<P>,
+    ) -> Self {
+        self.request.auth = Some(Auth::Basic(
+            username.to_string(),
+            password.map(|password| password.to_string()),
+        ));
+        self
+    }
+
+    /// Send `body` with the request and again with every resume, for servers
+    /// that serve ranges of the answer to a `POST` or `PUT`.
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.request.body = Some(ReplayBody::Bytes(body.into()));
+        self
+    }
+
+    /// Like [`RequestBuilder::body`], for bodies that are streamed or too big
+    /// to keep around: `body` is called for every attempt and must produce
+    /// the same content each time.
+    pub fn body_with<F>(mut self, body: F) -> Self
+    where
+        F: Fn() -> reqwest::Body + Send + Sync + 'static,
+    {
+        self.request.body = Some(ReplayBody::Factory(Arc::new(body)));
+        self
+    }
+
+    /// Append `query` to the URL's query string, once for all attempts.
+    pub fn query<T: Serialize + ?Sized>(mut self, query: &T) -> Self {
+        match serde_urlencoded::to_string(query) {
+            Ok(encoded) if encoded.is_empty() => {}
+            Ok(encoded) => {
+                let query = match self.request.url.query() {
+                    Some(existing) if !existing.is_empty() => format!("{existing}&{encoded}"),
+                    _ => encoded,
+                };
+                self.request.url.set_query(Some(&query));
+            }
+            Err(err) => self.request.error = Some(Deferred::Query(err)),
+        }
+        self
+    }
+
+    /// Send this request and every resume of it through `proxy` instead of
+    /// the client's proxies. Builds a `reqwest::Client` of its own, so a
+    /// client made with [`Client::from_reqwest`] loses its other settings.
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        let mut config = self.request.config.clone();
+        config.proxies = vec![proxy];
+        match config.build() {
+            Ok(client) => {
+                self.request.client = client;
+                self.request.config = config;
+            }
+            Err(err) => self.request.error = Some(Deferred::Client(Arc::new(err))),
+        }
+        self
+    }
+
+    /// Timeout of each attempt, from connecting until the body is read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.request.timeout = Some(timeout);
+        self
+    }
+
+    /// Give up with [`Error::Timeout`] if the whole transfer, including every
+    /// retry and resume, takes longer than `deadline`.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.request.deadline = Some(deadline);
+        self
+    }
+
+    /// Abort the request, any pending retry and the body stream with
+    /// [`Error::Cancelled`] once `token` is cancelled.
+    pub fn cancel_token(mut self, token: CancellationToken) -> Self {
+        self.request.cancel = Some(token);
+        self
+    }
+
+    /// Like [`RequestBuilder::send`], but cancelling `token` aborts the request,
+    /// any pending retry and the body stream with [`Error::Cancelled`].
+    pub fn send_with_cancel(
+        &self,
+        token: CancellationToken,
+    ) -> impl Future<Output = Result<Response>> + Send + 'static {
+        let mut builder = RequestBuilder {
+            request: self.request.clone(),
+        };
+        builder.request.cancel = Some(token);
+        builder.send()
+    }
+
+    /// Limit the body stream to `bytes_per_sec`; change it while the transfer
+    /// runs through [`Decoder::rate_limit`].
+    pub fn limit_rate(self, bytes_per_sec: u64) -> Self {
+        self.rate_limit(RateLimit::new(Some(bytes_per_sec)))
+    }
+
+    /// Throttle the body stream with `rate_limit`, which may be shared between
+    /// transfers to cap their combined speed.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.request.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Override the client's retry policy for this request.
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.request.retry = retry;
+        self
+    }
+
+    /// Override only the backoff of this request's retry policy.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.request.retry.backoff = backoff;
+        self
+    }
+
+    /// Shorthand for [`RequestBuilder::fallback`] with no limit on restarts.
+    pub fn on_mismatch(self, on_mismatch: MismatchPolicy) -> Self {
+        self.fallback(on_mismatch.into())
+    }
+
+    /// What to do when the transfer can't be resumed where it stopped. The
+    /// default fails with the reason; see [`FallbackPolicy`].
+    pub fn fallback(mut self, fallback: FallbackPolicy) -> Self {
+        self.request.fallback = fallback;
+        self
+    }
+
+    /// Only resume if the server sent an `ETag` or `Last-Modified` to validate
+    /// the resumed range against; fail on interruption otherwise.
+    pub fn require_validator(mut self, require_validator: bool) -> Self {
+        self.request.require_validator = require_validator;
+        self
+    }
+
+    /// Some servers advertise `Accept-Ranges: bytes` but answer ranged requests
+    /// with the full, unchanged body. By default the bytes already received are
+    /// skipped; disable this to fall back according to
+    /// [`RequestBuilder::fallback`] instead.
+    pub fn skip_ignored_range(mut self, skip_ignored_range: bool) -> Self {
+        self.request.skip_ignored_range = skip_ignored_range;
+        self
+    }
+
+    /// Try to resume even if the server didn't send `Accept-Ranges: bytes`,
+    /// as some support ranges anyway. The response is validated like any other
+    /// resume, so a server that can't do it falls back according to
+    /// [`RequestBuilder::skip_ignored_range`] and [`RequestBuilder::fallback`].
+    pub fn resume_without_accept_ranges(mut self, resume_without_accept_ranges: bool) -> Self {
+        self.request.resume_without_accept_ranges = resume_without_accept_ranges;
+        self
+    }
+
+    /// Only fetch bytes `start..end` of the body, or everything from `start`
+    /// on if `end` is `None`. Resumes re-request just the rest of the window
+    /// and a server that sends the whole body anyway has the bytes outside of
+    /// it skipped, see [`RequestBuilder::skip_ignored_range`].
+    pub fn range(mut self, start: u64, end: Option<u64>) -> Self {
+        self.request.first = start;
+        self.request.start = start;
+        self.request.end = end.map(|end| end.max(start + 1));
+        self
+    }
+
+    /// Call `on_event` for every connection, chunk, retry and failure, e.g. to
+    /// log retries or show them in the UI. It runs on the task polling the
+    /// stream, so it should return quickly.
+    pub fn on_event<F>(mut self, on_event: F) -> Self
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.request.on_event = Some(EventHook(Arc::new(on_event)));
+        self
+    }
+
+    /// Change every attempt before it's sent, e.g. to sign the URL again or add
+    /// a fresh token. `map_request` gets the request, the number of the
+    /// attempt, starting at 1 for the first request, and the offset it asks
+    /// for. The `Range` and `If-Range` headers
+    /// are already set and shouldn't be touched.
+    pub fn map_request<F>(mut self, map_request: F) -> Self
+    where
+        F: Fn(reqwest::RequestBuilder, u32, u64) -> reqwest::RequestBuilder + Send + Sync + 'static,
+    {
+        self.request.map_request = Some(MapRequest(Arc::new(map_request)));
+        self
+    }
+
+    /// Call `refresh_auth` when an attempt is answered with `401 Unauthorized`
+    /// or `403 Forbidden`, e.g. because the token or signed URL expired during
+    /// a long download, and try again from the same byte with what it returns.
+    /// Returning `None` fails the request with that status. Each refresh
+    /// counts as a failed attempt against the retry policy.
+    pub fn refresh_auth<F, Fut>(mut self, refresh_auth: F) -> Self
+    where
+        F: Fn(StatusCode) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<Refresh>> + Send + 'static,
+    {
+        self.request.refresh_auth = Some(RefreshHook(Arc::new(move |status| {
+            Box::pin(refresh_auth(status))
+        })));
+        self
+    }
+
+    /// Send a second, identical request if the first one got no response
+    /// within `hedge_after`, for servers whose first byte is sometimes very
+    /// slow, and keep whichever answers first; the other is cancelled. Applies
+    /// to the first request and every resume, but not to bodies that can't be
+    /// sent twice. The extra connection isn't counted against
+    /// [`Client::max_connections_per_host`].
+    pub fn hedge_after(mut self, hedge_after: Duration) -> Self {
+        self.request.hedge_after = Some(hedge_after);
+        self
+    }
+
+    /// Reconnect from the current position when no bytes arrive for
+    /// `stall_timeout`, for connections that stay open but stop delivering.
+    /// Each stall counts as a failed attempt against the retry policy.
+    pub fn stall_timeout(mut self, stall_timeout: Duration) -> Self {
+        self.request.stall_timeout = Some(stall_timeout);
+        self
+    }
+
+    /// Other URLs serving the same content, tried in order when the current
+    /// one keeps failing or can't resume. A download continues at the same
+    /// byte on the next mirror if its copy has the same strong ETag or size.
+    pub fn mirrors(mut self, mirrors: impl IntoIterator<Item = Url>) -> Self {
+        let guard = &self.request.config.guard;
+        for mirror in mirrors {
+            match guard.blocked_url(&mirror) {
+                Some(addr) => log::warn!("skipping mirror {} at private address {}", mirror, addr),
+                None => self.request.mirrors.push(mirror),
+            }
+        }
+        self
+    }
+
+    /// Control the body stream with `transfer` rather than a handle of its
+    /// own, e.g. to pause a [`RequestBuilder::download_to_file`], which doesn't
+    /// hand out its [`Response`].
+    pub fn transfer_handle(mut self, transfer: TransferHandle) -> Self {
+        self.request.transfer = transfer;
+        self
+    }
+
+    /// Measure the body stream with `progress` rather than a handle of its
+    /// own, e.g. to show the speed of a [`RequestBuilder::download_to_file`].
+    pub fn progress_handle(mut self, progress: ProgressHandle) -> Self {
+        self.request.progress = Some(progress);
+        self
+    }
+
+    /// Fail with [`Error::TooLarge`] if the body, or the window set with
+    /// [`RequestBuilder::range`], would exceed `max` bytes: before streaming
+    /// if `Content-Length` says so, or as soon as the stream grows past it.
+    pub fn max_size(mut self, max: u64) -> Self {
+        self.request.max_size = Some(max);
+        self
+    }
+
+    /// When downloading to a file, fail with [`Error::InsufficientSpace`]
+    /// before writing anything if the rest of the body would leave less than
+    /// `bytes` free on its disk. Bodies of unknown size aren't checked.
+    pub fn min_free_space(mut self, bytes: u64) -> Self {
+        self.request.min_free_space = Some(bytes);
+        self
+    }
+
+    /// Connect every resume to the IP address the first response came from,
+    /// so a round-robin DNS pool can't hand a resume to a server with another
+    /// version of the file. Has no effect through a proxy, which resolves the
+    /// host itself.
+    pub fn pin_ip(mut self, pin_ip: bool) -> Self {
+        self.request.pin_ip = pin_ip;
+        self
+    }
+
+    /// Send resumes straight to the URL the first response was redirected to,
+    /// e.g. a signed CDN URL, rather than following the redirect again and
+    /// possibly landing on a different copy. The original URL is resolved
+    /// again if the pinned one answers `403` or `410`, as expired signed URLs
+    /// do.
+    pub fn pin_redirects(mut self, pin_redirects: bool) -> Self {
+        self.request.pin_redirects = pin_redirects;
+        self
+    }
+
+    /// Send `Accept-Encoding: identity` with the first request and every
+    /// resume, overriding any `Accept-Encoding` header set on the request. A
+    /// server picking a different encoding on a later attempt would make the
+    /// offsets of the bytes already received meaningless.
+    pub fn identity_encoding(mut self, identity_encoding: bool) -> Self {
+        self.request.identity_encoding = identity_encoding;
+        self
+    }
+
+    pub fn send(&self) -> impl Future<Output = Result<Response>> + Send + 'static {
+        let mut request = self.request.clone();
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "send",
+            url = %request.url,
+            method = %request.method,
+            start = request.start,
+        );
+        let send = async move {
+            let deadline = request.deadline.map(|deadline| Instant::now() + deadline);
+            let cancel = request.cancel.clone();
+            let url = request.url.clone();
+            let response = async {
+                // the connection slot is held until the body stream ends
+                let permit = request.host_limits.acquire(&request.url).await;
+                Ok::<_, Error>((permit, request.execute().await?))
+            };
+            let response = async {
+                match deadline {
+                    Some(deadline) => {
+                        timeout_at(deadline, response)
+                            .await
+                            .map_err(|_| Error::Timeout {
+                                url: url.clone(),
+                                pos: 0,
+                            })?
+                    }
+                    None => response.await,
+                }
+            };
+            let (permit, response) = match &cancel {
+                Some(token) => match select(pin!(response), pin!(token.cancelled())).await {
+                    Either::Left((response, _)) => response?,
+                    Either::Right(_) => {
+                        return Err(Error::Cancelled {
+                            url: url.clone(),
+                            pos: 0,
+                        })
+                    }
+                },
+                None => response.await?,
+            };
+            let mut skip = 0;
+            if request.start > 0 {
+                let content_range = response.headers().get(CONTENT_RANGE);
+                if response.status() == StatusCode::OK {
+                    if request.first > 0 && !request.skip_ignored_range {
+                        let err = Error::RangeNotHonored {
+                            url: request.url.clone(),
+                            pos: 0,
+                            content_range: None,
+                        };
+                        // nothing was received yet, so starting over is
+                        // skipping to the window in the whole body after all
+                        if !request.fallback.allows_restart(&err, 0) {
+                            return Err(err);
+                        }
+                    }
+                    if request.start > request.first {
+                        log::warn!(
+                            "{} sent the whole body, downloading from the start",
+                            request.url
+                        );
+                    }
+                    request.start = request.first;
+                    skip = request.first;
+                } else if response.status() == StatusCode::PARTIAL_CONTENT
+                    && content_range.and_then(content_range::start) != Some(request.start)
+                {
+                    return Err(Error::RangeNotHonored {
+                        url: request.url.clone(),
+                        pos: 0,
+                        content_range: content_range
+                            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned()),
+                    });
+                }
+            }
+            if request.pin_ip {
+                request.pin_address(&response);
+            }
+            if let (Some(max), Some(len)) = (request.max_size, response.content_length()) {
+                // what the body will add up to, counting from the window start
+                if request.start - request.first + len.saturating_sub(skip) > max {
+                    return Err(Error::TooLarge {
+                        url: request.url.clone(),
+                        pos: 0,
+                        max,
+                    });
+                }
+            }
+            request.emit(|| Event::Connected {
+                attempt: 1,
+                pos: 0,
+                status: response.status(),
+                url: response.url().clone(),
+            });
+            let accept_byte_ranges =
+                response.status() == StatusCode::PARTIAL_CONTENT || accepts_byte_ranges(&response);
+            let etag = strong_etag(&response);
+            let last_modified = strong_last_modified(&response);
+            Ok(Response {
+                request,
+                response,
+                accept_byte_ranges,
+                etag,
+                last_modified,
+                skip,
+                deadline,
+                permit,
+            })
+        };
+        #[cfg(feature = "tracing")]
+        let send = tracing::Instrument::instrument(send, span);
+        send
+    }
+
+    /// Download to `path` like [`Response::download_to_file`], continuing from
+    /// the `<path>.part` file an earlier attempt left behind if there is one.
+    pub fn download_to_file(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> impl Future<Output = Result<Download>> + Send + 'static {
+        let mut builder = RequestBuilder {
+            request: self.request.clone(),
+        };
+        let path = path.as_ref().to_owned();
+        async move {
+            let part = part_path(&path);
+            let mut len = fs::metadata(&part)
+                .await
+                .map_or(0, |metadata| metadata.len());
+            let sidecar = Sidecar::read(&path).await.ok().filter(|sidecar| {
+                let request = &builder.request;
+                std::iter::once(&request.url)
+                    .chain(&request.mirrors)
+                    .any(|url| sidecar.url == url.as_str())
+            });
+            let mut total = None;
+            if let Some(sidecar) = sidecar.filter(|_| len > 0) {
+                if len > sidecar.pos {
+                    // bytes written after the last checkpoint may not have
+                    // reached the disk intact
+                    let file = fs::OpenOptions::new()
+                        .write(true)
+                        .open(&part)
+                        .await
+                        .map_err(|err| Error::io(&builder.request.url, 0, err))?;
+                    file.set_len(sidecar.pos)
+                        .await
+                        .map_err(|err| Error::io(&builder.request.url, 0, err))?;
+                    len = sidecar.pos;
+                }
+                builder.request.if_range = sidecar.validator();
+                total = sidecar.total;
+            }
+            if len > 0 {
+                let request = &builder.request;
+                let mut overlap = request.verify_overlap;
+                let window = request.end.map(|end| end - request.first).or(total);
+                if window == Some(len) {
+                    // asking for the bytes after a complete file would fail
+                    overlap = overlap.max(1);
+                }
+                let start = request.first + len - overlap.min(len);
+                log::info!(
+                    "resuming {} from {} bytes already on disk",
+                    builder.request.url,
+                    len
+                );
+                builder.request.start = start;
+            }
+            let response = builder.send().await?;
+            let actual = (response.headers().get(CONTENT_RANGE)).and_then(content_range::total);
+            if let (Some(expected), Some(actual)) = (total, actual) {
+                if expected != actual {
+                    return Err(Error::SizeChanged {
+                        url: builder.request.url.clone(),
+                        pos: 0,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+            response.download_to_file(&path).await
+        }
+    }
+
+    /// Hash the body while it streams and fail with
+    /// [`Error::ChecksumMismatch`] at the end if it doesn't match `checksum`.
+    /// When a download resumes from a `.part` file, the bytes already on disk
+    /// are hashed first.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.request.checksum = Some(checksum);
+        self
+    }
+
+    /// Hash the body while it streams, without anything to compare it to,
+    /// and report the digest in the [`TransferSummary`] once it's complete.
+    /// As with [`Self::checksum`], the bytes already in a `.part` file are
+    /// hashed first.
+    #[cfg(feature = "digest")]
+    pub fn digest(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.request.digest = Some(algorithm);
+        self
+    }
+
+    /// Verify the body against a hex-encoded SHA-256 digest.
+    pub fn verify_sha256(self, hex: impl Into<String>) -> Self {
+        self.checksum(Checksum::Sha256(hex.into()))
+    }
+
+    /// Verify the body against a hex-encoded BLAKE3 digest.
+    pub fn verify_blake3(self, hex: impl Into<String>) -> Self {
+        self.checksum(Checksum::Blake3(hex.into()))
+    }
+
+    /// Verify the body against a hex-encoded MD5 digest.
+    pub fn verify_md5(self, hex: impl Into<String>) -> Self {
+        self.checksum(Checksum::Md5(hex.into()))
+    }
+
+    /// On every resume, whether of a download from its `.part` file or of a
+    /// stream whose connection dropped, fetch the last `len` bytes received
+    /// again and check they match before trusting the rest. Catches servers
+    /// that ignore ranges or change files without changing their validators;
+    /// a mismatch falls back according to [`RequestBuilder::fallback`].
+    pub fn verify_overlap(mut self, len: u64) -> Self {
+        self.request.verify_overlap = len;
+        self
+    }
+
+    /// Split chunks larger than `size` bytes before the body stream yields
+    /// them, without copying. Chunks are otherwise as large as the connection
+    /// delivers, which bounds neither memory per chunk nor the work a
+    /// consumer does per poll.
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        self.request.chunk_size = Some(size.max(1));
+        self
+    }
+
+    /// Send the request as `HEAD` to learn the size of the body and whether it
+    /// can be resumed before downloading it.
+    pub fn probe(&self) -> impl Future<Output = Result<Probe>> + Send + 'static {
+        let mut request = self.request.clone();
+        request.method = Method::HEAD;
+        request.body = None;
+        async move {
+            let response = request.execute().await?;
+            let response = response
+                .error_for_status()
+                .map_err(|err| Error::network(&request.url, 0, err))?;
+            // `reqwest::Response::content_length` is the size of the (empty) HEAD body
+            let content_length = response
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok()?.trim().parse().ok());
+            Ok(Probe {
+                content_length,
+                accept_byte_ranges: accepts_byte_ranges(&response),
+                etag: strong_etag(&response),
+                last_modified: strong_last_modified(&response),
+                url: response.url().clone(),
+            })
+        }
+    }
+
+    /// Open the body for random access, see [`RangeReader`]. The first block is
+    /// fetched right away to learn the size of the body and its validators.
+    pub fn range_reader(&self) -> impl Future<Output = Result<RangeReader>> + Send + 'static {
+        let mut request = self.request.clone();
+        // blocks from different versions of the file mustn't be mixed
+        request.fallback = FallbackPolicy::Error;
+        request.skip_ignored_range = false;
+        request.checksum = None;
+        request.if_range = None;
+        async move {
+            let block_size = RangeReader::DEFAULT_BLOCK_SIZE;
+            let end = block_size * (1 + RangeReader::DEFAULT_READ_AHEAD);
+            let (response, len) = fetch_range(request.clone(), 0, end).await?;
+            request.if_range = response.etag.clone().or(response.last_modified.clone());
+            let mut cache = BlockCache::new(RangeReader::DEFAULT_CACHE_BLOCKS);
+            cache.insert(0, collect_range(response).await?, block_size);
+            Ok(RangeReader {
+                request,
+                len,
+                pos: 0,
+                block_size,
+                read_ahead: RangeReader::DEFAULT_READ_AHEAD,
+                cache,
+                fetch: None,
+            })
+        }
+    }
+}
+
+/// Server support for resuming a response, see [`Response::capabilities`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The server answered with `206 Partial Content` or sent
+    /// `Accept-Ranges: bytes`.
+    pub accept_byte_ranges: bool,
+    /// There's a strong `ETag` to send as `If-Range`.
+    pub etag: bool,
+    /// There's a `Last-Modified` date precise enough for `If-Range`.
+    pub last_modified: bool,
+    /// Size of the whole body, if the server told us.
+    pub total: Option<u64>,
+    pub content_encoding: Option<String>,
+}
+
+impl Capabilities {
+    /// Whether an interrupted transfer can continue where it stopped and be
+    /// sure the rest belongs to the same version of the file: ranges are
+    /// supported and there's a validator to check them against.
+    pub fn is_resumable(&self) -> bool {
+        self.accept_byte_ranges && (self.etag || self.last_modified)
+    }
+}
+
+/// What a `HEAD` request revealed about a resource, see [`RequestBuilder::probe`].
+#[derive(Clone, Debug)]
+pub struct Probe {
+    pub content_length: Option<u64>,
+    /// Whether interrupted downloads can be resumed and split into ranges.
+    pub accept_byte_ranges: bool,
+    pub etag: Option<HeaderValue>,
+    pub last_modified: Option<HeaderValue>,
+    /// The URL after following redirects.
+    pub url: Url,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    request: Request,
+    response: reqwest::Response,
+    accept_byte_ranges: bool,
+    // validators sent as `If-Range` when resuming, the ETag being preferred
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    // bytes before the requested window in a response that ignored the range
+    skip: u64,
+    deadline: Option<Instant>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Response {
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        self.response.headers()
+    }
+
+    /// Size of the body, if the server sent a `Content-Length`.
+    pub fn content_length(&self) -> Option<u64> {
+        self.response.content_length()
+    }
+
+    /// Size of the whole body from `Content-Range` or `Content-Length`, even if
+    /// this response only carries part of it. `None` if the length is unknown,
+    /// e.g. for a chunked response or `Content-Range: bytes 0-99/*`.
+    pub fn total(&self) -> Option<u64> {
+        match self.response.headers().get(CONTENT_RANGE) {
+            Some(content_range) => content_range::total(content_range),
+            None => self.response.content_length(),
+        }
+    }
+
+    /// The URL after following redirects. Resumes are sent to the original
+    /// one, or straight to this one with [`RequestBuilder::pin_redirects`].
+    pub fn url(&self) -> &Url {
+        self.response.url()
+    }
+
+    /// What the response tells about resuming it, e.g. to decide whether to
+    /// offer pausing the download.
+    pub fn capabilities(&self) -> Capabilities {
+        let content_encoding = self.headers().get(CONTENT_ENCODING);
+        Capabilities {
+            accept_byte_ranges: self.accept_byte_ranges,
+            etag: self.etag.is_some(),
+            last_modified: self.last_modified.is_some(),
+            total: self.total(),
+            content_encoding: content_encoding
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+        }
+    }
+
+    /// Name to save the body under: the file name from `Content-Disposition`,
+    /// or else the last segment of the URL's path. It's sanitized so it can be
+    /// joined to a directory, see [`content_disposition::sanitize`].
+    pub fn suggested_filename(&self) -> Option<String> {
+        self.headers()
+            .get(CONTENT_DISPOSITION)
+            .and_then(content_disposition::filename)
+            .or_else(|| content_disposition::from_url(self.url()))
+    }
+
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.response.remote_addr()
+    }
+
+    /// Handle to pause and resume the body stream, e.g. from a pause button.
+    pub fn transfer_handle(&self) -> TransferHandle {
+        self.request.transfer.clone()
+    }
+
+    /// Convert the response into a `Stream` of `Bytes` that transparently
+    /// re-requests the remaining bytes when the connection drops.
+    pub fn bytes_stream(self) -> Decoder {
+        let total = self.total();
+        let progress = self.request.progress.clone().unwrap_or_default();
+        let cancelled = self.request.cancel.as_ref().map(cancelled);
+        let hasher = self.request.checksum.as_ref().map(Hasher::new);
+        #[cfg(feature = "digest")]
+        let digest = self.request.digest.map(Hasher::digest);
+        let body = body_stream(self.response, self.request.chunk_size);
+        #[cfg(feature = "tracing")]
+        let span =
+            tracing::debug_span!("body", url = %self.request.url, start = self.request.start);
+        Decoder {
+            #[cfg(feature = "tracing")]
+            span,
+            #[cfg(feature = "digest")]
+            digest,
+            summary: None,
+            started: Instant::now(),
+            request: self.request,
+            state: State::Streaming(body),
+            backoff: None,
+            permit: self.permit,
+            accept_byte_ranges: self.accept_byte_ranges,
+            etag: self.etag,
+            last_modified: self.last_modified,
+            pos: 0,
+            total,
+            deadline: self
+                .deadline
+                .map(|deadline| Box::pin(sleep_until(deadline))),
+            hasher,
+            cancelled,
+            throttle: None,
+            stall: None,
+            skip: self.skip,
+            failures: 0,
+            failing_since: None,
+            attempts: 1,
+            restarts: 0,
+            switched: false,
+            paused: None,
+            tail: Vec::new(),
+            overlap: Bytes::new(),
+            progress,
+        }
+    }
+
+    /// Read the whole body, resuming as needed. If it restarts because the
+    /// file changed on the server, see [`MismatchPolicy::Restart`], only the
+    /// new body is kept.
+    pub async fn bytes(self) -> Result<Bytes> {
+        let mut stream = self.bytes_stream();
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            if stream.position() == chunk.len() as u64 {
+                body.clear();
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body.into())
+    }
+
+    /// Read the whole body as UTF-8, replacing invalid sequences.
+    pub async fn text(self) -> Result<String> {
+        let body = self.bytes().await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Read the whole body and deserialize it from JSON; invalid JSON fails
+    /// with [`Error::Decode`].
+    pub async fn json<T: DeserializeOwned>(self) -> Result<T> {
+        let url = self.request.url.clone();
+        let body = self.bytes().await?;
+        serde_json::from_slice(&body).map_err(|err| Error::Decode {
+            url,
+            pos: body.len() as u64,
+            source: err.into(),
+        })
+    }
+
+    /// Stream the body into `<path>.part`, then move it to `path` once it's
+    /// complete and synced to disk, so `path` never holds a partial file.
+    ///
+    /// If the request started past the first byte, it's appended to the
+    /// existing `.part` file instead; see [`RequestBuilder::download_to_file`].
+    ///
+    /// When the length of the body is known, the space for it is allocated up
+    /// front, so a full disk fails the download right away rather than near
+    /// the end, and the file is less fragmented.
+    pub async fn download_to_file(self, path: impl AsRef<Path>) -> Result<Download> {
+        let path = path.as_ref();
+        let part = part_path(path);
+        let url = self.request.url.clone();
+        // position in the file rather than the body
+        let start = self.request.start - self.request.first;
+        let headers = self.response.headers().clone();
+        let io_err = |pos| {
+            let url = &url;
+            move |err| Error::io(url, pos, err)
+        };
+        // closed before renaming, which Windows doesn't allow on open files
+        let (bytes_written, resumed_from, summary) = {
+            let mut file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(start == 0)
+                .open(&part)
+                .await
+                .map_err(io_err(0))?;
+            let len = file.metadata().await.map_err(io_err(0))?.len();
+            if len < start {
+                let err = std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "partial file is shorter than the resume position",
+                );
+                return Err(Error::io(&url, 0, err));
+            }
+            // bytes the server sends again to check them against what we have
+            let mut stream = self.bytes_stream();
+            #[cfg(feature = "digest")]
+            let hashed = stream.hasher.is_some() || stream.digest.is_some();
+            #[cfg(not(feature = "digest"))]
+            let hashed = stream.hasher.is_some();
+            if hashed && start > 0 {
+                let mut buf = vec![0; 64 * 1024];
+                let mut remaining = start;
+                while remaining > 0 {
+                    let n = remaining.min(buf.len() as u64) as usize;
+                    file.read_exact(&mut buf[..n]).await.map_err(io_err(0))?;
+                    if let Some(hasher) = &mut stream.hasher {
+                        hasher.update(&buf[..n]);
+                    }
+                    #[cfg(feature = "digest")]
+                    if let Some(digest) = &mut stream.digest {
+                        digest.update(&buf[..n]);
+                    }
+                    remaining -= n as u64;
+                }
+            }
+            let mut overlap = vec![0; (len - start) as usize];
+            file.seek(SeekFrom::Start(start)).await.map_err(io_err(0))?;
+            file.read_exact(&mut overlap).await.map_err(io_err(0))?;
+            let mut resumed_from = len;
+            let mut bytes_written = 0;
+            let mut restarts = 0;
+            // how much of the file the sidecar vouches for
+            let mut checkpoint = len;
+            stream
+                .sidecar(checkpoint)
+                .save(path, &file)
+                .await
+                .map_err(io_err(0))?;
+            let mut batch = Batch::new(&file).await.map_err(io_err(0))?;
+            let request = &stream.request;
+            let window = request
+                .end
+                .or(stream.total())
+                .map(|end| end - request.first);
+            if let (Some(min_free), Some(window)) = (request.min_free_space, window) {
+                check_free_space(&url, path, window.saturating_sub(len) + min_free)?;
+            }
+            // the sidecar saved above keeps a resume from trusting the rest
+            let preallocated = matches!(window, Some(window) if window > len);
+            if let (true, Some(window)) = (preallocated, window) {
+                batch.allocate(window).await.map_err(io_err(0))?;
+            }
+            while let Some(bytes) = stream.next().await {
+                let mut bytes = match bytes {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        let size = resumed_from + bytes_written;
+                        let saved = match batch.flush().await {
+                            Ok(()) => stream.sidecar(size).save(path, &file).await,
+                            Err(err) => Err(err),
+                        };
+                        if let Err(err) = saved {
+                            log::warn!("failed to save resume state of {}: {}", url, err);
+                        }
+                        return Err(err);
+                    }
+                };
+                let pos = stream.position();
+                if stream.restarts() != restarts {
+                    restarts = stream.restarts();
+                    overlap.clear();
+                    resumed_from = 0;
+                    bytes_written = 0;
+                    checkpoint = 0;
+                    batch.clear();
+                    file.set_len(0).await.map_err(io_err(pos))?;
+                    file.seek(SeekFrom::Start(0)).await.map_err(io_err(pos))?;
+                }
+                if !overlap.is_empty() {
+                    let n = overlap.len().min(bytes.len());
+                    if bytes[..n] != overlap[..n] {
+                        let err = Error::ValidatorMismatch {
+                            url: url.clone(),
+                            pos,
+                        };
+                        if !stream.may_restart(&err) {
+                            return Err(err);
+                        }
+                        log::warn!("{} doesn't match {}", url, part.display());
+                        stream.start_over();
+                        continue;
+                    }
+                    overlap.drain(..n);
+                    bytes = bytes.slice(n..);
+                }
+                bytes_written += bytes.len() as u64;
+                batch.push(bytes);
+                if batch.is_full() {
+                    batch.flush().await.map_err(io_err(pos))?;
+                }
+                let size = resumed_from + bytes_written;
+                if size - checkpoint >= CHECKPOINT_INTERVAL {
+                    // the sidecar only vouches for what's in the file
+                    batch.flush().await.map_err(io_err(pos))?;
+                    stream
+                        .sidecar(size)
+                        .save(path, &file)
+                        .await
+                        .map_err(io_err(pos))?;
+                    checkpoint = size;
+                }
+            }
+            let pos = stream.position();
+            if !overlap.is_empty() {
+                // the partial file is longer than what the server has
+                return Err(Error::ValidatorMismatch { url, pos });
+            }
+            batch.flush().await.map_err(io_err(pos))?;
+            if preallocated {
+                let len = resumed_from + bytes_written;
+                file.set_len(len).await.map_err(io_err(pos))?;
+            }
+            file.sync_all().await.map_err(io_err(pos))?;
+            let summary = stream.summary.take().unwrap_or_default();
+            (bytes_written, resumed_from, summary)
+        };
+        fs::rename(&part, path)
+            .await
+            .map_err(io_err(resumed_from + bytes_written))?;
+        if let Err(err) = fs::remove_file(sidecar_path(path)).await {
+            log::warn!("failed to remove resume state of {}: {}", url, err);
+        }
+        Ok(Download {
+            bytes_written,
+            resumed_from,
+            headers,
+            summary,
+        })
+    }
+
+    /// Download the body over `n` connections, each fetching a contiguous range
+    /// and resuming on its own. Chunks arrive out of order, tagged with their
+    /// offset in the body; [`Segmented::download_to_file`] writes them at
+    /// their place in a file.
+    ///
+    /// Falls back to a single connection if the server doesn't support ranges
+    /// or didn't send a `Content-Length`.
+    pub fn segmented(mut self, n: u64) -> Segmented {
+        let len = self
+            .response
+            .content_length()
+            .filter(|_| self.accept_byte_ranges);
+        let n = match len {
+            Some(len) if len > 0 => n.clamp(1, len),
+            Some(_) => 1,
+            None => {
+                if n > 1 {
+                    log::debug!(
+                        "not segmenting {}: its length or range support is unknown",
+                        self.request.url
+                    );
+                }
+                1
+            }
+        };
+        // mixing ranges of different versions of the file would corrupt it
+        if n > 1 {
+            self.request.fallback = FallbackPolicy::Error;
+            self.request.skip_ignored_range = false;
+            if self.request.checksum.take().is_some() {
+                log::warn!(
+                    "not verifying the checksum of {}: segments arrive out of order",
+                    self.request.url
+                );
+            }
+            #[cfg(feature = "digest")]
+            if self.request.digest.take().is_some() {
+                log::warn!(
+                    "not hashing {}: segments arrive out of order",
+                    self.request.url
+                );
+            }
+        }
+        // only used to split the body, which takes a known length
+        let len = len.unwrap_or_default();
+        let start = self.request.start;
+        let bounds = |i: u64| start + len * i / n;
+        let file = Placement {
+            url: self.request.url.clone(),
+            first: self.request.first,
+            start,
+            len,
+            min_free_space: self.request.min_free_space,
+            headers: self.response.headers().clone(),
+        };
+        // the first segment reuses the connection that's already open
+        self.request.end = (n > 1).then(|| bounds(1));
+        let first = self.bytes_stream();
+        let summary = Arc::new(Mutex::new(TransferSummary::default()));
+        let segment = |decoder| Segment {
+            decoder,
+            summary: summary.clone(),
+        };
+        let mut segments: Vec<_> = (1..n)
+            .map(|i| segment(first.fork(bounds(i), Some(bounds(i + 1)))))
+            .collect();
+        segments.insert(0, segment(first));
+        Segmented {
+            segments: futures::stream::select_all(segments),
+            file,
+            summary,
+        }
+    }
+
+    /// Read the resumable body through `tokio::io::AsyncRead` or
+    /// `futures::io::AsyncRead`, e.g. to feed a decompressor or `tokio::io::copy`.
+    pub fn into_async_read(self) -> BodyReader {
+        BodyReader {
+            decoder: self.bytes_stream(),
+            chunk: Bytes::new(),
+        }
+    }
+
+    /// Like [`Response::bytes_stream`], but the body is read on a separate task
+    /// while the consumer works, up to `max_buffered` bytes ahead. Once that
+    /// much is waiting the task stops reading, so a slow consumer holds the
+    /// connection back instead of the body piling up in memory. The cap can
+    /// be exceeded by the chunk being read, see [`RequestBuilder::chunk_size`].
+    ///
+    /// Must be called within a Tokio runtime.
+    pub fn buffered_stream(self, max_buffered: usize) -> BufferedStream {
+        let max_buffered = max_buffered.clamp(1, u32::MAX as usize);
+        let budget = Arc::new(Semaphore::new(max_buffered));
+        let (sender, chunks) = mpsc::unbounded_channel();
+        let mut decoder = self.bytes_stream();
+        let stream = BufferedStream {
+            chunks,
+            budget: budget.clone(),
+            max_buffered,
+            progress: decoder.progress(),
+            transfer: decoder.transfer_handle(),
+        };
+        tokio::spawn(async move {
+            while let Some(chunk) = decoder.next().await {
+                let permit = match &chunk {
+                    Ok(bytes) => {
+                        let len = bytes.len().min(max_buffered) as u32;
+                        match budget.clone().acquire_many_owned(len).await {
+                            Ok(permit) => Some(permit),
+                            Err(_) => return,
+                        }
+                    }
+                    Err(_) => None,
+                };
+                // the stream was dropped, which drops the decoder as well
+                if sender.send((chunk, permit)).is_err() {
+                    return;
+                }
+            }
+        });
+        stream
+    }
+
+    /// Like [`Response::bytes_stream`], but decompresses a gzip, deflate,
+    /// brotli or zstd `Content-Encoding`. Resuming still works on the encoded
+    /// bytes, so [`DecodedStream::position`] counts those; a checksum applies
+    /// to them as well.
+    pub fn decoded_stream(self) -> DecodedStream {
+        let encoding = self
+            .headers()
+            .get(CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_ascii_lowercase());
+        let url = self.request.url.clone();
+        let body = self.into_async_read();
+        let reader = match encoding.as_deref() {
+            None | Some("identity") => Decompress::Identity(body),
+            Some("gzip" | "x-gzip") => Decompress::Gzip(GzipDecoder::new(body)),
+            Some("deflate") => Decompress::Deflate(ZlibDecoder::new(body)),
+            Some("br") => Decompress::Brotli(BrotliDecoder::new(body)),
+            Some("zstd") => Decompress::Zstd(ZstdDecoder::new(body)),
+            Some(encoding) => {
+                log::warn!("not decoding {}: unknown encoding {}", url, encoding);
+                Decompress::Identity(body)
+            }
+        };
+        DecodedStream {
+            reader,
+            buf: vec![0; DecodedStream::BUF_SIZE].into_boxed_slice(),
+        }
+    }
+
+    /// Like [`Response::bytes_stream`], but every chunk comes with a
+    /// [`Progress`] snapshot to drive a progress bar.
+    pub fn bytes_stream_with_progress(self) -> ProgressStream {
+        ProgressStream {
+            decoder: self.bytes_stream(),
+            last: None,
+            speed: 0.0,
+        }
+    }
+}
+
+/// Future resolving once `token` is cancelled.
+fn cancelled(token: &CancellationToken) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    let token = token.clone();
+    Box::pin(async move { token.cancelled().await })
+}
+
+/// Whether `response` advertises `Accept-Ranges: bytes`.
+fn accepts_byte_ranges(response: &reqwest::Response) -> bool {
+    response
+        .headers()
+        .get_all(ACCEPT_RANGES)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|unit| unit.trim().eq_ignore_ascii_case("bytes"))
+}
+
+/// The `ETag` of `response`, if it's usable as an `If-Range` validator.
+fn strong_etag(response: &reqwest::Response) -> Option<HeaderValue> {
+    response
+        .headers()
+        .get(ETAG)
+        .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+        .cloned()
+}
+
+/// The `Last-Modified` of `response`, if it's usable as an `If-Range` validator.
+///
+/// A modification date is only a strong validator if it lies at least one
+/// second before the response's `Date` (RFC 7232, section 2.2.2): the file
+/// could otherwise have changed again within the same second.
+fn strong_last_modified(response: &reqwest::Response) -> Option<HeaderValue> {
+    let headers = response.headers();
+    let last_modified = headers.get(LAST_MODIFIED)?;
+    let parse = |value: &HeaderValue| {
+        chrono::DateTime::parse_from_rfc2822(value.to_str().ok()?.trim()).ok()
+    };
+    match (parse(last_modified), headers.get(DATE).and_then(parse)) {
+        (None, _) => None,
+        (Some(modified), Some(date)) if date - modified < chrono::Duration::seconds(1) => None,
+        _ => Some(last_modified.clone()),
+    }
+}
+
+type BytesStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+/// The body of `response`, in chunks of at most `chunk_size` bytes.
+fn body_stream(response: reqwest::Response, chunk_size: Option<usize>) -> BytesStream {
+    let Some(chunk_size) = chunk_size else {
+        return Box::pin(response.bytes_stream());
+    };
+    Box::pin(response.bytes_stream().flat_map(move |chunk| {
+        let chunks = match chunk {
+            Ok(mut bytes) => {
+                let mut chunks = Vec::with_capacity(bytes.len().div_ceil(chunk_size));
+                while bytes.len() > chunk_size {
+                    chunks.push(Ok(bytes.split_to(chunk_size)));
+                }
+                chunks.push(Ok(bytes));
+                chunks
+            }
+            Err(err) => vec![Err(err)],
+        };
+        futures::stream::iter(chunks)
+    }))
+}
+type ResponseFuture = BoxFuture<
+    'static,
+    (
+        Option<OwnedSemaphorePermit>,
+        reqwest::Result<reqwest::Response>,
+    ),
+>;
+
+/// What a [`Decoder`] is doing between polls.
+enum State {
+    Streaming(BytesStream),
+    // waiting for `backoff` before sending the range request
+    Sleeping,
+    Reconnecting(ResponseFuture),
+    // credentials being refreshed after the error, see `refresh_auth`
+    Refreshing(BoxFuture<'static, Option<Refresh>>, reqwest::Error),
+    // no connection: paused, failed, or done
+    Idle,
+}
+
+/// What a [`Decoder`] is doing, see [`Decoder::state`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecoderState {
+    /// Reading the body of a response.
+    Streaming,
+    /// Waiting out the backoff after a failure.
+    Sleeping,
+    /// Waiting for a connection slot or the response to a range request.
+    Reconnecting,
+    /// Waiting for new credentials, see [`RequestBuilder::refresh_auth`].
+    Refreshing,
+    /// Paused with [`TransferHandle::pause`].
+    Paused,
+    /// Not connected because the body is complete or the stream failed.
+    Idle,
+}
+
+/// Resumable body stream returned by [`Response::bytes_stream`].
+pub struct Decoder {
+    request: Request,
+    state: State,
+    // backoff before the next reconnect, reset rather than reallocated
+    backoff: Option<Pin<Box<Sleep>>>,
+    // connection slot for the host, see `Client::max_connections_per_host`
+    permit: Option<OwnedSemaphorePermit>,
+    accept_byte_ranges: bool,
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    pos: u64,
+    // size of the whole body, if the server told us
+    total: Option<u64>,
+    deadline: Option<Pin<Box<Sleep>>>,
+    cancelled: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    // pause imposed by the rate limit before reading the next chunk
+    throttle: Option<Pin<Box<Sleep>>>,
+    // fires when the body sent nothing for `stall_timeout`
+    stall: Option<Pin<Box<Sleep>>>,
+    // digest of everything yielded so far, if a checksum is to be verified
+    hasher: Option<Hasher>,
+    // the same for `RequestBuilder::digest`
+    #[cfg(feature = "digest")]
+    digest: Option<Hasher>,
+    // set once the body is complete
+    summary: Option<TransferSummary>,
+    // bytes at the start of `body` that were already yielded
+    skip: u64,
+    // consecutive failed attempts since the last chunk was received
+    failures: u32,
+    // when the first of those failures happened
+    failing_since: Option<Instant>,
+    attempts: u32,
+    restarts: u32,
+    // switched to a mirror whose first response is yet to be validated
+    switched: bool,
+    // `Some(disconnected)` while paused, see `TransferHandle`
+    paused: Option<bool>,
+    // last `verify_overlap` bytes yielded, to check a resumed body against
+    tail: Vec<u8>,
+    // what's left to check of the start of a resumed body
+    overlap: Bytes,
+    progress: ProgressHandle,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    // when the stream was created, for the summary and `transfer_duration_seconds`
+    started: Instant,
+}
+
+impl Decoder {
+    /// Number of bytes yielded since the last restart.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Size of the whole body from `Content-Length` or `Content-Range`; `None`
+    /// while it's unknown, see [`Response::total`].
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Handle to change the speed limit while the transfer runs, if one was set.
+    pub fn rate_limit(&self) -> Option<&RateLimit> {
+        self.request.rate_limit.as_ref()
+    }
+
+    /// Number of requests made so far, starting at 1 for the initial one.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// How many times the stream started over from the first byte, see
+    /// [`MismatchPolicy::Restart`].
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// Handle to pause and resume this stream and any others sharing it.
+    pub fn transfer_handle(&self) -> TransferHandle {
+        self.request.transfer.clone()
+    }
+
+    /// What the stream is doing, e.g. to show "reconnecting" for a transfer
+    /// that's making no progress.
+    pub fn state(&self) -> DecoderState {
+        if self.paused.is_some() {
+            return DecoderState::Paused;
+        }
+        match self.state {
+            State::Streaming(_) => DecoderState::Streaming,
+            State::Sleeping => DecoderState::Sleeping,
+            State::Reconnecting(_) => DecoderState::Reconnecting,
+            State::Refreshing(..) => DecoderState::Refreshing,
+            State::Idle => DecoderState::Idle,
+        }
+    }
+
+    /// What the transfer took, once the stream has ended without an error.
+    pub fn summary(&self) -> Option<&TransferSummary> {
+        self.summary.as_ref()
+    }
+
+    /// Handle to the transfer's speed and ETA, updated as chunks arrive. It
+    /// can be polled from elsewhere, e.g. a UI timer, while the stream runs.
+    pub fn progress(&self) -> ProgressHandle {
+        self.progress.clone()
+    }
+
+    /// Re-request the remaining bytes after waiting `delay`.
+    fn reconnect(&mut self, delay: Duration) {
+        self.skip = 0;
+        self.attempts += 1;
+        self.overlap = Bytes::from(self.tail.clone());
+        if delay.is_zero() {
+            self.connect();
+        } else {
+            let deadline = Instant::now() + delay;
+            match self.backoff.as_mut() {
+                Some(backoff) => backoff.as_mut().reset(deadline),
+                None => self.backoff = Some(Box::pin(sleep_until(deadline))),
+            }
+            self.state = State::Sleeping;
+        }
+    }
+
+    /// Send the range request for the rest of the body.
+    fn connect(&mut self) {
+        // another server's validators wouldn't match
+        let validator = self.validator().filter(|_| !self.switched);
+        let builder = self
+            .request
+            .ranged(self.resume_offset(), validator, self.attempts);
+        let send = self.request.send(builder);
+        // a stream keeps its slot across reconnects to the same host
+        let host_limits = self.request.host_limits.clone();
+        let url = self.request.url.clone();
+        let has_permit = self.permit.is_some();
+        self.state = State::Reconnecting(Box::pin(async move {
+            let permit = match has_permit {
+                true => None,
+                false => host_limits.acquire(&url).await,
+            };
+            host_limits.pace(&url).await;
+            (permit, send.await)
+        }));
+    }
+
+    /// Resume state of a download whose first `pos` bytes are on disk.
+    fn sidecar(&self, pos: u64) -> Sidecar {
+        let to_string = |value: &HeaderValue| value.to_str().ok().map(str::to_owned);
+        Sidecar {
+            url: self.request.url.to_string(),
+            pos,
+            etag: self.etag.as_ref().and_then(to_string),
+            last_modified: self.last_modified.as_ref().and_then(to_string),
+            total: self.total,
+        }
+    }
+
+    /// Position of the next byte in the whole body.
+    fn offset(&self) -> u64 {
+        self.request.start + self.pos
+    }
+
+    /// Where the body resumes: before [`Decoder::offset`] by the bytes that
+    /// are fetched again to be verified.
+    fn resume_offset(&self) -> u64 {
+        self.offset() - self.overlap.len() as u64
+    }
+
+    /// A stream for the `start..end` range of the same body, sharing this one's
+    /// validators, deadline and cancellation. It connects when first polled.
+    fn fork(&self, start: u64, end: Option<u64>) -> Decoder {
+        let mut request = self.request.clone();
+        request.start = start;
+        request.end = end;
+        let mut decoder = Decoder {
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!(parent: &self.span, "segment", start, end = ?end),
+            // segments can't be hashed in order
+            #[cfg(feature = "digest")]
+            digest: None,
+            summary: None,
+            started: Instant::now(),
+            cancelled: request.cancel.as_ref().map(cancelled),
+            request,
+            state: State::Idle,
+            backoff: None,
+            permit: None,
+            accept_byte_ranges: self.accept_byte_ranges,
+            etag: self.etag.clone(),
+            last_modified: self.last_modified.clone(),
+            pos: 0,
+            total: self.total,
+            deadline: self
+                .deadline
+                .as_ref()
+                .map(|deadline| Box::pin(sleep_until(deadline.deadline()))),
+            throttle: None,
+            stall: None,
+            // segments can't be hashed in order
+            hasher: None,
+            skip: 0,
+            failures: 0,
+            failing_since: None,
+            attempts: 0,
+            restarts: 0,
+            switched: false,
+            paused: None,
+            tail: Vec::new(),
+            overlap: Bytes::new(),
+            progress: ProgressHandle::new(),
+        };
+        decoder.reconnect(Duration::ZERO);
+        decoder
+    }
+
+    /// Drop the connection and any pending retry so the stream ends after `err`.
+    fn abort(&mut self, err: Error) -> Error {
+        self.state = State::Idle;
+        self.permit = None;
+        self.deadline = None;
+        self.cancelled = None;
+        err
+    }
+
+    /// Forget everything received so far and start over from the first byte.
+    fn restart(&mut self) {
+        log::warn!(
+            "{} can't be resumed from byte {}, restarting",
+            self.request.url,
+            self.pos
+        );
+        self.pos = 0;
+        self.request.start = self.request.first;
+        self.restarts += 1;
+        self.tail.clear();
+        self.overlap = Bytes::new();
+        self.hasher = self.request.checksum.as_ref().map(Hasher::new);
+        #[cfg(feature = "digest")]
+        {
+            self.digest = self.request.digest.map(Hasher::digest);
+        }
+    }
+
+    /// Compare the digest of the body with the expected one once it's
+    /// complete, returning whether there was one to compare it with.
+    fn verify(&mut self) -> Result<bool> {
+        let (Some(hasher), Some(checksum)) = (self.hasher.take(), &self.request.checksum) else {
+            return Ok(false);
+        };
+        let actual = hasher.finalize();
+        if actual.eq_ignore_ascii_case(checksum.hex()) {
+            return Ok(true);
+        }
+        Err(Error::ChecksumMismatch {
+            url: self.request.url.clone(),
+            pos: self.pos,
+            algorithm: checksum.algorithm(),
+            expected: checksum.hex().to_owned(),
+            actual,
+        })
+    }
+
+    /// End of the body: the stream is over unless the checksum doesn't match.
+    fn finish(&mut self) -> Poll<Option<Result<Bytes>>> {
+        self.state = State::Idle;
+        self.permit = None;
+        match self.verify() {
+            Ok(verified) => {
+                self.summary = Some(TransferSummary {
+                    bytes: self.pos,
+                    verified,
+                    #[cfg(feature = "digest")]
+                    digest: self.digest.take().map(Hasher::into_checksum),
+                    #[cfg(not(feature = "digest"))]
+                    digest: None,
+                    attempts: self.attempts,
+                    duration: self.started.elapsed(),
+                });
+                Poll::Ready(None)
+            }
+            Err(err) => Poll::Ready(Some(Err(self.abort(err)))),
+        }
+    }
+
+    /// Whether the fallback policy allows starting over after `err` made
+    /// resuming impossible.
+    fn may_restart(&self, err: &Error) -> bool {
+        self.request.fallback.allows_restart(err, self.restarts)
+    }
+
+    /// Throw away the current connection and download from the first byte.
+    fn start_over(&mut self) {
+        self.restart();
+        self.reconnect(Duration::ZERO);
+    }
+
+    fn validator(&self) -> Option<&HeaderValue> {
+        self.etag.as_ref().or(self.last_modified.as_ref())
+    }
+
+    /// Whether a full `200` response to a ranged request is the same file we
+    /// were receiving, i.e. the server just ignored `Range`.
+    fn ignored_range(&self, response: &reqwest::Response) -> bool {
+        let headers = response.headers();
+        if headers.contains_key(CONTENT_RANGE) {
+            return false;
+        }
+        match (&self.etag, &self.last_modified) {
+            (Some(etag), _) => headers.get(ETAG) == Some(etag),
+            (None, Some(last_modified)) => headers.get(LAST_MODIFIED) == Some(last_modified),
+            // the length is all there is to go on, if it's known
+            (None, None) => self.total.is_none() || response.content_length() == self.total,
+        }
+    }
+
+    /// Schedule a reconnect after `err`, or hand the error back if we must give up.
+    fn retry(&mut self, err: reqwest::Error) -> Option<Error> {
+        let err = self.resume(err)?;
+        self.failover(err)
+    }
+
+    /// Continue from the next mirror instead of failing with `err`, if one is left.
+    fn failover(&mut self, err: Error) -> Option<Error> {
+        if !self.request.next_mirror(&err) {
+            return Some(err);
+        }
+        // the slot was for the previous host
+        self.permit = None;
+        self.failures = 0;
+        self.failing_since = None;
+        self.switched = true;
+        self.retry_after(Duration::ZERO, &err);
+        None
+    }
+
+    /// Reconnect after `delay`, telling the observer why.
+    fn retry_after(&mut self, delay: Duration, reason: &dyn fmt::Display) {
+        self.retry_after_failure(delay, reason, None);
+    }
+
+    /// [`Self::retry_after`] a connection failure of kind `failure`.
+    fn retry_after_failure(
+        &mut self,
+        delay: Duration,
+        reason: &dyn fmt::Display,
+        failure: Option<FailureKind>,
+    ) {
+        self.request.emit(|| Event::Reconnecting {
+            attempt: self.attempts + 1,
+            pos: self.pos,
+            delay,
+            reason: reason.to_string(),
+            failure,
+        });
+        self.reconnect(delay);
+    }
+
+    /// Whether the first response from a mirror serves the same file as the
+    /// previous server, judging by its strong ETag or else its size.
+    fn same_file(&self, response: &reqwest::Response) -> bool {
+        if let (Some(etag), Some(new)) = (&self.etag, strong_etag(response)) {
+            if *etag == new {
+                return true;
+            }
+        }
+        let total = match response.headers().get(CONTENT_RANGE) {
+            Some(content_range) => content_range::total(content_range),
+            None => response.content_length(),
+        };
+        self.total.is_some() && total == self.total
+    }
+
+    /// Reconnect to the current server after `err` unless the retry policy or
+    /// the server rule it out.
+    fn resume(&mut self, err: reqwest::Error) -> Option<Error> {
+        if !self.accept_byte_ranges && !self.request.resume_without_accept_ranges {
+            let err = Error::network(&self.request.url, self.pos, err);
+            if !self.may_restart(&err) {
+                return Some(err);
+            }
+            self.failed();
+            if !self.may_retry() {
+                return Some(err);
+            }
+            self.restart();
+            self.retry_after(self.request.retry.delay(self.failures), &err);
+            return None;
+        }
+        if self.request.require_validator && self.validator().is_none() {
+            log::warn!(
+                "not resuming {}: no ETag or Last-Modified to validate against",
+                self.request.url
+            );
+            return Some(Error::network(&self.request.url, self.pos, err));
+        }
+        self.failed();
+        if !(self.request.retry.retryable)(&err) {
+            return Some(Error::network(&self.request.url, self.pos, err));
+        }
+        let kind = FailureKind::of(&err);
+        let retry = &self.request.retry;
+        if !retry.may_retry_kind(kind, self.failures, self.failing_since) {
+            return Some(self.too_many_retries(err));
+        }
+        log::warn!(
+            "resuming {} from byte {} (attempt {}): {}",
+            self.request.url,
+            self.pos,
+            self.failures + 1,
+            err
+        );
+        let delay = self.request.retry.delay_kind(kind, self.failures);
+        self.retry_after_failure(delay, &err, Some(kind));
+        None
+    }
+
+    /// Restart the stall timer, e.g. after a chunk arrived.
+    fn reset_stall(&mut self) {
+        if let (Some(stall), Some(timeout)) = (&mut self.stall, self.request.stall_timeout) {
+            stall.as_mut().reset(Instant::now() + timeout);
+        }
+    }
+
+    /// Drop a connection that stopped delivering bytes and request the rest
+    /// again, or give up if the retry budget is spent.
+    fn stalled(&mut self) -> Option<Error> {
+        log::warn!(
+            "{} stalled at byte {}, reconnecting",
+            self.request.url,
+            self.pos
+        );
+        self.state = State::Idle;
+        self.stall = None;
+        #[cfg(feature = "metrics")]
+        metrics::counter!("stalls_total", "host" => host_label(&self.request.url)).increment(1);
+        self.failed();
+        let retry = &self.request.retry;
+        if !retry.may_retry_kind(FailureKind::Timeout, self.failures, self.failing_since) {
+            let err = Error::Timeout {
+                url: self.request.url.clone(),
+                pos: self.pos,
+            };
+            return self.failover(err);
+        }
+        let delay = retry.delay_kind(FailureKind::Timeout, self.failures);
+        self.retry_after_failure(delay, &"stalled", Some(FailureKind::Timeout));
+        None
+    }
+
+    /// Count a failed attempt against the retry budget.
+    fn failed(&mut self) {
+        self.failures += 1;
+        self.failing_since.get_or_insert_with(Instant::now);
+    }
+
+    fn may_retry(&self) -> bool {
+        self.request
+            .retry
+            .may_retry(self.failures, self.failing_since)
+    }
+
+    fn too_many_retries(&self, source: reqwest::Error) -> Error {
+        Error::TooManyRetries {
+            url: self.request.url.clone(),
+            pos: self.pos,
+            attempts: self.failures,
+            source,
+        }
+    }
+
+    /// Handle the response to a ranged request: stream its body if it
+    /// continues exactly where we left off.
+    fn resumed(&mut self, response: reqwest::Response) -> Result<()> {
+        if self.switched {
+            if !self.same_file(&response) {
+                return Err(Error::ValidatorMismatch {
+                    url: self.request.url.clone(),
+                    pos: self.pos,
+                });
+            }
+            self.switched = false;
+            self.etag = strong_etag(&response);
+            self.last_modified = strong_last_modified(&response);
+        }
+        let status = response.status();
+        if status == StatusCode::PARTIAL_CONTENT {
+            let content_range = response.headers().get(CONTENT_RANGE);
+            if content_range.and_then(content_range::start) != Some(self.resume_offset()) {
+                let content_range = content_range
+                    .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+                let err = Error::RangeNotHonored {
+                    url: self.request.url.clone(),
+                    pos: self.pos,
+                    content_range,
+                };
+                if !self.may_restart(&err) {
+                    return Err(err);
+                }
+                self.start_over();
+                return Ok(());
+            }
+            if let Some(total) = content_range.and_then(content_range::total) {
+                if let Some(expected) = self.total.filter(|&expected| expected != total) {
+                    let err = Error::SizeChanged {
+                        url: self.request.url.clone(),
+                        pos: self.pos,
+                        expected,
+                        actual: total,
+                    };
+                    if !self.may_restart(&err) {
+                        return Err(err);
+                    }
+                    self.total = Some(total);
+                    self.start_over();
+                    return Ok(());
+                }
+                self.total = Some(total);
+            }
+        } else if status == StatusCode::OK {
+            if self.offset() > 0 && self.ignored_range(&response) && self.request.skip_ignored_range
+            {
+                log::warn!(
+                    "{} ignored the range request, skipping {} bytes",
+                    self.request.url,
+                    self.resume_offset()
+                );
+                self.skip = self.resume_offset();
+            } else if self.offset() > 0 {
+                let err = if self.ignored_range(&response) {
+                    Error::RangeNotHonored {
+                        url: self.request.url.clone(),
+                        pos: self.pos,
+                        content_range: None,
+                    }
+                } else {
+                    // with `If-Range`, a full response means the file changed
+                    Error::ValidatorMismatch {
+                        url: self.request.url.clone(),
+                        pos: self.pos,
+                    }
+                };
+                if !self.may_restart(&err) {
+                    return Err(err);
+                }
+                self.restart();
+                // the new body starts before the window
+                self.skip = self.request.first;
+            }
+            self.etag = strong_etag(&response);
+            self.last_modified = strong_last_modified(&response);
+            self.total = response.content_length();
+        } else {
+            return Err(Error::network(
+                &self.request.url,
+                self.pos,
+                status_error(response),
+            ));
+        }
+        self.request.emit(|| Event::Connected {
+            attempt: self.attempts,
+            pos: self.pos,
+            status,
+            url: response.url().clone(),
+        });
+        self.state = State::Streaming(body_stream(response, self.request.chunk_size));
+        self.reset_stall();
+        Ok(())
+    }
+}
+
+impl Stream for Decoder {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        #[cfg(feature = "tracing")]
+        let _entered = this.span.clone().entered();
+        let mut item = ready!(this.poll_body(cx));
+        if let (Some(Ok(_)), Some(max)) = (&item, this.request.max_size) {
+            if this.offset() - this.request.first > max {
+                let err = Error::TooLarge {
+                    url: this.request.url.clone(),
+                    pos: this.pos,
+                    max,
+                };
+                item = Some(Err(this.abort(err)));
+            }
+        }
+        match &item {
+            Some(Ok(bytes)) => {
+                let remaining =
+                    (this.request.end.or(this.total)).map(|end| end.saturating_sub(this.offset()));
+                let pos = this.offset() - this.request.first;
+                this.progress.record(bytes.len() as u64, pos, remaining);
+                this.request.emit(|| Event::ChunkReceived {
+                    pos: this.pos,
+                    len: bytes.len(),
+                })
+            }
+            Some(Err(err)) => this.request.emit(|| Event::Failed {
+                pos: this.pos,
+                error: err.to_string(),
+            }),
+            None => {}
+        }
+        #[cfg(feature = "metrics")]
+        if !matches!(item, Some(Ok(_))) {
+            metrics::histogram!("transfer_duration_seconds", "host" => host_label(&this.request.url))
+                .record(this.started.elapsed().as_secs_f64());
+        }
+        Poll::Ready(item)
+    }
+}
+
+/// Lets the resumable body be handed straight to `hyper` 1.x or `axum` as a
+/// response body, e.g. `axum::body::Body::new(decoder)`, without buffering it.
+/// For `hyper` 0.14, `hyper::Body::wrap_stream` takes the [`Stream`] instead.
+#[cfg(feature = "http-body")]
+impl http_body::Body for Decoder {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Bytes>>>> {
+        self.poll_next(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map(http_body::Frame::data)))
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        match self.request.end.or(self.total) {
+            Some(end) => http_body::SizeHint::with_exact(end.saturating_sub(self.offset())),
+            None => http_body::SizeHint::new(),
+        }
+    }
+}
+
+impl Decoder {
+    fn poll_body(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes>>> {
+        if let Some(cancelled) = self.cancelled.as_mut() {
+            if cancelled.as_mut().poll(cx).is_ready() {
+                let err = Error::Cancelled {
+                    url: self.request.url.clone(),
+                    pos: self.pos,
+                };
+                return Poll::Ready(Some(Err(self.abort(err))));
+            }
+        }
+        if let Some(deadline) = self.deadline.as_mut() {
+            if deadline.as_mut().poll(cx).is_ready() {
+                let err = Error::Timeout {
+                    url: self.request.url.clone(),
+                    pos: self.pos,
+                };
+                return Poll::Ready(Some(Err(self.abort(err))));
+            }
+        }
+        if let Some(disconnect) = self.request.transfer.poll_paused(cx) {
+            let disconnected = self.paused == Some(true);
+            // nothing's left to request once the whole body arrived
+            if disconnect && !disconnected && self.total != Some(self.offset()) {
+                log::debug!(
+                    "{} paused at byte {}, disconnecting",
+                    self.request.url,
+                    self.pos
+                );
+                self.state = State::Idle;
+                self.permit = None;
+                self.stall = None;
+                self.paused = Some(true);
+            } else {
+                self.paused = Some(disconnected);
+            }
+            return Poll::Pending;
+        }
+        match self.paused.take() {
+            Some(true) => self.reconnect(Duration::ZERO),
+            // time spent paused isn't the server's fault either
+            Some(false) => self.reset_stall(),
+            None => {}
+        }
+        if let Some(throttle) = self.throttle.as_mut() {
+            ready!(throttle.as_mut().poll(cx));
+            self.throttle = None;
+            // time spent throttled isn't the server's fault
+            self.reset_stall();
+        }
+        loop {
+            if let (State::Sleeping, Some(backoff)) = (&self.state, self.backoff.as_mut()) {
+                ready!(backoff.as_mut().poll(cx));
+                self.connect();
+            }
+            if let State::Refreshing(refreshing, _) = &mut self.state {
+                let refresh = ready!(refreshing.as_mut().poll(cx));
+                let State::Refreshing(_, err) = std::mem::replace(&mut self.state, State::Idle)
+                else {
+                    unreachable!()
+                };
+                match refresh {
+                    Some(refresh) => {
+                        self.request.refresh(refresh);
+                        self.retry_after(Duration::ZERO, &"refreshed credentials");
+                    }
+                    None => {
+                        let err = Error::network(&self.request.url, self.pos, err);
+                        if let Some(err) = self.failover(err) {
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                    }
+                }
+                continue;
+            }
+            if let State::Reconnecting(reconnect) = &mut self.state {
+                let (permit, response) = ready!(reconnect.as_mut().poll(cx));
+                self.state = State::Idle;
+                if permit.is_some() {
+                    self.permit = permit;
+                }
+                match response {
+                    Ok(response) if is_throttled(response.status()) => {
+                        self.failed();
+                        if !self.may_retry() {
+                            let err = self.too_many_retries(status_error(response));
+                            match self.failover(err) {
+                                Some(err) => return Poll::Ready(Some(Err(err))),
+                                None => continue,
+                            }
+                        }
+                        let delay = self.request.retry.throttled_delay(&response, self.failures);
+                        log::warn!(
+                            "{} throttled with {}, retrying in {:?}",
+                            self.request.url,
+                            response.status(),
+                            delay
+                        );
+                        self.retry_after(delay, &response.status());
+                        continue;
+                    }
+                    Ok(response)
+                        if self.request.refresh_auth.is_some()
+                            && is_unauthorized(response.status()) =>
+                    {
+                        self.failed();
+                        let status = response.status();
+                        let err = status_error(response);
+                        if !self.may_retry() {
+                            let err = self.too_many_retries(err);
+                            match self.failover(err) {
+                                Some(err) => return Poll::Ready(Some(Err(err))),
+                                None => continue,
+                            }
+                        }
+                        log::info!(
+                            "resuming {} failed with {}, refreshing credentials",
+                            self.request.url,
+                            status
+                        );
+                        let refresh_auth = self.request.refresh_auth.clone().unwrap();
+                        self.state = State::Refreshing((refresh_auth.0)(status), err);
+                        continue;
+                    }
+                    Ok(response) if response.status().is_server_error() => {
+                        self.failed();
+                        if !self.may_retry() {
+                            let err = self.too_many_retries(status_error(response));
+                            match self.failover(err) {
+                                Some(err) => return Poll::Ready(Some(Err(err))),
+                                None => continue,
+                            }
+                        }
+                        log::warn!(
+                            "resuming {} failed with {}",
+                            self.request.url,
+                            response.status()
+                        );
+                        let delay = self.request.retry.delay(self.failures);
+                        self.retry_after(delay, &response.status());
+                        continue;
+                    }
+                    Ok(response) => {
+                        if let Err(err) = self.resumed(response) {
+                            if let Some(err) = self.failover(err) {
+                                return Poll::Ready(Some(Err(err)));
+                            }
+                        }
+                        // a restart may have scheduled another request
+                        continue;
+                    }
+                    Err(err) => match self.retry(err) {
+                        Some(err) => return Poll::Ready(Some(Err(err))),
+                        None => continue,
+                    },
+                }
+            }
+            if matches!(self.request.end, Some(end) if self.offset() >= end) {
+                self.state = State::Idle;
+                return self.finish();
+            }
+            let polled = match &mut self.state {
+                State::Streaming(body) => body.as_mut().poll_next(cx),
+                // nothing's left to read after an error
+                _ => Poll::Ready(None),
+            };
+            match polled {
+                Poll::Ready(Some(Ok(mut bytes))) => {
+                    if self.skip > 0 {
+                        let skipped = self.skip.min(bytes.len() as u64);
+                        self.skip -= skipped;
+                        bytes = bytes.slice(skipped as usize..);
+                        if bytes.is_empty() {
+                            continue;
+                        }
+                    }
+                    if !self.overlap.is_empty() {
+                        let n = self.overlap.len().min(bytes.len());
+                        if bytes[..n] != self.overlap[..n] {
+                            let err = Error::ValidatorMismatch {
+                                url: self.request.url.clone(),
+                                pos: self.pos,
+                            };
+                            if !self.may_restart(&err) {
+                                return Poll::Ready(Some(Err(self.abort(err))));
+                            }
+                            log::warn!(
+                                "{} sent different bytes before {} on resume",
+                                self.request.url,
+                                self.offset()
+                            );
+                            self.start_over();
+                            continue;
+                        }
+                        self.overlap.advance(n);
+                        bytes = bytes.slice(n..);
+                        if bytes.is_empty() {
+                            continue;
+                        }
+                    }
+                    if let Some(end) = self.request.end {
+                        let remaining = end - self.offset();
+                        if bytes.len() as u64 > remaining {
+                            bytes.truncate(remaining as usize);
+                        }
+                    }
+                    self.pos += bytes.len() as u64;
+                    self.failures = 0;
+                    self.failing_since = None;
+                    self.reset_stall();
+                    if let Some(hasher) = &mut self.hasher {
+                        hasher.update(&bytes);
+                    }
+                    #[cfg(feature = "digest")]
+                    if let Some(digest) = &mut self.digest {
+                        digest.update(&bytes);
+                    }
+                    if self.request.verify_overlap > 0 {
+                        let keep = self.request.verify_overlap as usize;
+                        self.tail
+                            .extend_from_slice(&bytes[bytes.len().saturating_sub(keep)..]);
+                        let excess = self.tail.len().saturating_sub(keep);
+                        self.tail.drain(..excess);
+                    }
+                    if let Some(rate_limit) = &self.request.rate_limit {
+                        let wait = rate_limit.consume(bytes.len() as u64);
+                        if !wait.is_zero() {
+                            self.throttle = Some(Box::pin(sleep(wait)));
+                        }
+                    }
+                    return Poll::Ready(Some(Ok(bytes)));
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    if let Some(err) = self.retry(err) {
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                }
+                Poll::Ready(None) => return self.finish(),
+                Poll::Pending => {
+                    let Some(timeout) = self.request.stall_timeout else {
+                        return Poll::Pending;
+                    };
+                    let stall = self.stall.get_or_insert_with(|| Box::pin(sleep(timeout)));
+                    ready!(stall.as_mut().poll(cx));
+                    if let Some(err) = self.stalled() {
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Result of [`Response::download_to_file`].
+#[derive(Clone, Debug)]
+pub struct Download {
+    pub bytes_written: u64,
+    /// Bytes that were already in the `.part` file when the download resumed.
+    pub resumed_from: u64,
+    /// Headers of the initial response.
+    pub headers: HeaderMap,
+    pub summary: TransferSummary,
+}
+
+/// How a transfer went, from [`Decoder::summary`] or [`Download::summary`].
+#[derive(Clone, Debug, Default)]
+pub struct TransferSummary {
+    /// Bytes received since the last restart.
+    pub bytes: u64,
+    /// Whether the body matched the [`RequestBuilder::checksum`]. `false` if
+    /// there was none, or it was dropped because the body was segmented.
+    pub verified: bool,
+    /// Digest of the whole body, if one was asked for with
+    /// [`RequestBuilder::digest`].
+    pub digest: Option<Checksum>,
+    /// Requests made, starting at 1 for the initial one.
+    pub attempts: u32,
+    pub duration: Duration,
+}
+
+/// Fail with [`Error::InsufficientSpace`] unless the disk a download to `path`
+/// is written to has `needed` bytes free.
+fn check_free_space(url: &Url, path: &Path, needed: u64) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let available = fs4::available_space(dir).map_err(|err| Error::io(url, 0, err))?;
+    if available < needed {
+        return Err(Error::InsufficientSpace {
+            url: url.clone(),
+            pos: 0,
+            needed,
+            available,
+        });
+    }
+    Ok(())
+}
+
+/// Where a download to `path` is kept until it's complete.
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Expected digest of a body, hex-encoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    Sha256(String),
+    Blake3(String),
+    Md5(String),
+}
+
+impl Checksum {
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            Checksum::Sha256(_) => "SHA-256",
+            Checksum::Blake3(_) => "BLAKE3",
+            Checksum::Md5(_) => "MD5",
+        }
+    }
+
+    pub fn hex(&self) -> &str {
+        match self {
+            Checksum::Sha256(hex) | Checksum::Blake3(hex) | Checksum::Md5(hex) => hex.trim(),
+        }
+    }
+}
+
+/// Algorithm for [`RequestBuilder::digest`].
+#[cfg(feature = "digest")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+/// Running digest for a [`Checksum`].
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Md5(md5::Md5),
+}
+
+impl Hasher {
+    fn new(checksum: &Checksum) -> Self {
+        match checksum {
+            Checksum::Sha256(_) => Hasher::Sha256(sha2::Sha256::new()),
+            Checksum::Blake3(_) => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            Checksum::Md5(_) => Hasher::Md5(md5::Md5::new()),
+        }
+    }
+
+    #[cfg(feature = "digest")]
+    fn digest(algorithm: DigestAlgorithm) -> Self {
+        match algorithm {
+            DigestAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            DigestAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+            Hasher::Md5(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// The digest, hex-encoded.
+    fn finalize(self) -> String {
+        let digest = match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+        };
+        digest.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// The digest as a [`Checksum`] of the same algorithm.
+    #[cfg(feature = "digest")]
+    fn into_checksum(self) -> Checksum {
+        match self {
+            Hasher::Sha256(_) => Checksum::Sha256(self.finalize()),
+            Hasher::Blake3(_) => Checksum::Blake3(self.finalize()),
+            Hasher::Md5(_) => Checksum::Md5(self.finalize()),
+        }
+    }
+}
+
+/// Where the resume state of a download to `path` is saved.
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = part_path(path).into_os_string();
+    sidecar.push(".json");
+    PathBuf::from(sidecar)
+}
+
+/// Delete the `.part` file and resume state an interrupted download to `path`
+/// left behind, so the next one starts over.
+pub async fn discard_partial(path: impl AsRef<Path>) -> std::io::Result<()> {
+    let path = path.as_ref();
+    for path in [part_path(path), sidecar_path(path)] {
+        match fs::remove_file(&path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Move the `.part` file a download to `path` left behind to `to` and drop
+/// its resume state, e.g. to keep a body that failed its checksum out of the
+/// way of the next attempt.
+pub async fn quarantine_partial(
+    path: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    fs::rename(part_path(path), to).await?;
+    discard_partial(path).await
+}
+
+/// Hex-encoded digest of the file at `path`, with the algorithm of
+/// `checksum`, to verify a download that couldn't be while it streamed.
+pub async fn hash_file(path: impl AsRef<Path>, checksum: &Checksum) -> std::io::Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Hasher::new(checksum);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+/// Bytes to download between two updates of the sidecar.
+const CHECKPOINT_INTERVAL: u64 = 8 * 1024 * 1024;
+
+/// Chunks of a download waiting to be written with a single vectored write on
+/// a blocking thread; `tokio::fs::File` would copy each of them into its own
+/// buffer first.
+struct Batch {
+    // shares the offset of the `tokio::fs::File` it was cloned from
+    file: Option<std::fs::File>,
+    chunks: Vec<Bytes>,
+    len: usize,
+}
+
+impl Batch {
+    /// Written once this many bytes are waiting.
+    const MAX_LEN: usize = 4 * 1024 * 1024;
+    /// Written once this many chunks are waiting, well below `IOV_MAX`.
+    const MAX_CHUNKS: usize = 64;
+
+    async fn new(file: &fs::File) -> std::io::Result<Self> {
+        Ok(Batch {
+            file: Some(file.try_clone().await?.into_std().await),
+            chunks: Vec::new(),
+            len: 0,
+        })
+    }
+
+    fn push(&mut self, bytes: Bytes) {
+        self.len += bytes.len();
+        self.chunks.push(bytes);
+    }
+
+    fn is_full(&self) -> bool {
+        self.len >= Self::MAX_LEN || self.chunks.len() >= Self::MAX_CHUNKS
+    }
+
+    /// Drop what's waiting, e.g. when the download starts over.
+    fn clear(&mut self) {
+        self.chunks.clear();
+        self.len = 0;
+    }
+
+    /// Reserve disk space for the first `len` bytes of the file.
+    async fn allocate(&mut self, len: u64) -> std::io::Result<()> {
+        let Some(file) = self.file.take() else {
+            return Err(std::io::Error::other("an earlier write was cancelled"));
+        };
+        let (file, result) = tokio::task::spawn_blocking(move || {
+            let result = fs4::FileExt::allocate(&file, len);
+            (file, result)
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+        self.file = Some(file);
+        result
+    }
+
+    async fn flush(&mut self) -> std::io::Result<()> {
+        if self.chunks.is_empty() {
+            return Ok(());
+        }
+        let Some(mut file) = self.file.take() else {
+            return Err(std::io::Error::other("an earlier write was cancelled"));
+        };
+        let chunks = std::mem::take(&mut self.chunks);
+        self.len = 0;
+        let (file, result) = tokio::task::spawn_blocking(move || {
+            let result = write_all_vectored(&mut file, chunks);
+            (file, result)
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+        self.file = Some(file);
+        result
+    }
+}
+
+/// Write each of `chunks` at its offset in `file`, less `first`.
+fn write_chunks_at(file: &std::fs::File, chunks: &[Chunk], first: u64) -> std::io::Result<()> {
+    for chunk in chunks {
+        write_all_at(file, &chunk.bytes, chunk.offset - first)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &std::fs::File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &std::fs::File, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Write all of `chunks` with as few calls to `write_vectored` as possible.
+fn write_all_vectored(
+    writer: &mut impl std::io::Write,
+    mut chunks: Vec<Bytes>,
+) -> std::io::Result<()> {
+    chunks.retain(|chunk| !chunk.is_empty());
+    let mut first = 0;
+    while first < chunks.len() {
+        let slices = chunks[first..]
+            .iter()
+            .map(|chunk| std::io::IoSlice::new(chunk))
+            .collect::<Vec<_>>();
+        let mut n = match writer.write_vectored(&slices) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        while n > 0 {
+            let chunk = &mut chunks[first];
+            if n < chunk.len() {
+                chunk.advance(n);
+                break;
+            }
+            n -= chunk.len();
+            first += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Resume state saved as `<path>.part.json` next to a partial download so it
+/// survives the app exiting or crashing.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Sidecar {
+    pub url: String,
+    /// Length of the `.part` file known to be on disk.
+    pub pos: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub total: Option<u64>,
+}
+
+impl Sidecar {
+    /// The resume state of an interrupted download to `path`.
+    pub async fn read(path: &Path) -> std::io::Result<Sidecar> {
+        let json = fs::read(sidecar_path(path)).await?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Sync `file`, then record that its first `pos` bytes can be trusted.
+    async fn save(&self, path: &Path, file: &fs::File) -> std::io::Result<()> {
+        file.sync_data().await?;
+        let sidecar = sidecar_path(path);
+        let mut tmp = sidecar.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp, &sidecar).await
+    }
+
+    fn validator(&self) -> Option<HeaderValue> {
+        let validator = self.etag.as_ref().or(self.last_modified.as_ref())?;
+        HeaderValue::from_str(validator).ok()
+    }
+}
+
+/// Stream returned by [`Response::buffered_stream`].
+pub struct BufferedStream {
+    // chunks read ahead, each holding its share of the budget until yielded
+    chunks: mpsc::UnboundedReceiver<(Result<Bytes>, Option<OwnedSemaphorePermit>)>,
+    budget: Arc<Semaphore>,
+    max_buffered: usize,
+    progress: ProgressHandle,
+    transfer: TransferHandle,
+}
+
+impl BufferedStream {
+    /// Bytes read from the network but not yielded yet.
+    pub fn buffered_bytes(&self) -> usize {
+        self.max_buffered - self.budget.available_permits()
+    }
+
+    /// The transfer's speed and ETA, which run ahead of what was yielded by up
+    /// to [`Self::buffered_bytes`].
+    pub fn progress(&self) -> ProgressHandle {
+        self.progress.clone()
+    }
+
+    pub fn transfer_handle(&self) -> TransferHandle {
+        self.transfer.clone()
+    }
+}
+
+impl Stream for BufferedStream {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // dropping the permit makes room for the next chunk
+        let chunk = ready!(self.chunks.poll_recv(cx)).map(|(chunk, _permit)| chunk);
+        Poll::Ready(chunk)
+    }
+}
+
+/// Reader returned by [`Response::into_async_read`].
+pub struct BodyReader {
+    decoder: Decoder,
+    // rest of the last chunk that didn't fit into the caller's buffer
+    chunk: Bytes,
+}
+
+impl BodyReader {
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// Wait for the next chunk unless part of the last one is left; leaves
+    /// `chunk` empty at the end of the body.
+    fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while self.chunk.is_empty() {
+            match ready!(Pin::new(&mut self.decoder).poll_next(cx)) {
+                Some(Ok(bytes)) => self.chunk = bytes,
+                Some(Err(err)) => return Poll::Ready(Err(err.into())),
+                None => break,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl tokio::io::AsyncRead for BodyReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_chunk(cx))?;
+        let n = self.chunk.len().min(buf.remaining());
+        buf.put_slice(&self.chunk[..n]);
+        self.chunk.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl tokio::io::AsyncBufRead for BodyReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        let this = self.get_mut();
+        ready!(this.poll_chunk(cx))?;
+        Poll::Ready(Ok(&this.chunk))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.chunk.advance(amt);
+    }
+}
+
+impl futures::io::AsyncRead for BodyReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.poll_chunk(cx))?;
+        let n = self.chunk.len().min(buf.len());
+        buf[..n].copy_from_slice(&self.chunk[..n]);
+        self.chunk.advance(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl futures::io::AsyncBufRead for BodyReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        let this = self.get_mut();
+        ready!(this.poll_chunk(cx))?;
+        Poll::Ready(Ok(&this.chunk))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.chunk.advance(amt);
+    }
+}
+
+/// Random access to a remote body over `Range` requests, implementing
+/// `AsyncRead` and `AsyncSeek` of both tokio and futures, see
+/// [`RequestBuilder::range_reader`].
+///
+/// Reads fetch whole blocks, plus a few blocks of read-ahead, and keep the
+/// most recently used ones in memory, so reading e.g. a zip central directory
+/// or GGUF header takes a request or two instead of the whole file. Every
+/// request is sent with `If-Range`, so a file that changed in the meantime
+/// fails with [`Error::ValidatorMismatch`].
+pub struct RangeReader {
+    request: Request,
+    len: u64,
+    pos: u64,
+    block_size: u64,
+    read_ahead: u64,
+    cache: BlockCache,
+    fetch: Option<BoxFuture<'static, Result<(u64, Bytes)>>>,
+}
+
+impl RangeReader {
+    const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024;
+    const DEFAULT_READ_AHEAD: u64 = 1;
+    const DEFAULT_CACHE_BLOCKS: usize = 64;
+
+    /// Size of the body.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Smallest range fetched at once (64 KiB by default).
+    pub fn block_size(mut self, block_size: u64) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Number of blocks fetched after the one being read (1 by default).
+    pub fn read_ahead(mut self, blocks: u64) -> Self {
+        self.read_ahead = blocks;
+        self
+    }
+
+    /// Number of blocks kept in memory (64 by default).
+    pub fn cache_blocks(mut self, blocks: usize) -> Self {
+        self.cache.capacity = blocks.max(1);
+        self.cache.evict();
+        self
+    }
+
+    /// Cached bytes from the current position on, fetching them first if needed.
+    /// Empty at the end of the body.
+    fn poll_bytes(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<Bytes>> {
+        loop {
+            if self.pos >= self.len {
+                return Poll::Ready(Ok(Bytes::new()));
+            }
+            if let Some(bytes) = self.cache.get(self.pos) {
+                return Poll::Ready(Ok(bytes));
+            }
+            let fetch = match &mut self.fetch {
+                Some(fetch) => fetch,
+                None => {
+                    let start = self.pos - self.pos % self.block_size;
+                    let end = (start + self.block_size * (1 + self.read_ahead)).min(self.len);
+                    let request = self.request.clone();
+                    self.fetch.insert(Box::pin(async move {
+                        let (response, _) = fetch_range(request, start, end).await?;
+                        Ok((start, collect_range(response).await?))
+                    }))
+                }
+            };
+            let result = ready!(fetch.as_mut().poll(cx));
+            self.fetch = None;
+            // cached even if a seek made it useless for the pending read
+            let (start, bytes) = result?;
+            let end = start + bytes.len() as u64;
+            // a server may send less than was asked for, but without the
+            // block being read it would only be asked for again and again
+            if self.pos - self.pos % self.block_size == start && self.pos >= end {
+                return Poll::Ready(Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "{} sent bytes {start}-{end} instead of the block at {}",
+                        self.request.url, self.pos
+                    ),
+                )));
+            }
+            self.cache.insert(start, bytes, self.block_size);
+        }
+    }
+
+    fn seek_to(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+impl fmt::Debug for RangeReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RangeReader")
+            .field("url", &self.request.url)
+            .field("len", &self.len)
+            .field("pos", &self.pos)
+            .finish_non_exhaustive()
+    }
+}
+
+impl tokio::io::AsyncRead for RangeReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let bytes = ready!(self.poll_bytes(cx))?;
+        let n = bytes.len().min(buf.remaining());
+        buf.put_slice(&bytes[..n]);
+        self.pos += n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl tokio::io::AsyncSeek for RangeReader {
+    fn start_seek(mut self: Pin<&mut Self>, pos: SeekFrom) -> std::io::Result<()> {
+        self.seek_to(pos).map(drop)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+impl futures::io::AsyncRead for RangeReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let bytes = ready!(self.poll_bytes(cx))?;
+        let n = bytes.len().min(buf.len());
+        buf[..n].copy_from_slice(&bytes[..n]);
+        self.pos += n as u64;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl futures::io::AsyncSeek for RangeReader {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(self.seek_to(pos))
+    }
+}
+
+/// Least recently used blocks of a [`RangeReader`], most recent last.
+#[derive(Debug)]
+struct BlockCache {
+    blocks: VecDeque<(u64, Bytes)>,
+    capacity: usize,
+}
+
+impl BlockCache {
+    fn new(capacity: usize) -> Self {
+        BlockCache {
+            blocks: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Bytes from `pos` to the end of the block containing it.
+    fn get(&mut self, pos: u64) -> Option<Bytes> {
+        let i = self
+            .blocks
+            .iter()
+            .position(|(start, bytes)| (*start..start + bytes.len() as u64).contains(&pos))?;
+        let block = self.blocks.remove(i)?;
+        let bytes = block.1.slice((pos - block.0) as usize..);
+        self.blocks.push_back(block);
+        Some(bytes)
+    }
+
+    /// Add the range starting at `start`, split into blocks of `block_size`.
+    fn insert(&mut self, start: u64, bytes: Bytes, block_size: u64) {
+        let block_size = block_size as usize;
+        for offset in (0..bytes.len()).step_by(block_size) {
+            let end = (offset + block_size).min(bytes.len());
+            let block_start = start + offset as u64;
+            self.blocks.retain(|(start, _)| *start != block_start);
+            self.blocks
+                .push_back((block_start, bytes.slice(offset..end)));
+        }
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.blocks.len() > self.capacity {
+            self.blocks.pop_front();
+        }
+    }
+}
+
+/// Send a request for `start..end` of the body and check the server answered
+/// with exactly that range. Returns the response along with the size of the
+/// whole body.
+async fn fetch_range(mut request: Request, start: u64, end: u64) -> Result<(Response, u64)> {
+    request.start = start;
+    request.end = Some(end);
+    let deadline = request.deadline.map(|deadline| Instant::now() + deadline);
+    let permit = request.host_limits.acquire(&request.url).await;
+    let response = request.execute().await?;
+    let status = response.status();
+    let content_range = response.headers().get(CONTENT_RANGE);
+    let len = content_range.and_then(content_range::total);
+    if status == StatusCode::RANGE_NOT_SATISFIABLE && start == 0 && len == Some(0) {
+        // an empty body has no bytes to ask for
+    } else if status == StatusCode::OK && request.if_range.is_some() {
+        return Err(Error::ValidatorMismatch {
+            url: request.url.clone(),
+            pos: start,
+        });
+    } else if !status.is_success() {
+        return Err(Error::network(&request.url, start, status_error(response)));
+    } else if status != StatusCode::PARTIAL_CONTENT
+        || content_range.and_then(content_range::start) != Some(start)
+        || len.is_none()
+    {
+        return Err(Error::RangeNotHonored {
+            url: request.url.clone(),
+            pos: start,
+            content_range: content_range
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned()),
+        });
+    }
+    let etag = strong_etag(&response);
+    let last_modified = strong_last_modified(&response);
+    let response = Response {
+        request,
+        response,
+        accept_byte_ranges: true,
+        etag,
+        last_modified,
+        skip: 0,
+        deadline,
+        permit,
+    };
+    Ok((response, len.unwrap_or(0)))
+}
+
+/// Read the whole (resumable) body of a ranged response into memory.
+async fn collect_range(response: Response) -> Result<Bytes> {
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Ok(Bytes::new());
+    }
+    let mut body = response.bytes_stream();
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.next().await {
+        buf.extend_from_slice(&chunk?);
+    }
+    Ok(buf.into())
+}
+
+/// Stream of decompressed `Bytes` returned by [`Response::decoded_stream`].
+pub struct DecodedStream {
+    reader: Decompress,
+    buf: Box<[u8]>,
+}
+
+impl DecodedStream {
+    const BUF_SIZE: usize = 64 * 1024;
+
+    pub fn decoder(&self) -> &Decoder {
+        self.reader.body().decoder()
+    }
+
+    /// Encoded bytes received so far, see [`Decoder::position`].
+    pub fn position(&self) -> u64 {
+        self.decoder().position()
+    }
+
+    /// Get back the error the body stream failed with, or blame the encoding.
+    fn error(&self, err: std::io::Error) -> Error {
+        let kind = err.kind();
+        let decoder = self.decoder();
+        let source = match err.into_inner().map(|inner| inner.downcast::<Error>()) {
+            Some(Ok(err)) => return *err,
+            Some(Err(inner)) => std::io::Error::new(kind, inner),
+            None => kind.into(),
+        };
+        Error::Decode {
+            url: decoder.request.url.clone(),
+            pos: decoder.pos,
+            source,
+        }
+    }
+}
+
+impl Stream for DecodedStream {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let mut buf = tokio::io::ReadBuf::new(&mut this.buf);
+        let result = ready!(tokio::io::AsyncRead::poll_read(
+            Pin::new(&mut this.reader),
+            cx,
+            &mut buf
+        ));
+        Poll::Ready(match result {
+            Ok(()) if buf.filled().is_empty() => None,
+            Ok(()) => Some(Ok(Bytes::copy_from_slice(buf.filled()))),
+            Err(err) => Some(Err(this.error(err))),
+        })
+    }
+}
+
+/// Decompressor for each supported `Content-Encoding`.
+enum Decompress {
+    Identity(BodyReader),
+    Gzip(GzipDecoder<BodyReader>),
+    // HTTP's "deflate" is zlib-wrapped
+    Deflate(ZlibDecoder<BodyReader>),
+    Brotli(BrotliDecoder<BodyReader>),
+    Zstd(ZstdDecoder<BodyReader>),
+}
+
+impl Decompress {
+    fn body(&self) -> &BodyReader {
+        match self {
+            Decompress::Identity(body) => body,
+            Decompress::Gzip(reader) => reader.get_ref(),
+            Decompress::Deflate(reader) => reader.get_ref(),
+            Decompress::Brotli(reader) => reader.get_ref(),
+            Decompress::Zstd(reader) => reader.get_ref(),
+        }
+    }
+}
+
+impl tokio::io::AsyncRead for Decompress {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match &mut *self {
+            Decompress::Identity(body) => Pin::new(body).poll_read(cx, buf),
+            Decompress::Gzip(reader) => Pin::new(reader).poll_read(cx, buf),
+            Decompress::Deflate(reader) => Pin::new(reader).poll_read(cx, buf),
+            Decompress::Brotli(reader) => Pin::new(reader).poll_read(cx, buf),
+            Decompress::Zstd(reader) => Pin::new(reader).poll_read(cx, buf),
+        }
+    }
+}
+
+/// Chunk of a [`Segmented`] download.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    /// Position of `bytes` in the whole body.
+    pub offset: u64,
+    pub bytes: Bytes,
+}
+
+/// Stream of [`Chunk`]s returned by [`Response::segmented`]. An error ends
+/// only the segment it occurred in; callers usually give up on the first one.
+pub struct Segmented {
+    segments: futures::stream::SelectAll<Segment>,
+    file: Placement,
+    // of the segments that are done
+    summary: Arc<Mutex<TransferSummary>>,
+}
+
+/// Where a [`Segmented`] body goes in the file it's downloaded to.
+struct Placement {
+    url: Url,
+    // the body's offset that's the file's first byte
+    first: u64,
+    start: u64,
+    len: u64,
+    min_free_space: Option<u64>,
+    headers: HeaderMap,
+}
+
+impl Segmented {
+    /// Write each segment at its offset in `<path>.part` as it arrives, then
+    /// move it to `path` once all of them are complete and synced to disk,
+    /// like [`Response::download_to_file`]. The space for the whole body is
+    /// allocated first.
+    ///
+    /// A `.part` file with holes can't be resumed from, so there's no
+    /// sidecar, and when a segment fails the file is cut back to what it
+    /// held before.
+    pub async fn download_to_file(mut self, path: impl AsRef<Path>) -> Result<Download> {
+        let path = path.as_ref();
+        let part = part_path(path);
+        let url = self.file.url.clone();
+        let io_err = |pos| {
+            let url = &url;
+            move |err| Error::io(url, pos, err)
+        };
+        // position in the file rather than the body
+        let start = self.file.start - self.file.first;
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(start == 0)
+            .open(&part)
+            .await
+            .map_err(io_err(0))?;
+        let len = file.metadata().await.map_err(io_err(0))?.len();
+        if len < start {
+            let err = std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "partial file is shorter than the resume position",
+            );
+            return Err(Error::io(&url, 0, err));
+        }
+        match self.write(&file, &part).await {
+            Ok(bytes_written) => {
+                let pos = self.file.start + bytes_written;
+                fs::rename(&part, path).await.map_err(io_err(pos))?;
+                let summary = self.summary.lock().unwrap().clone();
+                Ok(Download {
+                    bytes_written,
+                    resumed_from: start,
+                    headers: self.file.headers,
+                    summary,
+                })
+            }
+            Err(err) => {
+                if let Err(err) = file.set_len(start).await {
+                    log::warn!("failed to truncate {}: {}", part.display(), err);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Allocate `file`, write the segments into it and return the number of
+    /// bytes written.
+    async fn write(&mut self, file: &fs::File, part: &Path) -> Result<u64> {
+        let url = self.file.url.clone();
+        let io_err = |pos| {
+            let url = &url;
+            move |err| Error::io(url, pos, err)
+        };
+        let first = self.file.first;
+        let size = self.file.start - first + self.file.len;
+        if let Some(min_free) = self.file.min_free_space {
+            check_free_space(&url, part, self.file.len + min_free)?;
+        }
+        // written to on blocking threads, at its offsets
+        let std_file = file.try_clone().await.map_err(io_err(0))?.into_std().await;
+        let std_file = Arc::new(std_file);
+        let allocated = std_file.clone();
+        tokio::task::spawn_blocking(move || fs4::FileExt::allocate(&*allocated, size))
+            .await
+            .map_err(std::io::Error::other)
+            .and_then(|result| result)
+            .map_err(io_err(0))?;
+        let mut bytes_written = 0;
+        let mut batch = Vec::new();
+        let mut batch_len = 0;
+        loop {
+            let chunk = self.next().await.transpose()?;
+            let done = chunk.is_none();
+            if let Some(chunk) = chunk {
+                batch_len += chunk.bytes.len();
+                bytes_written += chunk.bytes.len() as u64;
+                batch.push(chunk);
+            }
+            if done || batch_len >= Batch::MAX_LEN || batch.len() >= Batch::MAX_CHUNKS {
+                let pos = batch.first().map_or(0, |chunk: &Chunk| chunk.offset);
+                let chunks = std::mem::take(&mut batch);
+                batch_len = 0;
+                let file = std_file.clone();
+                tokio::task::spawn_blocking(move || write_chunks_at(&file, &chunks, first))
+                    .await
+                    .map_err(std::io::Error::other)
+                    .and_then(|result| result)
+                    .map_err(io_err(pos))?;
+            }
+            if done {
+                break;
+            }
+        }
+        let pos = self.file.start + bytes_written;
+        file.set_len(size).await.map_err(io_err(pos))?;
+        file.sync_all().await.map_err(io_err(pos))?;
+        Ok(bytes_written)
+    }
+}
+
+impl Stream for Segmented {
+    type Item = Result<Chunk>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.segments).poll_next(cx)
+    }
+}
+
+/// One connection of a [`Segmented`] download.
+struct Segment {
+    decoder: Decoder,
+    // shared by the segments, each adding its own once it's done
+    summary: Arc<Mutex<TransferSummary>>,
+}
+
+impl Stream for Segment {
+    type Item = Result<Chunk>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = ready!(Pin::new(&mut self.decoder).poll_next(cx));
+        if let Some(done) = item
+            .is_none()
+            .then(|| self.decoder.summary.take())
+            .flatten()
+        {
+            let mut summary = self.summary.lock().unwrap();
+            summary.bytes += done.bytes;
+            summary.attempts += done.attempts;
+            summary.duration = summary.duration.max(done.duration);
+        }
+        let end = self.decoder.offset();
+        Poll::Ready(item.map(|bytes| {
+            bytes.map(|bytes| Chunk {
+                offset: end - bytes.len() as u64,
+                bytes,
+            })
+        }))
+    }
+}
+
+/// Token bucket capping the speed of one or more body streams, see
+/// [`RequestBuilder::limit_rate`]. Clones share the same bucket.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    bucket: Arc<Mutex<Bucket>>,
+    // also drawn from, see `RateLimit::child`
+    parent: Option<Box<RateLimit>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    bytes_per_sec: Option<u64>,
+    // may go negative: the debt is paid off by waiting before the next read
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    /// `None` doesn't limit the speed until [`RateLimit::set`] is called.
+    pub fn new(bytes_per_sec: Option<u64>) -> Self {
+        RateLimit {
+            bucket: Arc::new(Mutex::new(Bucket {
+                bytes_per_sec,
+                tokens: 0.0,
+                updated: Instant::now(),
+            })),
+            parent: None,
+        }
+    }
+
+    /// A bucket for one transfer that also draws from this one, to cap it
+    /// at `bytes_per_sec` while it shares this limit with other transfers.
+    pub fn child(&self, bytes_per_sec: Option<u64>) -> Self {
+        RateLimit {
+            parent: Some(Box::new(self.clone())),
+            ..RateLimit::new(bytes_per_sec)
+        }
+    }
+
+    pub fn get(&self) -> Option<u64> {
+        self.bucket.lock().unwrap().bytes_per_sec
+    }
+
+    /// Change the limit, taking effect from the next chunk.
+    pub fn set(&self, bytes_per_sec: Option<u64>) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.bytes_per_sec = bytes_per_sec;
+        bucket.tokens = bucket.tokens.max(0.0);
+        bucket.updated = Instant::now();
+    }
+
+    /// Take `len` bytes out of the bucket, and its parent's, and return how
+    /// long to wait before reading more.
+    fn consume(&self, len: u64) -> Duration {
+        let parent = (self.parent.as_ref()).map_or(Duration::ZERO, |parent| parent.consume(len));
+        let mut bucket = self.bucket.lock().unwrap();
+        let Some(rate) = bucket.bytes_per_sec.filter(|&rate| rate > 0) else {
+            return parent;
+        };
+        let rate = rate as f64;
+        let now = Instant::now();
+        // allow bursts of up to a second's worth of data
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(rate) - len as f64;
+        bucket.updated = now;
+        if bucket.tokens >= 0.0 {
+            parent
+        } else {
+            parent.max(Duration::from_secs_f64(-bucket.tokens / rate))
+        }
+    }
+}
+
+/// State of a transfer after a chunk, see [`Response::bytes_stream_with_progress`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    /// Bytes received so far; drops back to 0 if the stream restarts.
+    pub downloaded: u64,
+    /// Size of the whole body; `None` if the server didn't tell, e.g. for a
+    /// chunked response, rather than 0.
+    pub total: Option<u64>,
+    /// Number of the request currently streaming, starting at 1.
+    pub attempt: u32,
+    /// Smoothed transfer rate in bytes per second.
+    pub speed: f64,
+}
+
+impl Progress {
+    /// Share of the body received, from 0 to 1, or `None` if its size is
+    /// unknown so a progress bar should show activity instead.
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total? {
+            0 => Some(1.0),
+            total => Some((self.downloaded as f64 / total as f64).min(1.0)),
+        }
+    }
+}
+
+/// [`Decoder`] that also reports [`Progress`] with every chunk.
+pub struct ProgressStream {
+    decoder: Decoder,
+    // when the previous chunk arrived
+    last: Option<Instant>,
+    speed: f64,
+}
+
+impl ProgressStream {
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+}
+
+impl Stream for ProgressStream {
+    type Item = Result<(Bytes, Progress)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let bytes = match ready!(Pin::new(&mut this.decoder).poll_next(cx)) {
+            Some(Ok(bytes)) => bytes,
+            Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+            None => return Poll::Ready(None),
+        };
+        let now = Instant::now();
+        if let Some(last) = this.last {
+            let elapsed = now.duration_since(last).as_secs_f64();
+            if elapsed > 0.0 {
+                let speed = bytes.len() as f64 / elapsed;
+                // exponential moving average so a single fast or slow chunk
+                // doesn't make the displayed speed jump around
+                this.speed = if this.speed == 0.0 {
+                    speed
+                } else {
+                    0.7 * this.speed + 0.3 * speed
+                };
+            }
+        }
+        this.last = Some(now);
+        let progress = Progress {
+            downloaded: this.decoder.position(),
+            total: this.decoder.total(),
+            attempt: this.decoder.attempts(),
+            speed: this.speed,
+        };
+        Poll::Ready(Some(Ok((bytes, progress))))
+    }
+}
+
+/// Pauses and resumes a body stream, see [`Response::transfer_handle`]. Clones
+/// control the same transfer, including every segment of a
+/// [`Response::segmented`] download.
+#[derive(Clone, Debug, Default)]
+pub struct TransferHandle {
+    state: Arc<Mutex<PauseState>>,
+}
+
+#[derive(Debug, Default)]
+struct PauseState {
+    // `Some(disconnect)` while paused
+    paused: Option<bool>,
+    // streams waiting for the pause to change
+    wakers: Vec<Waker>,
+}
+
+impl TransferHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop reading the body. The connection stays open, so the server sees
+    /// the transfer stall; it may drop it after a while, in which case it's
+    /// resumed like any other interruption.
+    pub fn pause(&self) {
+        self.set(Some(false));
+    }
+
+    /// Stop reading the body and close the connection, freeing its slot for
+    /// [`Client::max_connections_per_host`]. [`TransferHandle::resume`] sends
+    /// a new ranged request for the rest.
+    pub fn pause_and_disconnect(&self) {
+        self.set(Some(true));
+    }
+
+    pub fn resume(&self) {
+        self.set(None);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused.is_some()
+    }
+
+    fn set(&self, paused: Option<bool>) {
+        let mut state = self.state.lock().unwrap();
+        state.paused = paused;
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Whether the stream should wait, and if so whether it should disconnect;
+    /// it's woken once that changes.
+    fn poll_paused(&self, cx: &mut Context<'_>) -> Option<bool> {
+        let mut state = self.state.lock().unwrap();
+        let disconnect = state.paused?;
+        if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Some(disconnect)
+    }
+}
+
+/// Shared view of a [`Decoder`]'s throughput, see [`Decoder::progress`].
+/// Clones share the same measurements.
+#[derive(Clone, Debug)]
+pub struct ProgressHandle {
+    meter: Arc<Mutex<Meter>>,
+}
+
+impl Default for ProgressHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+struct Meter {
+    started: Instant,
+    // arrival time and size of the chunks received within `WINDOW`
+    samples: VecDeque<(Instant, u64)>,
+    transferred: u64,
+    // how far into the body, or the window of a ranged request, it got
+    pos: u64,
+    remaining: Option<u64>,
+}
+
+impl ProgressHandle {
+    /// Speed is averaged over this much of the recent past.
+    const WINDOW: Duration = Duration::from_secs(5);
+
+    pub fn new() -> Self {
+        ProgressHandle {
+            meter: Arc::new(Mutex::new(Meter {
+                started: Instant::now(),
+                samples: VecDeque::new(),
+                transferred: 0,
+                pos: 0,
+                remaining: None,
+            })),
+        }
+    }
+
+    fn record(&self, len: u64, pos: u64, remaining: Option<u64>) {
+        let mut meter = self.meter.lock().unwrap();
+        let now = Instant::now();
+        meter.samples.push_back((now, len));
+        meter.transferred += len;
+        meter.pos = pos;
+        meter.remaining = remaining;
+        meter.prune(now);
+    }
+
+    /// Bytes per second averaged over the last few seconds; it falls towards
+    /// 0 while the stream stalls or reconnects.
+    pub fn speed(&self) -> f64 {
+        let mut meter = self.meter.lock().unwrap();
+        let now = Instant::now();
+        meter.prune(now);
+        let window = now.duration_since(meter.started).min(Self::WINDOW);
+        if window.is_zero() {
+            return 0.0;
+        }
+        let bytes: u64 = meter.samples.iter().map(|&(_, len)| len).sum();
+        bytes as f64 / window.as_secs_f64()
+    }
+
+    /// Estimated time until the body is complete at the current
+    /// [`ProgressHandle::speed`], if its size is known and anything is moving.
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.meter.lock().unwrap().remaining?;
+        let speed = self.speed();
+        (speed > 0.0).then(|| Duration::from_secs_f64(remaining as f64 / speed))
+    }
+
+    /// Bytes still to come, or `None` while the body's size is unknown.
+    pub fn remaining(&self) -> Option<u64> {
+        self.meter.lock().unwrap().remaining
+    }
+
+    /// Bytes of the body received so far, counting those a download to a
+    /// file already had on disk when it resumed.
+    pub fn position(&self) -> u64 {
+        self.meter.lock().unwrap().pos
+    }
+
+    /// Size of the body, or `None` while it's unknown.
+    pub fn total(&self) -> Option<u64> {
+        let meter = self.meter.lock().unwrap();
+        Some(meter.pos + meter.remaining?)
+    }
+
+    /// Bytes received over all attempts, including any that were received
+    /// again after a restart.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.meter.lock().unwrap().transferred
+    }
+}
+
+impl Meter {
+    fn prune(&mut self, now: Instant) {
+        while matches!(self.samples.front(), Some(&(time, _)) if time + ProgressHandle::WINDOW <= now)
+        {
+            self.samples.pop_front();
+        }
+    }
+}
+
+/// A request on its way, see [`hedged`].
+type Sending = BoxFuture<'static, reqwest::Result<reqwest::Response>>;
+
+/// Send `hedge` as well if `send` got no response within `after`, and return
+/// the first response; the other request is dropped, closing its connection.
+async fn hedged(
+    send: Sending,
+    hedge: Sending,
+    after: Duration,
+) -> reqwest::Result<reqwest::Response> {
+    let send = match select(send, Box::pin(sleep(after))).await {
+        Either::Left((response, _)) => return response,
+        Either::Right((_, send)) => send,
+    };
+    log::debug!("no response after {:?}, sending a hedged request", after);
+    match select(send, hedge).await {
+        // the other one may still succeed
+        Either::Left((Err(_), other)) | Either::Right((Err(_), other)) => other.await,
+        Either::Left((response, _)) | Either::Right((response, _)) => response,
+    }
+}
+
+/// Why the redirect policy stopped, found in the source chain of the
+/// resulting `reqwest::Error`.
+#[derive(Clone, Debug)]
+enum RedirectError {
+    Plaintext(Url),
+    TooMany(Vec<Url>),
+}
+
+impl fmt::Display for RedirectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedirectError::Plaintext(url) => write!(f, "redirected to plaintext {url}"),
+            RedirectError::TooMany(chain) => write!(f, "{} redirects", chain.len() - 1),
+        }
+    }
+}
+
+impl std::error::Error for RedirectError {}
+
+/// The first error of type `E` in the source chain of `err`, itself included.
+fn find_source<'a, E: std::error::Error + 'static>(
+    err: &'a (dyn std::error::Error + 'static),
+) -> Option<&'a E> {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref() {
+            return Some(err);
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// Switch an `http://` URL to `https://`, keeping any explicit port.
+fn upgrade(url: &mut Url) {
+    if url.scheme() == "http" {
+        // only fails for schemes that aren't special, which `http` is
+        let _ = url.set_scheme("https");
+    }
+}
+
+/// Turn an error response into the corresponding `reqwest::Error`.
+fn status_error(response: reqwest::Response) -> reqwest::Error {
+    response
+        .error_for_status()
+        .expect_err("called with a non-error status")
+}
+
+/// Shortcut for `Client::new().get(url).send()`.
+pub async fn get(url: Url) -> Result<Response> {
+    Client::new().get(url).send().await
+}
+
+pub mod blocking;
+#[cfg(feature = "cache")]
+pub mod cache;
+pub mod chunks;
+pub mod content_disposition;
+pub mod content_range;
+mod guard;
+pub mod metalink;
+pub mod middleware;
+pub mod seekable;
+#[cfg(feature = "sigv4")]
+pub mod sigv4;
+mod spki;
+pub mod upload;
+
+#[cfg(test)]
+mod test_server;
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "digest")]
+    use super::DigestAlgorithm;
+    use super::{
+        classify,
+        middleware::{Middleware, Next},
+        parse_retry_after,
+        test_server::{Faults, TestServer},
+        upgrade, write_all_vectored, Backoff, BlockCache, Checksum, Client, DecoderState, Error,
+        FailureKind, FallbackPolicy, MismatchPolicy, PlaintextPolicy, ProgressHandle, RateLimit,
+        Refresh, Response, RetryPolicy, Sidecar,
+    };
+    use bytes::Bytes;
+    use futures::{future::BoxFuture, StreamExt};
+    use reqwest::StatusCode;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+    use tokio::time::Instant;
+
+    fn body() -> Bytes {
+        (0..10_000u32)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    fn client() -> Client {
+        Client::new().retry_policy(
+            RetryPolicy::new()
+                .max_attempts(5)
+                .backoff(Backoff::Fixed(Duration::from_secs(1))),
+        )
+    }
+
+    async fn collect(response: Response) -> super::Result<Vec<u8>> {
+        let mut stream = response.bytes_stream();
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk?);
+        }
+        Ok(body)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumes_after_dropped_connections() {
+        let faults = Faults {
+            drop_at: vec![1000, 6000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let response = client().get(server.url()).send().await.unwrap();
+        assert_eq!(collect(response).await.unwrap(), &body()[..]);
+        assert_eq!(
+            server.ranges(),
+            [
+                None,
+                Some("bytes=1000-".to_owned()),
+                Some("bytes=6000-".to_owned())
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn verifies_overlap_on_resume() {
+        let faults = Faults {
+            drop_at: vec![1000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let response = client()
+            .get(server.url())
+            .verify_overlap(100)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(collect(response).await.unwrap(), &body()[..]);
+        assert_eq!(server.ranges(), [None, Some("bytes=900-".to_owned())]);
+    }
+
+    #[cfg(feature = "digest")]
+    #[tokio::test(start_paused = true)]
+    async fn summarizes_resumed_transfers() {
+        use sha2::{Digest, Sha256};
+
+        let faults = Faults {
+            drop_at: vec![1000, 6000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let response = client()
+            .get(server.url())
+            .digest(DigestAlgorithm::Sha256)
+            .send()
+            .await
+            .unwrap();
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            chunk.unwrap();
+        }
+        let summary = stream.summary().unwrap();
+        assert_eq!(summary.bytes, body().len() as u64);
+        assert_eq!(summary.attempts, 3);
+        let hex = Sha256::digest(body())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        assert_eq!(summary.digest, Some(Checksum::Sha256(hex)));
+    }
+
+    #[tokio::test]
+    async fn verifies_checksums_of_downloads() {
+        use sha2::{Digest, Sha256};
+
+        let hex: String = Sha256::digest(body())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let server = TestServer::start(body(), Faults::default()).await;
+        let path = std::env::temp_dir().join(format!("verified-{}", std::process::id()));
+        let download = client()
+            .get(server.url())
+            .verify_sha256(hex.clone())
+            .download_to_file(&path)
+            .await
+            .unwrap();
+        assert!(download.summary.verified);
+        let checksum = Checksum::Sha256(hex.clone());
+        assert_eq!(super::hash_file(&path, &checksum).await.unwrap(), hex);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn buffers_bounded_chunks() {
+        let faults = Faults {
+            drop_at: vec![3000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let response = client()
+            .get(server.url())
+            .chunk_size(512)
+            .send()
+            .await
+            .unwrap();
+        let mut stream = response.buffered_stream(2048);
+        let mut received = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.unwrap();
+            assert!(chunk.len() <= 512);
+            assert!(stream.buffered_bytes() <= 2048);
+            received.extend_from_slice(&chunk);
+        }
+        assert_eq!(received, &body()[..]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tracks_decoder_state() {
+        let faults = Faults {
+            drop_at: vec![1000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let response = client().get(server.url()).send().await.unwrap();
+        let mut stream = response.bytes_stream();
+        assert_eq!(stream.state(), DecoderState::Streaming);
+        let mut states = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            chunk.unwrap();
+            states.push(stream.state());
+        }
+        assert!(states.iter().all(|state| *state == DecoderState::Streaming));
+        assert_eq!(stream.state(), DecoderState::Idle);
+        assert_eq!(stream.attempts(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_capabilities() {
+        let server = TestServer::start(body(), Faults::default()).await;
+        let response = client().get(server.url()).send().await.unwrap();
+        let capabilities = response.capabilities();
+        assert!(capabilities.is_resumable());
+        assert!(capabilities.accept_byte_ranges && capabilities.etag);
+        assert_eq!(capabilities.total, Some(body().len() as u64));
+        assert_eq!(capabilities.content_encoding, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn middleware_sees_every_attempt() {
+        struct Count(Arc<AtomicUsize>);
+
+        impl Middleware for Count {
+            fn handle(
+                &self,
+                request: reqwest::Request,
+                next: Next,
+            ) -> BoxFuture<'static, reqwest::Result<reqwest::Response>> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                next.run(request)
+            }
+        }
+
+        let faults = Faults {
+            drop_at: vec![1000, 6000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let sent = Arc::new(AtomicUsize::new(0));
+        let client = Client::builder().with(Count(sent.clone())).build().unwrap();
+        let response = client.get(server.url()).send().await.unwrap();
+        assert_eq!(collect(response).await.unwrap(), &body()[..]);
+        assert_eq!(sent.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refreshes_credentials_on_resume() {
+        let faults = Faults {
+            drop_at: vec![1000],
+            unauthorized: vec![1],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let count = refreshes.clone();
+        let response = client()
+            .get(server.url())
+            .bearer_auth("expired")
+            .refresh_auth(move |status| {
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                count.fetch_add(1, Ordering::SeqCst);
+                async { Some(Refresh::BearerToken("fresh".to_owned())) }
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(collect(response).await.unwrap(), &body()[..]);
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            server.ranges(),
+            [
+                None,
+                Some("bytes=1000-".to_owned()),
+                Some("bytes=1000-".to_owned())
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hedges_slow_requests() {
+        let faults = Faults {
+            delay_first: Some(Duration::from_secs(30)),
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let started = Instant::now();
+        let response = client()
+            .get(server.url())
+            .hedge_after(Duration::from_secs(1))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(collect(response).await.unwrap(), &body()[..]);
+        assert!(started.elapsed() < Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn maps_every_attempt() {
+        let faults = Faults {
+            drop_at: vec![1000, 6000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let seen = attempts.clone();
+        let response = client()
+            .get(server.url())
+            .map_request(move |builder, attempt, pos| {
+                seen.lock().unwrap().push((attempt, pos));
+                builder
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(collect(response).await.unwrap(), &body()[..]);
+        assert_eq!(*attempts.lock().unwrap(), [(1, 0), (2, 1000), (3, 6000)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skips_bytes_when_range_is_ignored() {
+        let faults = Faults {
+            drop_at: vec![4000],
+            ignore_range: true,
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let response = client().get(server.url()).send().await.unwrap();
+        assert_eq!(collect(response).await.unwrap(), &body()[..]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn falls_back_when_range_is_ignored() {
+        let faults = Faults {
+            ignore_range: true,
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let request = || {
+            (client().get(server.url()))
+                .range(5000, None)
+                .skip_ignored_range(false)
+        };
+        let err = request().send().await.unwrap_err();
+        assert!(matches!(err, Error::RangeNotHonored { .. }));
+
+        let asked = Arc::new(AtomicUsize::new(0));
+        let counter = asked.clone();
+        let fallback = FallbackPolicy::ask(move |err| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            matches!(err, Error::RangeNotHonored { .. })
+        });
+        let response = request().fallback(fallback).send().await.unwrap();
+        assert_eq!(collect(response).await.unwrap(), &body()[5000..]);
+        assert_eq!(asked.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fails_when_etag_changes() {
+        let faults = Faults {
+            drop_at: vec![4000],
+            change_etag_at: Some(1),
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let response = client().get(server.url()).send().await.unwrap();
+        let err = collect(response).await.unwrap_err();
+        assert!(matches!(err, Error::ValidatorMismatch { pos: 4000, .. }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn child_rate_limits_draw_from_their_parent() {
+        let global = RateLimit::new(Some(1000));
+        let fast = global.child(Some(4000));
+        let slow = global.child(Some(250));
+        assert_eq!(fast.consume(500), Duration::from_millis(500));
+        assert_eq!(slow.consume(500), Duration::from_secs(2));
+        // the global bucket is 1000 bytes in debt now
+        assert_eq!(fast.consume(0), Duration::from_secs(1));
+        global.set(None);
+        assert_eq!(fast.consume(0), Duration::from_millis(125));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn progress_speed_covers_recent_chunks() {
+        let progress = ProgressHandle::new();
+        tokio::time::advance(Duration::from_secs(1)).await;
+        progress.record(1000, 1000, Some(4000));
+        tokio::time::advance(Duration::from_secs(1)).await;
+        progress.record(1000, 2000, Some(3000));
+        assert_eq!(progress.speed(), 1000.0);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(3)));
+        assert_eq!(progress.bytes_transferred(), 2000);
+        assert_eq!(progress.total(), Some(5000));
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(progress.speed(), 0.0);
+        assert_eq!(progress.eta(), None);
+        assert_eq!(progress.bytes_transferred(), 2000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejects_bodies_over_max_size() {
+        let server = TestServer::start(body(), Faults::default()).await;
+        let max = body().len() as u64 - 1;
+        let err = client()
+            .get(server.url())
+            .max_size(max)
+            .send()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::TooLarge { pos: 0, max: m, .. } if m == max));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bytes_keeps_only_the_restarted_body() {
+        let faults = Faults {
+            drop_at: vec![4000],
+            change_etag_at: Some(1),
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let response = client()
+            .get(server.url())
+            .on_mismatch(MismatchPolicy::Restart)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.bytes().await.unwrap(), body());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumes_each_segment() {
+        let faults = Faults {
+            drop_at: vec![1000, 7000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let response = client().get(server.url()).send().await.unwrap();
+        let mut segmented = response.segmented(4);
+        let mut received = vec![0; body().len()];
+        let mut len = 0;
+        while let Some(chunk) = segmented.next().await {
+            let chunk = chunk.unwrap();
+            received[chunk.offset as usize..][..chunk.bytes.len()].copy_from_slice(&chunk.bytes);
+            len += chunk.bytes.len();
+        }
+        assert_eq!(len, body().len());
+        assert_eq!(received, &body()[..]);
+        let mut ranges = server.ranges();
+        ranges.sort();
+        assert_eq!(
+            ranges,
+            [
+                None,
+                Some("bytes=1000-2499".to_owned()),
+                Some("bytes=2500-4999".to_owned()),
+                Some("bytes=5000-7499".to_owned()),
+                Some("bytes=7000-7499".to_owned()),
+                Some("bytes=7500-9999".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_segments_in_place() {
+        let faults = Faults {
+            drop_at: vec![1000, 7000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let path = std::env::temp_dir().join(format!("segmented-{}", std::process::id()));
+        let response = client().get(server.url()).send().await.unwrap();
+        let download = response.segmented(4).download_to_file(&path).await.unwrap();
+        assert_eq!(download.bytes_written, 10_000);
+        assert_eq!(download.summary.bytes, 10_000);
+        assert_eq!(std::fs::read(&path).unwrap(), &body()[..]);
+        assert!(!super::part_path(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+
+        // what was allocated is cut off again, as nothing can resume from it
+        let faults = Faults {
+            fail_from: Some(1),
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let once = Client::new().retry_policy(RetryPolicy::new().max_attempts(1));
+        let response = once.get(server.url()).send().await.unwrap();
+        assert!(response.segmented(4).download_to_file(&path).await.is_err());
+        let part = super::part_path(&path);
+        assert_eq!(std::fs::metadata(&part).unwrap().len(), 0);
+        std::fs::remove_file(&part).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_downloads_from_the_sidecar() {
+        let faults = Faults {
+            drop_at: vec![4000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let path = std::env::temp_dir().join(format!("sidecar-{}", std::process::id()));
+        let once = Client::new().retry_policy(RetryPolicy::new().max_attempts(1));
+        let err = once
+            .get(server.url())
+            .download_to_file(&path)
+            .await
+            .unwrap_err();
+        assert_eq!(err.position(), Some(4000));
+        let sidecar = Sidecar::read(&path).await.unwrap();
+        assert_eq!(sidecar.pos, 4000);
+        assert_eq!(sidecar.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(sidecar.total, Some(10_000));
+
+        let download = client()
+            .get(server.url())
+            .download_to_file(&path)
+            .await
+            .unwrap();
+        assert_eq!(download.resumed_from, 4000);
+        assert_eq!(download.bytes_written, 6000);
+        assert_eq!(std::fs::read(&path).unwrap(), &body()[..]);
+        assert!(Sidecar::read(&path).await.is_err());
+        assert_eq!(server.ranges(), [None, Some("bytes=4000-".to_owned())]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restarts_when_the_sidecar_is_stale() {
+        let server = TestServer::start(body(), Faults::default()).await;
+        let path = std::env::temp_dir().join(format!("stale-{}", std::process::id()));
+        // an earlier version of the file, which the server no longer has
+        std::fs::write(super::part_path(&path), [0xff; 4000]).unwrap();
+        let sidecar = Sidecar {
+            url: server.url().to_string(),
+            pos: 4000,
+            etag: Some("\"v0\"".to_owned()),
+            last_modified: None,
+            total: Some(10_000),
+        };
+        std::fs::write(
+            super::sidecar_path(&path),
+            serde_json::to_vec(&sidecar).unwrap(),
+        )
+        .unwrap();
+
+        let download = client()
+            .get(server.url())
+            .download_to_file(&path)
+            .await
+            .unwrap();
+        assert_eq!(download.resumed_from, 0);
+        assert_eq!(download.bytes_written, 10_000);
+        assert_eq!(std::fs::read(&path).unwrap(), &body()[..]);
+        assert!(!super::part_path(&path).exists());
+        assert!(!super::sidecar_path(&path).exists());
+        // sent with the stale validator, which the server turned down
+        assert_eq!(server.ranges(), [Some("bytes=4000-".to_owned())]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fails_over_to_mirrors_mid_body() {
+        let faults = Faults {
+            drop_at: vec![4000],
+            fail_from: Some(1),
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let mirror = TestServer::start(body(), Faults::default()).await;
+        let response = client()
+            .get(server.url())
+            .mirrors([mirror.url()])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(collect(response).await.unwrap(), &body()[..]);
+        let ranges = server.ranges();
+        assert_eq!(ranges[0], None);
+        assert!(ranges[1..]
+            .iter()
+            .all(|range| range.as_deref() == Some("bytes=4000-")));
+        // the same file, so it continues where the first server left off
+        assert_eq!(mirror.ranges(), [Some("bytes=4000-".to_owned())]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnects_stalled_bodies() {
+        let faults = Faults {
+            stall_at: vec![3000],
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let response = client()
+            .get(server.url())
+            .stall_timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .unwrap();
+        let started = Instant::now();
+        assert_eq!(collect(response).await.unwrap(), &body()[..]);
+        assert!(started.elapsed() >= Duration::from_secs(10));
+        assert_eq!(server.ranges(), [None, Some("bytes=3000-".to_owned())]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pausing_keeps_the_connection() {
+        let server = TestServer::start(body(), Faults::default()).await;
+        let response = client()
+            .get(server.url())
+            .chunk_size(1024)
+            .stall_timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .unwrap();
+        let transfer = response.transfer_handle();
+        let mut stream = response.bytes_stream();
+        let mut received = stream.next().await.unwrap().unwrap().to_vec();
+        transfer.pause();
+        // longer than the stall timeout, which doesn't run while paused
+        let paused = tokio::time::timeout(Duration::from_secs(60), stream.next()).await;
+        assert!(paused.is_err());
+        transfer.resume();
+        while let Some(chunk) = stream.next().await {
+            received.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(received, &body()[..]);
+        assert_eq!(server.ranges(), [None]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pausing_and_disconnecting_resumes_later() {
+        let server = TestServer::start(body(), Faults::default()).await;
+        let response = client()
+            .get(server.url())
+            .chunk_size(1024)
+            .send()
+            .await
+            .unwrap();
+        let transfer = response.transfer_handle();
+        let mut stream = response.bytes_stream();
+        let mut received = stream.next().await.unwrap().unwrap().to_vec();
+        let pos = stream.position();
+        transfer.pause_and_disconnect();
+        let paused = tokio::time::timeout(Duration::from_secs(60), stream.next()).await;
+        assert!(paused.is_err());
+        assert!(transfer.is_paused());
+        transfer.resume();
+        while let Some(chunk) = stream.next().await {
+            received.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(received, &body()[..]);
+        assert_eq!(server.ranges(), [None, Some(format!("bytes={pos}-"))]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn range_reader_fails_on_short_blocks() {
+        use tokio::io::AsyncReadExt;
+
+        let faults = Faults {
+            range_len: Some(500),
+            ..Faults::default()
+        };
+        let server = TestServer::start(body(), faults).await;
+        let mut reader = client().get(server.url()).range_reader().await.unwrap();
+        assert_eq!(reader.len(), 10_000);
+        let mut buf = Vec::new();
+        let err = reader.read_to_end(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        // the first block again, rather than over and over
+        assert_eq!(server.ranges().len(), 2);
+    }
+
+    #[test]
+    fn linear_backoff_is_capped() {
+        let backoff = Backoff::Linear {
+            initial: Duration::from_millis(100),
+            increment: Duration::from_millis(50),
+            max: Duration::from_millis(200),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(150));
+        assert_eq!(backoff.delay(10), Duration::from_millis(200));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn exponential_backoff_doubles_up_to_max() {
+        let backoff = Backoff::exponential(Duration::from_secs(1), Duration::from_secs(10));
+        assert_eq!(backoff.delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.delay(2), Duration::from_secs(2));
+        assert_eq!(backoff.delay(4), Duration::from_secs(8));
+        assert_eq!(backoff.delay(5), Duration::from_secs(10));
+        assert_eq!(backoff.delay(100), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn jitter_only_shortens_delay() {
+        let backoff =
+            Backoff::exponential(Duration::from_secs(1), Duration::from_secs(10)).with_jitter(0.5);
+        for retry in 1..8 {
+            let delay = backoff.delay(retry);
+            assert!(delay <= backoff.max_delay(retry));
+            assert!(delay >= backoff.max_delay(retry) / 2);
+        }
+    }
+
+    #[test]
+    fn max_elapsed_bounds_failure_streak() {
+        let policy = RetryPolicy::new().max_elapsed(Duration::from_secs(60));
+        assert!(policy.may_retry(100, None));
+        assert!(policy.may_retry(100, Some(Instant::now())));
+        let since = Instant::now() - Duration::from_secs(61);
+        assert!(!policy.may_retry(1, Some(since)));
+        assert!(RetryPolicy::new().may_retry(u32::MAX, Some(since)));
+    }
+
+    #[test]
+    fn max_attempts_counts_first_attempt() {
+        let policy = RetryPolicy::new().max_attempts(3);
+        assert_eq!(policy.max_attempts, Some(3));
+        assert_eq!(RetryPolicy::none().max_attempts, Some(1));
+        assert_eq!(RetryPolicy::new().max_attempts, None);
+        assert!(policy.may_retry(2, None));
+        assert!(!policy.may_retry(3, None));
+    }
+
+    #[test]
+    fn retry_after_seconds_and_dates() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let later = chrono::Utc::now() + chrono::Duration::seconds(90);
+        let delay = parse_retry_after(&later.to_rfc2822()).unwrap();
+        assert!(delay > Duration::from_secs(80) && delay <= Duration::from_secs(90));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn writes_all_chunks_vectored() {
+        /// Accepts at most 7 bytes per write.
+        struct Slow(Vec<u8>);
+
+        impl std::io::Write for Slow {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(7);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let chunks = ["abc", "", "defghij", "klmnopqrstu", "v"]
+            .map(|chunk| Bytes::from_static(chunk.as_bytes()))
+            .to_vec();
+        let mut writer = Slow(Vec::new());
+        write_all_vectored(&mut writer, chunks).unwrap();
+        assert_eq!(writer.0, b"abcdefghijklmnopqrstuv");
+    }
+
+    #[test]
+    fn classifies_failures() {
+        use std::io::{Error as IoError, ErrorKind};
+
+        #[derive(Debug)]
+        struct Wrapped(&'static str, IoError);
+
+        impl std::fmt::Display for Wrapped {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.0)
+            }
+        }
+
+        impl std::error::Error for Wrapped {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.1)
+            }
+        }
+
+        let classify = |message, kind| classify(&Wrapped(message, IoError::new(kind, "io")));
+        assert_eq!(
+            classify("error sending request", ErrorKind::ConnectionRefused),
+            Some(FailureKind::ConnectionRefused)
+        );
+        assert_eq!(
+            classify("error decoding response body", ErrorKind::ConnectionReset),
+            Some(FailureKind::Reset)
+        );
+        assert_eq!(
+            classify("dns error: failed to lookup address", ErrorKind::Other),
+            Some(FailureKind::Dns)
+        );
+        assert_eq!(
+            classify("invalid peer certificate: UnknownIssuer", ErrorKind::Other),
+            Some(FailureKind::Tls)
+        );
+        assert_eq!(classify("something else", ErrorKind::Other), None);
+    }
+
+    #[test]
+    fn retries_per_failure_kind() {
+        let policy = RetryPolicy::adaptive();
+        assert!(policy.may_retry_kind(FailureKind::Reset, 4, None));
+        assert!(!policy.may_retry_kind(FailureKind::Reset, 5, None));
+        assert!(policy.may_retry_kind(FailureKind::Other, 5, None));
+        assert_eq!(
+            policy.delay_kind(FailureKind::Reset, 3),
+            Duration::from_millis(200)
+        );
+        assert!(policy.delay_kind(FailureKind::Dns, 1) >= Duration::from_millis(2500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejects_plaintext_urls() {
+        let server = TestServer::start(body(), Faults::default()).await;
+        let client = Client::builder()
+            .plaintext(PlaintextPolicy::Reject)
+            .build()
+            .unwrap();
+        let err = client.get(server.url()).send().await.unwrap_err();
+        assert!(matches!(err, Error::Plaintext { target, .. } if target == server.url()));
+        assert!(server.ranges().is_empty());
+
+        let mut url = server.url();
+        upgrade(&mut url);
+        assert_eq!(url.scheme(), "https");
+        assert_eq!(url.port(), server.url().port());
+    }
+
+    #[test]
+    fn block_cache_splits_and_evicts() {
+        let mut cache = BlockCache::new(2);
+        cache.insert(100, Bytes::from(b"abcdefgh".to_vec()), 4);
+        assert_eq!(cache.get(102).as_deref(), Some(&b"cd"[..]));
+        assert_eq!(cache.get(104).as_deref(), Some(&b"efgh"[..]));
+        assert_eq!(cache.get(108), None);
+        assert_eq!(cache.get(99), None);
+
+        // 100..104 is now the least recently used block
+        cache.insert(0, Bytes::from(b"xy".to_vec()), 4);
+        assert_eq!(cache.get(100), None);
+        assert_eq!(cache.get(1).as_deref(), Some(&b"y"[..]));
+        assert_eq!(cache.get(107).as_deref(), Some(&b"h"[..]));
+    }
+}
@@ -0,0 +1,256 @@
+//! Blocking wrappers around the resumable [`Client`](super::Client), like
+//! `reqwest::blocking`, for callers that aren't async.
+//!
+//! Requests run on a runtime owned by the client. As with `reqwest::blocking`,
+//! none of this may be used from within an async runtime: blocking on it
+//! panics.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::{Buf, Bytes};
+use futures::StreamExt;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method, StatusCode, Url,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::runtime::Runtime;
+
+use super::{Checksum, Decoder, Download, Error, Event, Result, RetryPolicy};
+
+/// A resumable client whose requests block the calling thread.
+#[derive(Clone)]
+pub struct Client {
+    client: super::Client,
+    runtime: Arc<Runtime>,
+}
+
+impl Client {
+    /// Panics if the runtime can't be started, like `reqwest::blocking`.
+    pub fn new() -> Self {
+        Self::from_async(super::Client::new())
+    }
+
+    /// Block on requests from `client`, which keeps its configuration.
+    pub fn from_async(client: super::Client) -> Self {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("reqwest-resume-blocking")
+            .enable_all()
+            .build()
+            .expect("failed to start the blocking client's runtime");
+        Client {
+            client,
+            runtime: Arc::new(runtime),
+        }
+    }
+
+    pub fn get(&self, url: Url) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    pub fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        RequestBuilder {
+            builder: self.client.request(method, url),
+            runtime: self.runtime.clone(),
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("client", &self.client)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Blocking counterpart of [`super::RequestBuilder`]; see there for details.
+pub struct RequestBuilder {
+    builder: super::RequestBuilder,
+    runtime: Arc<Runtime>,
+}
+
+impl RequestBuilder {
+    pub fn header(mut self, key: HeaderName, value: HeaderValue) -> Self {
+        self.builder = self.builder.header(key, value);
+        self
+    }
+
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.builder = self.builder.headers(headers);
+        self
+    }
+
+    pub fn bearer_auth<T: fmt::Display>(mut self, token: T) -> Self {
+        self.builder = self.builder.bearer_auth(token);
+        self
+    }
+
+    pub fn basic_auth<U: fmt::Display, P: fmt::Display>(
+        mut self,
+        username: U,
+        password: Option<P>,
+    ) -> Self {
+        self.builder = self.builder.basic_auth(username, password);
+        self
+    }
+
+    pub fn query<T: Serialize + ?Sized>(mut self, query: &T) -> Self {
+        self.builder = self.builder.query(query);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.builder = self.builder.timeout(timeout);
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.builder = self.builder.deadline(deadline);
+        self
+    }
+
+    pub fn limit_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.builder = self.builder.limit_rate(bytes_per_sec);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.builder = self.builder.retry_policy(retry);
+        self
+    }
+
+    pub fn range(mut self, start: u64, end: Option<u64>) -> Self {
+        self.builder = self.builder.range(start, end);
+        self
+    }
+
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.builder = self.builder.checksum(checksum);
+        self
+    }
+
+    pub fn on_event<F>(mut self, on_event: F) -> Self
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.builder = self.builder.on_event(on_event);
+        self
+    }
+
+    pub fn send(&self) -> Result<Response> {
+        let response = self.runtime.block_on(self.builder.send())?;
+        Ok(Response {
+            response,
+            runtime: self.runtime.clone(),
+        })
+    }
+
+    /// See [`super::RequestBuilder::download_to_file`].
+    pub fn download_to_file(&self, path: impl AsRef<Path>) -> Result<Download> {
+        self.runtime.block_on(self.builder.download_to_file(path))
+    }
+}
+
+/// Blocking counterpart of [`super::Response`].
+pub struct Response {
+    response: super::Response,
+    runtime: Arc<Runtime>,
+}
+
+impl Response {
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        self.response.headers()
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.response.content_length()
+    }
+
+    pub fn url(&self) -> &Url {
+        self.response.url()
+    }
+
+    pub fn bytes(self) -> Result<Bytes> {
+        self.runtime.block_on(self.response.bytes())
+    }
+
+    pub fn text(self) -> Result<String> {
+        self.runtime.block_on(self.response.text())
+    }
+
+    pub fn json<T: DeserializeOwned>(self) -> Result<T> {
+        self.runtime.block_on(self.response.json())
+    }
+
+    /// Copy the body into `writer`, resuming as needed, and return the number
+    /// of bytes written.
+    pub fn copy_to<W: Write + ?Sized>(self, writer: &mut W) -> Result<u64> {
+        let url = self.response.request.url.clone();
+        let mut body = self.response.bytes_stream();
+        let mut written = 0;
+        while let Some(chunk) = self.runtime.block_on(body.next()) {
+            let chunk = chunk?;
+            writer
+                .write_all(&chunk)
+                .map_err(|err| Error::io(&url, body.position(), err))?;
+            written += chunk.len() as u64;
+        }
+        Ok(written)
+    }
+
+    /// See [`super::Response::download_to_file`].
+    pub fn download_to_file(self, path: impl AsRef<Path>) -> Result<Download> {
+        self.runtime.block_on(self.response.download_to_file(path))
+    }
+
+    /// Read the resumable body through `std::io::Read`.
+    pub fn into_reader(self) -> Reader {
+        Reader {
+            decoder: self.response.bytes_stream(),
+            chunk: Bytes::new(),
+            runtime: self.runtime,
+        }
+    }
+}
+
+/// `std::io::Read` over a resumable body, returned by
+/// [`Response::into_reader`]. Errors convert as described for
+/// `From<Error> for std::io::Error`.
+pub struct Reader {
+    decoder: Decoder,
+    chunk: Bytes,
+    runtime: Arc<Runtime>,
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.decoder.next()) {
+                Some(chunk) => self.chunk = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk[..len]);
+        self.chunk.advance(len);
+        Ok(len)
+    }
+}
@@ -0,0 +1,205 @@
+//! On-disk cache of response bodies keyed by URL, revalidated with
+//! `If-None-Match`/`If-Modified-Since` so an unchanged file costs a `304`.
+//!
+//! ```ignore
+//! let cache = Cache::new(app_cache_dir.join("http"));
+//! let manifest: Manifest = cache.json(client.get(url)).await?;
+//! ```
+//!
+//! Misses and changed files are downloaded with
+//! [`RequestBuilder::download_to_file`], so an interrupted download resumes
+//! on the next fetch. Every fetch revalidates: `Cache-Control` and `Expires`
+//! are ignored.
+
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use reqwest::{
+    header::{HeaderMap, HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode, Url,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use super::{status_error, Error, RequestBuilder, Result};
+
+/// Directory of cached bodies, each next to a `.json` file with its URL and
+/// validators.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+/// A body in the cache, from [`Cache::fetch`].
+#[derive(Clone, Debug)]
+pub struct Cached {
+    pub path: PathBuf,
+    /// Whether the server answered `304 Not Modified` and nothing was
+    /// downloaded.
+    pub revalidated: bool,
+}
+
+/// What's known about a cached body.
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Cache { dir: dir.into() }
+    }
+
+    /// The cached body of `request`, revalidated or downloaded first.
+    pub async fn fetch(&self, mut request: RequestBuilder) -> Result<Cached> {
+        let url = request.request.url.clone();
+        let path = self.path(&url);
+        fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|err| Error::io(&url, 0, err))?;
+        let entry = Entry::read(&path).await.ok().filter(|entry| {
+            entry.url == url.as_str() && (entry.etag.is_some() || entry.last_modified.is_some())
+        });
+        let cached = fs::try_exists(&path).await.unwrap_or(false);
+        let download = match entry {
+            Some(entry) if cached => {
+                for (name, value) in [
+                    (IF_NONE_MATCH, &entry.etag),
+                    (IF_MODIFIED_SINCE, &entry.last_modified),
+                ] {
+                    if let Some(value) = value.as_deref().and_then(|v| v.parse().ok()) {
+                        request = request.header(name, value);
+                    }
+                }
+                let response = request.send().await?;
+                if response.status() == StatusCode::NOT_MODIFIED {
+                    log::debug!("{} not modified, using {}", url, path.display());
+                    return Ok(Cached {
+                        path,
+                        revalidated: true,
+                    });
+                }
+                if !response.status().is_success() {
+                    return Err(Error::network(&url, 0, status_error(response.response)));
+                }
+                response.download_to_file(&path).await?
+            }
+            _ => request.download_to_file(&path).await?,
+        };
+        Entry::from_headers(&url, &download.headers)
+            .save(&path)
+            .await
+            .map_err(|err| Error::io(&url, 0, err))?;
+        Ok(Cached {
+            path,
+            revalidated: false,
+        })
+    }
+
+    /// The cached body of `request`, read into memory.
+    pub async fn bytes(&self, request: RequestBuilder) -> Result<Bytes> {
+        let url = request.request.url.clone();
+        let cached = self.fetch(request).await?;
+        let bytes = fs::read(&cached.path)
+            .await
+            .map_err(|err| Error::io(&url, 0, err))?;
+        Ok(bytes.into())
+    }
+
+    /// The cached body of `request`, deserialized from JSON.
+    pub async fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let url = request.request.url.clone();
+        let bytes = self.bytes(request).await?;
+        serde_json::from_slice(&bytes).map_err(|err| Error::io(&url, 0, err.into()))
+    }
+
+    /// Forget the cached body of `url`, if there is one.
+    pub async fn remove(&self, url: &Url) -> std::io::Result<()> {
+        let path = self.path(url);
+        for path in [entry_path(&path), path] {
+            match fs::remove_file(&path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the body of `url` is kept, named after the SHA-256 of the URL.
+    fn path(&self, url: &Url) -> PathBuf {
+        let hash = Sha256::digest(url.as_str().as_bytes());
+        let name = hash
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        self.dir.join(name)
+    }
+}
+
+impl Entry {
+    fn from_headers(url: &Url, headers: &HeaderMap) -> Self {
+        let header = |name: HeaderName| Some(headers.get(name)?.to_str().ok()?.to_owned());
+        Entry {
+            url: url.to_string(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    async fn read(path: &Path) -> std::io::Result<Entry> {
+        let json = fs::read(entry_path(path)).await?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    async fn save(&self, path: &Path) -> std::io::Result<()> {
+        let entry = entry_path(path);
+        let mut tmp = entry.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp, &entry).await
+    }
+}
+
+fn entry_path(path: &Path) -> PathBuf {
+    path.with_extension("json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        test_server::{Faults, TestServer},
+        Client,
+    };
+    use super::Cache;
+    use bytes::Bytes;
+
+    #[tokio::test(start_paused = true)]
+    async fn revalidates_cached_bodies() {
+        let body = Bytes::from_static(b"{\"models\": []}");
+        let faults = Faults {
+            change_etag_at: Some(2),
+            ..Faults::default()
+        };
+        let server = TestServer::start(body.clone(), faults).await;
+        let dir = std::env::temp_dir().join(format!("reqwest-resume-cache-{}", std::process::id()));
+        let cache = Cache::new(&dir);
+        let client = Client::new();
+
+        let first = cache.fetch(client.get(server.url())).await.unwrap();
+        assert!(!first.revalidated);
+        assert_eq!(cache.bytes(client.get(server.url())).await.unwrap(), body);
+        // the ETag changed, so the body is downloaded again
+        let third = cache.fetch(client.get(server.url())).await.unwrap();
+        assert!(!third.revalidated);
+        let fourth = cache.fetch(client.get(server.url())).await.unwrap();
+        assert!(fourth.revalidated);
+        assert_eq!(server.ranges(), [None, None, None, None]);
+
+        cache.remove(&server.url()).await.unwrap();
+        assert!(!first.path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,447 @@
+//! Content-addressed store of the chunks of downloaded files, so downloading
+//! one that only changed a little, or the same one from another URL, only
+//! fetches the chunks that aren't on disk already.
+//!
+//! Files are cut where a rolling hash of the last bytes hits a pattern, as in
+//! FastCDC, so a change only affects the chunks around it instead of shifting
+//! every boundary after it. Chunks are kept under their BLAKE3 digest. Which
+//! chunks a file on the server is made of comes from its [`ChunkIndex`],
+//! published next to it:
+//!
+//! ```ignore
+//! let store = ChunkStore::new(app_cache_dir.join("chunks"));
+//! let index: ChunkIndex = client.get(index_url).send().await?.json().await?;
+//! store.download(client.get(url), &index, &path).await?;
+//! ```
+//!
+//! Nothing is evicted; [`ChunkStore::clear`] deletes every chunk.
+
+use std::path::{Path, PathBuf};
+
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use super::{part_path, Download, Error, RequestBuilder, Result, TransferSummary};
+
+/// Chunks are at least this long, but for the last one of a file.
+const MIN_CHUNK: usize = 16 * 1024;
+/// Chunks are cut where the top 16 bits of the rolling hash are 0, so they're
+/// 64 KiB long on average.
+const MASK: u64 = 0xffff << 48;
+const MAX_CHUNK: usize = 256 * 1024;
+
+/// Random values the rolling hash adds for each byte.
+const GEAR: [u64; 256] = gear();
+
+/// Missing chunks fetched with a single range request, at most.
+const MAX_RANGE: u64 = 16 * 1024 * 1024;
+
+/// A chunk of a file.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Chunk {
+    pub offset: u64,
+    pub len: u64,
+    /// BLAKE3, hex-encoded.
+    pub digest: String,
+}
+
+/// The chunks a file is made of, in order.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChunkIndex {
+    pub size: u64,
+    pub chunks: Vec<Chunk>,
+}
+
+/// Directory of chunks, each in a file named after its digest.
+#[derive(Clone, Debug)]
+pub struct ChunkStore {
+    dir: PathBuf,
+}
+
+impl ChunkIndex {
+    /// The chunks of the file at `path`, e.g. to publish next to it.
+    pub async fn of_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut splitter = Splitter::open(path.as_ref()).await?;
+        let mut chunks = Vec::new();
+        while let Some((chunk, _)) = splitter.next().await? {
+            chunks.push(chunk);
+        }
+        Ok(ChunkIndex {
+            size: splitter.offset,
+            chunks,
+        })
+    }
+
+    /// Whether the chunks follow each other from the start of the file to
+    /// its end, none longer than they're cut, each with a digest it can be
+    /// stored under.
+    fn is_valid(&self) -> bool {
+        let mut offset = 0;
+        for chunk in &self.chunks {
+            if chunk.offset != offset
+                || chunk.len == 0
+                || chunk.len > MAX_CHUNK as u64
+                || !is_digest(&chunk.digest)
+            {
+                return false;
+            }
+            offset += chunk.len;
+        }
+        offset == self.size
+    }
+}
+
+impl ChunkStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ChunkStore { dir: dir.into() }
+    }
+
+    /// Store the chunks of the file at `path` that aren't already, returning
+    /// its index.
+    pub async fn insert_file(&self, path: impl AsRef<Path>) -> std::io::Result<ChunkIndex> {
+        let mut splitter = Splitter::open(path.as_ref()).await?;
+        let mut chunks = Vec::new();
+        while let Some((chunk, data)) = splitter.next().await? {
+            self.insert(&chunk.digest, &data).await?;
+            chunks.push(chunk);
+        }
+        Ok(ChunkIndex {
+            size: splitter.offset,
+            chunks,
+        })
+    }
+
+    /// Download the file `index` describes from `request` to `path`, copying
+    /// the chunks in the store and fetching the others with range requests,
+    /// which are stored as well. Every chunk is checked against its digest,
+    /// but [`RequestBuilder::checksum`] is ignored: the whole file is only
+    /// ever hashed by the caller.
+    ///
+    /// `bytes_written` counts every byte of the file, and the summary's
+    /// `bytes` those that were fetched.
+    pub async fn download(
+        &self,
+        request: RequestBuilder,
+        index: &ChunkIndex,
+        path: impl AsRef<Path>,
+    ) -> Result<Download> {
+        let path = path.as_ref();
+        let url = request.request.url.clone();
+        let io_err = |pos| {
+            let url = &url;
+            move |err| Error::io(url, pos, err)
+        };
+        if !index.is_valid() {
+            let err = std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid chunk index");
+            return Err(Error::io(&url, 0, err));
+        }
+        let part = part_path(path);
+        // closed before renaming, which Windows doesn't allow on open files
+        let fetched = {
+            let mut file = fs::File::create(&part).await.map_err(io_err(0))?;
+            let mut fetched = 0;
+            let mut chunks = index.chunks.iter().peekable();
+            while let Some(chunk) = chunks.next() {
+                if let Some(data) = self.get(chunk).await {
+                    file.write_all(&data).await.map_err(io_err(chunk.offset))?;
+                    continue;
+                }
+                // along with the missing chunks right after it
+                let start = chunk.offset;
+                let mut missing = vec![chunk];
+                let mut end = start + chunk.len;
+                while let Some(next) = chunks.peek() {
+                    if end + next.len - start > MAX_RANGE || self.contains(next).await {
+                        break;
+                    }
+                    end += next.len;
+                    missing.push(chunks.next().unwrap());
+                }
+                let mut range = RequestBuilder {
+                    request: request.request.clone(),
+                }
+                .range(start, Some(end));
+                range.request.checksum = None;
+                #[cfg(feature = "digest")]
+                {
+                    range.request.digest = None;
+                }
+                let body = range.send().await?.bytes().await?;
+                if body.len() as u64 != end - start {
+                    return Err(Error::RangeNotHonored {
+                        url,
+                        pos: start,
+                        content_range: None,
+                    });
+                }
+                for chunk in missing {
+                    let from = (chunk.offset - start) as usize;
+                    let data = &body[from..from + chunk.len as usize];
+                    let actual = blake3::hash(data).to_hex().to_string();
+                    if !actual.eq_ignore_ascii_case(&chunk.digest) {
+                        return Err(Error::ChecksumMismatch {
+                            url,
+                            pos: chunk.offset,
+                            algorithm: "BLAKE3",
+                            expected: chunk.digest.clone(),
+                            actual,
+                        });
+                    }
+                    self.insert(&chunk.digest, data)
+                        .await
+                        .map_err(io_err(chunk.offset))?;
+                }
+                file.write_all(&body).await.map_err(io_err(start))?;
+                fetched += body.len() as u64;
+            }
+            file.sync_all().await.map_err(io_err(index.size))?;
+            fetched
+        };
+        fs::rename(&part, path).await.map_err(io_err(index.size))?;
+        log::info!(
+            "assembled {} from {} stored and {} fetched bytes",
+            path.display(),
+            index.size - fetched,
+            fetched
+        );
+        Ok(Download {
+            bytes_written: index.size,
+            resumed_from: 0,
+            headers: HeaderMap::new(),
+            summary: TransferSummary {
+                bytes: fetched,
+                ..TransferSummary::default()
+            },
+        })
+    }
+
+    /// Delete every chunk.
+    pub async fn clear(&self) -> std::io::Result<()> {
+        match fs::remove_dir_all(&self.dir).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    async fn contains(&self, chunk: &Chunk) -> bool {
+        fs::try_exists(self.path(&chunk.digest))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// The bytes of `chunk`, unless they're missing or corrupt.
+    async fn get(&self, chunk: &Chunk) -> Option<Vec<u8>> {
+        let data = fs::read(self.path(&chunk.digest)).await.ok()?;
+        let actual = blake3::hash(&data).to_hex().to_string();
+        if !actual.eq_ignore_ascii_case(&chunk.digest) {
+            log::warn!("stored chunk {} is corrupt", chunk.digest);
+            return None;
+        }
+        Some(data)
+    }
+
+    async fn insert(&self, digest: &str, data: &[u8]) -> std::io::Result<()> {
+        let path = self.path(digest);
+        if fs::try_exists(&path).await? {
+            return Ok(());
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, data).await?;
+        fs::rename(&tmp, &path).await
+    }
+
+    /// Where the chunk with `digest` is kept, in a directory named after its
+    /// first two characters so none gets too big.
+    fn path(&self, digest: &str) -> PathBuf {
+        let digest = digest.to_ascii_lowercase();
+        let prefix = digest.get(..2).unwrap_or_default();
+        self.dir.join(prefix).join(&digest)
+    }
+}
+
+/// Whether `digest` is a hex-encoded BLAKE3 digest, rather than e.g. a path.
+fn is_digest(digest: &str) -> bool {
+    digest.len() == 64 && digest.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+/// Cuts a file into chunks as it's read.
+struct Splitter {
+    file: fs::File,
+    buf: Vec<u8>,
+    eof: bool,
+    // of the start of `buf` in the file
+    offset: u64,
+}
+
+impl Splitter {
+    async fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(Splitter {
+            file: fs::File::open(path).await?,
+            buf: Vec::with_capacity(MAX_CHUNK),
+            eof: false,
+            offset: 0,
+        })
+    }
+
+    /// The next chunk and its bytes, or `None` at the end of the file.
+    async fn next(&mut self) -> std::io::Result<Option<(Chunk, Vec<u8>)>> {
+        while !self.eof && self.buf.len() < MAX_CHUNK {
+            let len = self.buf.len();
+            self.buf.resize(MAX_CHUNK, 0);
+            let n = self.file.read(&mut self.buf[len..]).await?;
+            self.buf.truncate(len + n);
+            self.eof = n == 0;
+        }
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let data: Vec<u8> = self.buf.drain(..cut(&self.buf)).collect();
+        let chunk = Chunk {
+            offset: self.offset,
+            len: data.len() as u64,
+            digest: blake3::hash(&data).to_hex().to_string(),
+        };
+        self.offset += chunk.len;
+        Ok(Some((chunk, data)))
+    }
+}
+
+/// Length of the chunk at the start of `data`, which holds `MAX_CHUNK` bytes
+/// unless it's the rest of the file.
+fn cut(data: &[u8]) -> usize {
+    let end = data.len().min(MAX_CHUNK);
+    let mut hash = 0u64;
+    for (i, &byte) in data[..end].iter().enumerate().skip(MIN_CHUNK) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash & MASK == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// The table for [`GEAR`], from SplitMix64 so every build cuts files in the
+/// same places.
+const fn gear() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        test_server::{Faults, TestServer},
+        Client,
+    };
+    use super::{Chunk, ChunkIndex, ChunkStore, MAX_CHUNK};
+    use bytes::Bytes;
+
+    /// Bytes that don't repeat, so they're cut into chunks of varied sizes.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fetches_only_missing_chunks() {
+        let dir =
+            std::env::temp_dir().join(format!("reqwest-resume-chunks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = ChunkStore::new(dir.join("chunks"));
+        let old = noise(1024 * 1024, 1);
+        // the same file with a few bytes inserted in the middle
+        let mut new = old[..500_000].to_vec();
+        new.extend_from_slice(b"changed");
+        new.extend_from_slice(&old[500_000..]);
+
+        let old_path = dir.join("old.bin");
+        std::fs::write(&old_path, &old).unwrap();
+        store.insert_file(&old_path).await.unwrap();
+        let new_path = dir.join("new.bin");
+        std::fs::write(&new_path, &new).unwrap();
+        let index = ChunkIndex::of_file(&new_path).await.unwrap();
+        std::fs::remove_file(&new_path).unwrap();
+
+        let server = TestServer::start(Bytes::from(new.clone()), Faults::default()).await;
+        let client = Client::new();
+        let download = store
+            .download(client.get(server.url()), &index, &new_path)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&new_path).unwrap(), new);
+        assert_eq!(download.bytes_written, new.len() as u64);
+        // only the chunks around the change
+        assert!(download.summary.bytes > 0);
+        assert!(download.summary.bytes < new.len() as u64 / 2);
+        assert!(server.ranges().iter().all(Option::is_some));
+
+        // everything's stored now
+        let again = dir.join("again.bin");
+        let download = store
+            .download(client.get(server.url()), &index, &again)
+            .await
+            .unwrap();
+        assert_eq!(download.summary.bytes, 0);
+        assert_eq!(std::fs::read(&again).unwrap(), new);
+
+        store.clear().await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejects_invalid_indexes() {
+        let dir = std::env::temp_dir().join(format!(
+            "reqwest-resume-invalid-chunks-{}",
+            std::process::id()
+        ));
+        let store = ChunkStore::new(dir.join("chunks"));
+        let server = TestServer::start(Bytes::from(noise(1024, 1)), Faults::default()).await;
+        let index = |len: u64, digest: &str| ChunkIndex {
+            size: len,
+            chunks: vec![Chunk {
+                offset: 0,
+                len,
+                digest: digest.to_owned(),
+            }],
+        };
+        let digest = "ab".repeat(32);
+        let too_long = MAX_CHUNK as u64 + 1;
+        for index in [
+            index(1024, "../../../etc/passwd"),
+            index(1024, &"ab".repeat(33)),
+            index(1024, &"zz".repeat(32)),
+            index(too_long, &digest),
+            index(0, &digest),
+        ] {
+            let path = dir.join("file.bin");
+            let download = store.download(Client::new().get(server.url()), &index, &path);
+            assert!(download.await.is_err());
+        }
+        // nothing was fetched
+        assert!(server.ranges().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,185 @@
+//! File names from `Content-Disposition` headers (RFC 6266), e.g.
+//! `attachment; filename="model.bin"; filename*=UTF-8''mod%C3%A8le.bin`.
+//!
+//! Names come from the server, so they're sanitized before use: anything that
+//! could escape the download directory or that Windows rejects is replaced.
+
+use reqwest::{header::HeaderValue, Url};
+
+/// The file name in a `Content-Disposition` value, preferring the extended
+/// `filename*` form (RFC 8187) over `filename`, sanitized.
+pub fn filename(value: &HeaderValue) -> Option<String> {
+    // the plain form may hold raw UTF-8 that `to_str` rejects
+    let value = String::from_utf8_lossy(value.as_bytes());
+    let params = params(&value);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+    param("filename*")
+        .and_then(decode_extended)
+        .and_then(|name| sanitize(&name))
+        .or_else(|| sanitize(param("filename")?))
+}
+
+/// The last non-empty segment of the path of `url`, decoded and sanitized.
+pub fn from_url(url: &Url) -> Option<String> {
+    let segment = url
+        .path_segments()?
+        .rev()
+        .find(|segment| !segment.is_empty())?;
+    sanitize(&String::from_utf8_lossy(&percent_decode(segment)))
+}
+
+/// A file name without path separators, control characters or characters
+/// Windows doesn't allow, or `None` if nothing usable is left.
+pub fn sanitize(name: &str) -> Option<String> {
+    let name = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect::<String>();
+    // no hidden files, `.` or `..`, and Windows drops trailing dots and spaces
+    let name = name.trim_start_matches(['.', ' ']);
+    let name = name.trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        return None;
+    }
+    let mut end = name.len().min(255);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    Some(name[..end].to_owned())
+}
+
+/// The parameters after the disposition type, with quoted strings unescaped.
+fn params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = value.chars().peekable();
+    // skip the disposition type
+    for c in chars.by_ref() {
+        if c == ';' {
+            break;
+        }
+    }
+    loop {
+        let name: String = chars.by_ref().take_while(|&c| c != '=').collect();
+        let name = name.trim();
+        if name.is_empty() {
+            return params;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+            }
+        } else {
+            value = chars.by_ref().take_while(|&c| c != ';').collect();
+            value.truncate(value.trim_end().len());
+        }
+        params.push((name.to_owned(), value));
+    }
+}
+
+/// Decode `charset'language'percent-encoded`, for UTF-8 and ISO-8859-1.
+fn decode_extended(value: &str) -> Option<String> {
+    let (charset, rest) = value.split_once('\'')?;
+    let (_language, encoded) = rest.split_once('\'')?;
+    let bytes = percent_decode(encoded);
+    if charset.eq_ignore_ascii_case("UTF-8") {
+        String::from_utf8(bytes).ok()
+    } else if charset.eq_ignore_ascii_case("ISO-8859-1") {
+        Some(bytes.into_iter().map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{filename, sanitize};
+    use reqwest::header::HeaderValue;
+
+    fn parse(value: &'static str) -> Option<String> {
+        filename(&HeaderValue::from_static(value))
+    }
+
+    #[test]
+    fn parses_filenames() {
+        assert_eq!(
+            parse("attachment; filename=model.bin").as_deref(),
+            Some("model.bin")
+        );
+        assert_eq!(
+            parse(r#"attachment; filename="a \"quoted\"; name.txt""#).as_deref(),
+            Some("a _quoted_; name.txt")
+        );
+        let both = "attachment; filename=fallback.bin; filename*=UTF-8''mod%C3%A8le.bin";
+        assert_eq!(parse(both).as_deref(), Some("modèle.bin"));
+        assert_eq!(
+            parse("attachment; FILENAME*=iso-8859-1'en'%A3%20rates.txt").as_deref(),
+            Some("£ rates.txt")
+        );
+        // an undecodable extended name falls back to the plain one
+        assert_eq!(
+            parse("attachment; filename*=UTF-8''%FF; filename=plain.txt").as_deref(),
+            Some("plain.txt")
+        );
+        assert_eq!(parse("inline"), None);
+        assert_eq!(parse("attachment; size=3"), None);
+    }
+
+    #[test]
+    fn sanitizes_names() {
+        assert_eq!(
+            sanitize("../../etc/passwd").as_deref(),
+            Some("_.._etc_passwd")
+        );
+        assert_eq!(
+            sanitize(r"C:\Windows\win.ini").as_deref(),
+            Some("C__Windows_win.ini")
+        );
+        assert_eq!(sanitize("report.pdf. ").as_deref(), Some("report.pdf"));
+        assert_eq!(sanitize("a\nb\u{0}c").as_deref(), Some("abc"));
+        assert_eq!(sanitize(".."), None);
+        assert_eq!(sanitize("  "), None);
+        assert_eq!(sanitize(&"é".repeat(200)).map(|name| name.len()), Some(254));
+    }
+}
@@ -0,0 +1,137 @@
+//! Parsing of `Content-Range` response headers, e.g. `bytes 100-199/200`.
+//!
+//! Values that don't make sense, such as a range ending before it starts or
+//! past the complete length, are rejected like unparseable ones, since resuming
+//! from them would corrupt the body.
+
+use reqwest::header::HeaderValue;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentRange {
+    /// `bytes <first>-<last>/<total>`, where `total` may be `*` for unknown.
+    Bytes {
+        first: u64,
+        last: u64,
+        total: Option<u64>,
+    },
+    /// `bytes */<total>`, sent with `416 Range Not Satisfiable`.
+    Unsatisfied { total: u64 },
+}
+
+impl ContentRange {
+    pub fn parse(value: &HeaderValue) -> Option<Self> {
+        let range = value.to_str().ok()?.trim().strip_prefix("bytes ")?;
+        let (range, total) = range.split_once('/')?;
+        let total = match total.trim() {
+            "*" => None,
+            total => Some(parse_u64(total)?),
+        };
+        if range.trim() == "*" {
+            return Some(ContentRange::Unsatisfied { total: total? });
+        }
+        let (first, last) = range.split_once('-')?;
+        let (first, last) = (parse_u64(first)?, parse_u64(last)?);
+        if last < first || matches!(total, Some(total) if last >= total) {
+            return None;
+        }
+        Some(ContentRange::Bytes { first, last, total })
+    }
+
+    /// Position of the first byte in the response.
+    pub fn first(&self) -> Option<u64> {
+        match self {
+            ContentRange::Bytes { first, .. } => Some(*first),
+            ContentRange::Unsatisfied { .. } => None,
+        }
+    }
+
+    /// Complete length of the body, if the server knows it.
+    pub fn total(&self) -> Option<u64> {
+        match self {
+            ContentRange::Bytes { total, .. } => *total,
+            ContentRange::Unsatisfied { total } => Some(*total),
+        }
+    }
+}
+
+/// First byte position of a valid `Content-Range` header.
+pub fn start(value: &HeaderValue) -> Option<u64> {
+    ContentRange::parse(value)?.first()
+}
+
+/// Complete length from a valid `Content-Range` header.
+pub fn total(value: &HeaderValue) -> Option<u64> {
+    ContentRange::parse(value)?.total()
+}
+
+/// Digits only: `u64::from_str` would also accept a leading `+`.
+fn parse_u64(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{start, total, ContentRange};
+    use reqwest::header::HeaderValue;
+
+    fn parse(value: &'static str) -> Option<ContentRange> {
+        ContentRange::parse(&HeaderValue::from_static(value))
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(
+            parse("bytes 100-199/200"),
+            Some(ContentRange::Bytes {
+                first: 100,
+                last: 199,
+                total: Some(200)
+            })
+        );
+        assert_eq!(
+            parse(" bytes 0-0/* "),
+            Some(ContentRange::Bytes {
+                first: 0,
+                last: 0,
+                total: None
+            })
+        );
+        assert_eq!(
+            parse("bytes */200"),
+            Some(ContentRange::Unsatisfied { total: 200 })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_ranges() {
+        assert_eq!(parse("items 1-2/3"), None);
+        assert_eq!(parse("bytes 1-2"), None);
+        assert_eq!(parse("bytes */*"), None);
+        assert_eq!(parse("bytes 5-4/10"), None);
+        assert_eq!(parse("bytes 5-10/10"), None);
+        assert_eq!(parse("bytes +5-9/10"), None);
+        assert_eq!(parse("bytes -9/10"), None);
+        assert_eq!(parse("bytes 0-99999999999999999999/*"), None);
+    }
+
+    #[test]
+    fn start_offsets() {
+        let start = |value| start(&HeaderValue::from_static(value));
+        assert_eq!(start("bytes 100-199/200"), Some(100));
+        assert_eq!(start("bytes 0-0/*"), Some(0));
+        assert_eq!(start("bytes */200"), None);
+        assert_eq!(start("items 1-2/3"), None);
+    }
+
+    #[test]
+    fn total_lengths() {
+        let total = |value| total(&HeaderValue::from_static(value));
+        assert_eq!(total("bytes 100-199/200"), Some(200));
+        assert_eq!(total("bytes */200"), Some(200));
+        assert_eq!(total("bytes 0-0/*"), None);
+    }
+}
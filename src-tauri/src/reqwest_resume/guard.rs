@@ -0,0 +1,225 @@
+//! Refusing loopback, private and link-local addresses, see
+//! [`ClientBuilder::block_private_addresses`](super::ClientBuilder::block_private_addresses),
+//! so URLs from the webview can't reach services on the machine or the LAN.
+//!
+//! Host names are checked when they're resolved, which covers redirects and
+//! every resume, and URLs with an IP address before they're requested.
+
+use std::{
+    error::Error as StdError,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    Url,
+};
+
+use super::{find_source, Resolver};
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct AddressGuard {
+    pub enabled: bool,
+    // hosts exempt from the guard, e.g. a local model server
+    pub allowed_hosts: Vec<String>,
+}
+
+impl AddressGuard {
+    fn allows_host(&self, host: &str) -> bool {
+        !self.enabled
+            || self
+                .allowed_hosts
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+
+    /// The blocked address `url` points at, if its host is an IP address.
+    pub fn blocked_url(&self, url: &Url) -> Option<IpAddr> {
+        let host = url.host_str()?;
+        if self.allows_host(host) {
+            return None;
+        }
+        let ip = host.trim_start_matches('[').trim_end_matches(']');
+        ip.parse().ok().filter(is_private)
+    }
+
+    /// A resolver dropping blocked addresses from what `resolver`, or the
+    /// system's resolver, returns.
+    pub fn resolver(&self, resolver: Option<Resolver>) -> GuardedResolver {
+        GuardedResolver {
+            guard: self.clone(),
+            resolver,
+        }
+    }
+}
+
+/// Whether `ip` is loopback, private (RFC 1918, carrier-grade NAT, or a
+/// unique local IPv6 address), link-local, broadcast or in `0.0.0.0/8`,
+/// including IPv4 addresses mapped to IPv6 or translated by NAT64.
+fn is_private(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped().or_else(|| nat64(ip)) {
+            Some(ip) => is_private_v4(&ip),
+            None => is_private_v6(ip),
+        },
+    }
+}
+
+fn is_private_v4(ip: &Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        // "this network", 0.0.0.0/8, which unspecified is part of
+        || first == 0
+        // shared by carrier-grade NAT, 100.64.0.0/10
+        || (first == 100 && second & 0xc0 == 64)
+}
+
+/// The IPv4 address `ip` stands for if it's in NAT64's well-known prefix,
+/// `64:ff9b::/96`.
+fn nat64(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => {
+            Some(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)))
+        }
+        _ => None,
+    }
+}
+
+fn is_private_v6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // unique local, fc00::/7
+        || first & 0xfe00 == 0xfc00
+        // link-local, fe80::/10
+        || first & 0xffc0 == 0xfe80
+}
+
+/// The error a blocked connection or redirect fails with, found in the
+/// source chain by [`blocked_address`].
+#[derive(Debug)]
+pub(super) struct BlockedAddress(pub IpAddr);
+
+impl fmt::Display for BlockedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is a private address", self.0)
+    }
+}
+
+impl StdError for BlockedAddress {}
+
+/// The address the guard refused, if that's why `err` failed.
+pub(super) fn blocked_address(err: &(dyn StdError + 'static)) -> Option<IpAddr> {
+    find_source::<BlockedAddress>(err).map(|blocked| blocked.0)
+}
+
+#[derive(Clone)]
+pub(super) struct GuardedResolver {
+    guard: AddressGuard,
+    resolver: Option<Resolver>,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let guard = self.guard.clone();
+        let host = name.as_str().to_owned();
+        let resolving = self
+            .resolver
+            .as_ref()
+            .map(|resolver| resolver.resolve(name));
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = match resolving {
+                Some(resolving) => resolving.await?.collect(),
+                None => tokio::net::lookup_host((host.as_str(), 0)).await?.collect(),
+            };
+            if guard.allows_host(&host) {
+                return Ok(Box::new(addrs.into_iter()) as Addrs);
+            }
+            let allowed: Vec<_> = addrs
+                .iter()
+                .copied()
+                .filter(|addr| !is_private(&addr.ip()))
+                .collect();
+            match addrs.first() {
+                Some(addr) if allowed.is_empty() => {
+                    log::warn!("refusing to connect to {} at {}", host, addr.ip());
+                    Err(Box::new(BlockedAddress(addr.ip())) as Box<dyn StdError + Send + Sync>)
+                }
+                _ => Ok(Box::new(allowed.into_iter()) as Addrs),
+            }
+        })
+    }
+}
+
+impl fmt::Debug for GuardedResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GuardedResolver")
+            .field("guard", &self.guard)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_private, AddressGuard};
+    use reqwest::Url;
+    use std::net::IpAddr;
+
+    #[test]
+    fn classifies_private_addresses() {
+        let private = |ip: &str| is_private(&ip.parse::<IpAddr>().unwrap());
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "0.1.2.3",
+            "100.64.0.1",
+            "100.127.255.254",
+            "255.255.255.255",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:192.168.1.1",
+            "64:ff9b::10.0.0.1",
+            "64:ff9b::7f00:1",
+        ] {
+            assert!(private(ip), "{ip}");
+        }
+        for ip in [
+            "8.8.8.8",
+            "172.32.0.1",
+            "100.63.255.255",
+            "100.128.0.1",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+            "64:ff9b::1.1.1.1",
+        ] {
+            assert!(!private(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn blocks_ip_urls_unless_allowed() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let mut guard = AddressGuard {
+            enabled: true,
+            allowed_hosts: vec!["127.0.0.1".to_owned()],
+        };
+        assert_eq!(
+            guard.blocked_url(&url("http://[::1]:8080/")),
+            Some("::1".parse().unwrap())
+        );
+        assert_eq!(guard.blocked_url(&url("http://127.0.0.1/")), None);
+        assert_eq!(guard.blocked_url(&url("https://example.com/")), None);
+        guard.enabled = false;
+        assert_eq!(guard.blocked_url(&url("http://10.0.0.1/")), None);
+    }
+}
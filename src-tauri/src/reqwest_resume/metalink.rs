@@ -0,0 +1,241 @@
+//! Metalink 4 documents (RFC 5854, `.meta4`), listing files with the URLs
+//! they can be downloaded from and what they hash to. Their URLs are meant
+//! for [`RequestBuilder::mirrors`], the preferred one requested first, and
+//! their hash for [`RequestBuilder::checksum`]:
+//!
+//! ```ignore
+//! for file in metalink::parse(&xml)?.files {
+//!     let (url, mirrors) = file.urls.split_first().unwrap();
+//!     let mut request = client.get(url.clone()).mirrors(mirrors.to_vec());
+//!     if let Some(checksum) = file.checksum {
+//!         request = request.checksum(checksum);
+//!     }
+//!     request.download_to_file(dir.join(&file.name)).await?;
+//! }
+//! ```
+//!
+//! Only HTTP(S) URLs are kept; `metaurl`s to torrents and the like, piece
+//! hashes and signatures are ignored.
+//!
+//! [`RequestBuilder::mirrors`]: super::RequestBuilder::mirrors
+//! [`RequestBuilder::checksum`]: super::RequestBuilder::checksum
+
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use reqwest::Url;
+
+use super::Checksum;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metalink {
+    pub files: Vec<MetalinkFile>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetalinkFile {
+    /// Path to save it at, relative to a directory of the user's choice. It
+    /// comes from the document as is, so it has to be checked before use.
+    pub name: String,
+    pub size: Option<u64>,
+    /// Of the whole file, in the strongest algorithm a [`Checksum`] can have.
+    pub checksum: Option<Checksum>,
+    /// By `priority`, lowest first, then in the order they're listed.
+    pub urls: Vec<Url>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MetalinkError {
+    #[error("invalid XML: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("invalid Metalink: {0}")]
+    Invalid(String),
+}
+
+/// A `file` element being read.
+struct Reading {
+    file: MetalinkFile,
+    /// Of each type.
+    hashes: Vec<(String, String)>,
+    /// With their priorities.
+    urls: Vec<(Option<u32>, Url)>,
+}
+
+/// Elements of a `file` whose text is wanted.
+enum Field {
+    Size,
+    /// Of the type it's named.
+    Hash(String),
+    /// Of the priority it's given.
+    Url(Option<u32>),
+}
+
+/// The files `xml` lists, in order.
+pub fn parse(xml: &str) -> Result<Metalink, MetalinkError> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut metalink = Metalink::default();
+    let mut reading: Option<Reading> = None;
+    let mut field = None;
+    // piece hashes are of parts of the file, not the whole
+    let mut in_pieces = false;
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) => match element.local_name().as_ref() {
+                b"file" => {
+                    let name = attribute(&element, "name")?
+                        .ok_or_else(|| invalid("a file has no name"))?;
+                    let file = MetalinkFile {
+                        name,
+                        ..MetalinkFile::default()
+                    };
+                    reading = Some(Reading {
+                        file,
+                        hashes: Vec::new(),
+                        urls: Vec::new(),
+                    });
+                }
+                b"pieces" => in_pieces = true,
+                b"size" if reading.is_some() => field = Some(Field::Size),
+                b"hash" if reading.is_some() && !in_pieces => {
+                    let kind = attribute(&element, "type")?.unwrap_or_default();
+                    field = Some(Field::Hash(kind.to_ascii_lowercase()));
+                }
+                b"url" if reading.is_some() => {
+                    let priority = attribute(&element, "priority")?;
+                    let priority = priority.and_then(|priority| priority.parse().ok());
+                    field = Some(Field::Url(priority));
+                }
+                _ => {}
+            },
+            Event::Text(text) => {
+                let (Some(Reading { file, hashes, urls }), Some(field)) = (&mut reading, &field)
+                else {
+                    continue;
+                };
+                let text = text.unescape()?;
+                let text = text.trim();
+                match field {
+                    Field::Size => {
+                        let size = text.parse().map_err(|_| {
+                            invalid(format!("{} has an invalid size {text:?}", file.name))
+                        })?;
+                        file.size = Some(size);
+                    }
+                    Field::Hash(kind) => hashes.push((kind.clone(), text.to_owned())),
+                    Field::Url(priority) => match Url::parse(text) {
+                        Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                            urls.push((*priority, url))
+                        }
+                        _ => log::debug!("skipping url {:?} of {}", text, file.name),
+                    },
+                }
+            }
+            Event::End(element) => match element.local_name().as_ref() {
+                b"file" => {
+                    let Some(Reading {
+                        mut file,
+                        hashes,
+                        mut urls,
+                    }) = reading.take()
+                    else {
+                        continue;
+                    };
+                    file.checksum = checksum(&hashes);
+                    // stable, so those of the same priority stay in order
+                    urls.sort_by_key(|(priority, _)| priority.unwrap_or(u32::MAX));
+                    file.urls = urls.into_iter().map(|(_, url)| url).collect();
+                    metalink.files.push(file);
+                }
+                b"pieces" => in_pieces = false,
+                _ => field = None,
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(metalink)
+}
+
+fn invalid(message: impl Into<String>) -> MetalinkError {
+    MetalinkError::Invalid(message.into())
+}
+
+fn attribute(element: &BytesStart<'_>, name: &str) -> Result<Option<String>, MetalinkError> {
+    let Some(attribute) = element.try_get_attribute(name)? else {
+        return Ok(None);
+    };
+    Ok(Some(attribute.unescape_value()?.into_owned()))
+}
+
+/// The strongest of `hashes`, by their IANA names, that a [`Checksum`] can
+/// verify.
+fn checksum(hashes: &[(String, String)]) -> Option<Checksum> {
+    let hash = |kind: &str| {
+        let (_, hex) = hashes.iter().find(|(name, _)| name == kind)?;
+        Some(hex.to_ascii_lowercase())
+    };
+    (hash("sha-256").map(Checksum::Sha256)).or_else(|| hash("md5").map(Checksum::Md5))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Checksum, MetalinkError};
+
+    const EXAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <published>2024-01-01T00:00:00Z</published>
+  <file name="models/example.gguf">
+    <size>14471447</size>
+    <hash type="md5">0123456789ABCDEF0123456789ABCDEF</hash>
+    <hash type="sha-256">f0ad929cd259957e160ea442eb80986b5f01ee2ac6a4a9c0b69ef8f1f0bd3b96</hash>
+    <pieces length="262144" type="sha-256">
+      <hash>6b86b273ff34fce19d6b804eff5a3f5747ada4eaa22f1d49c01e52ddb7875b4b</hash>
+    </pieces>
+    <url location="de">https://de.example.com/example.gguf</url>
+    <url priority="2">ftp://ftp.example.com/example.gguf</url>
+    <url priority="1">https://example.com/example.gguf?a=1&amp;b=2</url>
+    <metaurl mediatype="torrent">https://example.com/example.torrent</metaurl>
+  </file>
+  <file name="readme.txt">
+    <url>http://example.com/readme.txt</url>
+  </file>
+</metalink>"#;
+
+    #[test]
+    fn parses_files() {
+        let metalink = parse(EXAMPLE).unwrap();
+        assert_eq!(metalink.files.len(), 2);
+        let file = &metalink.files[0];
+        assert_eq!(file.name, "models/example.gguf");
+        assert_eq!(file.size, Some(14471447));
+        assert_eq!(
+            file.checksum,
+            Some(Checksum::Sha256(
+                "f0ad929cd259957e160ea442eb80986b5f01ee2ac6a4a9c0b69ef8f1f0bd3b96".into()
+            ))
+        );
+        let urls: Vec<_> = file.urls.iter().map(|url| url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/example.gguf?a=1&b=2",
+                "https://de.example.com/example.gguf"
+            ]
+        );
+        let readme = &metalink.files[1];
+        assert_eq!((readme.size, &readme.checksum), (None, &None));
+        assert_eq!(readme.urls.len(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        let unnamed = "<metalink><file><url>https://example.com/a</url></file></metalink>";
+        assert!(matches!(parse(unnamed), Err(MetalinkError::Invalid(_))));
+        let size = r#"<metalink><file name="a"><size>big</size></file></metalink>"#;
+        assert!(matches!(parse(size), Err(MetalinkError::Invalid(_))));
+        let xml = r#"<metalink><file name="a"></metalink>"#;
+        assert!(matches!(parse(xml), Err(MetalinkError::Xml(_))));
+    }
+}
@@ -0,0 +1,102 @@
+//! Interception point for every request a resumable client sends, the first
+//! one and each range request resuming it alike, in the spirit of
+//! `reqwest-middleware`.
+//!
+//! ```ignore
+//! struct Log;
+//!
+//! impl Middleware for Log {
+//!     fn handle(&self, request: reqwest::Request, next: Next) -> BoxFuture<'static, reqwest::Result<reqwest::Response>> {
+//!         log::info!("{} {}", request.method(), request.url());
+//!         next.run(request)
+//!     }
+//! }
+//!
+//! let client = Client::builder().with(Log).build()?;
+//! ```
+
+use std::{fmt, sync::Arc};
+
+use futures::future::BoxFuture;
+
+/// Sees each request before it's sent and its response before it's resumed
+/// or returned; added with [`ClientBuilder::with`](super::ClientBuilder::with).
+///
+/// Errors are `reqwest` errors so the retry policy treats them like any
+/// other: a middleware that fails should return one it got from `next`.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(
+        &self,
+        request: reqwest::Request,
+        next: Next,
+    ) -> BoxFuture<'static, reqwest::Result<reqwest::Response>>;
+}
+
+/// The rest of the chain after a middleware, ending with the client itself.
+/// It can be cloned to send a request more than once, e.g. after refreshing
+/// a token.
+#[derive(Clone)]
+pub struct Next {
+    client: reqwest::Client,
+    chain: Middlewares,
+    index: usize,
+}
+
+impl Next {
+    pub fn run(
+        mut self,
+        request: reqwest::Request,
+    ) -> BoxFuture<'static, reqwest::Result<reqwest::Response>> {
+        match self.chain.0.get(self.index).cloned() {
+            Some(middleware) => {
+                self.index += 1;
+                middleware.handle(request, self)
+            }
+            None => Box::pin(self.client.execute(request)),
+        }
+    }
+}
+
+impl fmt::Debug for Next {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Next")
+            .field("remaining", &(self.chain.0.len() - self.index))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Middleware of a client, in the order they were added.
+#[derive(Clone, Default)]
+pub(super) struct Middlewares(Vec<Arc<dyn Middleware>>);
+
+impl Middlewares {
+    pub fn push(&mut self, middleware: impl Middleware) {
+        self.0.push(Arc::new(middleware));
+    }
+
+    /// Send `builder` through the chain, or straight away without middleware.
+    pub fn send(
+        &self,
+        builder: reqwest::RequestBuilder,
+    ) -> BoxFuture<'static, reqwest::Result<reqwest::Response>> {
+        if self.0.is_empty() {
+            return Box::pin(builder.send());
+        }
+        let (client, request) = builder.build_split();
+        match request {
+            Ok(request) => Next {
+                client,
+                chain: self.clone(),
+                index: 0,
+            }
+            .run(request),
+            Err(err) => Box::pin(async { Err(err) }),
+        }
+    }
+}
+
+impl fmt::Debug for Middlewares {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Middlewares({})", self.0.len())
+    }
+}
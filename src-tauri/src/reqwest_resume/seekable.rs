@@ -0,0 +1,458 @@
+//! Random reads of the decompressed content of a remote compressed file, for
+//! formats made of independently compressed frames: the [zstd seekable
+//! format] and gzip files with several members (e.g. BGZF).
+//!
+//! A [`FrameIndex`] maps decompressed offsets to the compressed range of the
+//! frame holding them, and a [`SeekableReader`] fetches and decompresses only
+//! the frames a read touches, through a [`RangeReader`].
+//!
+//! ```ignore
+//! let mut reader = client.get(url).range_reader().await?;
+//! let index = FrameIndex::zstd(&mut reader).await?;
+//! let mut reader = SeekableReader::new(reader, index);
+//! reader.seek(SeekFrom::Start(1 << 30)).await?;
+//! ```
+//!
+//! The zstd seek table is read from the end of the file in a request or two,
+//! but gzip has no index: [`FrameIndex::gzip`] decompresses the whole file
+//! once, so store the index (it's serializable) to only pay for that once.
+//!
+//! [zstd seekable format]: https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md
+
+use std::{
+    fmt,
+    io::{self, SeekFrom},
+    ops::Range,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use bytes::Bytes;
+use futures::{future::BoxFuture, ready};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, BufReader};
+
+use super::RangeReader;
+
+const SEEKABLE_MAGIC: u32 = 0x8F92_EAB1;
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A5E;
+const FOOTER_LEN: usize = 9;
+const SKIPPABLE_HEADER_LEN: u64 = 8;
+
+/// Compression format of a [`FrameIndex`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Format {
+    Zstd,
+    Gzip,
+}
+
+/// A zstd frame or gzip member, decompressible on its own.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Frame {
+    pub compressed: Range<u64>,
+    pub decompressed: Range<u64>,
+}
+
+/// Frames of a compressed file, in order.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FrameIndex {
+    format: Format,
+    frames: Vec<Frame>,
+}
+
+impl FrameIndex {
+    /// Index from the seek table at the end of a zstd file in the seekable
+    /// format.
+    pub async fn zstd(reader: &mut RangeReader) -> io::Result<Self> {
+        let len = reader.len();
+        if len < FOOTER_LEN as u64 + SKIPPABLE_HEADER_LEN {
+            return Err(invalid("no zstd seek table"));
+        }
+        let mut footer = [0; FOOTER_LEN];
+        reader
+            .seek(SeekFrom::Start(len - FOOTER_LEN as u64))
+            .await?;
+        reader.read_exact(&mut footer).await?;
+        let (count, entry_len) = parse_footer(&footer)?;
+        let table_len = u64::from(count) * entry_len as u64;
+        let start = (len - FOOTER_LEN as u64)
+            .checked_sub(table_len + SKIPPABLE_HEADER_LEN)
+            .ok_or_else(|| invalid("zstd seek table larger than the file"))?;
+        let mut table = vec![0; (SKIPPABLE_HEADER_LEN + table_len) as usize];
+        reader.seek(SeekFrom::Start(start)).await?;
+        reader.read_exact(&mut table).await?;
+        if u32_le(&table[..4]) != SKIPPABLE_MAGIC {
+            return Err(invalid("zstd seek table isn't in a skippable frame"));
+        }
+        let frames = parse_entries(&table[SKIPPABLE_HEADER_LEN as usize..], entry_len);
+        let index = FrameIndex {
+            format: Format::Zstd,
+            frames,
+        };
+        if index.frames.last().map_or(0, |frame| frame.compressed.end) != start {
+            return Err(invalid("zstd seek table doesn't match the file size"));
+        }
+        Ok(index)
+    }
+
+    /// Index of the members of a gzip file, found by decompressing it once.
+    pub async fn gzip(reader: &mut RangeReader) -> io::Result<Self> {
+        reader.seek(SeekFrom::Start(0)).await?;
+        let mut input = Counted {
+            inner: BufReader::with_capacity(256 * 1024, reader),
+            consumed: 0,
+        };
+        let mut frames = Vec::new();
+        let mut decompressed = 0;
+        while !input.fill_buf().await?.is_empty() {
+            let start = input.consumed;
+            // stops at the end of the member, leaving the next one unread
+            let mut member = GzipDecoder::new(&mut input);
+            let len = tokio::io::copy(&mut member, &mut tokio::io::sink()).await?;
+            frames.push(Frame {
+                compressed: start..input.consumed,
+                decompressed: decompressed..decompressed + len,
+            });
+            decompressed += len;
+        }
+        Ok(FrameIndex {
+            format: Format::Gzip,
+            frames,
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Size of the decompressed content.
+    pub fn decompressed_len(&self) -> u64 {
+        self.frames.last().map_or(0, |frame| frame.decompressed.end)
+    }
+
+    /// The frame holding the decompressed byte at `pos`.
+    pub fn find(&self, pos: u64) -> Option<&Frame> {
+        let i = self
+            .frames
+            .partition_point(|frame| frame.decompressed.end <= pos);
+        self.frames.get(i)
+    }
+}
+
+/// Number of frames and size of a seek table entry, which has a checksum
+/// when the descriptor's top bit is set.
+fn parse_footer(footer: &[u8; FOOTER_LEN]) -> io::Result<(u32, usize)> {
+    if u32_le(&footer[5..]) != SEEKABLE_MAGIC {
+        return Err(invalid("not a zstd file in the seekable format"));
+    }
+    let descriptor = footer[4];
+    if descriptor & 0x7c != 0 {
+        return Err(invalid("reserved bits set in the zstd seek table"));
+    }
+    let entry_len = if descriptor & 0x80 != 0 { 12 } else { 8 };
+    Ok((u32_le(&footer[..4]), entry_len))
+}
+
+fn parse_entries(table: &[u8], entry_len: usize) -> Vec<Frame> {
+    let (mut compressed, mut decompressed) = (0, 0);
+    let mut frames = Vec::with_capacity(table.len() / entry_len);
+    for entry in table.chunks_exact(entry_len) {
+        let compressed_len = u64::from(u32_le(&entry[..4]));
+        let decompressed_len = u64::from(u32_le(&entry[4..8]));
+        frames.push(Frame {
+            compressed: compressed..compressed + compressed_len,
+            decompressed: decompressed..decompressed + decompressed_len,
+        });
+        compressed += compressed_len;
+        decompressed += decompressed_len;
+    }
+    frames
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// An `AsyncBufRead` counting the bytes consumed, to find where gzip members
+/// end.
+struct Counted<R> {
+    inner: R,
+    consumed: u64,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for Counted<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.consumed += (buf.filled().len() - filled) as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for Counted<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.consumed += amt as u64;
+        Pin::new(&mut self.inner).consume(amt);
+    }
+}
+
+type FrameFetch = BoxFuture<'static, (RangeReader, io::Result<(u64, Bytes)>)>;
+
+/// Random access to the decompressed content of a remote file, implementing
+/// `AsyncRead` and `AsyncSeek` of both tokio and futures.
+///
+/// The frame being read is kept decompressed in memory, while the compressed
+/// bytes are cached by the [`RangeReader`], so size its blocks after the
+/// frames.
+pub struct SeekableReader {
+    reader: Option<RangeReader>,
+    index: FrameIndex,
+    pos: u64,
+    frame: Option<(u64, Bytes)>,
+    fetch: Option<FrameFetch>,
+}
+
+impl SeekableReader {
+    pub fn new(reader: RangeReader, index: FrameIndex) -> Self {
+        SeekableReader {
+            reader: Some(reader),
+            index,
+            pos: 0,
+            frame: None,
+            fetch: None,
+        }
+    }
+
+    /// Size of the decompressed content.
+    pub fn len(&self) -> u64 {
+        self.index.decompressed_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    pub fn index(&self) -> &FrameIndex {
+        &self.index
+    }
+
+    /// Decompressed bytes from the current position to the end of its frame,
+    /// decompressing the frame first if needed. Empty at the end.
+    fn poll_bytes(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Bytes>> {
+        loop {
+            if let Some((start, bytes)) = &self.frame {
+                if let Some(offset) = self.pos.checked_sub(*start) {
+                    if offset < bytes.len() as u64 {
+                        return Poll::Ready(Ok(bytes.slice(offset as usize..)));
+                    }
+                }
+            }
+            let fetch = match &mut self.fetch {
+                Some(fetch) => fetch,
+                None => {
+                    let Some(frame) = self.index.find(self.pos).cloned() else {
+                        return Poll::Ready(Ok(Bytes::new()));
+                    };
+                    let Some(reader) = self.reader.take() else {
+                        return Poll::Ready(Err(io::Error::other("frame fetch was dropped")));
+                    };
+                    let format = self.index.format;
+                    self.fetch
+                        .insert(Box::pin(fetch_frame(reader, format, frame)))
+                }
+            };
+            let (reader, result) = ready!(fetch.as_mut().poll(cx));
+            self.fetch = None;
+            self.reader = Some(reader);
+            self.frame = Some(result?);
+        }
+    }
+
+    fn seek_to(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// Fetch and decompress `frame`, handing the reader back for the next one.
+async fn fetch_frame(
+    mut reader: RangeReader,
+    format: Format,
+    frame: Frame,
+) -> (RangeReader, io::Result<(u64, Bytes)>) {
+    let result = async {
+        let mut compressed = vec![0; (frame.compressed.end - frame.compressed.start) as usize];
+        reader.seek(SeekFrom::Start(frame.compressed.start)).await?;
+        reader.read_exact(&mut compressed).await?;
+        let len = frame.decompressed.end - frame.decompressed.start;
+        let mut decompressed = Vec::with_capacity(len as usize);
+        match format {
+            Format::Zstd => {
+                ZstdDecoder::new(&compressed[..])
+                    .read_to_end(&mut decompressed)
+                    .await?
+            }
+            Format::Gzip => {
+                GzipDecoder::new(&compressed[..])
+                    .read_to_end(&mut decompressed)
+                    .await?
+            }
+        };
+        if decompressed.len() as u64 != len {
+            return Err(invalid("frame size doesn't match the index"));
+        }
+        Ok((frame.decompressed.start, Bytes::from(decompressed)))
+    }
+    .await;
+    (reader, result)
+}
+
+impl fmt::Debug for SeekableReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeekableReader")
+            .field("format", &self.index.format)
+            .field("len", &self.len())
+            .field("pos", &self.pos)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncRead for SeekableReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let bytes = ready!(self.poll_bytes(cx))?;
+        let n = bytes.len().min(buf.remaining());
+        buf.put_slice(&bytes[..n]);
+        self.pos += n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl tokio::io::AsyncSeek for SeekableReader {
+    fn start_seek(mut self: Pin<&mut Self>, pos: SeekFrom) -> io::Result<()> {
+        self.seek_to(pos).map(drop)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+impl futures::io::AsyncRead for SeekableReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let bytes = ready!(self.poll_bytes(cx))?;
+        let n = bytes.len().min(buf.len());
+        buf[..n].copy_from_slice(&bytes[..n]);
+        self.pos += n as u64;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl futures::io::AsyncSeek for SeekableReader {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        Poll::Ready(self.seek_to(pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_entries, parse_footer, Format, Frame, FrameIndex};
+
+    #[test]
+    fn parses_zstd_seek_tables() {
+        let mut footer = [0; 9];
+        footer[..4].copy_from_slice(&2u32.to_le_bytes());
+        footer[5..].copy_from_slice(&0x8F92_EAB1u32.to_le_bytes());
+        assert_eq!(parse_footer(&footer).unwrap(), (2, 8));
+        footer[4] = 0x80;
+        assert_eq!(parse_footer(&footer).unwrap(), (2, 12));
+        footer[4] = 0x04;
+        assert!(parse_footer(&footer).is_err());
+        footer[8] = 0;
+        assert!(parse_footer(&footer).is_err());
+
+        let mut table = Vec::new();
+        for (compressed, decompressed) in [(10u32, 100u32), (20, 50)] {
+            table.extend(compressed.to_le_bytes());
+            table.extend(decompressed.to_le_bytes());
+            table.extend(0xdead_beefu32.to_le_bytes());
+        }
+        assert_eq!(
+            parse_entries(&table, 12),
+            [
+                Frame {
+                    compressed: 0..10,
+                    decompressed: 0..100
+                },
+                Frame {
+                    compressed: 10..30,
+                    decompressed: 100..150
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_frames() {
+        let frames = [(0..10, 0..100), (10..12, 100..100), (12..30, 100..150)];
+        let index = FrameIndex {
+            format: Format::Gzip,
+            frames: frames
+                .into_iter()
+                .map(|(compressed, decompressed)| Frame {
+                    compressed,
+                    decompressed,
+                })
+                .collect(),
+        };
+        assert_eq!(index.decompressed_len(), 150);
+        assert_eq!(index.find(0).unwrap().compressed, 0..10);
+        assert_eq!(index.find(99).unwrap().compressed, 0..10);
+        // empty frames are skipped
+        assert_eq!(index.find(100).unwrap().compressed, 12..30);
+        assert_eq!(index.find(149).unwrap().compressed, 12..30);
+        assert!(index.find(150).is_none());
+    }
+}
@@ -0,0 +1,126 @@
+//! Public key pinning: the SHA-256 hash of a certificate's DER-encoded
+//! `SubjectPublicKeyInfo`, as printed by
+//! `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256`.
+//!
+//! Hashing the key instead of the whole certificate keeps pins valid across
+//! renewals that reuse the key.
+
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of the `SubjectPublicKeyInfo` of the DER certificate `cert`, or
+/// `None` if it can't be parsed.
+pub fn sha256(cert: &[u8]) -> Option<[u8; 32]> {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(&Sha256::digest(subject_public_key_info(cert)?));
+    Some(hash)
+}
+
+/// The DER `SubjectPublicKeyInfo`, including its header, found by skipping the
+/// fields of `TBSCertificate` that come before it (RFC 5280, section 4.1).
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    let certificate = Element::parse(cert, SEQUENCE)?.content;
+    let mut fields = Element::parse(certificate, SEQUENCE)?.content;
+    // the version is optional and explicitly tagged [0]
+    if fields.first() == Some(&VERSION) {
+        fields = Element::parse(fields, VERSION)?.rest;
+    }
+    // serialNumber, signature, issuer, validity and subject
+    for tag in [INTEGER, SEQUENCE, SEQUENCE, SEQUENCE, SEQUENCE] {
+        fields = Element::parse(fields, tag)?.rest;
+    }
+    Some(Element::parse(fields, SEQUENCE)?.der)
+}
+
+const INTEGER: u8 = 0x02;
+const SEQUENCE: u8 = 0x30;
+const VERSION: u8 = 0xa0;
+
+/// A DER element at the start of some bytes.
+struct Element<'a> {
+    /// The whole element, tag and length included.
+    der: &'a [u8],
+    content: &'a [u8],
+    /// What follows the element.
+    rest: &'a [u8],
+}
+
+impl<'a> Element<'a> {
+    /// Parse the element at the start of `der`, which must have `tag`.
+    fn parse(der: &'a [u8], tag: u8) -> Option<Self> {
+        if *der.first()? != tag {
+            return None;
+        }
+        let (header, len) = match *der.get(1)? {
+            len @ 0..=0x7f => (2, len as usize),
+            // long form, the low bits give the number of length bytes
+            first => {
+                let count = (first & 0x7f) as usize;
+                if count == 0 || count > 4 {
+                    return None;
+                }
+                let bytes = der.get(2..2 + count)?;
+                let len = bytes.iter().fold(0, |len, &byte| len << 8 | byte as usize);
+                (2 + count, len)
+            }
+        };
+        let end = header.checked_add(len)?;
+        let (element, rest) = (der.get(..end)?, &der[end..]);
+        Some(Element {
+            der: element,
+            content: &element[header..],
+            rest,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sha256, subject_public_key_info};
+    use sha2::{Digest, Sha256};
+
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut der = vec![tag];
+        if content.len() < 0x80 {
+            der.push(content.len() as u8);
+        } else {
+            der.extend([0x82, (content.len() >> 8) as u8, content.len() as u8]);
+        }
+        der.extend_from_slice(content);
+        der
+    }
+
+    fn certificate(version: bool, key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let spki = tlv(0x30, &tlv(0x03, key));
+        let mut tbs = Vec::new();
+        if version {
+            tbs.extend(tlv(0xa0, &tlv(0x02, &[2])));
+        }
+        tbs.extend(tlv(0x02, &[1, 2, 3]));
+        for field in [&b"alg"[..], b"issuer", b"validity", b"subject"] {
+            tbs.extend(tlv(0x30, field));
+        }
+        tbs.extend(&spki);
+        let mut cert = tlv(0x30, &tbs);
+        cert.extend(tlv(0x30, b"signature algorithm"));
+        cert.extend(tlv(0x03, b"signature"));
+        (tlv(0x30, &cert), spki)
+    }
+
+    #[test]
+    fn finds_the_public_key() {
+        for version in [true, false] {
+            let (cert, spki) = certificate(version, b"key");
+            assert_eq!(subject_public_key_info(&cert), Some(&spki[..]));
+        }
+        let (cert, spki) = certificate(true, &[7; 300]);
+        assert_eq!(sha256(&cert).unwrap()[..], Sha256::digest(&spki)[..]);
+    }
+
+    #[test]
+    fn rejects_truncated_certificates() {
+        let (cert, _) = certificate(true, &[7; 300]);
+        for len in [0, 1, 4, 20, cert.len() - 1] {
+            assert_eq!(sha256(&cert[..len]), None);
+        }
+    }
+}
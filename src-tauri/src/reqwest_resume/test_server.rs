@@ -0,0 +1,328 @@
+//! Minimal HTTP server for testing resumes against misbehaving servers. Tests
+//! run with tokio's paused clock (`start_paused = true`) so backoff delays
+//! elapse instantly and deterministically.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use reqwest::{StatusCode, Url};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Ways the server misbehaves; each dropped connection happens only once.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Faults {
+    /// Close the connection once the body reaches each of these offsets.
+    pub drop_at: Vec<u64>,
+    /// Stop sending, but keep the connection open, once the body reaches each
+    /// of these offsets.
+    pub stall_at: Vec<u64>,
+    /// Answer range requests with the whole body and a `200`.
+    pub ignore_range: bool,
+    /// Serve a different ETag from this request on, counting from 0.
+    pub change_etag_at: Option<usize>,
+    /// Answer these requests, counting from 0, with `401 Unauthorized`.
+    pub unauthorized: Vec<usize>,
+    /// Answer requests from this one on, counting from 0, with
+    /// `500 Internal Server Error`.
+    pub fail_from: Option<usize>,
+    /// Wait this long before answering the first request.
+    pub delay_first: Option<Duration>,
+    /// Answer range requests with at most this many bytes, as servers may.
+    pub range_len: Option<u64>,
+}
+
+/// A request as received, for [`TestServer::handle`].
+#[derive(Clone, Debug)]
+pub(super) struct Request {
+    pub method: String,
+    /// The path and query.
+    pub target: String,
+    headers: Vec<(String, String)>,
+    pub body: Bytes,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let mut headers = self.headers.iter();
+        let header = headers.find(|(key, _)| key.eq_ignore_ascii_case(name));
+        header.map(|(_, value)| value.as_str())
+    }
+}
+
+/// An answer from a [`TestServer::handle`] handler.
+#[derive(Debug)]
+pub(super) struct Reply {
+    status: StatusCode,
+    headers: Vec<(&'static str, String)>,
+    body: Bytes,
+}
+
+impl Reply {
+    pub fn new(status: StatusCode) -> Self {
+        Reply {
+            status,
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    faults: Faults,
+    // `Range` header of every request so far
+    ranges: Vec<Option<String>>,
+}
+
+pub(super) struct TestServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl TestServer {
+    /// Serve `body` at every path for as long as the runtime lives.
+    pub async fn start(body: Bytes, faults: Faults) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State {
+            faults,
+            ranges: Vec::new(),
+        }));
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let body = body.clone();
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = serve(stream, &body, &state).await {
+                        log::debug!("test server: {}", err);
+                    }
+                });
+            }
+        });
+        TestServer { addr, state }
+    }
+
+    /// Answer every request with what `handler` makes of it, for protocols
+    /// beyond fetching a body.
+    pub async fn handle(handler: impl FnMut(Request) -> Reply + Send + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handler = Arc::new(Mutex::new(handler));
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let reply = match read_request(&mut stream).await {
+                        Ok(Some(request)) => (handler.lock().unwrap())(request),
+                        Ok(None) => return,
+                        Err(err) => return log::debug!("test server: {}", err),
+                    };
+                    if let Err(err) = write_reply(&mut stream, reply).await {
+                        log::debug!("test server: {}", err);
+                    }
+                });
+            }
+        });
+        let state = Arc::new(Mutex::new(State {
+            faults: Faults::default(),
+            ranges: Vec::new(),
+        }));
+        TestServer { addr, state }
+    }
+
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("http://{}/file", self.addr)).unwrap()
+    }
+
+    /// `Range` header of every request received so far.
+    pub fn ranges(&self) -> Vec<Option<String>> {
+        self.state.lock().unwrap().ranges.clone()
+    }
+}
+
+/// Read a request, body and all, or `None` if the connection is closed first.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<Request>> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    let end = loop {
+        if let Some(i) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break i + 4;
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buf[..n]);
+    };
+    let body = request.split_off(end);
+    let head = String::from_utf8_lossy(&request);
+    let mut lines = head.lines();
+    let mut start = lines.next().unwrap_or_default().split(' ');
+    let (method, target) = (start.next(), start.next());
+    let headers = lines.filter_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        Some((key.trim().to_owned(), value.trim().to_owned()))
+    });
+    let mut request = Request {
+        method: method.unwrap_or_default().to_owned(),
+        target: target.unwrap_or_default().to_owned(),
+        headers: headers.collect(),
+        body: Bytes::new(),
+    };
+    let len = request
+        .header("content-length")
+        .and_then(|len| len.parse().ok());
+    let mut body = body;
+    while body.len() < len.unwrap_or(0) {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+    request.body = body.into();
+    Ok(Some(request))
+}
+
+async fn write_reply(stream: &mut TcpStream, reply: Reply) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {}\r\n", reply.status);
+    for (name, value) in &reply.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if reply.status != StatusCode::NO_CONTENT {
+        head.push_str(&format!("Content-Length: {}\r\n", reply.body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&reply.body).await?;
+    stream.flush().await
+}
+
+/// Answer a single request, then close the connection.
+async fn serve(mut stream: TcpStream, body: &[u8], state: &Mutex<State>) -> std::io::Result<()> {
+    let Some(request) = read_request(&mut stream).await? else {
+        return Ok(());
+    };
+    let header = |name: &str| request.header(name).map(str::to_owned);
+    let range = header("range");
+    let if_range = header("if-range");
+    let if_none_match = header("if-none-match");
+
+    let (error, delay) = {
+        let mut state = state.lock().unwrap();
+        let delay = state.faults.delay_first.take();
+        let n = state.ranges.len();
+        let error = if state.faults.unauthorized.contains(&n) {
+            Some("401 Unauthorized")
+        } else if matches!(state.faults.fail_from, Some(from) if n >= from) {
+            Some("500 Internal Server Error")
+        } else {
+            None
+        };
+        if error.is_some() {
+            state.ranges.push(range.clone());
+        }
+        (error, delay)
+    };
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+    if let Some(error) = error {
+        let head = format!("HTTP/1.1 {error}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        stream.write_all(head.as_bytes()).await?;
+        return stream.flush().await;
+    }
+
+    let len = body.len() as u64;
+    let (etag, range, drop_at, stall_at) = {
+        let mut state = state.lock().unwrap();
+        let etag = match state.faults.change_etag_at {
+            Some(at) if state.ranges.len() >= at => "\"v2\"",
+            _ => "\"v1\"",
+        };
+        state.ranges.push(range.clone());
+        let range = range
+            .filter(|_| !state.faults.ignore_range)
+            .filter(|_| !matches!(if_range.as_deref(), Some(if_range) if if_range != etag))
+            .and_then(|range| parse_range(&range, len));
+        let range = range.map(|(start, end)| match state.faults.range_len {
+            Some(range_len) => (start, end.min(start + range_len)),
+            None => (start, end),
+        });
+        let (start, end) = range.unwrap_or((0, len));
+        let drop_at = state
+            .faults
+            .drop_at
+            .iter()
+            .position(|&offset| start < offset && offset < end)
+            .map(|i| state.faults.drop_at.remove(i));
+        let stall_at = state
+            .faults
+            .stall_at
+            .iter()
+            .position(|&offset| start < offset && offset < end)
+            .map(|i| state.faults.stall_at.remove(i));
+        (etag, range, drop_at, stall_at)
+    };
+    if if_none_match.as_deref() == Some(etag) {
+        let head =
+            format!("HTTP/1.1 304 Not Modified\r\nETag: {etag}\r\nConnection: close\r\n\r\n");
+        stream.write_all(head.as_bytes()).await?;
+        return stream.flush().await;
+    }
+    let (start, end) = range.unwrap_or((0, len));
+
+    let mut head = match range {
+        Some(_) => format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+            start,
+            end - 1,
+            len
+        ),
+        None => "HTTP/1.1 200 OK\r\n".to_owned(),
+    };
+    head.push_str(&format!(
+        "Content-Length: {}\r\nETag: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n\r\n",
+        end - start,
+        etag
+    ));
+    stream.write_all(head.as_bytes()).await?;
+    let sent = drop_at.into_iter().chain(stall_at).min().unwrap_or(end);
+    stream
+        .write_all(&body[start as usize..sent as usize])
+        .await?;
+    stream.flush().await?;
+    if stall_at == Some(sent) {
+        std::future::pending::<()>().await;
+    }
+    Ok(())
+}
+
+/// Parse `bytes=<first>-[<last>]` into an exclusive range within `len`.
+fn parse_range(range: &str, len: u64) -> Option<(u64, u64)> {
+    let (first, last) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let first: u64 = first.trim().parse().ok()?;
+    let end = match last.trim() {
+        "" => len,
+        last => (last.parse::<u64>().ok()? + 1).min(len),
+    };
+    (first < end).then_some((first, end))
+}
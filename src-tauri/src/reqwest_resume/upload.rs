@@ -0,0 +1,791 @@
+//! Resumable uploads with the tus protocol, version 1.0
+//! (<https://tus.io/protocols/resumable-upload>): the counterpart of resuming
+//! downloads, for pushing large files to servers that support it.
+//!
+//! ```ignore
+//! let uploader = Uploader::new(client);
+//! let upload = uploader.create(endpoint, len, &[("filename", "model.bin")]).await?;
+//! uploader.upload_file(&upload, path).await?;
+//! ```
+//!
+//! After a failed `PATCH` the server's offset is asked for with `HEAD` and the
+//! upload continues from there, following the client's retry policy. The
+//! upload URL can be kept to resume in a later session.
+//!
+//! S3 and compatible stores get multipart uploads instead, see
+//! [`Uploader::upload_multipart`]: parts are sent concurrently and the ones
+//! that are done are recorded next to the file, so a crashed upload only
+//! sends what's missing.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bytes::Bytes;
+use futures::{stream, StreamExt};
+use quick_xml::{events::Event, Reader};
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE, ETAG, LOCATION},
+    Method, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+    time::Instant,
+};
+
+use super::{status_error, Error, FailureKind, Result};
+
+const TUS_RESUMABLE: &str = "Tus-Resumable";
+const TUS_VERSION: &str = "1.0.0";
+const UPLOAD_OFFSET: &str = "Upload-Offset";
+const UPLOAD_LENGTH: &str = "Upload-Length";
+const UPLOAD_METADATA: &str = "Upload-Metadata";
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+/// Uploads with the retry policy, timeout and middleware of a resumable
+/// [`Client`](super::Client).
+#[derive(Clone, Debug)]
+pub struct Uploader {
+    client: super::Client,
+    chunk_size: usize,
+    concurrency: usize,
+}
+
+impl Uploader {
+    pub fn new(client: super::Client) -> Self {
+        Uploader {
+            client,
+            chunk_size: 8 * 1024 * 1024,
+            concurrency: 4,
+        }
+    }
+
+    /// Bytes sent per `PATCH` or multipart part, 8 MiB by default. Smaller
+    /// chunks lose less progress when a request fails without the server
+    /// keeping a partial chunk; S3 wants parts of at least 5 MiB.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Parts of a multipart upload sent at once, 4 by default.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Create an upload of `len` bytes at `endpoint` and return its URL.
+    /// `metadata` is sent as `Upload-Metadata`.
+    ///
+    /// A creation that is retried after the server received it may leave an
+    /// unused upload behind.
+    pub async fn create(&self, endpoint: Url, len: u64, metadata: &[(&str, &str)]) -> Result<Url> {
+        let metadata = encode_metadata(metadata);
+        let response = self
+            .send(&endpoint, 0, || {
+                let builder = self.tus(Method::POST, &endpoint).header(UPLOAD_LENGTH, len);
+                if metadata.is_empty() {
+                    builder
+                } else {
+                    builder.header(UPLOAD_METADATA, metadata.as_str())
+                }
+            })
+            .await?;
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| protocol(&endpoint, 0, "the created upload has no Location"))?;
+        endpoint
+            .join(location)
+            .map_err(|_| protocol(&endpoint, 0, "the created upload's Location isn't a URL"))
+    }
+
+    /// Bytes of `upload` the server has, from `HEAD`.
+    pub async fn offset(&self, upload: &Url) -> Result<u64> {
+        let response = self
+            .send(upload, 0, || self.tus(Method::HEAD, upload))
+            .await?;
+        header_u64(response.headers(), UPLOAD_OFFSET)
+            .ok_or_else(|| protocol(upload, 0, "the upload has no valid Upload-Offset"))
+    }
+
+    /// Send the file at `path` to `upload`, starting from the server's offset,
+    /// and return the final offset.
+    pub async fn upload_file(&self, upload: &Url, path: impl AsRef<Path>) -> Result<u64> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|err| Error::io(upload, 0, err))?;
+        let len = file
+            .metadata()
+            .await
+            .map_err(|err| Error::io(upload, 0, err))?
+            .len();
+        self.upload(upload, Source::File(file), len).await
+    }
+
+    /// Send `body` to `upload`, starting from the server's offset, and return
+    /// the final offset.
+    pub async fn upload_bytes(&self, upload: &Url, body: Bytes) -> Result<u64> {
+        let len = body.len() as u64;
+        self.upload(upload, Source::Bytes(body), len).await
+    }
+
+    async fn upload(&self, upload: &Url, mut source: Source, len: u64) -> Result<u64> {
+        let mut offset = self.offset(upload).await?;
+        let mut failures = 0;
+        let mut failing_since = None;
+        while offset < len {
+            let size = (len - offset).min(self.chunk_size as u64);
+            let chunk = source
+                .chunk(offset, size as usize)
+                .await
+                .map_err(|err| Error::io(upload, offset, err))?;
+            let builder = self
+                .with_timeout(self.tus(Method::PATCH, upload))
+                .header(UPLOAD_OFFSET, offset)
+                .header(CONTENT_TYPE, OFFSET_OCTET_STREAM)
+                .body(chunk);
+            let sent = self.client.config.middleware.send(builder).await;
+            match sent.and_then(check) {
+                Ok(response) => {
+                    offset = match header_u64(response.headers(), UPLOAD_OFFSET) {
+                        Some(next) if next > offset && next <= offset + size => next,
+                        _ => {
+                            let reason = "Upload-Offset didn't advance with the chunk";
+                            return Err(protocol(upload, offset, reason));
+                        }
+                    };
+                    failures = 0;
+                    failing_since = None;
+                }
+                Err(err) => {
+                    failures += 1;
+                    let since = *failing_since.get_or_insert_with(Instant::now);
+                    self.back_off(upload, offset, err, failures, since).await?;
+                    // the server may have kept part of the chunk
+                    offset = self.offset(upload).await?;
+                }
+            }
+        }
+        if offset > len {
+            return Err(protocol(
+                upload,
+                offset,
+                "the upload is longer than the source",
+            ));
+        }
+        Ok(offset)
+    }
+
+    /// Upload the file at `path` to the S3 object `object` in parts, the size
+    /// of [`Self::chunk_size`]. Requests need to be signed, e.g. with the
+    /// `SigV4` middleware.
+    ///
+    /// The upload ID and the ETags of finished parts are saved to
+    /// `<path>.upload.json`, so calling this again after a crash or a failure
+    /// only sends the missing parts. The file is removed once the upload is
+    /// complete.
+    pub async fn upload_multipart(&self, object: &Url, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let sidecar_err = |source| Error::Sidecar {
+            path: multipart_path(path),
+            source,
+        };
+        let len = fs::metadata(path)
+            .await
+            .map_err(|err| Error::io(object, 0, err))?
+            .len();
+        let part_size = self.chunk_size as u64;
+        let mut state = match Multipart::read(path).await {
+            Ok(state)
+                if state.url == object.as_str()
+                    && state.len == len
+                    && state.part_size == part_size =>
+            {
+                state
+            }
+            _ => Multipart {
+                url: object.to_string(),
+                upload_id: self.initiate(object).await?,
+                len,
+                part_size,
+                parts: BTreeMap::new(),
+            },
+        };
+        state.save(path).await.map_err(sidecar_err)?;
+
+        let count = len.div_ceil(part_size).max(1) as u32;
+        let missing = (1..=count).filter(|number| !state.parts.contains_key(number));
+        let missing = missing.collect::<Vec<_>>();
+        let upload_id = state.upload_id.clone();
+        let mut parts = stream::iter(missing)
+            .map(|number| {
+                let offset = (number - 1) as u64 * part_size;
+                let size = part_size.min(len - offset);
+                let upload_id = &upload_id;
+                async move {
+                    let etag = self
+                        .upload_part(object, upload_id, number, path, offset, size)
+                        .await?;
+                    Ok::<_, Error>((number, etag))
+                }
+            })
+            .buffer_unordered(self.concurrency);
+        while let Some(part) = parts.next().await {
+            let (number, etag) = part?;
+            log::debug!("uploaded part {number} of {count} to {object}");
+            state.parts.insert(number, etag);
+            state.save(path).await.map_err(sidecar_err)?;
+        }
+
+        self.complete(object, &state).await?;
+        if let Err(err) = fs::remove_file(multipart_path(path)).await {
+            log::warn!(
+                "failed to remove the upload state of {}: {err}",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Start a multipart upload to `object` and return its ID.
+    async fn initiate(&self, object: &Url) -> Result<String> {
+        let mut url = object.clone();
+        url.query_pairs_mut().append_key_only("uploads");
+        let response = self
+            .send(&url, 0, || {
+                self.client.client.request(Method::POST, url.clone())
+            })
+            .await?;
+        let body = response
+            .text()
+            .await
+            .map_err(|err| Error::network(&url, 0, err))?;
+        xml_element(&body, "UploadId")
+            .ok_or_else(|| protocol(&url, 0, "the multipart upload has no UploadId"))
+    }
+
+    /// Send part `number`, `size` bytes at `offset` in the file at `path`, and
+    /// return its ETag.
+    async fn upload_part(
+        &self,
+        object: &Url,
+        upload_id: &str,
+        number: u32,
+        path: &Path,
+        offset: u64,
+        size: u64,
+    ) -> Result<String> {
+        let mut url = object.clone();
+        url.query_pairs_mut()
+            .append_pair("partNumber", &number.to_string())
+            .append_pair("uploadId", upload_id);
+        let file = fs::File::open(path)
+            .await
+            .map_err(|err| Error::io(&url, offset, err))?;
+        let part = Source::File(file)
+            .chunk(offset, size as usize)
+            .await
+            .map_err(|err| Error::io(&url, offset, err))?;
+        let response = self
+            .send(&url, offset, || {
+                let builder = self.client.client.request(Method::PUT, url.clone());
+                builder.body(part.clone())
+            })
+            .await?;
+        response
+            .headers()
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+            .ok_or_else(|| protocol(&url, offset, "the uploaded part has no ETag"))
+    }
+
+    /// Assemble the object from the uploaded parts.
+    async fn complete(&self, object: &Url, state: &Multipart) -> Result<()> {
+        let mut url = object.clone();
+        url.query_pairs_mut()
+            .append_pair("uploadId", &state.upload_id);
+        let body = complete_body(&state.parts);
+        let response = self
+            .send(&url, state.len, || {
+                let builder = self.client.client.request(Method::POST, url.clone());
+                builder.body(body.clone())
+            })
+            .await?;
+        // S3 can fail after it started answering with 200
+        let body = response
+            .text()
+            .await
+            .map_err(|err| Error::network(&url, state.len, err))?;
+        if xml_element(&body, "Code").is_some() {
+            log::warn!("completing the upload to {object} failed: {body}");
+            return Err(protocol(
+                &url,
+                state.len,
+                "the multipart upload couldn't be completed",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Send the request built by `request` until it succeeds or the retry
+    /// policy gives up.
+    async fn send(
+        &self,
+        url: &Url,
+        pos: u64,
+        request: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let mut failures = 0;
+        let since = Instant::now();
+        loop {
+            let builder = self.with_timeout(request());
+            let sent = self.client.config.middleware.send(builder).await;
+            match sent.and_then(check) {
+                Ok(response) => return Ok(response),
+                Err(err) => {
+                    failures += 1;
+                    self.back_off(url, pos, err, failures, since).await?;
+                }
+            }
+        }
+    }
+
+    fn tus(&self, method: Method, url: &Url) -> reqwest::RequestBuilder {
+        let builder = self.client.client.request(method, url.clone());
+        builder.header(TUS_RESUMABLE, TUS_VERSION)
+    }
+
+    fn with_timeout(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.client.defaults.timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        }
+    }
+
+    /// Wait before retrying after `err`, or fail with it if the retry policy
+    /// doesn't allow another attempt.
+    async fn back_off(
+        &self,
+        url: &Url,
+        pos: u64,
+        err: reqwest::Error,
+        failures: u32,
+        since: Instant,
+    ) -> Result<()> {
+        let retry = &self.client.defaults.retry;
+        let retryable = match err.status() {
+            // 409 means the offset was stale, which the next HEAD fixes
+            Some(status) => {
+                status.is_server_error()
+                    || status == StatusCode::TOO_MANY_REQUESTS
+                    || status == StatusCode::CONFLICT
+            }
+            None => (retry.retryable)(&err),
+        };
+        if !retryable {
+            return Err(Error::network(url, pos, err));
+        }
+        let kind = FailureKind::of(&err);
+        if !retry.may_retry_kind(kind, failures, Some(since)) {
+            return Err(Error::TooManyRetries {
+                url: url.clone(),
+                pos,
+                attempts: failures,
+                source: err,
+            });
+        }
+        let delay = retry.delay_kind(kind, failures);
+        log::warn!("upload to {url} failed at {pos} bytes, retrying in {delay:?}: {err}");
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+}
+
+/// What an upload reads its chunks from.
+enum Source {
+    File(tokio::fs::File),
+    Bytes(Bytes),
+}
+
+impl Source {
+    async fn chunk(&mut self, offset: u64, len: usize) -> std::io::Result<Bytes> {
+        match self {
+            Source::File(file) => {
+                file.seek(std::io::SeekFrom::Start(offset)).await?;
+                let mut chunk = vec![0; len];
+                file.read_exact(&mut chunk).await?;
+                Ok(chunk.into())
+            }
+            Source::Bytes(bytes) => Ok(bytes.slice(offset as usize..offset as usize + len)),
+        }
+    }
+}
+
+/// Progress of a multipart upload, saved as `<path>.upload.json` next to the
+/// file being uploaded.
+#[derive(Debug, Deserialize, Serialize)]
+struct Multipart {
+    url: String,
+    upload_id: String,
+    len: u64,
+    part_size: u64,
+    /// ETags of the finished parts, by part number.
+    parts: BTreeMap<u32, String>,
+}
+
+impl Multipart {
+    async fn read(path: &Path) -> std::io::Result<Multipart> {
+        let json = fs::read(multipart_path(path)).await?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    async fn save(&self, path: &Path) -> std::io::Result<()> {
+        let state = multipart_path(path);
+        let mut tmp = state.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp, &state).await
+    }
+}
+
+fn multipart_path(path: &Path) -> PathBuf {
+    let mut state = path.as_os_str().to_owned();
+    state.push(".upload.json");
+    PathBuf::from(state)
+}
+
+/// The `CompleteMultipartUpload` document listing `parts` in order.
+fn complete_body(parts: &BTreeMap<u32, String>) -> String {
+    let mut body = String::from("<CompleteMultipartUpload>");
+    for (number, etag) in parts {
+        body.push_str(&format!(
+            "<Part><PartNumber>{number}</PartNumber><ETag>{}</ETag></Part>",
+            etag.replace('&', "&amp;").replace('<', "&lt;")
+        ));
+    }
+    body.push_str("</CompleteMultipartUpload>");
+    body
+}
+
+/// Text of the first `<name>` element in the XML `body`, which is all S3's
+/// answers need.
+fn xml_element(body: &str, name: &str) -> Option<String> {
+    let mut reader = Reader::from_str(body);
+    let mut inside = false;
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(element) if element.local_name().as_ref() == name.as_bytes() => {
+                inside = true;
+            }
+            Event::Empty(element) if element.local_name().as_ref() == name.as_bytes() => {
+                return Some(String::new());
+            }
+            Event::Text(text) if inside => return Some(text.unescape().ok()?.into_owned()),
+            Event::End(_) if inside => return Some(String::new()),
+            Event::Eof => return None,
+            _ => {}
+        }
+    }
+}
+
+fn check(response: reqwest::Response) -> reqwest::Result<reqwest::Response> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(status_error(response))
+    }
+}
+
+fn protocol(url: &Url, pos: u64, reason: &'static str) -> Error {
+    Error::Protocol {
+        url: url.clone(),
+        pos,
+        reason,
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// `Upload-Metadata`: comma-separated keys, each followed by its base64 value.
+fn encode_metadata(metadata: &[(&str, &str)]) -> String {
+    let pairs = metadata
+        .iter()
+        .map(|(key, value)| format!("{key} {}", BASE64.encode(value)));
+    pairs.collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use bytes::Bytes;
+    use reqwest::{
+        header::{HeaderMap, HeaderValue},
+        StatusCode,
+    };
+
+    use super::{
+        complete_body, encode_metadata, header_u64, multipart_path, xml_element, Uploader,
+        UPLOAD_OFFSET,
+    };
+    use crate::reqwest_resume::{
+        test_server::{Reply, Request, TestServer},
+        Backoff, Client, Error, RetryPolicy,
+    };
+
+    fn body() -> Bytes {
+        (0..10_000u32)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    fn uploader() -> Uploader {
+        Uploader::new(
+            Client::new().retry_policy(
+                RetryPolicy::new()
+                    .max_attempts(5)
+                    .backoff(Backoff::Fixed(Duration::from_secs(1))),
+            ),
+        )
+    }
+
+    /// What a tus server received so far.
+    #[derive(Default)]
+    struct Upload {
+        data: Vec<u8>,
+        /// The method and `Upload-Offset` of every request.
+        requests: Vec<String>,
+    }
+
+    /// Serve the tus upload `upload`, answering each `PATCH` with `patch`.
+    async fn tus(
+        upload: &Arc<Mutex<Upload>>,
+        mut patch: impl FnMut(&mut Upload, &Request) -> Reply + Send + 'static,
+    ) -> TestServer {
+        let upload = upload.clone();
+        TestServer::handle(move |request| {
+            let mut upload = upload.lock().unwrap();
+            let offset = request.header(UPLOAD_OFFSET).unwrap_or_default();
+            let logged = format!("{} {offset}", request.method);
+            upload.requests.push(logged.trim_end().to_owned());
+            match request.method.as_str() {
+                "HEAD" => Reply::new(StatusCode::OK).header(UPLOAD_OFFSET, upload.data.len()),
+                "PATCH" => patch(&mut upload, &request),
+                _ => Reply::new(StatusCode::METHOD_NOT_ALLOWED),
+            }
+        })
+        .await
+    }
+
+    /// Append the chunk, as a well-behaved server does.
+    fn append(upload: &mut Upload, request: &Request) -> Reply {
+        let offset = request
+            .header(UPLOAD_OFFSET)
+            .and_then(|offset| offset.parse().ok());
+        if offset != Some(upload.data.len()) {
+            return Reply::new(StatusCode::CONFLICT);
+        }
+        upload.data.extend_from_slice(&request.body);
+        Reply::new(StatusCode::NO_CONTENT).header(UPLOAD_OFFSET, upload.data.len())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn asks_for_the_offset_after_a_failed_patch() {
+        let upload = Arc::default();
+        let mut failed = false;
+        let server = tus(&upload, move |upload, request| {
+            if failed {
+                return append(upload, request);
+            }
+            // keeping half of the chunk
+            failed = true;
+            let half = request.body.len() / 2;
+            upload.data.extend_from_slice(&request.body[..half]);
+            Reply::new(StatusCode::INTERNAL_SERVER_ERROR)
+        })
+        .await;
+        let uploader = uploader().chunk_size(4000);
+        let offset = uploader.upload_bytes(&server.url(), body()).await.unwrap();
+        assert_eq!(offset, 10_000);
+        let upload = upload.lock().unwrap();
+        assert_eq!(upload.data, body().to_vec());
+        assert_eq!(
+            upload.requests,
+            ["HEAD", "PATCH 0", "HEAD", "PATCH 2000", "PATCH 6000"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn continues_from_the_servers_offset() {
+        let upload = Arc::new(Mutex::new(Upload {
+            data: body()[..3000].to_vec(),
+            requests: Vec::new(),
+        }));
+        let server = tus(&upload, append).await;
+        let offset = uploader()
+            .upload_bytes(&server.url(), body())
+            .await
+            .unwrap();
+        assert_eq!(offset, 10_000);
+        let upload = upload.lock().unwrap();
+        assert_eq!(upload.data, body().to_vec());
+        assert_eq!(upload.requests, ["HEAD", "PATCH 3000"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejects_offsets_that_dont_advance() {
+        let upload = Arc::default();
+        let server = tus(&upload, |upload, _| {
+            Reply::new(StatusCode::NO_CONTENT).header(UPLOAD_OFFSET, upload.data.len())
+        })
+        .await;
+        let err = uploader().upload_bytes(&server.url(), body()).await;
+        assert!(
+            matches!(err, Err(Error::Protocol { pos: 0, .. })),
+            "{err:?}"
+        );
+        assert_eq!(upload.lock().unwrap().requests, ["HEAD", "PATCH 0"]);
+    }
+
+    /// What an S3 store received so far.
+    #[derive(Default)]
+    struct Store {
+        /// The method and query of every request.
+        requests: Vec<String>,
+        /// The body that completed the upload.
+        completed: Option<String>,
+    }
+
+    /// Serve multipart uploads into `store`, refusing the first `PUT` of part
+    /// `refused`.
+    async fn s3(store: &Arc<Mutex<Store>>, refused: &'static str) -> TestServer {
+        let store = store.clone();
+        let mut refused = Some(refused);
+        TestServer::handle(move |request| {
+            let mut store = store.lock().unwrap();
+            let query = request
+                .target
+                .split_once('?')
+                .map_or("", |(_, query)| query);
+            store.requests.push(format!("{} {query}", request.method));
+            match (request.method.as_str(), query) {
+                ("POST", "uploads") => Reply::new(StatusCode::OK).body(
+                    "<InitiateMultipartUploadResult>\
+                     <UploadId>a&amp;b</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                ),
+                ("PUT", query) => {
+                    let part = query.strip_prefix("partNumber=").unwrap_or_default();
+                    let part = part.split('&').next().unwrap_or_default();
+                    if refused == Some(part) {
+                        refused = None;
+                        return Reply::new(StatusCode::FORBIDDEN);
+                    }
+                    Reply::new(StatusCode::OK).header("ETag", format!("\"{part}\""))
+                }
+                _ => {
+                    store.completed = Some(String::from_utf8_lossy(&request.body).into_owned());
+                    Reply::new(StatusCode::OK).body("<CompleteMultipartUploadResult/>")
+                }
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn resumes_multipart_uploads() {
+        let path = std::env::temp_dir().join(format!("upload-{}.bin", std::process::id()));
+        std::fs::write(&path, body()).unwrap();
+        let store = Arc::default();
+        let server = s3(&store, "2").await;
+        let uploader = uploader().chunk_size(4000).concurrency(1);
+
+        assert!(uploader
+            .upload_multipart(&server.url(), &path)
+            .await
+            .is_err());
+        let requests = std::mem::take(&mut store.lock().unwrap().requests);
+        assert_eq!(
+            requests,
+            [
+                "POST uploads",
+                "PUT partNumber=1&uploadId=a%26b",
+                "PUT partNumber=2&uploadId=a%26b"
+            ]
+        );
+
+        // after a crash, only the parts that are missing
+        uploader
+            .upload_multipart(&server.url(), &path)
+            .await
+            .unwrap();
+        let store = store.lock().unwrap();
+        assert_eq!(
+            store.requests,
+            [
+                "PUT partNumber=2&uploadId=a%26b",
+                "PUT partNumber=3&uploadId=a%26b",
+                "POST uploadId=a%26b"
+            ]
+        );
+        let parts = (1..=3).map(|number| (number, format!("\"{number}\"")));
+        let completed = complete_body(&parts.collect());
+        assert_eq!(store.completed.as_deref(), Some(completed.as_str()));
+        assert!(!multipart_path(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn encodes_metadata() {
+        assert_eq!(encode_metadata(&[]), "");
+        assert_eq!(
+            encode_metadata(&[("filename", "world_domination_plan.pdf"), ("empty", "")]),
+            "filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==,empty "
+        );
+    }
+
+    #[test]
+    fn parses_offsets() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(header_u64(&headers, UPLOAD_OFFSET), None);
+        headers.insert(UPLOAD_OFFSET, HeaderValue::from_static("70"));
+        assert_eq!(header_u64(&headers, UPLOAD_OFFSET), Some(70));
+        headers.insert(UPLOAD_OFFSET, HeaderValue::from_static("-1"));
+        assert_eq!(header_u64(&headers, UPLOAD_OFFSET), None);
+    }
+
+    #[test]
+    fn reads_multipart_answers() {
+        let initiated = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+            <InitiateMultipartUploadResult><Bucket>bucket</Bucket><Key>key</Key>\
+            <UploadId>VXBsb2FkIElE</UploadId></InitiateMultipartUploadResult>";
+        let upload_id = xml_element(initiated, "UploadId");
+        assert_eq!(upload_id.as_deref(), Some("VXBsb2FkIElE"));
+        assert_eq!(xml_element(initiated, "Code"), None);
+        let failed = "<Error><Code>InternalError</Code><Message>a &lt; b</Message></Error>";
+        assert_eq!(xml_element(failed, "Message").as_deref(), Some("a < b"));
+
+        let parts = BTreeMap::from([(2, "\"b\"".to_owned()), (1, "\"a\"".to_owned())]);
+        assert_eq!(
+            complete_body(&parts),
+            "<CompleteMultipartUpload>\
+             <Part><PartNumber>1</PartNumber><ETag>\"a\"</ETag></Part>\
+             <Part><PartNumber>2</PartNumber><ETag>\"b\"</ETag></Part>\
+             </CompleteMultipartUpload>"
+        );
+    }
+}